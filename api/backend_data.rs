@@ -2,7 +2,8 @@ use actix_web::web::Data;
 use ergo_auth::AuthData;
 use ergo_database::PostgresPool;
 use ergo_notifications::NotificationManager;
-use ergo_tasks::{actions::queue::ActionQueue, inputs::queue::InputQueue};
+use ergo_queues::Queue;
+use ergo_tasks::{actions::queue::ActionQueue, events::TaskEvents, inputs::queue::InputQueue};
 
 use crate::error::Result;
 
@@ -10,16 +11,29 @@ pub struct BackendAppState {
     pub pg: PostgresPool,
     pub auth: AuthData,
     pub notifications: NotificationManager,
+    pub task_events: TaskEvents,
     action_queue: ActionQueue,
     input_queue: InputQueue,
     pub redis_key_prefix: Option<String>,
 }
 
+impl BackendAppState {
+    /// All the job queues used by the server.
+    pub fn queues(&self) -> [&Queue; 3] {
+        [
+            &self.input_queue,
+            &self.action_queue,
+            self.notifications.queue(),
+        ]
+    }
+}
+
 pub type BackendAppStateData = Data<BackendAppState>;
 
 pub fn app_data(
     pg_pool: PostgresPool,
     notifications: NotificationManager,
+    task_events: TaskEvents,
     input_queue: InputQueue,
     action_queue: ActionQueue,
     redis_key_prefix: Option<String>,
@@ -28,6 +42,7 @@ pub fn app_data(
         auth: AuthData::new(pg_pool.clone())?,
         pg: pg_pool,
         notifications,
+        task_events,
         action_queue,
         input_queue,
         redis_key_prefix,
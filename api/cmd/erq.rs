@@ -1,6 +1,6 @@
 use std::{borrow::Cow, time::Duration};
 
-use ergo_queues::{Job, JobStatus, Queue};
+use ergo_queues::{Job, JobPriority, JobStatus, Queue};
 use structopt::StructOpt;
 
 use crate::error::Error;
@@ -15,7 +15,18 @@ pub struct Args {
 #[derive(Debug, StructOpt)]
 enum QueueCmd {
     #[structopt(about = "Add a job to the queue")]
-    Add { id: String, data: String },
+    Add {
+        id: String,
+        data: String,
+        #[structopt(
+            short,
+            long,
+            default_value = "normal",
+            parse(try_from_str = parse_priority),
+            help = "The job's priority: high, normal, or low"
+        )]
+        priority: JobPriority,
+    },
     #[structopt(about = "Show information about the queue")]
     Show,
     #[structopt(about = "List scheduled jobs")]
@@ -26,6 +37,25 @@ enum QueueCmd {
     ListProcessing,
     #[structopt(name = "show-job", about = "Show information about a job")]
     ShowJob { id: String },
+    #[structopt(about = "List jobs in the dead letter list")]
+    ListDead,
+    #[structopt(
+        name = "show-dead",
+        about = "Show a dead-lettered job, with the error from each attempt"
+    )]
+    ShowDead { id: String },
+    #[structopt(about = "Move dead-lettered jobs back to the pending list")]
+    RequeueDead {
+        #[structopt(required = true)]
+        ids: Vec<String>,
+    },
+    #[structopt(about = "Delete dead-lettered jobs")]
+    PurgeDead {
+        #[structopt(long, help = "Delete every job in the dead letter list")]
+        all: bool,
+        #[structopt(required_unless = "all", conflicts_with = "all")]
+        ids: Vec<String>,
+    },
     #[structopt(
         about = "Get and acknowledge the next job on the queue. (Don't use this in production)"
     )]
@@ -55,11 +85,12 @@ pub async fn main(args: Args) -> Result<(), Error> {
     let queue = Queue::new(redis_pool, args.queue.clone(), None, None, None);
 
     match args.cmd {
-        QueueCmd::Add { id, data } => {
+        QueueCmd::Add { id, data, priority } => {
             let bytes = data.into_bytes();
             let job = Job {
                 id,
                 payload: Cow::Owned(bytes),
+                priority,
                 ..Default::default()
             };
             queue.enqueue(&job).await?;
@@ -104,11 +135,59 @@ pub async fn main(args: Args) -> Result<(), Error> {
             Some(job) => println!("{:?}", job),
             None => println!("Job not found"),
         },
+        QueueCmd::ListDead => {
+            let tasks = queue.list_dead().await?;
+            for (task_id, died_at) in tasks {
+                println!("{}\t{}", task_id, died_at);
+            }
+        }
+        QueueCmd::ShowDead { id } => match queue.dead_job(&id).await? {
+            Some(dead) => {
+                println!("{:?}", dead.job);
+                println!("Failed at {}", dead.died_at);
+                for attempt in dead.errors {
+                    println!("{}\t{}\t{}", attempt.retry, attempt.time, attempt.error);
+                }
+            }
+            None => println!("Job not found in dead letter list"),
+        },
+        QueueCmd::RequeueDead { ids } => {
+            for id in ids {
+                if queue.requeue_dead_job(&id).await? {
+                    println!("Requeued {}", id);
+                } else {
+                    println!("{} not found in dead letter list", id);
+                }
+            }
+        }
+        QueueCmd::PurgeDead { all, ids } => {
+            if all {
+                let count = queue.purge_all_dead_jobs().await?;
+                println!("Purged {} jobs", count);
+            } else {
+                for id in ids {
+                    if queue.purge_dead_job(&id).await? {
+                        println!("Purged {}", id);
+                    } else {
+                        println!("{} not found in dead letter list", id);
+                    }
+                }
+            }
+        }
         QueueCmd::Stress(stress_args) => super::erq_stress::main(args.queue, stress_args).await?,
     }
     Ok(())
 }
 
+fn parse_priority(s: &str) -> Result<JobPriority, String> {
+    match s {
+        "high" => Ok(JobPriority::High),
+        "normal" => Ok(JobPriority::Normal),
+        "low" => Ok(JobPriority::Low),
+        _ => Err(format!("Unknown priority {}", s)),
+    }
+}
+
 async fn run_job(queue: &Queue, delay: Option<u64>, error: Option<String>) -> Result<(), Error> {
     let job = queue.get_job::<Box<serde_json::value::Value>>().await?;
 
@@ -0,0 +1,164 @@
+use ergo_database::{
+    database_configuration_from_env,
+    object_id::{OrgId, TaskId},
+};
+use structopt::StructOpt;
+
+use crate::{
+    error::{Error, Result},
+    log_retention::apply_log_retention,
+    service_config::LogRetentionConfig,
+};
+
+#[derive(Debug, StructOpt)]
+pub enum Args {
+    #[structopt(about = "Remove expired logs now, using the LOG_RETENTION_* settings")]
+    Run {
+        #[structopt(long, help = "Only count the logs that would be removed")]
+        dry_run: bool,
+    },
+    #[structopt(about = "Set the retention policy for an org or a task")]
+    SetPolicy {
+        #[structopt(long, required_unless = "task", help = "The org to set the policy on")]
+        org: Option<OrgId>,
+        #[structopt(
+            long,
+            conflicts_with = "org",
+            help = "The task to set the policy on, overriding the org policy"
+        )]
+        task: Option<TaskId>,
+        #[structopt(long, help = "How many days to keep logs")]
+        days: u32,
+        #[structopt(long, help = "Delete expired logs without archiving them")]
+        no_archive: bool,
+    },
+    #[structopt(about = "Remove the retention policy from an org or a task")]
+    RemovePolicy {
+        #[structopt(long, required_unless = "task")]
+        org: Option<OrgId>,
+        #[structopt(long, conflicts_with = "org")]
+        task: Option<TaskId>,
+    },
+    #[structopt(about = "List the retention policies")]
+    ListPolicies {
+        #[structopt(long, help = "Only show policies for this org")]
+        org: Option<OrgId>,
+    },
+}
+
+pub async fn main(args: Args) -> Result<()> {
+    crate::tracing_config::configure("log-retention", std::io::stdout);
+
+    let database_config = database_configuration_from_env()?;
+
+    match args {
+        Args::Run { dry_run } => {
+            let config = LogRetentionConfig::from_env()?;
+            let pool = crate::service_config::backend_pg_pool(&database_config).await?;
+            let stats = apply_log_retention(&pool, &config, dry_run).await?;
+
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            println!(
+                "{} {} inputs ({} archived) and {} standalone actions ({} archived)",
+                verb,
+                stats.inputs_removed,
+                stats.inputs_archived,
+                stats.actions_removed,
+                stats.actions_archived
+            );
+        }
+        Args::SetPolicy {
+            org,
+            task,
+            days,
+            no_archive,
+        } => {
+            let pool = crate::service_config::web_pg_pool(&database_config).await?;
+            let days = days as i32;
+            let result = match (org, task) {
+                (_, Some(task)) => sqlx::query!(
+                    r##"INSERT INTO log_retention_policies (org_id, task_id, retain_days, archive)
+                        SELECT org_id, task_id, $2, $3 FROM tasks WHERE task_id = $1
+                    ON CONFLICT (task_id) WHERE task_id IS NOT NULL
+                    DO UPDATE SET retain_days = EXCLUDED.retain_days,
+                        archive = EXCLUDED.archive,
+                        updated = now()"##,
+                    task.0,
+                    days,
+                    !no_archive
+                )
+                .execute(&pool)
+                .await?,
+                (Some(org), None) => {
+                    sqlx::query!(
+                        r##"INSERT INTO log_retention_policies (org_id, retain_days, archive)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (org_id) WHERE task_id IS NULL
+                        DO UPDATE SET retain_days = EXCLUDED.retain_days,
+                            archive = EXCLUDED.archive,
+                            updated = now()"##,
+                        org.0,
+                        days,
+                        !no_archive
+                    )
+                    .execute(&pool)
+                    .await?
+                }
+                (None, None) => unreachable!("structopt requires org or task"),
+            };
+
+            if result.rows_affected() == 0 {
+                return Err(Error::NotFound);
+            }
+        }
+        Args::RemovePolicy { org, task } => {
+            let pool = crate::service_config::web_pg_pool(&database_config).await?;
+            let result = match (org, task) {
+                (_, Some(task)) => {
+                    sqlx::query!(
+                        "DELETE FROM log_retention_policies WHERE task_id = $1",
+                        task.0
+                    )
+                    .execute(&pool)
+                    .await?
+                }
+                (Some(org), None) => {
+                    sqlx::query!(
+                        "DELETE FROM log_retention_policies WHERE org_id = $1 AND task_id IS NULL",
+                        org.0
+                    )
+                    .execute(&pool)
+                    .await?
+                }
+                (None, None) => unreachable!("structopt requires org or task"),
+            };
+
+            if result.rows_affected() == 0 {
+                return Err(Error::NotFound);
+            }
+        }
+        Args::ListPolicies { org } => {
+            let pool = crate::service_config::web_pg_pool(&database_config).await?;
+            let policies = sqlx::query!(
+                r##"SELECT org_id, task_id, retain_days, archive
+                FROM log_retention_policies
+                WHERE $1::uuid IS NULL OR org_id = $1
+                ORDER BY org_id, task_id NULLS FIRST"##,
+                org.map(|o| o.0)
+            )
+            .fetch_all(&pool)
+            .await?;
+
+            for policy in policies {
+                let target = match policy.task_id {
+                    Some(task_id) => format!("task {}", TaskId::from_uuid(task_id)),
+                    None => format!("org {}", OrgId::from_uuid(policy.org_id)),
+                };
+                let action = if policy.archive { "archive" } else { "delete" };
+                println!("{}: {} after {} days", target, action, policy.retain_days);
+            }
+        }
+    }
+
+    Ok(())
+}
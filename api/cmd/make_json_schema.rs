@@ -1,7 +1,9 @@
 use crate::routes::{
     actions::ExecutorInfo,
     inputs::InputPayload,
-    tasks::{InputsLogEntry, TaskDescription, TaskInput, TaskResult},
+    task_bundles::{TaskBundle, TaskImportResult},
+    task_versions::{TaskRollbackResult, TaskVersion, TaskVersionDiff},
+    tasks::{InputsLogEntry, TaskDescription, TaskInput, TaskResult, TaskSimulateRequest},
 };
 
 use ergo_tasks::{
@@ -12,10 +14,12 @@ use ergo_tasks::{
         Action, ActionCategory,
     },
     inputs::Input,
+    simulate::SimulateResult,
     state_machine::{
         ActionInvokeDef, ActionInvokeDefDataField, ActionPayloadBuilder, EventHandler,
         StateDefinition, StateMachine, StateMachineData, TransitionCondition, TransitionTarget,
     },
+    test_cases::{TaskTestCase, TaskTestRun},
 };
 
 use schemars::{schema::RootSchema, schema_for};
@@ -50,6 +54,33 @@ pub fn main() -> crate::error::Result<()> {
     let schema = schema_for!(TaskResult);
     write(&dir, "task_result", &schema)?;
 
+    let schema = schema_for!(TaskBundle);
+    write(&dir, "task_bundle", &schema)?;
+
+    let schema = schema_for!(TaskImportResult);
+    write(&dir, "task_import_result", &schema)?;
+
+    let schema = schema_for!(TaskVersion);
+    write(&dir, "task_version", &schema)?;
+
+    let schema = schema_for!(TaskVersionDiff);
+    write(&dir, "task_version_diff", &schema)?;
+
+    let schema = schema_for!(TaskRollbackResult);
+    write(&dir, "task_rollback_result", &schema)?;
+
+    let schema = schema_for!(TaskSimulateRequest);
+    write(&dir, "task_simulate_request", &schema)?;
+
+    let schema = schema_for!(SimulateResult);
+    write(&dir, "task_simulate_result", &schema)?;
+
+    let schema = schema_for!(TaskTestCase);
+    write(&dir, "task_test_case", &schema)?;
+
+    let schema = schema_for!(TaskTestRun);
+    write(&dir, "task_test_run", &schema)?;
+
     let schema = schema_for!(TemplateField);
     write(&dir, "template_field", &schema)?;
 
@@ -2,7 +2,10 @@ pub mod drain_queues;
 pub mod erq;
 pub mod erq_stress;
 pub mod hash_passwd;
+pub mod log_retention;
 pub mod make_api_key;
 pub mod make_id;
 pub mod make_json_schema;
 pub mod server;
+pub mod task_bundle;
+pub mod task_tests;
@@ -7,10 +7,18 @@ use tracing::{event, Level};
 pub struct Args {
     #[structopt(long, help = "Do not run the PostgreSQL queue stage drain tasks")]
     no_drain_queues: bool,
+    #[structopt(long, help = "Do not remove old logs in the background")]
+    no_log_retention: bool,
 }
 
 pub async fn main(args: Args) -> Result<(), crate::error::Error> {
     let shutdown = GracefulShutdown::new();
+    let log_retention = if args.no_log_retention {
+        None
+    } else {
+        Some(crate::service_config::LogRetentionConfig::from_env()?)
+    };
+
     let config = crate::server::Config {
         bind_address: Some(envoption::with_default("BIND_ADDRESS", "127.0.0.1")?),
         bind_port: envoption::with_default("BIND_PORT", 6543_u16)?,
@@ -18,6 +26,8 @@ pub async fn main(args: Args) -> Result<(), crate::error::Error> {
         redis_url: None,
         redis_queue_prefix: None,
         no_drain_queues: args.no_drain_queues,
+        log_retention,
+        email: crate::service_config::email_config_from_env()?,
         shutdown: shutdown.consumer(),
     };
 
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use ergo_database::object_id::TaskId;
+use structopt::StructOpt;
+
+use crate::{
+    error::{Error, Result},
+    routes::task_bundles::{TaskBundle, TaskImportQuery, TaskImportResult},
+};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(
+        long,
+        env = "ERGO_URL",
+        default_value = "http://localhost:6543",
+        help = "The server to connect to"
+    )]
+    url: String,
+    #[structopt(long, env = "API_KEY", hide_env_values = true)]
+    api_key: String,
+    #[structopt(subcommand)]
+    cmd: BundleCmd,
+}
+
+#[derive(Debug, StructOpt)]
+enum BundleCmd {
+    #[structopt(about = "Export a task, and the actions and inputs it uses, to a bundle")]
+    Export {
+        task_id: TaskId,
+        #[structopt(short, long, help = "Write the bundle to this file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    #[structopt(about = "Import a task bundle as a new task")]
+    Import {
+        file: PathBuf,
+        #[structopt(long, help = "Only report what would happen")]
+        dry_run: bool,
+        #[structopt(long, help = "Import even if there are conflicts, where possible")]
+        force: bool,
+    },
+}
+
+pub async fn main(args: Args) -> Result<()> {
+    let client = reqwest::Client::new();
+    let base = args.url.trim_end_matches('/');
+
+    match args.cmd {
+        BundleCmd::Export { task_id, output } => {
+            let bundle: TaskBundle = client
+                .get(format!("{}/api/tasks/{}/export", base, task_id))
+                .bearer_auth(&args.api_key)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let output_json = serde_json::to_string_pretty(&bundle)?;
+            match output {
+                Some(path) => std::fs::write(path, output_json)?,
+                None => println!("{}", output_json),
+            }
+        }
+        BundleCmd::Import {
+            file,
+            dry_run,
+            force,
+        } => {
+            let bundle: TaskBundle = serde_json::from_slice(&std::fs::read(file)?)?;
+            let response = client
+                .post(format!("{}/api/tasks/import", base))
+                .bearer_auth(&args.api_key)
+                .query(&TaskImportQuery { dry_run, force })
+                .json(&bundle)
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() && status != reqwest::StatusCode::CONFLICT {
+                return Err(response.error_for_status().unwrap_err().into());
+            }
+
+            let result: TaskImportResult = response.json().await?;
+            print_import_result(&result);
+
+            if status == reqwest::StatusCode::CONFLICT {
+                return Err(Error::StringError(
+                    "The task was not imported because of conflicts".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_import_result(result: &TaskImportResult) {
+    for action in &result.actions {
+        println!(
+            "Action {}: {} -> {} ({:?})",
+            action.name, action.bundle_id, action.id, action.resolution
+        );
+    }
+
+    for input in &result.inputs {
+        println!(
+            "Input {}: {} -> {} ({:?})",
+            input.name, input.bundle_id, input.id, input.resolution
+        );
+    }
+
+    for conflict in &result.conflicts {
+        println!(
+            "Conflict: {}",
+            serde_json::to_string(conflict).unwrap_or_default()
+        );
+    }
+
+    if let Some(task_id) = &result.task_id {
+        println!("Imported task {}", task_id);
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+use ergo_database::object_id::{TaskId, UserId};
+use ergo_tasks::test_cases::{run_test_cases, TaskTestRun};
+use structopt::StructOpt;
+
+use crate::{
+    error::{Error, Result},
+    routes::tasks::TaskInput,
+};
+
+#[derive(Debug, StructOpt)]
+pub enum Args {
+    #[structopt(about = "Run the tests saved with a task on the server")]
+    Run {
+        task_id: TaskId,
+        #[structopt(
+            long,
+            env = "ERGO_URL",
+            default_value = "http://localhost:6543",
+            help = "The server to connect to"
+        )]
+        url: String,
+        #[structopt(long, env = "API_KEY", hide_env_values = true)]
+        api_key: String,
+    },
+    #[structopt(about = "Run the tests in a task JSON file locally")]
+    RunFile { file: PathBuf },
+}
+
+pub async fn main(args: Args) -> Result<()> {
+    let run = match args {
+        Args::Run {
+            task_id,
+            url,
+            api_key,
+        } => {
+            reqwest::Client::new()
+                .post(format!(
+                    "{}/api/tasks/{}/tests/run",
+                    url.trim_end_matches('/'),
+                    task_id
+                ))
+                .bearer_auth(&api_key)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<TaskTestRun>()
+                .await?
+        }
+        Args::RunFile { file } => {
+            let task: TaskInput = serde_json::from_slice(&std::fs::read(file)?)?;
+            run_test_cases(
+                &TaskId::new(),
+                &task.name,
+                &UserId::new(),
+                &task.compiled,
+                &task.tests,
+            )
+            .await
+        }
+    };
+
+    print_test_run(&run);
+
+    let failed = run.num_failed();
+    if failed > 0 {
+        return Err(Error::StringError(format!(
+            "{} of {} task tests failed",
+            failed,
+            run.results.len()
+        )));
+    }
+
+    Ok(())
+}
+
+fn print_test_run(run: &TaskTestRun) {
+    for result in &run.results {
+        let status = if result.passed { "PASS" } else { "FAIL" };
+        println!("{} {}", status, result.name);
+        for failure in &result.failures {
+            println!("    {}", failure);
+        }
+    }
+}
@@ -21,6 +21,12 @@ pub enum Error {
     #[error("Not found")]
     NotFound,
 
+    #[error("Invalid {0} header")]
+    InvalidHeader(&'static str),
+
+    #[error("Invalid query parameter {0}")]
+    InvalidQuery(String),
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
@@ -92,6 +98,9 @@ pub enum Error {
 
     #[error(transparent)]
     NotificationError(#[from] ergo_notifications::Error),
+
+    #[error("Metrics error: {0}")]
+    MetricsError(#[from] prometheus::Error),
 }
 
 impl<T: std::error::Error> From<EnvOptionError<T>> for Error {
@@ -145,8 +154,24 @@ impl actix_web::error::ResponseError for Error {
             Error::AuthError(ergo_auth::Error::AuthorizationError) => StatusCode::FORBIDDEN,
             Error::NotFound => StatusCode::NOT_FOUND,
             Error::UnknownExecutor(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidHeader(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
             Error::ActixError { status_code, .. } => *status_code,
             Error::TasksError(ergo_tasks::Error::NotFound) => StatusCode::NOT_FOUND,
+            Error::TasksError(
+                ergo_tasks::Error::TaskValidateError(_)
+                | ergo_tasks::Error::TaskTriggerNotFound(_)
+                | ergo_tasks::Error::ConfigStateMismatch(_)
+                | ergo_tasks::Error::MissingDataFlowNodeName(_)
+                | ergo_tasks::Error::MissingDataFlowNodeInput(_),
+            ) => StatusCode::BAD_REQUEST,
+            Error::NotificationError(
+                ergo_notifications::Error::InvalidEndpoint(_)
+                | ergo_notifications::Error::InvalidPolicy(_)
+                | ergo_notifications::Error::TemplateError(_)
+                | ergo_notifications::Error::EmailAddressError(_)
+                | ergo_notifications::Error::JsonError(_),
+            ) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
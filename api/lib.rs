@@ -3,6 +3,7 @@ pub mod auth;
 pub mod backend_data;
 pub mod cmd;
 pub mod error;
+pub mod log_retention;
 pub mod routes;
 pub mod server;
 pub mod service_config;
@@ -0,0 +1,314 @@
+//! Remove old entries from `inputs_log` and `actions_log`, optionally archiving them first.
+//!
+//! The retention period comes from the task's policy, then the org's policy, and then the
+//! default in [LogRetentionConfig]. Archived logs are written as gzipped JSON Lines, one file per
+//! org and day, at `{archive_dir}/{org_id}/{YYYY-MM-DD}.jsonl.gz`. Each run appends a new gzip
+//! member to the file, which `zcat` and other gzip readers handle transparently.
+//!
+//! Rows are only deleted after the archive is written and synced to disk, so a crash between the
+//! two steps may archive the same rows twice but never loses them.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use const_format::concatcp;
+use ergo_database::{object_id::OrgId, PostgresPool};
+use ergo_graceful_shutdown::GracefulShutdownConsumer;
+use flate2::{write::GzEncoder, Compression};
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::{error::Result, service_config::LogRetentionConfig};
+
+// Parameters for all the queries below:
+// $1. default retention days, or NULL to keep logs without a policy
+// $2. archive logs that use the default retention period
+// $3. whether an archive directory is available
+const POLICY_JOINS: &str = r##"
+    JOIN tasks t ON t.task_id = logs.task_id
+    LEFT JOIN log_retention_policies tp ON tp.task_id = logs.task_id
+    LEFT JOIN log_retention_policies op ON op.org_id = t.org_id AND op.task_id IS NULL
+"##;
+
+const POLICY_FILTER: &str = r##"
+    COALESCE(tp.retain_days, op.retain_days, $1) IS NOT NULL
+    AND logs.created < now() - make_interval(days => COALESCE(tp.retain_days, op.retain_days, $1))
+    -- Without an archive directory, leave alone any logs that should be archived.
+    AND ($3 OR NOT COALESCE(tp.archive, op.archive, $2))
+"##;
+
+const ARCHIVE_COLUMN: &str = "COALESCE(tp.archive, op.archive, $2) AS archive";
+
+const EXPIRED_INPUTS: &str = concatcp!(
+    "FROM inputs_log logs",
+    POLICY_JOINS,
+    "WHERE logs.status <> 'pending' AND ",
+    POLICY_FILTER,
+    r##"
+    AND NOT EXISTS (
+        SELECT 1 FROM actions_log al
+        WHERE al.inputs_log_id = logs.inputs_log_id AND al.status IN ('pending', 'running')
+    )"##
+);
+
+const EXPIRED_ACTIONS: &str = concatcp!(
+    "FROM actions_log logs",
+    POLICY_JOINS,
+    "WHERE logs.inputs_log_id IS NULL AND logs.status NOT IN ('pending', 'running') AND ",
+    POLICY_FILTER
+);
+
+/// Each input is archived with its actions nested inside it.
+const SELECT_EXPIRED_INPUTS: &str = concatcp!(
+    "SELECT logs.inputs_log_id AS id, t.org_id, logs.created, ",
+    ARCHIVE_COLUMN,
+    r##",
+    jsonb_build_object('type', 'input') || to_jsonb(logs) || jsonb_build_object('actions',
+        COALESCE(
+            (SELECT jsonb_agg(to_jsonb(al) ORDER BY al.created)
+            FROM actions_log al WHERE al.inputs_log_id = logs.inputs_log_id),
+            '[]'::jsonb
+        )
+    ) AS record
+    "##,
+    EXPIRED_INPUTS,
+    " ORDER BY logs.created LIMIT $4"
+);
+
+const SELECT_EXPIRED_ACTIONS: &str = concatcp!(
+    "SELECT logs.actions_log_id AS id, t.org_id, logs.created, ",
+    ARCHIVE_COLUMN,
+    ", jsonb_build_object('type', 'action') || to_jsonb(logs) AS record ",
+    EXPIRED_ACTIONS,
+    " ORDER BY logs.created LIMIT $4"
+);
+
+const COUNT_EXPIRED_INPUTS: &str = concatcp!(
+    "SELECT COUNT(*), COUNT(*) FILTER (WHERE COALESCE(tp.archive, op.archive, $2)) ",
+    EXPIRED_INPUTS
+);
+
+const COUNT_EXPIRED_ACTIONS: &str = concatcp!(
+    "SELECT COUNT(*), COUNT(*) FILTER (WHERE COALESCE(tp.archive, op.archive, $2)) ",
+    EXPIRED_ACTIONS
+);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionStats {
+    /// The number of inputs removed, including those that were archived.
+    pub inputs_removed: u64,
+    pub inputs_archived: u64,
+    /// The number of actions removed that were not part of an input. Actions from an input are
+    /// archived and removed along with the input.
+    pub actions_removed: u64,
+    pub actions_archived: u64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ExpiredRow {
+    id: Uuid,
+    org_id: Uuid,
+    created: DateTime<Utc>,
+    archive: bool,
+    record: serde_json::Value,
+}
+
+#[derive(Clone, Copy)]
+enum LogKind {
+    Inputs,
+    Actions,
+}
+
+impl LogKind {
+    fn select_query(&self) -> &'static str {
+        match self {
+            Self::Inputs => SELECT_EXPIRED_INPUTS,
+            Self::Actions => SELECT_EXPIRED_ACTIONS,
+        }
+    }
+
+    fn count_query(&self) -> &'static str {
+        match self {
+            Self::Inputs => COUNT_EXPIRED_INPUTS,
+            Self::Actions => COUNT_EXPIRED_ACTIONS,
+        }
+    }
+}
+
+/// Remove all the logs that have expired. With `dry_run`, the logs are counted but not touched.
+pub async fn apply_log_retention(
+    pool: &PostgresPool,
+    config: &LogRetentionConfig,
+    dry_run: bool,
+) -> Result<RetentionStats> {
+    let mut stats = RetentionStats::default();
+    for kind in [LogKind::Inputs, LogKind::Actions] {
+        let (removed, archived) = if dry_run {
+            count_expired(pool, config, kind).await?
+        } else {
+            remove_expired(pool, config, kind).await?
+        };
+
+        match kind {
+            LogKind::Inputs => {
+                stats.inputs_removed = removed;
+                stats.inputs_archived = archived;
+            }
+            LogKind::Actions => {
+                stats.actions_removed = removed;
+                stats.actions_archived = archived;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+async fn count_expired(
+    pool: &PostgresPool,
+    config: &LogRetentionConfig,
+    kind: LogKind,
+) -> Result<(u64, u64)> {
+    let (total, archived): (i64, i64) = sqlx::query_as(kind.count_query())
+        .bind(config.default_retain_days.map(|d| d as i32))
+        .bind(config.default_archive)
+        .bind(config.archive_dir.is_some())
+        .fetch_one(pool)
+        .await?;
+
+    Ok((total as u64, archived as u64))
+}
+
+async fn remove_expired(
+    pool: &PostgresPool,
+    config: &LogRetentionConfig,
+    kind: LogKind,
+) -> Result<(u64, u64)> {
+    let mut removed = 0;
+    let mut archived = 0;
+    loop {
+        let rows: Vec<ExpiredRow> = sqlx::query_as(kind.select_query())
+            .bind(config.default_retain_days.map(|d| d as i32))
+            .bind(config.default_archive)
+            .bind(config.archive_dir.is_some())
+            .bind(config.batch_size as i64)
+            .fetch_all(pool)
+            .await?;
+
+        if rows.is_empty() {
+            break;
+        }
+
+        let batch_len = rows.len();
+        let ids = rows.iter().map(|r| r.id).collect::<Vec<_>>();
+        let to_archive = rows.into_iter().filter(|r| r.archive).collect::<Vec<_>>();
+
+        if !to_archive.is_empty() {
+            // The queries only return rows to archive when there is a directory for them.
+            let dir = config.archive_dir.clone().unwrap_or_default();
+            archived += to_archive.len() as u64;
+            tokio::task::spawn_blocking(move || write_archive(&dir, to_archive)).await??;
+        }
+
+        let mut tx = pool.begin().await?;
+        match kind {
+            LogKind::Inputs => {
+                sqlx::query!(
+                    "DELETE FROM actions_log WHERE inputs_log_id = ANY($1)",
+                    &ids
+                )
+                .execute(&mut tx)
+                .await?;
+                sqlx::query!("DELETE FROM inputs_log WHERE inputs_log_id = ANY($1)", &ids)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            LogKind::Actions => {
+                sqlx::query!(
+                    "DELETE FROM actions_log WHERE actions_log_id = ANY($1)",
+                    &ids
+                )
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        removed += batch_len as u64;
+        if batch_len < config.batch_size as usize {
+            break;
+        }
+    }
+
+    Ok((removed, archived))
+}
+
+/// The archive file that holds logs for an org on a particular day.
+pub fn archive_path(dir: &Path, org_id: &OrgId, date: NaiveDate) -> PathBuf {
+    dir.join(org_id.to_string())
+        .join(format!("{}.jsonl.gz", date.format("%Y-%m-%d")))
+}
+
+fn write_archive(dir: &Path, rows: Vec<ExpiredRow>) -> Result<()> {
+    let mut files: BTreeMap<(Uuid, NaiveDate), Vec<serde_json::Value>> = BTreeMap::new();
+    for row in rows {
+        files
+            .entry((row.org_id, row.created.naive_utc().date()))
+            .or_default()
+            .push(row.record);
+    }
+
+    for ((org_id, date), records) in files {
+        let path = archive_path(dir, &OrgId::from_uuid(org_id), date);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let mut encoder = GzEncoder::new(std::io::BufWriter::new(file), Compression::default());
+        for record in records {
+            serde_json::to_writer(&mut encoder, &record)?;
+            encoder.write_all(b"\n")?;
+        }
+
+        let file = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Periodically remove expired logs until shutdown.
+pub fn start_log_retention_task(
+    pool: PostgresPool,
+    config: LogRetentionConfig,
+    mut shutdown: GracefulShutdownConsumer,
+) -> tokio::task::JoinHandle<()> {
+    if config.archive_dir.is_none() {
+        event!(
+            Level::WARN,
+            "LOG_ARCHIVE_DIR is not set, so logs with an archiving policy will not be removed"
+        );
+    }
+
+    tokio::spawn(async move {
+        loop {
+            match apply_log_retention(&pool, &config, false).await {
+                Ok(stats) => event!(Level::INFO, ?stats, "Applied log retention"),
+                Err(e) => event!(Level::ERROR, error=%e, "Failed to apply log retention"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(config.check_interval) => continue,
+                _ = shutdown.wait_for_shutdown() => break,
+            }
+        }
+    })
+}
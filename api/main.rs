@@ -7,6 +7,12 @@ enum Args {
     Server(cmd::server::Args),
     #[structopt(about = "Run a task that only drains the Postgres queues")]
     DrainQueues,
+    #[structopt(about = "Manage log retention policies and remove expired logs")]
+    LogRetention(cmd::log_retention::Args),
+    #[structopt(about = "Export and import tasks")]
+    TaskBundle(cmd::task_bundle::Args),
+    #[structopt(about = "Run task test cases")]
+    TaskTests(cmd::task_tests::Args),
     #[structopt(about = "Development commands")]
     Dev(DevCmds),
 }
@@ -43,6 +49,9 @@ async fn run() -> Result<(), error::Error> {
     match args {
         Args::Server(s) => cmd::server::main(s).await,
         Args::DrainQueues => cmd::drain_queues::main().await,
+        Args::LogRetention(args) => cmd::log_retention::main(args).await,
+        Args::TaskBundle(args) => cmd::task_bundle::main(args).await,
+        Args::TaskTests(args) => cmd::task_tests::main(args).await,
         Args::Dev(cmd) => match cmd {
             DevCmds::HashPassword(args) => cmd::hash_passwd::main(args),
             DevCmds::MakeApiKey(args) => cmd::make_api_key::main(args).await,
@@ -15,7 +15,7 @@ use ergo_tasks::actions::{
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::Connection;
+use sqlx::{Connection, Postgres, Transaction};
 
 use crate::{error::Result, web_app_server::AppStateData};
 
@@ -101,65 +101,73 @@ impl ActionPayload {
     }
 }
 
-#[post("/actions")]
-pub async fn new_action(
-    data: AppStateData,
-    auth: Authenticated,
-    payload: web::Json<ActionPayload>,
-) -> Result<impl Responder> {
-    auth.expect_admin()?;
-
-    let payload: Action = payload.into_inner().into_action(ActionId::new());
-    payload
-        .validate()
-        .await
-        .map_err(ergo_tasks::Error::ActionValidateError)?;
-
-    let mut conn = data.pg.acquire().await?;
-    let mut tx = conn.begin().await?;
-
+/// Add a new action, along with its allowed account types.
+pub(crate) async fn insert_action(
+    tx: &mut Transaction<'_, Postgres>,
+    action: &Action,
+) -> Result<()> {
     sqlx::query!(
         "INSERT INTO actions (action_id, action_category_id, name, description,
         executor_id, executor_template, template_fields, account_required,
         postprocess_script, timeout) VALUES
         ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
-        &payload.action_id.0,
-        &payload.action_category_id.0,
-        &payload.name,
-        &payload.description as _,
-        &payload.executor_id,
-        sqlx::types::Json(&payload.executor_template) as _,
-        sqlx::types::Json(&payload.template_fields) as _,
-        &payload.account_required,
-        payload.postprocess_script.as_ref(),
-        payload.timeout,
+        &action.action_id.0,
+        &action.action_category_id.0,
+        &action.name,
+        &action.description as _,
+        &action.executor_id,
+        sqlx::types::Json(&action.executor_template) as _,
+        sqlx::types::Json(&action.template_fields) as _,
+        &action.account_required,
+        action.postprocess_script.as_ref(),
+        action.timeout,
     )
-    .execute(&mut tx)
+    .execute(&mut *tx)
     .await?;
 
-    if !payload.account_types.is_empty() {
+    if !action.account_types.is_empty() {
         let q = format!(
             "INSERT INTO allowed_action_account_types (account_type_id, action_id) VALUES {} ON CONFLICT DO NOTHING",
-            sql_insert_parameters::<2>(payload.account_types.len())
+            sql_insert_parameters::<2>(action.account_types.len())
         );
 
         let mut query = sqlx::query(&q);
-        for account_type in &payload.account_types {
-            query = query.bind(account_type).bind(payload.action_id.0);
+        for account_type in &action.account_types {
+            query = query.bind(account_type).bind(action.action_id.0);
         }
 
-        query.execute(&mut tx).await?;
+        query.execute(&mut *tx).await?;
     }
 
     sqlx::query!(
         r##"DELETE FROM allowed_action_account_types
         WHERE action_id=$1 AND account_type_id <> ANY($2)"##,
-        &payload.action_id.0,
-        &payload.account_types
+        &action.action_id.0,
+        &action.account_types
     )
-    .execute(&mut tx)
+    .execute(&mut *tx)
     .await?;
 
+    Ok(())
+}
+
+#[post("/actions")]
+pub async fn new_action(
+    data: AppStateData,
+    auth: Authenticated,
+    payload: web::Json<ActionPayload>,
+) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let payload: Action = payload.into_inner().into_action(ActionId::new());
+    payload
+        .validate()
+        .await
+        .map_err(ergo_tasks::Error::ActionValidateError)?;
+
+    let mut conn = data.pg.acquire().await?;
+    let mut tx = conn.begin().await?;
+    insert_action(&mut tx, &payload).await?;
     tx.commit().await?;
 
     Ok(HttpResponse::Created().json(payload))
@@ -8,7 +8,7 @@ use ergo_database::object_id::{InputCategoryId, InputId};
 use ergo_tasks::inputs::Input;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::Connection;
+use sqlx::{Connection, Postgres, Transaction};
 
 use crate::{error::Result, web_app_server::AppStateData};
 
@@ -47,6 +47,22 @@ pub async fn list_inputs(data: AppStateData) -> Result<impl Responder> {
     Ok(HttpResponse::Ok().json(inputs))
 }
 
+pub(crate) async fn insert_input(tx: &mut Transaction<'_, Postgres>, input: &Input) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO inputs (input_id, input_category_id, name, description, payload_schema) VALUES
+        ($1, $2, $3, $4, $5)",
+        &input.input_id.0,
+        &input.input_category_id as _,
+        &input.name,
+        &input.description as _,
+        &input.payload_schema
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
 #[post("/inputs")]
 pub async fn new_input(
     data: AppStateData,
@@ -62,19 +78,7 @@ pub async fn new_input(
 
     let mut conn = data.pg.acquire().await?;
     let mut tx = conn.begin().await?;
-
-    sqlx::query!(
-        "INSERT INTO inputs (input_id, input_category_id, name, description, payload_schema) VALUES
-        ($1, $2, $3, $4, $5)",
-        &payload.input_id.0,
-        &payload.input_category_id as _,
-        &payload.name,
-        &payload.description as _,
-        &payload.payload_schema
-    )
-    .execute(&mut tx)
-    .await?;
-
+    insert_input(&mut tx, &payload).await?;
     tx.commit().await?;
 
     Ok(HttpResponse::Created().json(payload))
@@ -0,0 +1,90 @@
+use actix_web::{web, HttpResponse, Responder};
+use ergo_auth::Authenticated;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, Encoder, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+
+use crate::{backend_data::BackendAppStateData, error::Result};
+
+lazy_static! {
+    static ref QUEUE_JOBS: IntGaugeVec = register_int_gauge_vec!(
+        "erq_queue_jobs",
+        "Jobs currently in each queue, by state",
+        &["queue", "state"]
+    )
+    .unwrap();
+    static ref QUEUE_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "erq_queue_events_total",
+        "Job events recorded by each queue since it was created",
+        &["queue", "event"]
+    )
+    .unwrap();
+}
+
+/// Advance a counter to a total that is tracked elsewhere. If the total went backwards, the
+/// source was reset, so the counter starts over too.
+fn sync_counter(counter: &IntCounterVec, labels: &[&str], total: usize) {
+    let c = counter.with_label_values(labels);
+    let total = total as u64;
+    let current = c.get();
+    if total >= current {
+        c.inc_by(total - current);
+    } else {
+        c.reset();
+        c.inc_by(total);
+    }
+}
+
+/// Queue statistics are kept in Redis, so they are read at scrape time. Everything else is
+/// recorded as it happens.
+async fn update_queue_metrics(data: &BackendAppStateData) -> Result<()> {
+    for queue in data.queues() {
+        let status = queue.status().await?;
+        let name = queue.name();
+
+        for (state, value) in [
+            ("running", status.current_running),
+            ("scheduled", status.current_scheduled),
+            ("pending", status.current_pending),
+            ("dead", status.current_dead),
+        ] {
+            QUEUE_JOBS
+                .with_label_values(&[name, state])
+                .set(value as i64);
+        }
+
+        for (event, total) in [
+            ("enqueued", status.total_enqueued),
+            ("scheduled", status.total_scheduled),
+            ("retrieved", status.total_retrieved),
+            ("succeeded", status.total_succeeded),
+            ("errored", status.total_errored),
+            ("failed", status.total_failed),
+        ] {
+            sync_counter(&QUEUE_EVENTS, &[name, event], total);
+        }
+    }
+
+    Ok(())
+}
+
+/// Export the metrics in the Prometheus text format. The metrics cover every organization, so
+/// only admins can read them.
+async fn metrics(data: BackendAppStateData, auth: Authenticated) -> Result<impl Responder> {
+    auth.expect_admin()?;
+    update_queue_metrics(&data).await?;
+
+    let encoder = TextEncoder::new();
+    let mut output = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut output)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(output))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
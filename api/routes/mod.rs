@@ -2,5 +2,9 @@ pub mod accounts;
 pub mod action_categories;
 pub mod actions;
 pub mod inputs;
+pub mod metrics;
+pub mod notifications;
 pub mod status;
+pub mod task_bundles;
+pub mod task_versions;
 pub mod tasks;
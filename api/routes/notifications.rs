@@ -0,0 +1,553 @@
+//! Manage where notifications are sent. An endpoint is a destination on a service, such as a
+//! Slack webhook or an email address, and a listener sends a type of event from a task, or from
+//! all of the org's tasks, to an endpoint.
+
+use actix_web::{
+    delete, get, post, put,
+    web::{self, Path},
+    HttpResponse, Responder,
+};
+use ergo_auth::Authenticated;
+use ergo_database::object_id::{NotifyEndpointId, NotifyListenerId, TaskId};
+use ergo_notifications::{
+    validate_endpoint, Notification, NotifyDestination, NotifyEvent, NotifyPolicy, NotifyService,
+    NotifyTemplate,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use crate::{
+    backend_data::BackendAppStateData,
+    error::{Error, Result},
+    web_app_server::AppStateData,
+};
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Replaces secret values in an endpoint's config when it is returned from the API.
+pub const REDACTED: &str = "********";
+
+/// Hide the webhook secret and header values, which often hold credentials.
+fn redact_config(config: Option<serde_json::Value>) -> Option<serde_json::Value> {
+    let mut config = config?;
+    if let Some(obj) = config.as_object_mut() {
+        if let Some(secret) = obj.get_mut("secret").filter(|s| s.is_string()) {
+            *secret = serde_json::Value::from(REDACTED);
+        }
+
+        if let Some(headers) = obj.get_mut("headers").and_then(|h| h.as_object_mut()) {
+            for value in headers.values_mut().filter(|v| v.is_string()) {
+                *value = serde_json::Value::from(REDACTED);
+            }
+        }
+    }
+
+    Some(config)
+}
+
+/// Put back the stored values for any secrets that were sent back still redacted, so that a
+/// client can send an endpoint back as it was returned.
+fn restore_redacted(config: Option<&mut serde_json::Value>, existing: Option<&serde_json::Value>) {
+    let (config, existing) = match (
+        config.and_then(|c| c.as_object_mut()),
+        existing.and_then(|e| e.as_object()),
+    ) {
+        (Some(c), Some(e)) => (c, e),
+        _ => return,
+    };
+
+    if let Some(secret) = config
+        .get_mut("secret")
+        .filter(|s| s.as_str() == Some(REDACTED))
+    {
+        *secret = existing.get("secret").cloned().unwrap_or_default();
+    }
+
+    if let Some(headers) = config.get_mut("headers").and_then(|h| h.as_object_mut()) {
+        let existing_headers = existing.get("headers").and_then(|h| h.as_object());
+        for (name, value) in headers
+            .iter_mut()
+            .filter(|(_, v)| v.as_str() == Some(REDACTED))
+        {
+            if let Some(old) = existing_headers.and_then(|h| h.get(name)) {
+                *value = old.clone();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyEndpoint {
+    pub notify_endpoint_id: NotifyEndpointId,
+    pub service: NotifyService,
+    pub destination: String,
+    /// The endpoint's config, with the webhook secret and header values replaced by [REDACTED].
+    pub config: Option<serde_json::Value>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyEndpointInput {
+    pub service: NotifyService,
+    pub destination: String,
+    /// Extra settings for the service, such as the secret and headers for a webhook.
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl NotifyEndpointInput {
+    fn validate(&self) -> Result<()> {
+        validate_endpoint(self.service, &self.destination, self.config.as_ref())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyListener {
+    pub notify_listener_id: NotifyListenerId,
+    pub notify_endpoint_id: NotifyEndpointId,
+    /// The task to listen on, or `None` to listen on all of the org's tasks.
+    pub task_id: Option<TaskId>,
+    pub event: NotifyEvent,
+    pub enabled: bool,
+    pub template: Option<NotifyTemplate>,
+    pub policy: NotifyPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyListenerInput {
+    pub notify_endpoint_id: NotifyEndpointId,
+    #[serde(default)]
+    pub task_id: Option<TaskId>,
+    pub event: NotifyEvent,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub template: Option<NotifyTemplate>,
+    /// Limits on how often the listener sends notifications.
+    #[serde(default)]
+    pub policy: NotifyPolicy,
+}
+
+#[get("/notifications/endpoints")]
+async fn list_endpoints(data: AppStateData, auth: Authenticated) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let mut endpoints = sqlx::query_as!(
+        NotifyEndpoint,
+        r##"SELECT notify_endpoint_id AS "notify_endpoint_id: NotifyEndpointId",
+            service AS "service: NotifyService",
+            destination, config, enabled
+        FROM notify_endpoints
+        WHERE org_id=$1
+        ORDER BY notify_endpoint_id"##,
+        auth.org_id().0
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    for endpoint in &mut endpoints {
+        endpoint.config = redact_config(endpoint.config.take());
+    }
+
+    Ok(HttpResponse::Ok().json(endpoints))
+}
+
+#[post("/notifications/endpoints")]
+async fn new_endpoint(
+    data: AppStateData,
+    auth: Authenticated,
+    payload: web::Json<NotifyEndpointInput>,
+) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let payload = payload.into_inner();
+    payload.validate()?;
+
+    let endpoint_id = NotifyEndpointId::new();
+    sqlx::query!(
+        "INSERT INTO notify_endpoints (notify_endpoint_id, org_id, service, destination, config, enabled)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+        endpoint_id.0,
+        auth.org_id().0,
+        payload.service as _,
+        &payload.destination,
+        payload.config.as_ref(),
+        payload.enabled
+    )
+    .execute(&data.pg)
+    .await?;
+
+    Ok(HttpResponse::Created().json(NotifyEndpoint {
+        notify_endpoint_id: endpoint_id,
+        service: payload.service,
+        destination: payload.destination,
+        config: redact_config(payload.config),
+        enabled: payload.enabled,
+    }))
+}
+
+#[put("/notifications/endpoints/{endpoint_id}")]
+async fn update_endpoint(
+    endpoint_id: Path<NotifyEndpointId>,
+    data: AppStateData,
+    auth: Authenticated,
+    payload: web::Json<NotifyEndpointInput>,
+) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let endpoint_id = endpoint_id.into_inner();
+    let mut payload = payload.into_inner();
+
+    let mut tx = data.pg.begin().await?;
+    let existing = sqlx::query_scalar!(
+        "SELECT config FROM notify_endpoints
+        WHERE notify_endpoint_id=$1 AND org_id=$2
+        FOR UPDATE",
+        endpoint_id.0,
+        auth.org_id().0
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    restore_redacted(payload.config.as_mut(), existing.as_ref());
+    payload.validate()?;
+
+    sqlx::query!(
+        "UPDATE notify_endpoints SET service=$3, destination=$4, config=$5, enabled=$6
+        WHERE notify_endpoint_id=$1 AND org_id=$2",
+        endpoint_id.0,
+        auth.org_id().0,
+        payload.service as _,
+        &payload.destination,
+        payload.config.as_ref(),
+        payload.enabled
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(NotifyEndpoint {
+        notify_endpoint_id: endpoint_id,
+        service: payload.service,
+        destination: payload.destination,
+        config: redact_config(payload.config),
+        enabled: payload.enabled,
+    }))
+}
+
+/// Delete an endpoint, along with all of its listeners.
+#[delete("/notifications/endpoints/{endpoint_id}")]
+async fn delete_endpoint(
+    endpoint_id: Path<NotifyEndpointId>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let endpoint_id = endpoint_id.into_inner();
+
+    let result = sqlx::query!(
+        "WITH deleted_listeners AS (
+            DELETE FROM notify_listeners WHERE notify_endpoint_id=$1 AND org_id=$2
+        )
+        DELETE FROM notify_endpoints WHERE notify_endpoint_id=$1 AND org_id=$2",
+        endpoint_id.0,
+        auth.org_id().0
+    )
+    .execute(&data.pg)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NotifyTestRequest {
+    /// The type of event to send. Defaults to `action_success`.
+    #[serde(default)]
+    pub event: Option<NotifyEvent>,
+    /// A template to try out, formatted the same way as a listener's template.
+    #[serde(default)]
+    pub template: Option<NotifyTemplate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyTestResult {
+    pub sent: bool,
+    /// Why the notification could not be sent.
+    pub error: Option<String>,
+}
+
+/// Send a sample notification to an endpoint right away, and report whether it worked.
+#[post("/notifications/endpoints/{endpoint_id}/test")]
+async fn test_endpoint(
+    endpoint_id: Path<NotifyEndpointId>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+    payload: Option<web::Json<NotifyTestRequest>>,
+) -> Result<impl Responder> {
+    auth.expect_admin()?;
+
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let event = payload.event.unwrap_or(NotifyEvent::ActionSuccess);
+    if let Some(template) = payload.template.as_ref() {
+        template.validate(event)?;
+    }
+
+    let endpoint = sqlx::query!(
+        r##"SELECT service AS "service: NotifyService", destination, config
+        FROM notify_endpoints
+        WHERE notify_endpoint_id=$1 AND org_id=$2"##,
+        endpoint_id.into_inner().0,
+        auth.org_id().0
+    )
+    .fetch_optional(&data.pg)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let target = NotifyDestination {
+        service: endpoint.service,
+        destination: endpoint.destination,
+        config: endpoint.config,
+        template: payload.template,
+    };
+
+    let result = data
+        .notifications
+        .send_now(&target, &Notification::sample(event))
+        .await;
+
+    Ok(HttpResponse::Ok().json(NotifyTestResult {
+        sent: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }))
+}
+
+#[get("/notifications/listeners")]
+async fn list_listeners(data: AppStateData, auth: Authenticated) -> Result<impl Responder> {
+    let listeners = sqlx::query!(
+        r##"SELECT notify_listener_id AS "notify_listener_id: NotifyListenerId",
+            notify_endpoint_id AS "notify_endpoint_id: NotifyEndpointId",
+            object_id, event AS "event: NotifyEvent", enabled,
+            template AS "template: Json<NotifyTemplate>",
+            policy AS "policy: Json<NotifyPolicy>"
+        FROM notify_listeners
+        WHERE org_id=$1
+        ORDER BY notify_listener_id"##,
+        auth.org_id().0
+    )
+    .fetch_all(&data.pg)
+    .await?
+    .into_iter()
+    .map(|row| NotifyListener {
+        notify_listener_id: row.notify_listener_id,
+        notify_endpoint_id: row.notify_endpoint_id,
+        task_id: (!row.object_id.is_nil()).then(|| TaskId::from_uuid(row.object_id)),
+        event: row.event,
+        enabled: row.enabled,
+        template: row.template.map(|t| t.0),
+        policy: row.policy.map(|p| p.0).unwrap_or_default(),
+    })
+    .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(listeners))
+}
+
+/// Check that the user can write to the task that a listener watches, and that the endpoint is
+/// in the same org. Only admins can add listeners for all of the org's tasks.
+async fn validate_listener(
+    data: &AppStateData,
+    auth: &Authenticated,
+    payload: &NotifyListenerInput,
+) -> Result<()> {
+    if let Some(template) = payload.template.as_ref() {
+        template.validate(payload.event)?;
+    }
+    payload.policy.validate()?;
+
+    let task_id = match payload.task_id.as_ref() {
+        Some(task_id) => task_id.0,
+        None => {
+            auth.expect_admin()?;
+            Uuid::nil()
+        }
+    };
+
+    let user_ids = auth.user_entity_ids();
+    let valid = sqlx::query_scalar!(
+        r##"SELECT EXISTS(
+            SELECT 1 FROM notify_endpoints WHERE notify_endpoint_id=$1 AND org_id=$2
+        ) AND ($3 = uuid_nil() OR EXISTS(
+            SELECT 1 FROM tasks
+            WHERE task_id=$3 AND org_id=$2 AND NOT deleted
+            AND EXISTS(SELECT 1 FROM user_entity_permissions
+                WHERE permissioned_object IN (uuid_nil(), task_id)
+                AND user_entity_id=ANY($4)
+                AND permission_type = 'write'
+            )
+        )) AS "valid!""##,
+        payload.notify_endpoint_id.0,
+        auth.org_id().0,
+        task_id,
+        user_ids.as_slice()
+    )
+    .fetch_one(&data.pg)
+    .await?;
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+#[post("/notifications/listeners")]
+async fn new_listener(
+    data: AppStateData,
+    auth: Authenticated,
+    payload: web::Json<NotifyListenerInput>,
+) -> Result<impl Responder> {
+    let payload = payload.into_inner();
+    validate_listener(&data, &auth, &payload).await?;
+
+    let listener_id = NotifyListenerId::new();
+    sqlx::query!(
+        "INSERT INTO notify_listeners
+            (notify_listener_id, notify_endpoint_id, object_id, event, org_id, enabled, template,
+                policy)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        listener_id.0,
+        payload.notify_endpoint_id.0,
+        payload
+            .task_id
+            .as_ref()
+            .map(|id| id.0)
+            .unwrap_or_else(Uuid::nil),
+        payload.event as _,
+        auth.org_id().0,
+        payload.enabled,
+        payload.template.as_ref().map(Json) as _,
+        Json(&payload.policy) as _
+    )
+    .execute(&data.pg)
+    .await?;
+
+    Ok(HttpResponse::Created().json(NotifyListener {
+        notify_listener_id: listener_id,
+        notify_endpoint_id: payload.notify_endpoint_id,
+        task_id: payload.task_id,
+        event: payload.event,
+        enabled: payload.enabled,
+        template: payload.template,
+        policy: payload.policy,
+    }))
+}
+
+#[put("/notifications/listeners/{listener_id}")]
+async fn update_listener(
+    listener_id: Path<NotifyListenerId>,
+    data: AppStateData,
+    auth: Authenticated,
+    payload: web::Json<NotifyListenerInput>,
+) -> Result<impl Responder> {
+    let listener_id = listener_id.into_inner();
+    let payload = payload.into_inner();
+    validate_listener(&data, &auth, &payload).await?;
+
+    let user_ids = auth.user_entity_ids();
+    let result = sqlx::query!(
+        "UPDATE notify_listeners
+        SET notify_endpoint_id=$3, object_id=$4, event=$5, enabled=$6, template=$7, policy=$8
+        WHERE notify_listener_id=$1 AND org_id=$2
+        AND CASE WHEN object_id = uuid_nil() THEN $9
+            ELSE EXISTS(SELECT 1 FROM user_entity_permissions
+                WHERE permissioned_object IN (uuid_nil(), object_id)
+                AND user_entity_id=ANY($10)
+                AND permission_type = 'write')
+            END",
+        listener_id.0,
+        auth.org_id().0,
+        payload.notify_endpoint_id.0,
+        payload
+            .task_id
+            .as_ref()
+            .map(|id| id.0)
+            .unwrap_or_else(Uuid::nil),
+        payload.event as _,
+        payload.enabled,
+        payload.template.as_ref().map(Json) as _,
+        Json(&payload.policy) as _,
+        auth.expect_admin().is_ok(),
+        user_ids.as_slice()
+    )
+    .execute(&data.pg)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(NotifyListener {
+        notify_listener_id: listener_id,
+        notify_endpoint_id: payload.notify_endpoint_id,
+        task_id: payload.task_id,
+        event: payload.event,
+        enabled: payload.enabled,
+        template: payload.template,
+        policy: payload.policy,
+    }))
+}
+
+/// Delete a listener. Like with adding a listener, this needs write access to the listener's
+/// task, or admin access for a listener on all of the org's tasks.
+#[delete("/notifications/listeners/{listener_id}")]
+async fn delete_listener(
+    listener_id: Path<NotifyListenerId>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let user_ids = auth.user_entity_ids();
+    let result = sqlx::query!(
+        "DELETE FROM notify_listeners WHERE notify_listener_id=$1 AND org_id=$2
+        AND CASE WHEN object_id = uuid_nil() THEN $3
+            ELSE EXISTS(SELECT 1 FROM user_entity_permissions
+                WHERE permissioned_object IN (uuid_nil(), object_id)
+                AND user_entity_id=ANY($4)
+                AND permission_type = 'write')
+            END",
+        listener_id.into_inner().0,
+        auth.org_id().0,
+        auth.expect_admin().is_ok(),
+        user_ids.as_slice()
+    )
+    .execute(&data.pg)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_endpoints)
+        .service(new_endpoint)
+        .service(update_endpoint)
+        .service(delete_endpoint)
+        .service(test_endpoint)
+        .service(list_listeners)
+        .service(new_listener)
+        .service(update_listener)
+        .service(delete_listener);
+}
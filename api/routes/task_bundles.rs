@@ -0,0 +1,619 @@
+//! Export a task along with the actions and inputs it uses, so that it can be imported into
+//! another org or server.
+
+use actix_web::{
+    get, post,
+    web::{self, Path},
+    HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+use ergo_auth::Authenticated;
+use ergo_database::object_id::{ActionCategoryId, ActionId, InputCategoryId, InputId, TaskId};
+use ergo_tasks::{
+    actions::{execute::ScriptOrTemplate, template::TemplateFields, Action, ActionCategory},
+    inputs::Input,
+    PeriodicTaskTriggerInput,
+};
+use fxhash::FxHashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, Postgres, Transaction};
+
+use super::{
+    actions::insert_action,
+    inputs::insert_input,
+    tasks::{
+        create_task, fetch_task, validate_task_config, TaskActionInput, TaskInput, TaskTriggerInput,
+    },
+};
+use crate::{
+    error::{Error, Result},
+    web_app_server::AppStateData,
+};
+
+pub const TASK_BUNDLE_VERSION: u32 = 1;
+
+/// A task and everything it needs to run, other than accounts.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskBundle {
+    pub version: u32,
+    pub exported: DateTime<Utc>,
+    /// The ID of the task on the server it was exported from.
+    pub source_task_id: TaskId,
+    /// The task definition. Account IDs are removed from the task actions, the state is reset,
+    /// and handoffs are left out since they refer to other tasks.
+    pub task: TaskInput,
+    pub actions: Vec<Action>,
+    pub inputs: Vec<Input>,
+    pub action_categories: Vec<ActionCategory>,
+}
+
+#[get("/tasks/{task_id}/export")]
+async fn export_task(
+    task_id: Path<TaskId>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let task = fetch_task(&data.pg, &auth, &task_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let actions = task
+        .actions
+        .0
+        .into_iter()
+        .map(|(local_id, action)| {
+            let action = TaskActionInput {
+                name: action.name,
+                action_id: action.action_id,
+                account_id: None,
+                action_template: action.action_template,
+                run_limit: action.run_limit,
+            };
+            (local_id, action)
+        })
+        .collect::<FxHashMap<_, _>>();
+
+    let triggers = task
+        .triggers
+        .0
+        .into_iter()
+        .map(|(local_id, trigger)| {
+            let periodic = trigger.periodic.map(|periodic| {
+                periodic
+                    .into_iter()
+                    .map(|p| PeriodicTaskTriggerInput {
+                        name: p.name,
+                        schedule: p.schedule,
+                        payload: p.payload,
+                        enabled: p.enabled,
+                    })
+                    .collect()
+            });
+
+            let trigger = TaskTriggerInput {
+                input_id: trigger.input_id,
+                name: trigger.name,
+                description: trigger.description,
+                periodic,
+                priority: trigger.priority,
+            };
+            (local_id, trigger)
+        })
+        .collect::<FxHashMap<_, _>>();
+
+    let mut action_ids = actions.values().map(|a| a.action_id.0).collect::<Vec<_>>();
+    action_ids.sort();
+    action_ids.dedup();
+    let mut input_ids = triggers.values().map(|t| t.input_id.0).collect::<Vec<_>>();
+    input_ids.sort();
+    input_ids.dedup();
+
+    let actions_defs = sqlx::query_as!(
+        Action,
+        r##"SELECT
+        action_id as "action_id: ActionId",
+        action_category_id as "action_category_id: ActionCategoryId",
+        name,
+        description,
+        executor_id,
+        executor_template as "executor_template: ScriptOrTemplate",
+        template_fields as "template_fields: TemplateFields",
+        timeout,
+        postprocess_script,
+        account_required,
+        COALESCE(array_agg(account_type_id) FILTER(WHERE account_type_id IS NOT NULL), ARRAY[]::text[]) "account_types!"
+        FROM actions
+        LEFT JOIN allowed_action_account_types USING(action_id)
+        WHERE action_id = ANY($1)
+        GROUP BY action_id
+        ORDER BY action_id"##,
+        &action_ids
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    let inputs = sqlx::query_as!(
+        Input,
+        r##"SELECT
+            input_id as "input_id: InputId",
+            input_category_id as "input_category_id: InputCategoryId",
+            name, description, payload_schema
+        FROM inputs
+        WHERE input_id = ANY($1)
+        ORDER BY input_id"##,
+        &input_ids
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    let category_ids = actions_defs
+        .iter()
+        .map(|a| a.action_category_id.0)
+        .collect::<Vec<_>>();
+    let action_categories = sqlx::query_as!(
+        ActionCategory,
+        r##"SELECT
+        action_category_id as "action_category_id: ActionCategoryId",
+        name, description
+        FROM action_categories
+        WHERE action_category_id = ANY($1)
+        ORDER BY action_category_id"##,
+        &category_ids
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    let bundle = TaskBundle {
+        version: TASK_BUNDLE_VERSION,
+        exported: Utc::now(),
+        source_task_id: task_id,
+        task: TaskInput {
+            name: task.name,
+            description: task.description,
+            alias: task.alias,
+            enabled: task.enabled,
+            compiled: task.compiled.0,
+            source: task.source.0,
+            state: None,
+            actions,
+            triggers,
+            handoffs: Vec::new(),
+            tests: task.tests.0,
+            run_limit: task.run_limit.map(|l| l.0),
+        },
+        actions: actions_defs,
+        inputs,
+        action_categories,
+    };
+
+    Ok(HttpResponse::Ok().json(bundle))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskImportQuery {
+    /// Check the bundle and report what would happen, without importing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Import the task even if there are conflicts, where possible.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportResolution {
+    /// An object with the same ID already exists.
+    Existing,
+    /// An object with the same name exists, and is used in place of the bundled one.
+    MatchedByName,
+    /// The object was added from the bundle.
+    Created,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct ImportedObject<ID> {
+    /// The object's ID in the bundle.
+    pub bundle_id: ID,
+    /// The object's ID on this server.
+    pub id: ID,
+    pub name: String,
+    pub resolution: ImportResolution,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImportConflict {
+    /// A matching action exists, but its definition differs from the bundle. With `force`, the
+    /// existing action is used.
+    ActionDiffers {
+        bundle_id: ActionId,
+        action_id: ActionId,
+        name: String,
+    },
+    /// A matching input exists, but its definition differs from the bundle. With `force`, the
+    /// existing input is used.
+    InputDiffers {
+        bundle_id: InputId,
+        input_id: InputId,
+        name: String,
+    },
+    /// The action doesn't exist, and only admins can create it.
+    MissingAction { action_id: ActionId, name: String },
+    /// The input doesn't exist, and only admins can create it.
+    MissingInput { input_id: InputId, name: String },
+    /// The action can not be created because its category doesn't exist here.
+    MissingActionCategory {
+        action_id: ActionId,
+        action_category_id: ActionCategoryId,
+    },
+    /// Another task in the org has the same alias. With `force`, the task is imported without
+    /// an alias.
+    AliasInUse { alias: String },
+    /// The task action needs an account, which must be set after importing. With `force`, the
+    /// task is imported without it.
+    AccountRequired {
+        task_action_local_id: String,
+        action_id: ActionId,
+    },
+}
+
+impl ImportConflict {
+    fn can_force(&self) -> bool {
+        !matches!(
+            self,
+            Self::MissingAction { .. }
+                | Self::MissingInput { .. }
+                | Self::MissingActionCategory { .. }
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TaskImportResult {
+    /// The ID of the new task, or `None` if the task was not imported.
+    pub task_id: Option<TaskId>,
+    pub actions: Vec<ImportedObject<ActionId>>,
+    pub inputs: Vec<ImportedObject<InputId>>,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Compare the parts of an action that affect how it runs.
+fn same_action(a: &Action, b: &Action) -> bool {
+    let mut a_account_types = a.account_types.clone();
+    let mut b_account_types = b.account_types.clone();
+    a_account_types.sort();
+    b_account_types.sort();
+
+    a.executor_id == b.executor_id
+        && a.executor_template == b.executor_template
+        && a.template_fields == b.template_fields
+        && a.postprocess_script == b.postprocess_script
+        && a.timeout == b.timeout
+        && a.account_required == b.account_required
+        && a_account_types == b_account_types
+}
+
+async fn find_action(
+    tx: &mut Transaction<'_, Postgres>,
+    action: &Action,
+) -> Result<Option<(Action, ImportResolution)>> {
+    let existing = sqlx::query_as!(
+        Action,
+        r##"SELECT
+        action_id as "action_id: ActionId",
+        action_category_id as "action_category_id: ActionCategoryId",
+        name,
+        description,
+        executor_id,
+        executor_template as "executor_template: ScriptOrTemplate",
+        template_fields as "template_fields: TemplateFields",
+        timeout,
+        postprocess_script,
+        account_required,
+        COALESCE(array_agg(account_type_id) FILTER(WHERE account_type_id IS NOT NULL), ARRAY[]::text[]) "account_types!"
+        FROM actions
+        LEFT JOIN allowed_action_account_types USING(action_id)
+        WHERE action_id = $1 OR name = $2
+        GROUP BY action_id
+        -- Prefer a match on the ID.
+        ORDER BY action_id <> $1, action_id"##,
+        &action.action_id.0,
+        &action.name
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let found = existing.into_iter().next().map(|existing| {
+        let resolution = if existing.action_id == action.action_id {
+            ImportResolution::Existing
+        } else {
+            ImportResolution::MatchedByName
+        };
+        (existing, resolution)
+    });
+
+    Ok(found)
+}
+
+async fn find_input(
+    tx: &mut Transaction<'_, Postgres>,
+    input: &Input,
+) -> Result<Option<(Input, ImportResolution)>> {
+    let existing = sqlx::query_as!(
+        Input,
+        r##"SELECT
+            input_id as "input_id: InputId",
+            input_category_id as "input_category_id: InputCategoryId",
+            name, description, payload_schema
+        FROM inputs
+        WHERE input_id = $1 OR name = $2
+        ORDER BY input_id <> $1, input_id"##,
+        &input.input_id.0,
+        &input.name
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let found = existing.into_iter().next().map(|existing| {
+        let resolution = if existing.input_id == input.input_id {
+            ImportResolution::Existing
+        } else {
+            ImportResolution::MatchedByName
+        };
+        (existing, resolution)
+    });
+
+    Ok(found)
+}
+
+/// Find the category to use when creating an action, first by ID and then by name.
+async fn find_action_category(
+    tx: &mut Transaction<'_, Postgres>,
+    bundle: &TaskBundle,
+    action_category_id: &ActionCategoryId,
+) -> Result<Option<ActionCategoryId>> {
+    let name = bundle
+        .action_categories
+        .iter()
+        .find(|c| &c.action_category_id == action_category_id)
+        .map(|c| c.name.as_str());
+
+    let found = sqlx::query_scalar!(
+        r##"SELECT action_category_id as "action_category_id: ActionCategoryId"
+        FROM action_categories
+        WHERE action_category_id = $1 OR name = $2
+        ORDER BY action_category_id <> $1
+        LIMIT 1"##,
+        &action_category_id.0,
+        name
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(found)
+}
+
+#[post("/tasks/import")]
+async fn import_task(
+    data: AppStateData,
+    auth: Authenticated,
+    query: web::Query<TaskImportQuery>,
+    bundle: web::Json<TaskBundle>,
+) -> Result<impl Responder> {
+    let bundle = bundle.into_inner();
+    if bundle.version != TASK_BUNDLE_VERSION {
+        return Err(Error::InvalidQuery(format!(
+            "Unsupported task bundle version {}",
+            bundle.version
+        )));
+    }
+
+    let can_create = auth.expect_admin().is_ok();
+    let mut result = TaskImportResult::default();
+
+    let mut conn = data.pg.acquire().await?;
+    let mut tx = conn.begin().await?;
+
+    let mut action_map = FxHashMap::default();
+    for action in &bundle.actions {
+        let id = match find_action(&mut tx, action).await? {
+            Some((existing, resolution)) => {
+                if !same_action(&existing, action) {
+                    result.conflicts.push(ImportConflict::ActionDiffers {
+                        bundle_id: action.action_id.clone(),
+                        action_id: existing.action_id.clone(),
+                        name: existing.name.clone(),
+                    });
+                }
+
+                action_map.insert(action.action_id.clone(), existing.clone());
+                Some((existing.action_id, resolution))
+            }
+            None if !can_create => {
+                result.conflicts.push(ImportConflict::MissingAction {
+                    action_id: action.action_id.clone(),
+                    name: action.name.clone(),
+                });
+                None
+            }
+            None => {
+                let category =
+                    find_action_category(&mut tx, &bundle, &action.action_category_id).await?;
+                match category {
+                    Some(action_category_id) => {
+                        let new_action = Action {
+                            action_category_id,
+                            ..action.clone()
+                        };
+                        insert_action(&mut tx, &new_action).await?;
+                        action_map.insert(action.action_id.clone(), new_action);
+                        Some((action.action_id.clone(), ImportResolution::Created))
+                    }
+                    None => {
+                        result
+                            .conflicts
+                            .push(ImportConflict::MissingActionCategory {
+                                action_id: action.action_id.clone(),
+                                action_category_id: action.action_category_id.clone(),
+                            });
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some((id, resolution)) = id {
+            result.actions.push(ImportedObject {
+                bundle_id: action.action_id.clone(),
+                id,
+                name: action.name.clone(),
+                resolution,
+            });
+        }
+    }
+
+    let mut input_map = FxHashMap::default();
+    for input in &bundle.inputs {
+        let id = match find_input(&mut tx, input).await? {
+            Some((existing, resolution)) => {
+                if existing.payload_schema != input.payload_schema {
+                    result.conflicts.push(ImportConflict::InputDiffers {
+                        bundle_id: input.input_id.clone(),
+                        input_id: existing.input_id.clone(),
+                        name: existing.name.clone(),
+                    });
+                }
+
+                Some((existing.input_id, resolution))
+            }
+            None if !can_create => {
+                result.conflicts.push(ImportConflict::MissingInput {
+                    input_id: input.input_id.clone(),
+                    name: input.name.clone(),
+                });
+                None
+            }
+            None => {
+                // Input categories are optional, so leave it out if this server doesn't have it.
+                let input_category_id = match input.input_category_id.as_ref() {
+                    Some(id) => {
+                        sqlx::query_scalar!(
+                            r##"SELECT input_category_id AS "input_category_id: InputCategoryId"
+                            FROM input_categories WHERE input_category_id = $1"##,
+                            id.0
+                        )
+                        .fetch_optional(&mut tx)
+                        .await?
+                    }
+                    None => None,
+                };
+
+                let new_input = Input {
+                    input_category_id,
+                    ..input.clone()
+                };
+                insert_input(&mut tx, &new_input).await?;
+                Some((input.input_id.clone(), ImportResolution::Created))
+            }
+        };
+
+        if let Some((id, resolution)) = id {
+            input_map.insert(input.input_id.clone(), id.clone());
+            result.inputs.push(ImportedObject {
+                bundle_id: input.input_id.clone(),
+                id,
+                name: input.name.clone(),
+                resolution,
+            });
+        }
+    }
+
+    let mut task = bundle.task;
+    for (local_id, task_action) in task.actions.iter_mut() {
+        // Accounts belong to an org, so a bundle never carries them over.
+        task_action.account_id = None;
+        match action_map.get(&task_action.action_id) {
+            Some(action) => {
+                task_action.action_id = action.action_id.clone();
+                if action.account_required {
+                    result.conflicts.push(ImportConflict::AccountRequired {
+                        task_action_local_id: local_id.clone(),
+                        action_id: action.action_id.clone(),
+                    });
+                }
+            }
+            None => {
+                // Actions from the bundle that couldn't be imported already have a conflict.
+                if !bundle
+                    .actions
+                    .iter()
+                    .any(|a| a.action_id == task_action.action_id)
+                {
+                    result.conflicts.push(ImportConflict::MissingAction {
+                        action_id: task_action.action_id.clone(),
+                        name: task_action.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    for trigger in task.triggers.values_mut() {
+        match input_map.get(&trigger.input_id) {
+            Some(input_id) => trigger.input_id = input_id.clone(),
+            None => {
+                if !bundle.inputs.iter().any(|i| i.input_id == trigger.input_id) {
+                    result.conflicts.push(ImportConflict::MissingInput {
+                        input_id: trigger.input_id.clone(),
+                        name: trigger.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(alias) = task.alias.as_ref() {
+        let alias_in_use = sqlx::query_scalar!(
+            r##"SELECT EXISTS(
+                SELECT 1 FROM tasks WHERE org_id = $1 AND alias = $2 AND NOT deleted
+            ) "exists!""##,
+            auth.org_id().0,
+            alias
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        if alias_in_use {
+            result.conflicts.push(ImportConflict::AliasInUse {
+                alias: alias.clone(),
+            });
+            task.alias = None;
+        }
+    }
+
+    let blocked = result
+        .conflicts
+        .iter()
+        .any(|c| !query.force || !c.can_force());
+    if blocked {
+        return Ok(HttpResponse::Conflict().json(result));
+    }
+
+    validate_task_config(&task)?;
+
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(result));
+    }
+
+    let task_id = create_task(&mut tx, &data.redis_key_prefix, &auth, task).await?;
+    tx.commit().await?;
+
+    result.task_id = Some(task_id);
+    Ok(HttpResponse::Created().json(result))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(export_task).service(import_task);
+}
@@ -0,0 +1,270 @@
+//! The version history of a task's config. Each update that changes the config saves a new
+//! version, and a task can be rolled back to any earlier version.
+
+use actix_web::{
+    get, post,
+    web::{self, Path, Query},
+    HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+use ergo_auth::Authenticated;
+use ergo_database::object_id::{TaskId, UserId};
+use ergo_tasks::{
+    diff::{diff_json, ConfigChange},
+    state_machine::cancel_timers,
+    StateMigration, TaskConfig, TaskState,
+};
+use fxhash::FxHashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Connection};
+
+use crate::{
+    error::{Error, Result},
+    web_app_server::AppStateData,
+};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskVersion {
+    pub version: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub created: Option<DateTime<Utc>>,
+    /// The user who saved this version. This is empty for versions saved before authors were
+    /// tracked, or if the user has since been deleted.
+    pub created_by: Option<UserId>,
+    pub created_by_name: Option<String>,
+    /// True if the task currently uses this version.
+    pub current: bool,
+}
+
+#[get("/tasks/{task_id}/versions")]
+async fn list_task_versions(
+    task_id: Path<TaskId>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let user_ids = auth.user_entity_ids();
+    let task_id = task_id.into_inner();
+
+    let versions = sqlx::query_as!(
+        TaskVersion,
+        r##"SELECT tt.task_template_version AS version,
+            tt.name, tt.description, tt.created,
+            tt.created_by AS "created_by: UserId",
+            users.name AS "created_by_name?",
+            tt.task_template_version = tasks.task_template_version AS "current!"
+        FROM tasks
+        JOIN task_templates tt USING (task_template_id)
+        LEFT JOIN users ON users.user_id = tt.created_by
+        WHERE task_id=$1 AND tasks.org_id=$2 AND NOT tasks.deleted
+        AND EXISTS(SELECT 1 FROM user_entity_permissions
+            WHERE permissioned_object IN (uuid_nil(), task_id)
+            AND user_entity_id=ANY($3)
+            AND permission_type = 'read'
+        )
+        ORDER BY tt.task_template_version DESC"##,
+        task_id.0,
+        auth.org_id().0,
+        user_ids.as_slice()
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    if versions.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskVersionDiffQuery {
+    pub from: i64,
+    /// The version to compare against. Defaults to the task's current version.
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskVersionDiff {
+    pub from: i64,
+    pub to: i64,
+    /// Changes to the compiled task config.
+    pub compiled: Vec<ConfigChange>,
+    /// Changes to the source that the config was compiled from.
+    pub source: Vec<ConfigChange>,
+}
+
+#[get("/tasks/{task_id}/versions/diff")]
+async fn diff_task_versions(
+    task_id: Path<TaskId>,
+    query: Query<TaskVersionDiffQuery>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let user_ids = auth.user_entity_ids();
+    let task_id = task_id.into_inner();
+    let query = query.into_inner();
+
+    let versions = sqlx::query!(
+        r##"SELECT tt.task_template_version AS version,
+            tasks.task_template_version AS current_version,
+            tt.source,
+            tt.compiled AS "compiled: Json<TaskConfig>"
+        FROM tasks
+        JOIN task_templates tt USING (task_template_id)
+        WHERE task_id=$1 AND tasks.org_id=$2 AND NOT tasks.deleted
+        AND tt.task_template_version IN ($4, COALESCE($5, tasks.task_template_version))
+        AND EXISTS(SELECT 1 FROM user_entity_permissions
+            WHERE permissioned_object IN (uuid_nil(), task_id)
+            AND user_entity_id=ANY($3)
+            AND permission_type = 'read'
+        )"##,
+        task_id.0,
+        auth.org_id().0,
+        user_ids.as_slice(),
+        query.from,
+        query.to
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    let to = match (query.to, versions.first()) {
+        (Some(to), _) => to,
+        (None, Some(v)) => v.current_version,
+        (None, None) => return Err(Error::NotFound),
+    };
+
+    let from_version = versions.iter().find(|v| v.version == query.from);
+    let to_version = versions.iter().find(|v| v.version == to);
+    let (from_version, to_version) = match (from_version, to_version) {
+        (Some(f), Some(t)) => (f, t),
+        _ => return Err(Error::NotFound),
+    };
+
+    let diff = TaskVersionDiff {
+        from: query.from,
+        to,
+        compiled: from_version.compiled.diff(&to_version.compiled),
+        source: diff_json(&from_version.source, &to_version.source),
+    };
+
+    Ok(HttpResponse::Ok().json(diff))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskRollbackResult {
+    pub task_template_version: i64,
+    /// How much of the task's state could be carried over to the older config.
+    pub state: StateMigration,
+}
+
+#[post("/tasks/{task_id}/versions/{version}/rollback")]
+async fn rollback_task(
+    path: Path<(TaskId, i64)>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let user_ids = auth.user_entity_ids();
+    let (task_id, version) = path.into_inner();
+
+    let mut conn = data.pg.acquire().await?;
+    let mut tx = conn.begin().await?;
+
+    let current = sqlx::query!(
+        r##"SELECT task_template_id, task_template_version,
+            state AS "state: Json<TaskState>",
+            compiled AS "compiled: Json<TaskConfig>"
+        FROM tasks
+        JOIN task_templates USING (task_template_id, task_template_version)
+        WHERE task_id=$1 AND tasks.org_id=$2 AND NOT tasks.deleted
+        AND EXISTS(SELECT 1 FROM user_entity_permissions
+            WHERE permissioned_object IN (uuid_nil(), task_id)
+            AND user_entity_id=ANY($3)
+            AND permission_type = 'write'
+        )
+        FOR UPDATE OF tasks"##,
+        task_id.0,
+        auth.org_id().0,
+        user_ids.as_slice()
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if current.task_template_version == version {
+        return Ok(HttpResponse::Ok().json(TaskRollbackResult {
+            task_template_version: version,
+            state: StateMigration::Kept,
+        }));
+    }
+
+    let target = sqlx::query_scalar!(
+        r##"SELECT compiled AS "compiled: Json<TaskConfig>"
+        FROM task_templates
+        WHERE task_template_id=$1 AND task_template_version=$2"##,
+        current.task_template_id,
+        version
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    // The triggers and actions aren't versioned, so make sure that the old config still works
+    // with the current ones.
+    let task_triggers = sqlx::query_scalar!(
+        "SELECT task_trigger_local_id FROM task_triggers WHERE task_id=$1",
+        task_id.0
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|id| (id, ()))
+    .collect::<FxHashMap<_, _>>();
+
+    let task_actions = sqlx::query_scalar!(
+        "SELECT task_action_local_id FROM task_actions WHERE task_id=$1",
+        task_id.0
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|id| (id, ()))
+    .collect::<FxHashMap<_, _>>();
+
+    target
+        .validate(
+            &FxHashMap::default(),
+            &FxHashMap::default(),
+            &task_triggers,
+            &task_actions,
+        )
+        .map_err(ergo_tasks::Error::TaskValidateError)?;
+
+    let (state, migration) = target.migrate_state(&current.compiled, &current.state);
+    let dropped_timers = current.state.dropped_timers(&state);
+    cancel_timers(&mut tx, data.redis_key_prefix.as_deref(), &dropped_timers).await?;
+
+    sqlx::query!(
+        "UPDATE tasks SET task_template_version=$2, state=$3, modified=now()
+        WHERE task_id=$1",
+        task_id.0,
+        version,
+        Json(&state) as _
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(TaskRollbackResult {
+        task_template_version: version,
+        state: migration,
+    }))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_task_versions)
+        .service(diff_task_versions)
+        .service(rollback_task);
+}
@@ -5,25 +5,38 @@ use crate::{
 };
 
 use actix_web::{
-    delete, get, post, put,
-    web::{self, Path},
+    delete, get,
+    http::header,
+    post, put,
+    web::{self, Path, Query},
     HttpRequest, HttpResponse, Responder,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use ergo_auth::Authenticated;
-use ergo_database::object_id::{
-    AccountId, ActionId, InputId, OrgId, TaskId, TaskTemplateId, TaskTriggerId, UserId,
+use ergo_database::{
+    object_id::{
+        AccountId, ActionId, InputId, OrgId, TaskId, TaskTemplateId, TaskTriggerId, UserId,
+    },
+    PostgresPool,
 };
+use ergo_notifications::{Notification, NotifyEvent};
+use ergo_queues::JobPriority;
 use ergo_tasks::{
-    actions::{ActionStatus, TaskAction, TaskActionTemplate},
-    inputs::{EnqueueInputOptions, InputStatus},
-    PeriodicTaskTriggerInput, TaskConfig, TaskState, TaskTrigger,
+    actions::{ActionStatus, TaskAction, TaskActionInvocations, TaskActionTemplate},
+    dataflow::{DataFlowLog, DataFlowState},
+    handoff::TaskHandoff,
+    inputs::{EnqueueInputOptions, InputStatus, TriggerPriority},
+    limits::RunLimit,
+    simulate::{simulate_trigger, Simulation},
+    test_cases::{run_test_cases, TaskTestCase, TaskTestRun},
+    PeriodicTaskTriggerInput, TaskConfig, TaskState, TaskTrigger, TaskValidateError,
 };
 use fxhash::FxHashMap;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::{Connection, Postgres, Transaction};
 use std::str::FromStr;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{field, instrument};
 use uuid::Uuid;
 
@@ -104,17 +117,18 @@ pub struct TaskResult {
     pub modified: DateTime<Utc>,
     pub actions: sqlx::types::Json<FxHashMap<String, TaskAction>>,
     pub triggers: sqlx::types::Json<FxHashMap<String, TaskTrigger>>,
+    pub handoffs: sqlx::types::Json<Vec<TaskHandoff>>,
+    pub tests: sqlx::types::Json<Vec<TaskTestCase>>,
+    #[schemars(with = "Option<RunLimit>")]
+    pub run_limit: Option<sqlx::types::Json<RunLimit>>,
 }
 
-#[get("/tasks/{task_id}")]
-#[instrument(skip(data), fields(task))]
-async fn get_task(
-    task_id: Path<TaskId>,
-    data: AppStateData,
-    req: HttpRequest,
-    auth: Authenticated,
-) -> Result<impl Responder> {
-    let task_id = task_id.into_inner();
+/// Fetch a task, if the user is allowed to read it.
+pub(crate) async fn fetch_task(
+    pg: &PostgresPool,
+    auth: &Authenticated,
+    task_id: &TaskId,
+) -> Result<Option<TaskResult>> {
     let user_ids = auth.user_entity_ids();
 
     let task = sqlx::query_as!(
@@ -127,7 +141,10 @@ async fn get_task(
         state as "state!: _",
         tasks.created, tasks.modified,
         COALESCE(task_triggers, '{}'::jsonb) as "triggers!: _",
-        COALESCE(task_actions, '{}'::jsonb) as "actions!: _"
+        COALESCE(task_actions, '{}'::jsonb) as "actions!: _",
+        handoffs as "handoffs!: _",
+        tests as "tests!: _",
+        tasks.run_limit as "run_limit: _"
         FROM tasks
         JOIN task_templates USING (task_template_id, task_template_version)
 
@@ -138,7 +155,8 @@ async fn get_task(
                 'task_id', task_actions.task_id,
                 'account_id', account_id,
                 'name', task_actions.name,
-                'action_template', task_actions.action_template
+                'action_template', task_actions.action_template,
+                'run_limit', task_actions.run_limit
             )) AS task_actions
 
             FROM task_actions WHERE task_actions.task_id = tasks.task_id
@@ -152,7 +170,8 @@ async fn get_task(
                 'input_id', input_id,
                 'name', task_triggers.name,
                 'description', task_triggers.description,
-                'periodic', periodic
+                'periodic', periodic,
+                'priority', task_triggers.priority
             )) task_triggers
             FROM task_triggers
             LEFT JOIN LATERAL (
@@ -180,9 +199,23 @@ async fn get_task(
         user_ids.as_slice(),
         &auth.org_id().0
     )
-    .fetch_optional(&data.pg)
+    .fetch_optional(pg)
     .await?;
 
+    Ok(task)
+}
+
+#[get("/tasks/{task_id}")]
+#[instrument(skip(data), fields(task))]
+async fn get_task(
+    task_id: Path<TaskId>,
+    data: AppStateData,
+    req: HttpRequest,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let task = fetch_task(&data.pg, &auth, &task_id).await?;
+
     tracing::Span::current().record("task", &field::debug(&task));
 
     match task {
@@ -224,6 +257,9 @@ pub struct TaskActionInput {
     pub action_id: ActionId,
     pub account_id: Option<AccountId>,
     pub action_template: Option<TaskActionTemplate>,
+    /// Limits on how often the action runs.
+    #[serde(default)]
+    pub run_limit: Option<RunLimit>,
 }
 
 impl PartialEq<TaskAction> for TaskActionInput {
@@ -232,6 +268,7 @@ impl PartialEq<TaskAction> for TaskActionInput {
             && self.action_id == other.action_id
             && self.account_id == other.account_id
             && self.action_template == other.action_template
+            && self.run_limit == other.run_limit
     }
 }
 
@@ -241,6 +278,10 @@ pub struct TaskTriggerInput {
     pub name: String,
     pub description: Option<String>,
     pub periodic: Option<Vec<PeriodicTaskTriggerInput>>,
+    /// The queue priority of inputs sent to this trigger. When omitted, inputs sent through
+    /// the API have normal priority and inputs from periodic triggers have low priority.
+    #[serde(default)]
+    pub priority: Option<TriggerPriority>,
 }
 
 impl PartialEq<TaskTrigger> for TaskTriggerInput {
@@ -248,6 +289,7 @@ impl PartialEq<TaskTrigger> for TaskTriggerInput {
         self.input_id == other.input_id
             && self.name == other.name
             && self.description == other.description
+            && self.priority == other.priority
     }
 }
 
@@ -262,44 +304,181 @@ pub struct TaskInput {
     pub state: Option<TaskState>,
     pub actions: FxHashMap<String, TaskActionInput>,
     pub triggers: FxHashMap<String, TaskTriggerInput>,
+    /// Triggers on other tasks to send data to after this task processes an input.
+    #[serde(default)]
+    pub handoffs: Vec<TaskHandoff>,
+    /// Test cases to run against the task's config.
+    #[serde(default)]
+    pub tests: Vec<TaskTestCase>,
+    /// Limits on how often the task processes inputs.
+    #[serde(default)]
+    pub run_limit: Option<RunLimit>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TaskSaveQuery {
+    /// Run the task's tests first, and don't save the task if any of them fail.
+    #[serde(default)]
+    pub run_tests: bool,
+}
+
+/// Run the tests in the payload, if the query asks for it. Returns the results if any failed.
+async fn failed_task_tests(
+    query: &TaskSaveQuery,
+    task_id: &TaskId,
+    auth: &Authenticated,
+    payload: &TaskInput,
+) -> Option<TaskTestRun> {
+    if !query.run_tests || payload.tests.is_empty() {
+        return None;
+    }
+
+    let run = run_test_cases(
+        task_id,
+        &payload.name,
+        auth.user_id(),
+        &payload.compiled,
+        &payload.tests,
+    )
+    .await;
+    (!run.passed).then_some(run)
+}
+
+pub(crate) fn validate_task_config(payload: &TaskInput) -> Result<()> {
+    payload
+        .compiled
+        .validate(
+            &FxHashMap::default(),
+            &FxHashMap::default(),
+            &payload.triggers,
+            &payload.actions,
+        )
+        .map_err(ergo_tasks::Error::TaskValidateError)?;
+    Ok(())
+}
+
+/// Check each handoff against the payload schema of the trigger that it sends to.
+async fn validate_task_handoffs(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Authenticated,
+    handoffs: &[TaskHandoff],
+) -> Result<()> {
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+    let mut errors = Vec::new();
+    for (index, handoff) in handoffs.iter().enumerate() {
+        // The user saving the task must be allowed to trigger the target, or else a handoff
+        // could send events to any task in the org.
+        let schema = sqlx::query_scalar!(
+            "SELECT inputs.payload_schema
+            FROM task_triggers tt
+            JOIN tasks USING(task_id)
+            JOIN inputs USING(input_id)
+            WHERE task_id=$1 AND task_trigger_local_id=$2 AND tasks.org_id=$3
+                AND NOT tasks.deleted
+                AND EXISTS(
+                    SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($4)
+                    AND permission_type = 'trigger_event'
+                    AND permissioned_object IN (uuid_nil(), task_trigger_id)
+                )",
+            handoff.task_id.0,
+            &handoff.trigger_id,
+            org_id.0,
+            ids.as_slice()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match schema {
+            Some(schema) => errors.extend(handoff.validate(index, &schema)),
+            None => errors.push(TaskValidateError::InvalidHandoffTrigger {
+                index,
+                task_id: handoff.task_id.to_string(),
+                trigger_id: handoff.trigger_id.clone(),
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ergo_tasks::Error::TaskValidateError(errors).into())
+    }
 }
 
 #[put("/tasks/{task_id}")]
 async fn update_task(
     task_id: Path<TaskId>,
+    query: Query<TaskSaveQuery>,
     data: AppStateData,
+    backend_data: BackendAppStateData,
     auth: Authenticated,
     payload: web::Json<TaskInput>,
 ) -> Result<HttpResponse> {
     let user_ids = auth.user_entity_ids();
     let task_id = task_id.into_inner();
+
+    // TODO Validate task actions against action templates.
+    validate_task_config(&payload)?;
+
+    // The tests run the submitted config, so make sure the user can change the task first.
+    let can_write = sqlx::query_scalar!(
+        r##"SELECT EXISTS(
+            SELECT 1 FROM tasks
+            WHERE task_id=$1 AND org_id=$2 AND EXISTS (
+                SELECT 1 FROM user_entity_permissions
+                WHERE permissioned_object IN (uuid_nil(), tasks.task_id)
+                AND user_entity_id=ANY($3)
+                AND permission_type = 'write'
+            )
+        ) AS "can_write!""##,
+        task_id.0,
+        auth.org_id().0,
+        user_ids.as_slice()
+    )
+    .fetch_one(&data.pg)
+    .await?;
+    if !can_write {
+        return Err(Error::NotFound);
+    }
+
+    if let Some(run) = failed_task_tests(&query, &task_id, &auth, &payload).await {
+        return Ok(HttpResponse::UnprocessableEntity().json(run));
+    }
+
     let mut conn = data.pg.acquire().await?;
     let mut tx = conn.begin().await?;
 
-    // TODO Validate task actions against action templates.
+    validate_task_handoffs(&mut tx, &auth, &payload.handoffs).await?;
 
     struct TaskUpdateResult {
         task_template_id: Uuid,
         task_template_version: i64,
+        was_enabled: bool,
     }
 
     let TaskUpdateResult {
         task_template_id,
         task_template_version,
+        was_enabled,
     } = sqlx::query_as!(
         TaskUpdateResult,
-        "UPDATE tasks SET
+        r##"UPDATE tasks SET
         name=$2, description=$3, alias=$4, enabled=$5,
         state=COALESCE($6, state),
+        handoffs=$9,
+        run_limit=$10,
         modified=now()
+        FROM (SELECT enabled AS was_enabled FROM tasks WHERE task_id=$1 FOR UPDATE) old
         WHERE task_id=$1 AND org_id=$7 AND EXISTS (
             SELECT 1 FROM user_entity_permissions
             WHERE permissioned_object IN (uuid_nil(), tasks.task_id)
             AND user_entity_id=ANY($8)
             AND permission_type = 'write'
             )
-        RETURNING task_template_id, task_template_version
-        ",
+        RETURNING task_template_id, task_template_version, old.was_enabled AS "was_enabled!"
+        "##,
         task_id.0,
         payload.name,
         payload.description as _,
@@ -307,38 +486,65 @@ async fn update_task(
         payload.enabled,
         payload.state.as_ref().map(sqlx::types::Json) as _,
         auth.org_id().0,
-        user_ids.as_slice()
+        user_ids.as_slice(),
+        sqlx::types::Json(&payload.handoffs) as _,
+        payload.run_limit.as_ref().map(sqlx::types::Json) as _
     )
     .fetch_optional(&mut tx)
     .await?
     .ok_or(Error::NotFound)?;
 
-    sqlx::query!(
-        "UPDATE task_templates
-        SET source=$3, compiled=$4
-        WHERE task_template_id=$1 AND task_template_version=$2",
+    // Save the config as a new version when it or its tests change, so that the old one stays in
+    // the history.
+    let new_version = sqlx::query_scalar!(
+        r##"INSERT INTO task_templates (task_template_id, task_template_version, org_id,
+            name, description, source, compiled, initial_state, created_by, tests)
+        SELECT task_template_id,
+            (SELECT MAX(task_template_version) + 1 FROM task_templates WHERE task_template_id=$1),
+            org_id, $3, $4, $5, $6, $7, $8, $9
+        FROM task_templates
+        WHERE task_template_id=$1 AND task_template_version=$2
+            AND (source <> $5 OR compiled <> $6 OR tests <> $9)
+        RETURNING task_template_version"##,
         task_template_id,
         task_template_version,
+        payload.name,
+        payload.description as _,
         &payload.source,
         sqlx::types::Json(&payload.compiled) as _,
+        sqlx::types::Json(payload.compiled.default_state()) as _,
+        auth.user_id().0,
+        sqlx::types::Json(&payload.tests) as _
     )
-    .execute(&mut tx)
+    .fetch_optional(&mut tx)
     .await?;
 
+    if let Some(new_version) = new_version {
+        sqlx::query!(
+            "UPDATE tasks SET task_template_version=$2 WHERE task_id=$1",
+            task_id.0,
+            new_version
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
     for (action_local_id, action) in &payload.actions {
         sqlx::query!(
             "INSERT INTO task_actions
-            (task_id, task_action_local_id, action_id, account_id, name, action_template)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            (task_id, task_action_local_id, action_id, account_id, name, action_template, run_limit)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             ON CONFLICT (task_id, task_action_local_id) DO UPDATE SET
                 action_id=EXCLUDED.action_id, account_id=EXCLUDED.account_id,
-                name=EXCLUDED.name, action_template=EXCLUDED.action_template",
+                name=EXCLUDED.name, action_template=EXCLUDED.action_template,
+                run_limit=EXCLUDED.run_limit",
             &task_id.0,
             action_local_id,
             &action.action_id.0,
             action.account_id.as_ref().map(|x| x.0),
             action.name,
-            sqlx::types::Json(&action.action_template) as _
+            sqlx::types::Json(&action.action_template) as _,
+            action.run_limit.as_ref().map(sqlx::types::Json) as _
         )
         .execute(&mut tx)
         .await?;
@@ -364,14 +570,15 @@ async fn update_task(
     for (trigger_local_id, trigger) in &payload.triggers {
         let updated = sqlx::query!(
             "UPDATE task_triggers
-            SET input_id=$3, name=$4, description=$5
+            SET input_id=$3, name=$4, description=$5, priority=$6
             WHERE task_id=$1 and task_trigger_local_id=$2
             RETURNING task_trigger_id",
             &task_id.0,
             &trigger_local_id,
             &trigger.input_id.0,
             &trigger.name,
-            &trigger.description as _
+            &trigger.description as _,
+            trigger.priority as _
         )
         .fetch_optional(&mut tx)
         .await?;
@@ -429,6 +636,24 @@ async fn update_task(
         .await?;
     }
 
+    if was_enabled && !payload.enabled {
+        let notification = Notification {
+            event: NotifyEvent::TaskDisabled,
+            task_id: task_id.clone(),
+            task_name: payload.name.clone(),
+            local_id: task_id.to_string(),
+            local_object_name: payload.name.clone(),
+            local_object_id: None,
+            payload: None,
+            error: None,
+            log_id: None,
+        };
+        backend_data
+            .notifications
+            .notify(&mut tx, &org_id.0, notification)
+            .await?;
+    }
+
     tx.commit().await?;
     Ok(HttpResponse::Ok().finish())
 }
@@ -447,15 +672,16 @@ async fn add_task_trigger(
     let trigger_id = TaskTriggerId::new();
     sqlx::query!(
         "INSERT INTO task_triggers (task_trigger_id, task_id, input_id, task_trigger_local_id,
-                name, description
+                name, description, priority
             ) VALUES
-            ($1, $2, $3, $4, $5, $6)",
+            ($1, $2, $3, $4, $5, $6, $7)",
         trigger_id.0,
         task_id.0,
         trigger.input_id.0,
         local_id,
         trigger.name,
-        trigger.description as _
+        trigger.description as _,
+        trigger.priority as _
     )
     .execute(&mut *tx)
     .await?;
@@ -497,26 +723,48 @@ pub struct NewTaskResult {
 
 #[post("/tasks")]
 async fn new_task_handler(
+    query: Query<TaskSaveQuery>,
     data: AppStateData,
     auth: Authenticated,
     payload: web::Json<TaskInput>,
 ) -> Result<HttpResponse> {
-    new_task(data, auth, payload).await
+    new_task(query, data, auth, payload).await
 }
 
 #[instrument(skip(data))]
 async fn new_task(
+    query: Query<TaskSaveQuery>,
     data: AppStateData,
     auth: Authenticated,
     payload: web::Json<TaskInput>,
 ) -> Result<HttpResponse> {
     let payload = payload.into_inner();
-    let user_id = auth.user_id();
 
     // TODO Validate task actions against action templates.
+    validate_task_config(&payload)?;
+
+    // The task doesn't have an ID yet, so the tests run with a temporary one.
+    if let Some(run) = failed_task_tests(&query, &TaskId::new(), &auth, &payload).await {
+        return Ok(HttpResponse::UnprocessableEntity().json(run));
+    }
 
     let mut conn = data.pg.acquire().await?;
     let mut tx = conn.begin().await?;
+    let task_id = create_task(&mut tx, &data.redis_key_prefix, &auth, payload).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(NewTaskResult { task_id }))
+}
+
+/// Add a new task, owned by the authenticated user. The task config should already be validated.
+pub(crate) async fn create_task(
+    tx: &mut Transaction<'_, Postgres>,
+    redis_key_prefix: &Option<String>,
+    auth: &Authenticated,
+    payload: TaskInput,
+) -> Result<TaskId> {
+    let user_id = auth.user_id();
+    validate_task_handoffs(tx, auth, &payload.handoffs).await?;
 
     let task_id = TaskId::new();
     let task_template_id = TaskTemplateId::new();
@@ -529,8 +777,8 @@ async fn new_task(
     sqlx::query!(
         r##"
         INSERT INTO task_templates (task_template_id, task_template_version, org_id,
-            name, description, source, compiled, initial_state) VALUES
-            ($1, $2, $3, $4, $5, $6, $7, $8)"##,
+            name, description, source, compiled, initial_state, created_by, tests) VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"##,
         &task_template_id.0,
         0,
         &org_id.0,
@@ -538,15 +786,17 @@ async fn new_task(
         payload.description,
         &payload.source,
         sqlx::types::Json(payload.compiled) as _,
-        sqlx::types::Json(&task_state) as _
+        sqlx::types::Json(&task_state) as _,
+        user_id.0,
+        sqlx::types::Json(&payload.tests) as _
     )
-    .execute(&mut tx)
+    .execute(&mut *tx)
     .await?;
 
     sqlx::query!(
         "INSERT INTO tasks (task_id, org_id, task_template_id, task_template_version, name,
-        description, alias, enabled, state) VALUES
-        ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        description, alias, enabled, state, handoffs, run_limit) VALUES
+        ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
         &task_id.0,
         &org_id.0,
         &task_template_id.0,
@@ -555,9 +805,11 @@ async fn new_task(
         payload.description,
         payload.alias,
         payload.enabled,
-        sqlx::types::Json(&task_state) as _
+        sqlx::types::Json(&task_state) as _,
+        sqlx::types::Json(&payload.handoffs) as _,
+        payload.run_limit.as_ref().map(sqlx::types::Json) as _
     )
-    .execute(&mut tx)
+    .execute(&mut *tx)
     .await?;
 
     sqlx::query!(
@@ -568,30 +820,31 @@ async fn new_task(
         &auth.user_id().0,
         &task_id.0
     )
-    .execute(&mut tx)
+    .execute(&mut *tx)
     .await?;
 
     for (local_id, action) in &payload.actions {
         sqlx::query!(
             "INSERT INTO task_actions (task_id, task_action_local_id,
-                action_id, account_id, name, action_template)
+                action_id, account_id, name, action_template, run_limit)
                 VALUES
-                ($1, $2, $3, $4, $5, $6)",
+                ($1, $2, $3, $4, $5, $6, $7)",
             &task_id.0,
             local_id,
             &action.action_id.0,
             action.account_id.as_ref().map(|x| x.0),
             action.name,
-            sqlx::types::Json(action.action_template.as_ref()) as _
+            sqlx::types::Json(action.action_template.as_ref()) as _,
+            action.run_limit.as_ref().map(sqlx::types::Json) as _
         )
-        .execute(&mut tx)
+        .execute(&mut *tx)
         .await?;
     }
 
     for (local_id, trigger) in &payload.triggers {
         add_task_trigger(
-            &mut tx,
-            &data.redis_key_prefix,
+            tx,
+            redis_key_prefix,
             local_id,
             &task_id,
             payload.name.as_str(),
@@ -603,9 +856,7 @@ async fn new_task(
         .await?;
     }
 
-    tx.commit().await?;
-
-    Ok(HttpResponse::Created().json(NewTaskResult { task_id }))
+    Ok(task_id)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -613,8 +864,28 @@ pub struct TaskTriggerResponse {
     pub log_id: Uuid,
 }
 
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Read the `Idempotency-Key` header, which webhook senders can use to avoid running the
+/// same input twice when they retry a request.
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>> {
+    req.headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .map(|key| key.trim())
+                .filter(|key| !key.is_empty() && key.len() <= 255)
+                .map(String::from)
+                .ok_or(Error::InvalidHeader(IDEMPOTENCY_KEY_HEADER))
+        })
+        .transpose()
+}
+
 #[post("/tasks/{task_id}/trigger/{trigger_id}")]
 async fn post_task_trigger(
+    req: HttpRequest,
     path: Path<TaskAndTriggerPath>,
     data: BackendAppStateData,
     auth: Authenticated,
@@ -622,6 +893,7 @@ async fn post_task_trigger(
 ) -> Result<impl Responder> {
     let ids = auth.user_entity_ids();
     let org_id = auth.org_id();
+    let idempotency_key = idempotency_key(&req)?;
 
     let TaskAndTriggerPath {
         task_id,
@@ -689,6 +961,9 @@ async fn post_task_trigger(
         redis_key_prefix: data.redis_key_prefix.as_deref(),
         trigger_at: None,
         periodic_trigger_id: None,
+        parent_inputs_log_id: None,
+        priority: JobPriority::Normal,
+        idempotency_key,
     })
     .await?;
 
@@ -717,13 +992,104 @@ pub struct InputsLogEntry {
     pub task_trigger_name: String,
     pub task_trigger_local_id: String,
     pub timestamp: DateTime<Utc>,
+    pub created: DateTime<Utc>,
     pub actions: sqlx::types::Json<Vec<InputLogEntryAction>>,
+    /// The input of the task run that handed off to this one, if any.
+    pub parent_inputs_log_id: Option<Uuid>,
+}
+
+/// Filters for the log list. Every filter is optional, and all given filters must match.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct LogsQuery {
+    pub task_id: Option<TaskId>,
+    /// The local ID of the task trigger.
+    pub trigger: Option<String>,
+    pub status: Option<InputStatus>,
+    /// Only runs with an action with this local ID.
+    pub action: Option<String>,
+    /// Only runs with an action with this status.
+    pub action_status: Option<ActionStatus>,
+    /// Only runs created at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only runs created before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// A Postgres JSON path that must match the input payload.
+    pub payload_match: Option<String>,
+    /// A Postgres JSON path that must match the result of one of the run's actions.
+    pub result_match: Option<String>,
+    pub limit: Option<u32>,
+    /// The cursor returned in the `X-Next-Cursor` header of the previous page.
+    pub cursor: Option<String>,
+}
+
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+const DEFAULT_LOGS_LIMIT: u32 = 50;
+const MAX_LOGS_LIMIT: u32 = 500;
+
+/// A position in the log list, which is ordered by creation time and then ID.
+struct LogCursor {
+    created: DateTime<Utc>,
+    inputs_log_id: Uuid,
+}
+
+impl LogCursor {
+    fn encode(&self) -> String {
+        let value = format!("{}:{}", self.created.timestamp_micros(), self.inputs_log_id);
+        base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(cursor: &str) -> Result<LogCursor> {
+        let invalid = || Error::InvalidQuery("cursor".to_string());
+        let value = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+            .ok_or_else(invalid)?;
+        let (created, id) = value.split_once(':').ok_or_else(invalid)?;
+        let created = created.parse::<i64>().map_err(|_| invalid())?;
+
+        Ok(LogCursor {
+            created: Utc
+                .timestamp_opt(created / 1_000_000, (created % 1_000_000) as u32 * 1000)
+                .single()
+                .ok_or_else(invalid)?,
+            inputs_log_id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Make sure that a JSON path given by the user is valid, so that a mistake shows up as a bad
+/// request instead of a database error.
+async fn validate_json_path(pg: &PostgresPool, name: &str, path: Option<&str>) -> Result<()> {
+    if let Some(path) = path {
+        sqlx::query("SELECT $1::jsonpath")
+            .bind(path)
+            .execute(pg)
+            .await
+            .map_err(|_| Error::InvalidQuery(name.to_string()))?;
+    }
+
+    Ok(())
 }
 
+/// List task runs, most recent first. If there are more results, the `X-Next-Cursor` header
+/// contains a cursor to fetch the next page.
 #[get("/logs")]
-async fn get_logs(data: BackendAppStateData, auth: Authenticated) -> Result<impl Responder> {
+async fn get_logs(
+    query: web::Query<LogsQuery>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
     let ids = auth.user_entity_ids();
     let org_id = auth.org_id();
+    let query = query.into_inner();
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LOGS_LIMIT)
+        .clamp(1, MAX_LOGS_LIMIT);
+    let cursor = query.cursor.as_deref().map(LogCursor::decode).transpose()?;
+    validate_json_path(&data.pg, "payload_match", query.payload_match.as_deref()).await?;
+    validate_json_path(&data.pg, "result_match", query.result_match.as_deref()).await?;
 
     let logs = sqlx::query_as!(
         InputsLogEntry,
@@ -736,6 +1102,7 @@ async fn get_logs(data: BackendAppStateData, auth: Authenticated) -> Result<impl
                 MAX(tt.name) AS "task_trigger_name!",
                 il.task_trigger_local_id,
                 il.updated AS "timestamp",
+                il.created,
                 COALESCE(
                     jsonb_agg(jsonb_build_object(
                         'actions_log_id', al.actions_log_id,
@@ -746,12 +1113,331 @@ async fn get_logs(data: BackendAppStateData, auth: Authenticated) -> Result<impl
                         'timestamp', al.updated
                     ))
                     FILTER (WHERE al.actions_log_id IS NOT NULL)
-                , '[]'::jsonb) AS "actions!: sqlx::types::Json<Vec<InputLogEntryAction>>"
+                , '[]'::jsonb) AS "actions!: sqlx::types::Json<Vec<InputLogEntryAction>>",
+                il.parent_inputs_log_id
             FROM tasks
             JOIN inputs_log il USING (task_id)
             LEFT JOIN actions_log al USING(inputs_log_id)
-            LEFT JOIN task_actions ta USING(task_action_local_id)
+            LEFT JOIN task_actions ta ON ta.task_id = al.task_id
+                AND ta.task_action_local_id = al.task_action_local_id
             JOIN task_triggers tt USING(task_trigger_id)
+            WHERE tasks.org_id = $2 AND
+                EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($1)
+                    AND permission_type = 'read'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+                AND ($3::uuid IS NULL OR tasks.task_id = $3)
+                AND ($4::text IS NULL OR il.task_trigger_local_id = $4)
+                AND ($5::input_status IS NULL OR il.status = $5)
+                AND ($6::timestamptz IS NULL OR il.created >= $6)
+                AND ($7::timestamptz IS NULL OR il.created < $7)
+                AND ($8::text IS NULL OR jsonb_path_exists(il.payload, $8::text::jsonpath))
+                AND (($9::text IS NULL AND $10::action_status IS NULL AND $11::text IS NULL)
+                    OR EXISTS(SELECT 1 FROM actions_log fal
+                        WHERE fal.inputs_log_id = il.inputs_log_id
+                        AND ($9::text IS NULL OR fal.task_action_local_id = $9)
+                        AND ($10::action_status IS NULL OR fal.status = $10)
+                        AND ($11::text IS NULL OR
+                            jsonb_path_exists(fal.result, $11::text::jsonpath))
+                    ))
+                AND ($12::timestamptz IS NULL OR (il.created, il.inputs_log_id) < ($12, $13::uuid))
+            GROUP BY tasks.task_id, inputs_log_id
+            ORDER BY il.created DESC, il.inputs_log_id DESC
+            LIMIT $14
+        "##,
+        ids.as_slice(),
+        org_id.0,
+        query.task_id.as_ref().map(|id| id.0),
+        query.trigger,
+        query.status as _,
+        query.since,
+        query.until,
+        query.payload_match,
+        query.action,
+        query.action_status as _,
+        query.result_match,
+        cursor.as_ref().map(|c| c.created),
+        cursor.as_ref().map(|c| c.inputs_log_id),
+        limit as i64
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    let mut response = HttpResponse::Ok();
+    if logs.len() == limit as usize {
+        if let Some(last) = logs.last() {
+            let next = LogCursor {
+                created: last.created,
+                inputs_log_id: last.inputs_log_id,
+            };
+            response.insert_header((NEXT_CURSOR_HEADER, next.encode()));
+        }
+    }
+
+    Ok(response.json(logs))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct InputLogDetailAction {
+    pub actions_log_id: Uuid,
+    pub task_action_local_id: String,
+    /// The action's name, if it still exists in the task.
+    pub task_action_name: Option<String>,
+    pub payload: serde_json::Value,
+    pub result: serde_json::Value,
+    pub status: ActionStatus,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct InputsLogDetail {
+    pub inputs_log_id: Uuid,
+    pub task_name: String,
+    pub task_id: TaskId,
+    pub input_status: InputStatus,
+    pub payload: serde_json::Value,
+    /// Information about the run, or the error if it failed.
+    pub info: serde_json::Value,
+    /// The trigger's name, if it still exists in the task.
+    pub task_trigger_name: Option<String>,
+    pub task_trigger_local_id: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub actions: sqlx::types::Json<Vec<InputLogDetailAction>>,
+    pub parent_inputs_log_id: Option<Uuid>,
+}
+
+/// Return everything about a single run, including the full input payload and action results.
+#[get("/logs/{inputs_log_id}")]
+async fn get_log_detail(
+    inputs_log_id: Path<Uuid>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+    let inputs_log_id = inputs_log_id.into_inner();
+
+    let log = sqlx::query_as!(
+        InputsLogDetail,
+        r##"
+            SELECT inputs_log_id,
+                tasks.name AS task_name,
+                tasks.task_id AS "task_id: TaskId",
+                il.status AS "input_status!: InputStatus",
+                COALESCE(il.payload, 'null'::jsonb) AS "payload!",
+                COALESCE(il.info, 'null'::jsonb) AS "info!",
+                tt.name AS "task_trigger_name?",
+                il.task_trigger_local_id,
+                il.created,
+                il.updated,
+                COALESCE(
+                    (SELECT jsonb_agg(jsonb_build_object(
+                        'actions_log_id', al.actions_log_id,
+                        'task_action_local_id', al.task_action_local_id,
+                        'task_action_name', ta.name,
+                        'payload', COALESCE(al.payload, 'null'::jsonb),
+                        'result', COALESCE(al.result, 'null'::jsonb),
+                        'status', al.status,
+                        'created', al.created,
+                        'updated', al.updated
+                    ) ORDER BY al.created)
+                    FROM actions_log al
+                    LEFT JOIN task_actions ta ON ta.task_id = al.task_id
+                        AND ta.task_action_local_id = al.task_action_local_id
+                    WHERE al.inputs_log_id = il.inputs_log_id)
+                , '[]'::jsonb) AS "actions!: sqlx::types::Json<Vec<InputLogDetailAction>>",
+                il.parent_inputs_log_id
+            FROM inputs_log il
+            JOIN tasks USING (task_id)
+            LEFT JOIN task_triggers tt USING (task_trigger_id)
+            WHERE il.inputs_log_id = $3 AND tasks.org_id = $2 AND
+                EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($1)
+                    AND permission_type = 'read'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+        "##,
+        ids.as_slice(),
+        org_id.0,
+        inputs_log_id
+    )
+    .fetch_optional(&data.pg)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(log))
+}
+
+#[derive(Debug, Deserialize)]
+struct LogStreamQuery {
+    task_id: Option<TaskId>,
+}
+
+const LOG_STREAM_KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
+async fn can_read_task(
+    pg: &PostgresPool,
+    user_entity_ids: &[Uuid],
+    org_id: &OrgId,
+    task_id: &TaskId,
+) -> Result<bool> {
+    let allowed = sqlx::query_scalar!(
+        r##"SELECT EXISTS(
+            SELECT 1 FROM tasks
+            WHERE task_id = $1 AND org_id = $2 AND
+                EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($3)
+                    AND permission_type = 'read'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+        ) AS "allowed!""##,
+        task_id.0,
+        org_id.0,
+        user_entity_ids
+    )
+    .fetch_one(pg)
+    .await?;
+
+    Ok(allowed)
+}
+
+/// Stream task run events as Server-Sent Events. Each message is a JSON-encoded `TaskEvent`.
+/// If the client falls too far behind, it receives a `lagged` event with the number of events
+/// that it missed.
+#[get("/logs/stream")]
+async fn stream_logs(
+    query: web::Query<LogStreamQuery>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let task_filter = query.into_inner().task_id;
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id().clone();
+    let pg = data.pg.clone();
+
+    if let Some(task_id) = &task_filter {
+        if !can_read_task(&pg, ids.as_slice(), &org_id, task_id).await? {
+            return Err(Error::NotFound);
+        }
+    }
+
+    let mut events = data.task_events.subscribe();
+    let stream = async_stream::stream! {
+        let mut readable: FxHashMap<TaskId, bool> = FxHashMap::default();
+        let mut keepalive = tokio::time::interval(LOG_STREAM_KEEPALIVE);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if task_filter.as_ref().map(|t| t != &event.task_id).unwrap_or(false) {
+                            continue;
+                        }
+
+                        let allowed = match readable.get(&event.task_id) {
+                            Some(allowed) => *allowed,
+                            None => {
+                                match can_read_task(&pg, ids.as_slice(), &org_id, &event.task_id).await {
+                                    Ok(allowed) => {
+                                        readable.insert(event.task_id.clone(), allowed);
+                                        allowed
+                                    }
+                                    Err(e) => {
+                                        yield Err(e);
+                                        break;
+                                    }
+                                }
+                            }
+                        };
+
+                        if allowed {
+                            match serde_json::to_string(event.as_ref()) {
+                                Ok(json) => yield Ok(web::Bytes::from(format!("data: {}\n\n", json))),
+                                Err(e) => {
+                                    yield Err(Error::from(e));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        yield Ok::<_, Error>(web::Bytes::from(format!("event: lagged\ndata: {}\n\n", missed)));
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => {
+                    yield Ok(web::Bytes::from_static(b": keepalive\n\n"));
+                }
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream))
+}
+
+/// Return every run in the chain of handoffs that `inputs_log_id` is part of, starting
+/// from the first run. Runs of tasks that the user can not read are omitted.
+#[get("/logs/{inputs_log_id}/chain")]
+async fn get_log_chain(
+    inputs_log_id: Path<Uuid>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+    let inputs_log_id = inputs_log_id.into_inner();
+
+    let logs = sqlx::query_as!(
+        InputsLogEntry,
+        r##"
+            WITH RECURSIVE ancestors AS (
+                SELECT inputs_log_id, parent_inputs_log_id
+                FROM inputs_log WHERE inputs_log_id = $3
+                UNION
+                SELECT il.inputs_log_id, il.parent_inputs_log_id
+                FROM inputs_log il
+                JOIN ancestors a ON il.inputs_log_id = a.parent_inputs_log_id
+            ),
+            chain AS (
+                SELECT inputs_log_id FROM ancestors WHERE parent_inputs_log_id IS NULL
+                UNION
+                SELECT il.inputs_log_id
+                FROM inputs_log il
+                JOIN chain c ON il.parent_inputs_log_id = c.inputs_log_id
+            )
+            SELECT inputs_log_id AS "inputs_log_id!",
+                tasks.name AS task_name,
+                tasks.task_id AS "task_id: TaskId",
+                il.status AS "input_status!: InputStatus",
+                COALESCE(il.info, 'null'::jsonb) AS "info!",
+                MAX(tt.name) AS "task_trigger_name!",
+                il.task_trigger_local_id,
+                il.updated AS "timestamp",
+                il.created,
+                COALESCE(
+                    jsonb_agg(jsonb_build_object(
+                        'actions_log_id', al.actions_log_id,
+                        'task_action_local_id', ta.task_action_local_id,
+                        'task_action_name', ta.name,
+                        'result', COALESCE(al.result, 'null'::jsonb),
+                        'status', al.status,
+                        'timestamp', al.updated
+                    ))
+                    FILTER (WHERE al.actions_log_id IS NOT NULL)
+                , '[]'::jsonb) AS "actions!: sqlx::types::Json<Vec<InputLogEntryAction>>",
+                il.parent_inputs_log_id
+            FROM chain
+            JOIN inputs_log il USING (inputs_log_id)
+            JOIN tasks ON tasks.task_id = il.task_id
+            LEFT JOIN actions_log al USING(inputs_log_id)
+            LEFT JOIN task_actions ta ON ta.task_id = al.task_id
+                AND ta.task_action_local_id = al.task_action_local_id
+            JOIN task_triggers tt ON tt.task_trigger_id = il.task_trigger_id
             WHERE tasks.org_id = $2 AND
                 EXISTS(SELECT 1 FROM user_entity_permissions
                     WHERE user_entity_id = ANY($1)
@@ -759,16 +1445,285 @@ async fn get_logs(data: BackendAppStateData, auth: Authenticated) -> Result<impl
                     AND permissioned_object IN (uuid_nil(), tasks.task_id)
                 )
             GROUP BY tasks.task_id, inputs_log_id
+            ORDER BY il.created
+        "##,
+        ids.as_slice(),
+        org_id.0,
+        inputs_log_id
+    )
+    .fetch_all(&data.pg)
+    .await?;
+
+    if logs.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(HttpResponse::Ok().json(logs))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DataFlowHistoryEntry {
+    pub inputs_log_id: Uuid,
+    pub input_status: InputStatus,
+    pub task_trigger_local_id: String,
+    pub timestamp: DateTime<Utc>,
+    /// The error message, if the run failed.
+    pub error: Option<String>,
+    #[schemars(with = "serde_json::Value")]
+    pub log: sqlx::types::Json<DataFlowLog>,
+}
+
+#[get("/tasks/{task_id}/dataflow/history")]
+async fn get_dataflow_history(
+    task_id: Path<TaskId>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+
+    let history = sqlx::query_as!(
+        DataFlowHistoryEntry,
+        r##"
+            SELECT inputs_log_id,
+                il.status AS "input_status!: InputStatus",
+                il.task_trigger_local_id,
+                il.updated AS "timestamp",
+                CASE WHEN il.status = 'error' THEN il.info->>'msg' END AS error,
+                il.info AS "log!: sqlx::types::Json<DataFlowLog>"
+            FROM tasks
+            JOIN inputs_log il USING (task_id)
+            WHERE tasks.task_id = $1 AND tasks.org_id = $3
+                AND il.info IS NOT NULL AND il.info <> 'null'::jsonb
+                -- Failed runs are included too, since they store the log of the nodes that ran.
+                -- Errors that happened before the graph started running have no log.
+                AND jsonb_typeof(il.info->'run') = 'array'
+                AND EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($2)
+                    AND permission_type = 'read'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
             ORDER BY il.updated DESC
             LIMIT 50
         "##,
+        &task_id.0,
         ids.as_slice(),
-        org_id.0
+        &org_id.0
     )
     .fetch_all(&data.pg)
     .await?;
 
-    Ok(HttpResponse::Ok().json(logs))
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DataFlowReplayRequest {
+    /// The run to replay.
+    pub inputs_log_id: Uuid,
+    /// The node to start from.
+    pub node: String,
+    /// The state to replay against. If omitted, the task's current state is used.
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub state: Option<DataFlowState>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DataFlowReplayResponse {
+    pub state: DataFlowState,
+    pub log: DataFlowLog,
+    pub actions: TaskActionInvocations,
+}
+
+/// Rerun a previous DataFlow run starting from a particular node. The results are returned but
+/// not saved, and no actions are enqueued.
+#[post("/tasks/{task_id}/dataflow/replay")]
+async fn replay_dataflow(
+    task_id: Path<TaskId>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+    body: web::Json<DataFlowReplayRequest>,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+    let DataFlowReplayRequest {
+        inputs_log_id,
+        node,
+        state,
+    } = body.into_inner();
+
+    let run = sqlx::query!(
+        r##"
+            SELECT tasks.name,
+                compiled AS "compiled!: sqlx::types::Json<TaskConfig>",
+                state AS "state!: sqlx::types::Json<TaskState>",
+                il.info AS "log!: sqlx::types::Json<DataFlowLog>"
+            FROM tasks
+            JOIN task_templates USING (task_template_id, task_template_version)
+            JOIN inputs_log il ON il.task_id = tasks.task_id AND il.inputs_log_id = $2
+            WHERE tasks.task_id = $1 AND tasks.org_id = $4 AND NOT tasks.deleted
+                AND il.info IS NOT NULL
+                AND EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($3)
+                    AND permission_type = 'write'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+        "##,
+        &task_id.0,
+        &inputs_log_id,
+        ids.as_slice(),
+        &org_id.0
+    )
+    .fetch_optional(&data.pg)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let (config, current_state) = match (run.compiled.0, run.state.0) {
+        (TaskConfig::DataFlow(config), TaskState::DataFlow(state)) => (config, state),
+        _ => {
+            return Err(Error::StringError(
+                "Only DataFlow tasks can be replayed".to_string(),
+            ))
+        }
+    };
+
+    let (state, log, actions) = config
+        .replay_from_node(&run.name, state.unwrap_or(current_state), &run.log.0, &node)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(DataFlowReplayResponse {
+        state,
+        log,
+        actions,
+    }))
+}
+
+/// Run the tests saved with the task's current config.
+#[post("/tasks/{task_id}/tests/run")]
+async fn run_task_tests(
+    task_id: Path<TaskId>,
+    data: AppStateData,
+    auth: Authenticated,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+
+    let task = sqlx::query!(
+        r##"
+            SELECT tasks.name,
+                compiled AS "compiled!: sqlx::types::Json<TaskConfig>",
+                tests AS "tests!: sqlx::types::Json<Vec<TaskTestCase>>"
+            FROM tasks
+            JOIN task_templates USING (task_template_id, task_template_version)
+            WHERE tasks.task_id = $1 AND tasks.org_id = $3 AND NOT tasks.deleted
+                AND EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($2)
+                    AND permission_type = 'write'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+        "##,
+        &task_id.0,
+        ids.as_slice(),
+        &org_id.0
+    )
+    .fetch_optional(&data.pg)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let run = run_test_cases(
+        &task_id,
+        &task.name,
+        auth.user_id(),
+        &task.compiled,
+        &task.tests,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(run))
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct TaskSimulateRequest {
+    /// The local ID of the trigger to send the payload to.
+    pub trigger_id: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    /// Simulate with this config instead of the task's current config. Unless `state` is also
+    /// given, the task's current state is migrated to work with this config.
+    pub config: Option<TaskConfig>,
+    /// The state to start from. If omitted, the task's current state is used.
+    pub state: Option<TaskState>,
+}
+
+/// Send a payload to a task trigger without saving the new state, running any actions, or
+/// sending any handoffs. The response shows what would have happened.
+#[post("/tasks/{task_id}/simulate")]
+async fn simulate_task_trigger(
+    task_id: Path<TaskId>,
+    data: BackendAppStateData,
+    auth: Authenticated,
+    body: web::Json<TaskSimulateRequest>,
+) -> Result<impl Responder> {
+    let task_id = task_id.into_inner();
+    let ids = auth.user_entity_ids();
+    let org_id = auth.org_id();
+    let TaskSimulateRequest {
+        trigger_id,
+        payload,
+        config,
+        state,
+    } = body.into_inner();
+
+    let task = sqlx::query!(
+        r##"
+            SELECT tasks.name,
+                compiled AS "compiled!: sqlx::types::Json<TaskConfig>",
+                state AS "state!: sqlx::types::Json<TaskState>",
+                handoffs AS "handoffs!: sqlx::types::Json<Vec<TaskHandoff>>"
+            FROM tasks
+            JOIN task_templates USING (task_template_id, task_template_version)
+            WHERE tasks.task_id = $1 AND tasks.org_id = $3 AND NOT tasks.deleted
+                AND EXISTS(SELECT 1 FROM user_entity_permissions
+                    WHERE user_entity_id = ANY($2)
+                    AND permission_type = 'write'
+                    AND permissioned_object IN (uuid_nil(), tasks.task_id)
+                )
+        "##,
+        &task_id.0,
+        ids.as_slice(),
+        &org_id.0
+    )
+    .fetch_optional(&data.pg)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let (config, state) = match (config, state) {
+        (Some(config), Some(state)) => (config, state),
+        (Some(config), None) => {
+            let (state, _) = config.migrate_state(&task.compiled, &task.state);
+            (config, state)
+        }
+        (None, state) => (task.compiled.0, state.unwrap_or(task.state.0)),
+    };
+
+    let result = simulate_trigger(
+        &data.pg,
+        Simulation {
+            task_id: &task_id,
+            task_name: &task.name,
+            user_id: auth.user_id(),
+            config,
+            state,
+            handoffs: &task.handoffs,
+            trigger_id: &trigger_id,
+            payload,
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(result))
 }
 
 pub fn config(cfg: &mut web::ServiceConfig) {
@@ -778,5 +1733,12 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(new_task_handler)
         .service(update_task)
         .service(delete_task)
-        .service(get_logs);
+        .service(get_logs)
+        .service(stream_logs)
+        .service(get_log_detail)
+        .service(get_log_chain)
+        .service(get_dataflow_history)
+        .service(replay_dataflow)
+        .service(simulate_task_trigger)
+        .service(run_task_tests);
 }
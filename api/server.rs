@@ -1,4 +1,7 @@
-use crate::{error::Result, routes};
+use crate::{
+    error::Result, log_retention::start_log_retention_task, routes,
+    service_config::LogRetentionConfig,
+};
 
 use std::{env, net::TcpListener, path::PathBuf};
 
@@ -12,9 +15,10 @@ use actix_web::{
 use ergo_auth::middleware::AuthenticateMiddlewareFactory;
 use ergo_database::DatabaseConfiguration;
 use ergo_graceful_shutdown::GracefulShutdownConsumer;
-use ergo_notifications::NotificationManager;
+use ergo_notifications::{EmailConfig, NotificationManager};
 use ergo_tasks::{
     actions::{
+        accounts::monitor_expiring_accounts,
         dequeue::{ActionExecutor, ActionExecutorConfig},
         queue::ActionQueue,
     },
@@ -36,6 +40,11 @@ pub struct Config {
     pub redis_queue_prefix: Option<String>,
 
     pub no_drain_queues: bool,
+    /// Settings for the background task that removes old logs. The task doesn't run if this is
+    /// `None`.
+    pub log_retention: Option<LogRetentionConfig>,
+    /// SMTP settings for email notifications. Email notifications fail if this is `None`.
+    pub email: Option<EmailConfig>,
     pub shutdown: GracefulShutdownConsumer,
 }
 
@@ -46,6 +55,8 @@ pub struct ServerTasks {
     input_runner: TaskExecutor,
     action_runner: ActionExecutor,
     periodic_task_monitor: tokio::task::JoinHandle<()>,
+    account_expiry_monitor: tokio::task::JoinHandle<()>,
+    log_retention: Option<tokio::task::JoinHandle<()>>,
 }
 
 pub struct Server {
@@ -63,6 +74,8 @@ pub async fn start(config: Config) -> Result<Server> {
         redis_url,
         redis_queue_prefix,
         no_drain_queues,
+        log_retention,
+        email,
         shutdown,
     } = config;
 
@@ -85,15 +98,20 @@ pub async fn start(config: Config) -> Result<Server> {
         backend_pg_pool.clone(),
         redis_pool.clone(),
         shutdown.clone(),
+        email,
     )?;
 
     notifications.start_task_queue_loop()?;
 
     let web_app_data =
         crate::web_app_server::app_data(web_pg_pool.clone(), redis_queue_prefix.clone());
+    let task_events =
+        ergo_tasks::events::TaskEvents::new(backend_pg_pool.clone(), shutdown.clone());
+
     let backend_app_data = crate::backend_data::app_data(
         backend_pg_pool.clone(),
         notifications.clone(),
+        task_events,
         input_queue,
         action_queue,
         redis_queue_prefix.clone(),
@@ -113,10 +131,23 @@ pub async fn start(config: Config) -> Result<Server> {
     let periodic_task_monitor = monitor_missing_periodic_triggers(
         shutdown.clone(),
         backend_pg_pool.clone(),
+        Some(notifications.clone()),
         redis_queue_prefix.clone(),
         None,
     );
 
+    let account_expiry_monitor = monitor_expiring_accounts(
+        shutdown.clone(),
+        backend_pg_pool.clone(),
+        notifications.clone(),
+        None,
+    );
+
+    let log_retention = log_retention.map(|config| {
+        info!("Starting log retention task");
+        start_log_retention_task(backend_pg_pool.clone(), config, shutdown.clone())
+    });
+
     let input_runner = TaskExecutor::new(TaskExecutorConfig {
         redis_pool: redis_pool.clone(),
         pg_pool: backend_pg_pool.clone(),
@@ -172,7 +203,11 @@ pub async fn start(config: Config) -> Result<Server> {
                 .configure(routes::actions::config)
                 .configure(routes::action_categories::config)
                 .configure(routes::inputs::config)
+                .configure(routes::metrics::config)
+                .configure(routes::notifications::config)
                 .configure(routes::status::config)
+                .configure(routes::task_bundles::config)
+                .configure(routes::task_versions::config)
                 .configure(routes::tasks::config),
         );
 
@@ -203,6 +238,8 @@ pub async fn start(config: Config) -> Result<Server> {
             input_runner,
             action_runner,
             periodic_task_monitor,
+            account_expiry_monitor,
+            log_retention,
         },
     })
 }
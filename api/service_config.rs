@@ -1,5 +1,8 @@
+use std::{path::PathBuf, time::Duration};
+
 use crate::error::Error;
 use ergo_database::{DatabaseConfiguration, PostgresAuth, PostgresPool};
+use ergo_notifications::{EmailConfig, EmailTemplates, SmtpTls};
 use log::LevelFilter;
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
@@ -39,3 +42,69 @@ pub async fn backend_pg_pool(configuration: &DatabaseConfiguration) -> Result<Po
 pub async fn web_pg_pool(configuration: &DatabaseConfiguration) -> Result<PostgresPool, Error> {
     pg_pool(PostgresAuth::from_env("WEB", "ergo_web")?, configuration).await
 }
+
+/// Settings for removing old input and action logs. Per-org and per-task policies are stored in
+/// the `log_retention_policies` table, and these settings apply when no policy exists.
+#[derive(Clone, Debug)]
+pub struct LogRetentionConfig {
+    /// The directory that archived logs are written to. Logs whose policy requires archiving
+    /// are kept until this is set.
+    pub archive_dir: Option<PathBuf>,
+    /// How long to keep logs when there is no policy. If `None`, they are kept forever.
+    pub default_retain_days: Option<u32>,
+    /// Whether to archive logs that expire under the default retention period.
+    pub default_archive: bool,
+    /// How often the background task checks for expired logs.
+    pub check_interval: Duration,
+    /// The maximum number of rows to remove in each transaction.
+    pub batch_size: u32,
+}
+
+impl LogRetentionConfig {
+    pub fn from_env() -> Result<LogRetentionConfig, Error> {
+        Ok(LogRetentionConfig {
+            archive_dir: envoption::optional("LOG_ARCHIVE_DIR")?,
+            default_retain_days: envoption::optional("LOG_RETENTION_DAYS")?,
+            default_archive: envoption::with_default("LOG_RETENTION_ARCHIVE", true)?,
+            check_interval: Duration::from_secs(envoption::with_default(
+                "LOG_RETENTION_INTERVAL_SECS",
+                3600_u64,
+            )?),
+            batch_size: envoption::with_default("LOG_RETENTION_BATCH_SIZE", 500_u32)?,
+        })
+    }
+}
+
+/// Read the SMTP settings for email notifications. Email notifications are disabled when
+/// `SMTP_HOST` is not set.
+pub fn email_config_from_env() -> Result<Option<EmailConfig>, Error> {
+    let host: Option<String> = envoption::optional("SMTP_HOST")?;
+    let host = match host {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+
+    let read_template = |var: &str| -> Result<Option<String>, Error> {
+        let path: Option<PathBuf> = envoption::optional(var)?;
+        path.map(std::fs::read_to_string)
+            .transpose()
+            .map_err(|e| Error::StringError(format!("Reading {}: {}", var, e)))
+    };
+
+    let default_templates = EmailTemplates::default();
+    let templates = EmailTemplates {
+        subject: envoption::with_default("SMTP_SUBJECT_TEMPLATE", default_templates.subject)?,
+        text: read_template("SMTP_TEXT_TEMPLATE_FILE")?.unwrap_or(default_templates.text),
+        html: read_template("SMTP_HTML_TEMPLATE_FILE")?,
+    };
+
+    Ok(Some(EmailConfig {
+        host,
+        port: envoption::with_default("SMTP_PORT", 587_u16)?,
+        tls: envoption::with_default("SMTP_TLS", SmtpTls::StartTls)?,
+        username: envoption::optional("SMTP_USERNAME")?,
+        password: envoption::optional("SMTP_PASSWORD")?,
+        from: envoption::require("SMTP_FROM")?,
+        templates,
+    }))
+}
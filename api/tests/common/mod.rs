@@ -6,6 +6,7 @@ use fxhash::FxHashMap;
 use once_cell::sync::Lazy;
 
 mod client;
+mod notifications;
 mod tasks;
 
 pub use client::*;
@@ -54,6 +55,8 @@ async fn start_app(
         redis_url: redis_url.clone(),
         redis_queue_prefix: Some(redis_key_prefix.clone()),
         no_drain_queues: false,
+        log_retention: None,
+        email: None,
         shutdown: shutdown.consumer(),
     };
     Lazy::force(&ergo_test::TRACING);
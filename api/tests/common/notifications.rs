@@ -0,0 +1,110 @@
+use ergo_api::routes::notifications::{
+    NotifyEndpoint, NotifyEndpointInput, NotifyListener, NotifyListenerInput, NotifyTestRequest,
+    NotifyTestResult,
+};
+use ergo_database::object_id::{NotifyEndpointId, NotifyListenerId};
+
+use super::TestClient;
+use reqwest::{Response, Result};
+
+impl TestClient {
+    pub async fn list_notify_endpoints(&self) -> Result<Vec<NotifyEndpoint>> {
+        self.get("notifications/endpoints")
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn new_notify_endpoint(
+        &self,
+        endpoint: &NotifyEndpointInput,
+    ) -> Result<NotifyEndpoint> {
+        self.post("notifications/endpoints")
+            .json(endpoint)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn put_notify_endpoint(
+        &self,
+        endpoint_id: &NotifyEndpointId,
+        endpoint: &NotifyEndpointInput,
+    ) -> Result<NotifyEndpoint> {
+        self.put(format!("notifications/endpoints/{}", endpoint_id))
+            .json(endpoint)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn delete_notify_endpoint(&self, endpoint_id: &NotifyEndpointId) -> Result<Response> {
+        self.delete(format!("notifications/endpoints/{}", endpoint_id))
+            .send()
+            .await?
+            .error_for_status()
+    }
+
+    pub async fn test_notify_endpoint(
+        &self,
+        endpoint_id: &NotifyEndpointId,
+        request: &NotifyTestRequest,
+    ) -> Result<NotifyTestResult> {
+        self.post(format!("notifications/endpoints/{}/test", endpoint_id))
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn list_notify_listeners(&self) -> Result<Vec<NotifyListener>> {
+        self.get("notifications/listeners")
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn new_notify_listener(
+        &self,
+        listener: &NotifyListenerInput,
+    ) -> Result<NotifyListener> {
+        self.post("notifications/listeners")
+            .json(listener)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn put_notify_listener(
+        &self,
+        listener_id: &NotifyListenerId,
+        listener: &NotifyListenerInput,
+    ) -> Result<NotifyListener> {
+        self.put(format!("notifications/listeners/{}", listener_id))
+            .json(listener)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn delete_notify_listener(&self, listener_id: &NotifyListenerId) -> Result<Response> {
+        self.delete(format!("notifications/listeners/{}", listener_id))
+            .send()
+            .await?
+            .error_for_status()
+    }
+}
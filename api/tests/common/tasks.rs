@@ -1,12 +1,17 @@
 use ergo_api::routes::{
     actions::ActionPayload,
     inputs::InputPayload,
+    task_bundles::{TaskBundle, TaskImportQuery},
+    task_versions::{TaskRollbackResult, TaskVersion, TaskVersionDiff, TaskVersionDiffQuery},
     tasks::{
-        InputsLogEntry, NewTaskResult, TaskDescription, TaskInput, TaskResult, TaskTriggerResponse,
+        InputsLogDetail, InputsLogEntry, LogsQuery, NewTaskResult, TaskDescription, TaskInput,
+        TaskResult, TaskSaveQuery, TaskSimulateRequest, TaskTriggerResponse, NEXT_CURSOR_HEADER,
     },
 };
 use ergo_database::object_id::{ActionId, InputId, TaskId};
-use ergo_tasks::{actions::Action, inputs::Input};
+use ergo_tasks::{
+    actions::Action, inputs::Input, simulate::SimulateResult, test_cases::TaskTestRun,
+};
 
 use super::TestClient;
 use reqwest::{Response, Result};
@@ -28,6 +33,18 @@ impl TestClient {
         self.put(url).json(task).send().await?.error_for_status()
     }
 
+    /// Save a task, running its tests first. This doesn't check the response status, since
+    /// failing tests return an error along with the results.
+    pub async fn put_task_with_tests(&self, id: &TaskId, task: &TaskInput) -> Result<Response> {
+        let url = format!("tasks/{}", id);
+
+        self.put(url)
+            .query(&TaskSaveQuery { run_tests: true })
+            .json(task)
+            .send()
+            .await
+    }
+
     pub async fn list_tasks(&self) -> Result<Vec<TaskDescription>> {
         self.get("tasks")
             .send()
@@ -68,6 +85,24 @@ impl TestClient {
             .await
     }
 
+    pub async fn run_task_trigger_with_idempotency_key(
+        &self,
+        task: &str,
+        trigger: &str,
+        idempotency_key: &str,
+        payload: serde_json::Value,
+    ) -> Result<TaskTriggerResponse> {
+        let url = format!("tasks/{}/trigger/{}", task, trigger);
+        self.post(url)
+            .header("Idempotency-Key", idempotency_key)
+            .json(&payload)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
     pub async fn list_inputs(&self) -> Result<Vec<Input>> {
         self.get("inputs")
             .send()
@@ -146,4 +181,116 @@ impl TestClient {
             .json::<_>()
             .await
     }
+
+    /// Search the logs, returning the matching entries and the cursor for the next page.
+    pub async fn search_logs(
+        &self,
+        query: &LogsQuery,
+    ) -> Result<(Vec<InputsLogEntry>, Option<String>)> {
+        let response = self
+            .get("logs")
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?;
+        let cursor = response
+            .headers()
+            .get(NEXT_CURSOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let logs = response.json::<_>().await?;
+        Ok((logs, cursor))
+    }
+
+    pub async fn get_log_detail(&self, inputs_log_id: &uuid::Uuid) -> Result<InputsLogDetail> {
+        self.get(format!("logs/{}", inputs_log_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn export_task(&self, task_id: &TaskId) -> Result<TaskBundle> {
+        self.get(format!("tasks/{}/export", task_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    /// Import a task bundle. The response is returned as-is since a conflict is reported with
+    /// an error status.
+    pub async fn import_task(
+        &self,
+        bundle: &TaskBundle,
+        query: &TaskImportQuery,
+    ) -> Result<Response> {
+        self.post("tasks/import")
+            .query(query)
+            .json(bundle)
+            .send()
+            .await
+    }
+
+    pub async fn list_task_versions(&self, task_id: &TaskId) -> Result<Vec<TaskVersion>> {
+        self.get(format!("tasks/{}/versions", task_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn diff_task_versions(
+        &self,
+        task_id: &TaskId,
+        from: i64,
+        to: Option<i64>,
+    ) -> Result<TaskVersionDiff> {
+        self.get(format!("tasks/{}/versions/diff", task_id))
+            .query(&TaskVersionDiffQuery { from, to })
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn rollback_task(
+        &self,
+        task_id: &TaskId,
+        version: i64,
+    ) -> Result<TaskRollbackResult> {
+        self.post(format!("tasks/{}/versions/{}/rollback", task_id, version))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn simulate_task(
+        &self,
+        task_id: &TaskId,
+        request: &TaskSimulateRequest,
+    ) -> Result<SimulateResult> {
+        self.post(format!("tasks/{}/simulate", task_id))
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
+
+    pub async fn run_task_tests(&self, task_id: &TaskId) -> Result<TaskTestRun> {
+        self.post(format!("tasks/{}/tests/run", task_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<_>()
+            .await
+    }
 }
@@ -1,4 +1,6 @@
 mod auth;
 mod common;
+mod metrics;
+mod notifications;
 mod smoke_test;
 mod tasks;
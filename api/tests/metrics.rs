@@ -0,0 +1,45 @@
+use crate::common::run_app_test;
+
+#[actix_rt::test]
+async fn metrics() {
+    run_app_test(|app| async move {
+        let response = app.client.get("metrics").send().await?;
+        assert!(
+            response.status().is_client_error(),
+            "anonymous requests are rejected, saw {}",
+            response.status()
+        );
+
+        let member = app.add_user(&app.org_id, "member").await?;
+        let response = member.client.get("metrics").send().await?;
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "only admins can read metrics"
+        );
+
+        let response = app.admin_user.client.get("metrics").send().await?;
+
+        assert_eq!(
+            response.status().as_u16(),
+            200,
+            "response status code should be 200"
+        );
+
+        let body = response.text().await?;
+        assert!(
+            body.contains("# TYPE erq_queue_jobs gauge"),
+            "queue depth is exported"
+        );
+        assert!(
+            body.contains("state=\"pending\""),
+            "queue states are labelled"
+        );
+        assert!(
+            body.contains("erq_queue_events_total"),
+            "queue totals are exported"
+        );
+        Ok(())
+    })
+    .await
+}
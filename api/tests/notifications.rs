@@ -0,0 +1,536 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use ergo_api::routes::{
+    notifications::{NotifyEndpointInput, NotifyListenerInput, NotifyTestRequest, REDACTED},
+    tasks::TaskInput,
+};
+use ergo_database::{
+    object_id::{AccountId, TaskTriggerId},
+    RedisPool,
+};
+use ergo_graceful_shutdown::GracefulShutdown;
+use ergo_notifications::{
+    sign_body, DigestInterval, NotificationManager, NotifyEvent, NotifyPolicy, NotifyService,
+    NotifyTemplate, RateLimit,
+};
+use ergo_tasks::{
+    actions::accounts::notify_expiring_accounts,
+    inputs::InputInvocation,
+    scripting::{TaskJsConfig, TaskJsState},
+    state_machine::{EventHandler, StateDefinition, TransitionTarget},
+    Task, TaskConfig, TaskState,
+};
+use serde_json::json;
+use smallvec::smallvec;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use uuid::Uuid;
+
+use crate::{
+    common::{run_app_test, TestApp},
+    tasks::{
+        bootstrap_inputs_and_actions, simple_state_machine, simple_task_actions,
+        simple_task_triggers, BootstrappedActions, BootstrappedInputs,
+    },
+};
+
+/// Accept a single HTTP request and return its headers and body.
+async fn receive_one_request(listener: &TcpListener) -> Result<(String, Vec<u8>)> {
+    let (mut socket, _) = listener.accept().await?;
+
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let (headers, body_start) = loop {
+        let n = socket.read(&mut buf).await?;
+        anyhow::ensure!(n > 0, "Connection closed before the headers were read");
+        data.extend_from_slice(&buf[..n]);
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break (
+                String::from_utf8_lossy(&data[..pos]).to_lowercase(),
+                pos + 4,
+            );
+        }
+    };
+
+    let content_length = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|len| len.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while data.len() < body_start + content_length {
+        let n = socket.read(&mut buf).await?;
+        anyhow::ensure!(n > 0, "Connection closed before the body was read");
+        data.extend_from_slice(&buf[..n]);
+    }
+
+    socket
+        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+        .await?;
+
+    Ok((headers, data[body_start..].to_vec()))
+}
+
+/// Add a webhook endpoint and a listener for `event` on every task in the app's organization.
+/// Returns the socket that the webhook sends to.
+pub async fn listen_for_event(app: &TestApp, event: NotifyEvent) -> Result<TcpListener> {
+    let receiver = TcpListener::bind("127.0.0.1:0").await?;
+    let client = &app.admin_user.client;
+    let endpoint = client
+        .new_notify_endpoint(&NotifyEndpointInput {
+            service: NotifyService::Webhook,
+            destination: format!("http://{}/hook", receiver.local_addr()?),
+            config: None,
+            enabled: true,
+        })
+        .await?;
+    client
+        .new_notify_listener(&NotifyListenerInput {
+            notify_endpoint_id: endpoint.notify_endpoint_id,
+            task_id: None,
+            event,
+            enabled: true,
+            template: None,
+            policy: NotifyPolicy::default(),
+        })
+        .await?;
+
+    Ok(receiver)
+}
+
+/// Wait for the next notification sent to a receiver from [listen_for_event], and return the
+/// body of the webhook request.
+pub async fn next_notification(receiver: &TcpListener) -> Result<serde_json::Value> {
+    let (_, body) =
+        tokio::time::timeout(Duration::from_secs(30), receive_one_request(receiver)).await??;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// A notification manager that uses the app's queue, for running background checks directly.
+/// The app's own notification executor sends the notifications. The shutdown handle has to be
+/// kept alive along with the manager.
+pub fn notification_manager(app: &TestApp) -> Result<(NotificationManager, GracefulShutdown)> {
+    let shutdown = GracefulShutdown::new();
+    let redis_pool = RedisPool::new(app.redis_url.clone(), Some(app.redis_key_prefix.clone()))?;
+    let manager = NotificationManager::new(
+        app.database.pool.clone(),
+        redis_pool,
+        shutdown.consumer(),
+        None,
+    )?;
+    Ok((manager, shutdown))
+}
+
+/// A task with the standard triggers and actions, owned by the app's admin user.
+fn task_input(
+    name: &str,
+    compiled: TaskConfig,
+    state: TaskState,
+    inputs: &BootstrappedInputs,
+    actions: &BootstrappedActions,
+) -> TaskInput {
+    TaskInput {
+        name: name.to_string(),
+        description: None,
+        alias: None,
+        enabled: true,
+        compiled,
+        state: Some(state),
+        source: serde_json::Value::Null,
+        actions: simple_task_actions(actions),
+        triggers: simple_task_triggers(inputs),
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
+    }
+}
+
+#[actix_rt::test]
+async fn notification_endpoints() {
+    run_app_test(|app| async move {
+        let user = &app.admin_user;
+        let other_org = app.add_org("other org").await?;
+        let other_user = app.add_user(&other_org, "other user").await?;
+        let member = app.add_user(&app.org_id, "member").await?;
+
+        let receiver = TcpListener::bind("127.0.0.1:0").await?;
+        let receiver_url = format!("http://{}/hook", receiver.local_addr()?);
+
+        let mut endpoint_input = NotifyEndpointInput {
+            service: NotifyService::Webhook,
+            destination: "not a url".to_string(),
+            config: Some(json!({ "secret": "abc", "headers": { "X-Team": "ops" } })),
+            enabled: true,
+        };
+        user.client
+            .new_notify_endpoint(&endpoint_input)
+            .await
+            .expect_err("webhook destination must be a URL");
+
+        endpoint_input.destination = receiver_url;
+        member
+            .client
+            .new_notify_endpoint(&endpoint_input)
+            .await
+            .expect_err("only admins can add endpoints");
+        let endpoint = user.client.new_notify_endpoint(&endpoint_input).await?;
+        let endpoint_id = &endpoint.notify_endpoint_id;
+
+        // Secrets are not returned.
+        let redacted = json!({ "secret": REDACTED, "headers": { "X-Team": REDACTED } });
+        assert_eq!(endpoint.config.as_ref(), Some(&redacted));
+
+        let endpoints = user.client.list_notify_endpoints().await?;
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(&endpoints[0].notify_endpoint_id, endpoint_id);
+        assert_eq!(endpoints[0].config.as_ref(), Some(&redacted));
+        member
+            .client
+            .list_notify_endpoints()
+            .await
+            .expect_err("only admins can list endpoints");
+        other_user
+            .client
+            .list_notify_endpoints()
+            .await
+            .expect_err("only admins can list endpoints");
+
+        // Sending back the redacted config keeps the stored secrets.
+        let updated = user
+            .client
+            .put_notify_endpoint(
+                endpoint_id,
+                &NotifyEndpointInput {
+                    config: endpoints[0].config.clone(),
+                    ..endpoint_input
+                },
+            )
+            .await?;
+        assert_eq!(updated.config.as_ref(), Some(&redacted));
+
+        // Templates are checked when they're saved.
+        let mut listener_input = NotifyListenerInput {
+            notify_endpoint_id: endpoint_id.clone(),
+            task_id: None,
+            event: NotifyEvent::ActionError,
+            enabled: true,
+            template: Some(NotifyTemplate {
+                subject: None,
+                body: "{{no_such_field}}".to_string(),
+            }),
+            policy: NotifyPolicy::default(),
+        };
+        user.client
+            .new_notify_listener(&listener_input)
+            .await
+            .expect_err("invalid template");
+
+        listener_input.template = Some(NotifyTemplate {
+            subject: Some("{{description}}".to_string()),
+            body: "{{task_name}} failed: {{error}}".to_string(),
+        });
+        member
+            .client
+            .new_notify_listener(&listener_input)
+            .await
+            .expect_err("only admins can listen on all tasks");
+        let listener = user.client.new_notify_listener(&listener_input).await?;
+        other_user
+            .client
+            .new_notify_listener(&listener_input)
+            .await
+            .expect_err("other org can not listen on this endpoint");
+
+        let listeners = user.client.list_notify_listeners().await?;
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].template, listener_input.template);
+        assert_eq!(listeners[0].policy, NotifyPolicy::default());
+
+        // Policies are checked too.
+        listener_input.policy.rate_limit = Some(RateLimit {
+            max: 0,
+            period_secs: 60,
+        });
+        user.client
+            .put_notify_listener(&listener.notify_listener_id, &listener_input)
+            .await
+            .expect_err("rate limit of 0");
+
+        listener_input.policy = NotifyPolicy {
+            rate_limit: Some(RateLimit {
+                max: 10,
+                period_secs: 60,
+            }),
+            collapse_errors_secs: Some(300),
+            digest: Some(DigestInterval::Hourly),
+        };
+
+        listener_input.enabled = false;
+        let updated = user
+            .client
+            .put_notify_listener(&listener.notify_listener_id, &listener_input)
+            .await?;
+        assert!(!updated.enabled);
+        assert_eq!(updated.policy, listener_input.policy);
+        member
+            .client
+            .put_notify_listener(&listener.notify_listener_id, &listener_input)
+            .await
+            .expect_err("only admins can change a listener on all tasks");
+        member
+            .client
+            .delete_notify_listener(&listener.notify_listener_id)
+            .await
+            .expect_err("only admins can delete a listener on all tasks");
+
+        // Send a test notification and check what the webhook received.
+        let received = tokio::spawn(async move { receive_one_request(&receiver).await });
+        let result = user
+            .client
+            .test_notify_endpoint(
+                endpoint_id,
+                &NotifyTestRequest {
+                    event: Some(NotifyEvent::ActionError),
+                    template: listener_input.template.clone(),
+                },
+            )
+            .await?;
+        assert!(result.sent, "{:?}", result.error);
+
+        let (headers, body) = received.await??;
+        assert!(headers.contains("x-team: ops"));
+        assert!(headers.contains("x-ergo-event: action_error"));
+        let signature = format!("x-ergo-signature: {}", sign_body("abc", &body));
+        assert!(headers.contains(&signature));
+
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(body["event"], json!("action_error"));
+        assert_eq!(
+            body["message"],
+            json!({
+                "subject": "Action Error",
+                "body": "Test Notification failed: This is a test error"
+            })
+        );
+
+        // The receiver is gone, so this one fails.
+        let result = user
+            .client
+            .test_notify_endpoint(endpoint_id, &NotifyTestRequest::default())
+            .await?;
+        assert!(!result.sent);
+        assert!(result.error.is_some());
+        member
+            .client
+            .test_notify_endpoint(endpoint_id, &NotifyTestRequest::default())
+            .await
+            .expect_err("only admins can test endpoints");
+
+        other_user
+            .client
+            .delete_notify_endpoint(endpoint_id)
+            .await
+            .expect_err("other org can not delete the endpoint");
+
+        // Deleting the endpoint removes its listeners too.
+        user.client.delete_notify_endpoint(endpoint_id).await?;
+        assert!(user.client.list_notify_endpoints().await?.is_empty());
+        assert!(user.client.list_notify_listeners().await?.is_empty());
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn state_changed_notification() {
+    run_app_test(|app| async move {
+        let receiver = listen_for_event(&app, NotifyEvent::StateChanged).await?;
+        let (inputs, actions) = bootstrap_inputs_and_actions(&app).await;
+
+        let (mut config, state) = simple_state_machine();
+        if let TaskConfig::StateMachine(machines) = &mut config {
+            let states = &mut machines[0].states;
+            states.get_mut("initial").unwrap().on = smallvec![EventHandler {
+                trigger_id: "run_it".to_string(),
+                target: Some(TransitionTarget::One("done".to_string())),
+                actions: None,
+            }];
+            states.insert("done".to_string(), StateDefinition::default());
+        }
+
+        let task = task_input("changing task", config, state, &inputs, &actions);
+        let task_id = app.admin_user.client.new_task(&task).await?.task_id;
+        app.admin_user
+            .client
+            .run_task_trigger(
+                &task_id.to_string(),
+                "run_it",
+                json!({ "url": "https://example.com/" }),
+            )
+            .await?;
+
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("state_changed"));
+        assert_eq!(body["task_id"], json!(task_id));
+        assert_eq!(body["payload"]["old_state"], json!("initial"));
+        assert_eq!(body["payload"]["new_state"], json!("done"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn task_disabled_notification() {
+    run_app_test(|app| async move {
+        let receiver = listen_for_event(&app, NotifyEvent::TaskDisabled).await?;
+        let (inputs, actions) = bootstrap_inputs_and_actions(&app).await;
+
+        let (config, state) = simple_state_machine();
+        let mut task = task_input("disabled task", config, state, &inputs, &actions);
+        let task_id = app.admin_user.client.new_task(&task).await?.task_id;
+
+        task.enabled = false;
+        app.admin_user.client.put_task(&task_id, &task).await?;
+
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("task_disabled"));
+        assert_eq!(body["task_id"], json!(task_id));
+        assert_eq!(body["task_name"], json!("disabled task"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn script_input_error_notification() {
+    run_app_test(|app| async move {
+        let receiver = listen_for_event(&app, NotifyEvent::InputError).await?;
+        let (inputs, actions) = bootstrap_inputs_and_actions(&app).await;
+
+        let config = TaskConfig::Js(TaskJsConfig {
+            map: String::new(),
+            script: r#"throw new Error("script failed")"#.to_string(),
+            timeout: None,
+        });
+        let state = TaskState::Js(TaskJsState {
+            context: String::new(),
+        });
+        let task = task_input("failing script", config, state, &inputs, &actions);
+        let task_id = app.admin_user.client.new_task(&task).await?.task_id;
+
+        let task_trigger_id: Uuid = sqlx::query_scalar(
+            "SELECT task_trigger_id FROM task_triggers WHERE task_id=$1 AND task_trigger_local_id='run_it'",
+        )
+        .bind(task_id.0)
+        .fetch_one(&app.database.pool)
+        .await?;
+
+        // Run the input directly, so that the retries don't have to wait for the queue's backoff.
+        let (notifications, _shutdown) = notification_manager(&app)?;
+        for (attempt, final_attempt) in [(1, false), (2, true)] {
+            let invocation = InputInvocation {
+                task_id: task_id.clone(),
+                task_trigger_id: TaskTriggerId::from_uuid(task_trigger_id),
+                periodic_trigger_id: None,
+                input_id: inputs.url.input_id.clone(),
+                inputs_log_id: Uuid::new_v4(),
+                payload: json!({ "url": format!("https://example.com/{}", attempt) }),
+                user_id: app.admin_user.user_id.clone(),
+                priority: Default::default(),
+            };
+
+            Task::apply_input(
+                &app.database.pool,
+                Some(notifications.clone()),
+                Some(app.redis_key_prefix.clone()),
+                final_attempt,
+                invocation,
+            )
+            .await
+            .expect_err("script should fail");
+        }
+
+        // Only the final attempt sends a notification.
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("input_error"));
+        assert_eq!(body["task_id"], json!(task_id));
+        assert_eq!(
+            body["payload"],
+            json!({ "url": "https://example.com/2" }),
+            "notification is from the final attempt"
+        );
+        let error = body["error"].as_str().unwrap_or_default();
+        assert!(error.contains("script failed"), "error was {}", error);
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn account_expiring_notification() {
+    run_app_test(|app| async move {
+        let receiver = listen_for_event(&app, NotifyEvent::AccountExpiring).await?;
+        let (inputs, actions) = bootstrap_inputs_and_actions(&app).await;
+
+        let (config, state) = simple_state_machine();
+        let task = task_input("task with account", config, state, &inputs, &actions);
+        let task_id = app.admin_user.client.new_task(&task).await?.task_id;
+
+        let pool = &app.database.pool;
+        sqlx::query(
+            "INSERT INTO account_types (account_type_id, name) VALUES ('test', 'Test')
+            ON CONFLICT DO NOTHING",
+        )
+        .execute(pool)
+        .await?;
+        let account_id = AccountId::new();
+        sqlx::query(
+            "INSERT INTO accounts (account_id, account_type_id, name, org_id, expires)
+            VALUES ($1, 'test', 'Expiring account', $2, now() + interval '2 days')",
+        )
+        .bind(account_id.0)
+        .bind(app.org_id.0)
+        .execute(pool)
+        .await?;
+        sqlx::query("UPDATE task_actions SET account_id=$1 WHERE task_id=$2")
+            .bind(account_id.0)
+            .bind(task_id.0)
+            .execute(pool)
+            .await?;
+
+        let (notifications, _shutdown) = notification_manager(&app)?;
+        notify_expiring_accounts(pool, &notifications).await?;
+
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("account_expiring"));
+        assert_eq!(body["task_id"], json!(task_id));
+        assert_eq!(body["local_object_name"], json!("Expiring account"));
+        let first_expires = body["payload"]["expires"].clone();
+
+        // The account was already reported for this expiration time.
+        notify_expiring_accounts(pool, &notifications).await?;
+
+        // Renewing the account re-arms the notification for the new expiration time.
+        sqlx::query("UPDATE accounts SET expires = expires + interval '1 day' WHERE account_id=$1")
+            .bind(account_id.0)
+            .execute(pool)
+            .await?;
+        notify_expiring_accounts(pool, &notifications).await?;
+
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("account_expiring"));
+        assert_ne!(
+            body["payload"]["expires"], first_expires,
+            "the repeated check didn't send another notification"
+        );
+
+        Ok(())
+    })
+    .await
+}
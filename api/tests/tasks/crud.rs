@@ -10,12 +10,23 @@ use crate::{
 };
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
-use ergo_api::routes::tasks::{
-    NewTaskResult, TaskActionInput, TaskDescription, TaskInput, TaskTriggerInput,
+use ergo_api::routes::{
+    task_bundles::{
+        ImportConflict, ImportResolution, ImportedObject, TaskImportQuery, TaskImportResult,
+    },
+    tasks::{NewTaskResult, TaskActionInput, TaskDescription, TaskInput, TaskTriggerInput},
+};
+use ergo_database::object_id::{ActionId, OrgId, TaskId};
+use ergo_tasks::{
+    handoff::TaskHandoff,
+    inputs::TriggerPriority,
+    limits::RunLimit,
+    test_cases::{StateExpectation, TaskTestCase, TaskTestExpectations, TaskTestRun, TaskTestStep},
+    StateMigration, TaskConfig,
 };
-use ergo_database::object_id::{OrgId, TaskId};
 use futures::future::join_all;
 use fxhash::FxHashMap;
+use serde_json::json;
 
 use super::{BootstrappedActions, BootstrappedInputs};
 
@@ -52,6 +63,9 @@ async fn bootstrap_data(app: &TestApp) -> Result<BootstrappedData> {
             state: Some(states.clone()),
             actions: test_actions.clone(),
             triggers: test_triggers.clone(),
+            handoffs: Vec::new(),
+            tests: Vec::new(),
+            run_limit: None,
         },
         TaskInput {
             name: "task 2".to_string(),
@@ -63,6 +77,9 @@ async fn bootstrap_data(app: &TestApp) -> Result<BootstrappedData> {
             state: Some(states.clone()),
             actions: test_actions.clone(),
             triggers: test_triggers.clone(),
+            handoffs: Vec::new(),
+            tests: Vec::new(),
+            run_limit: None,
         },
         TaskInput {
             name: "task 3".to_string(),
@@ -74,6 +91,9 @@ async fn bootstrap_data(app: &TestApp) -> Result<BootstrappedData> {
             state: Some(states.clone()),
             actions: test_actions.clone(),
             triggers: test_triggers.clone(),
+            handoffs: Vec::new(),
+            tests: Vec::new(),
+            run_limit: None,
         },
     ];
 
@@ -87,6 +107,9 @@ async fn bootstrap_data(app: &TestApp) -> Result<BootstrappedData> {
         state: Some(states.clone()),
         actions: test_actions.clone(),
         triggers: test_triggers.clone(),
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
     };
 
     let reference_time = Utc::now();
@@ -368,6 +391,9 @@ async fn put_existing_task() {
             state: Some(state.clone()),
             actions: vec![].into_iter().collect::<FxHashMap<_, _>>(),
             triggers: vec![].into_iter().collect::<FxHashMap<_, _>>(),
+            handoffs: Vec::new(),
+            tests: Vec::new(),
+            run_limit: None,
         };
 
         user1
@@ -390,6 +416,190 @@ async fn put_existing_task() {
     .await
 }
 
+#[actix_rt::test]
+async fn task_versions() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1,
+            user2,
+            user1_tasks,
+            ..
+        } = bootstrap_data(&app).await?;
+
+        let (task_result, task) = &user1_tasks[0];
+        let task_id = &task_result.task_id;
+
+        let versions = user1.client.list_task_versions(task_id).await?;
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, 0);
+        assert_eq!(versions[0].created_by.as_ref(), Some(&user1.user_id));
+        assert_eq!(versions[0].created_by_name.as_deref(), Some("User 1"));
+        assert!(versions[0].current);
+
+        // Rename the machine's only state, leaving the task state as it was.
+        let mut updated = task.clone();
+        updated.state = None;
+        if let TaskConfig::StateMachine(machines) = &mut updated.compiled {
+            let state = machines[0].states.remove("initial").unwrap();
+            machines[0].states.insert("waiting".to_string(), state);
+            machines[0].initial = "waiting".to_string();
+        }
+
+        user1.client.put_task(task_id, &updated).await?;
+        // Saving the same config again does not create another version.
+        updated.name = "renamed task".to_string();
+        user1.client.put_task(task_id, &updated).await?;
+
+        let versions = user1.client.list_task_versions(task_id).await?;
+        let version_numbers = versions.iter().map(|v| v.version).collect::<Vec<_>>();
+        assert_eq!(version_numbers, vec![1, 0]);
+        assert!(versions[0].current);
+        assert!(!versions[1].current);
+
+        user2
+            .client
+            .list_task_versions(task_id)
+            .await
+            .expect_err("user 2 can not see user 1's task versions");
+
+        let diff = user1.client.diff_task_versions(task_id, 0, None).await?;
+        assert_eq!(diff.from, 0);
+        assert_eq!(diff.to, 1);
+        let paths = diff.compiled.iter().map(|c| c.path()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "/data/0/initial",
+                "/data/0/states/initial",
+                "/data/0/states/waiting"
+            ]
+        );
+        assert!(diff.source.is_empty());
+
+        user2
+            .client
+            .rollback_task(task_id, 0)
+            .await
+            .expect_err("user 2 can not roll back user 1's task");
+
+        // The task state still refers to the old state, so it carries over.
+        let result = user1.client.rollback_task(task_id, 0).await?;
+        assert_eq!(result.task_template_version, 0);
+        assert_eq!(result.state, StateMigration::Kept);
+
+        let task_result = user1.client.get_task(task_id).await?;
+        assert_eq!(task_result.task_template_version, 0);
+        assert_eq!(task_result.compiled.0, task.compiled);
+
+        // But it doesn't exist in version 1.
+        let result = user1.client.rollback_task(task_id, 1).await?;
+        assert_eq!(result.state, StateMigration::Reset);
+        let task_result = user1.client.get_task(task_id).await?;
+        assert_eq!(task_result.task_template_version, 1);
+        assert_eq!(task_result.state.0, updated.compiled.default_state());
+
+        user1
+            .client
+            .rollback_task(task_id, 5)
+            .await
+            .expect_err("rolling back to a version that does not exist");
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn task_tests() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1,
+            user2,
+            user1_tasks,
+            ..
+        } = bootstrap_data(&app).await?;
+
+        let (task_result, task) = &user1_tasks[0];
+        let task_id = &task_result.task_id;
+
+        let run = user1.client.run_task_tests(task_id).await?;
+        assert!(run.passed);
+        assert!(run.results.is_empty());
+
+        let test_case = |name: &str, state: &str| TaskTestCase {
+            name: name.to_string(),
+            initial_state: None,
+            steps: vec![TaskTestStep {
+                trigger_id: "run_it".to_string(),
+                payload: json!({}),
+            }],
+            expect: TaskTestExpectations {
+                state: vec![StateExpectation {
+                    pointer: "/data/0/state".to_string(),
+                    value: json!(state),
+                }],
+                actions: Some(vec![]),
+            },
+        };
+
+        let mut updated = task.clone();
+        updated.state = None;
+        updated.tests = vec![
+            test_case("stays put", "initial"),
+            test_case("goes somewhere", "elsewhere"),
+        ];
+
+        // A failing test blocks the save when the tests are requested.
+        let response = user1.client.put_task_with_tests(task_id, &updated).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let run = response.json::<TaskTestRun>().await?;
+        assert!(!run.passed);
+        assert_eq!(run.num_failed(), 1);
+        assert!(run.results[0].passed, "{:?}", run.results[0].failures);
+        assert_eq!(
+            run.results[1].failures,
+            vec![r#"Expected state /data/0/state to be "elsewhere", but it was "initial""#]
+        );
+
+        let task_result = user1.client.get_task(task_id).await?;
+        assert!(task_result.tests.0.is_empty());
+
+        // Users who can't write to the task don't get to run its tests.
+        let response = user2.client.put_task_with_tests(task_id, &updated).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        // Otherwise the tests are saved along with the task, as a new version.
+        user1.client.put_task(task_id, &updated).await?;
+        let task_result = user1.client.get_task(task_id).await?;
+        assert_eq!(task_result.tests.0, updated.tests);
+        assert_eq!(task_result.task_template_version, 1);
+
+        let run = user1.client.run_task_tests(task_id).await?;
+        assert!(!run.passed);
+        assert_eq!(run.results.len(), 2);
+
+        updated.tests.pop();
+        let response = user1
+            .client
+            .put_task_with_tests(task_id, &updated)
+            .await?
+            .error_for_status()?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let run = user1.client.run_task_tests(task_id).await?;
+        assert!(run.passed);
+
+        user2
+            .client
+            .run_task_tests(task_id)
+            .await
+            .expect_err("user 2 can not run user 1's task tests");
+
+        Ok(())
+    })
+    .await
+}
+
 #[actix_rt::test]
 async fn update_task_triggers() {
     run_app_test(|app| async move {
@@ -408,6 +618,7 @@ async fn update_task_triggers() {
                 description: None,
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         );
 
@@ -452,6 +663,7 @@ async fn update_task_triggers() {
                 description: Some("A description".to_string()),
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         );
 
@@ -481,6 +693,7 @@ async fn update_task_triggers() {
                 description: Some("A description".to_string()),
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         );
         task2.triggers.insert(
@@ -490,6 +703,7 @@ async fn update_task_triggers() {
                 description: Some("this is another change".to_string()),
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         );
         task2.triggers.insert(
@@ -499,6 +713,7 @@ async fn update_task_triggers() {
                 description: None,
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         );
 
@@ -541,6 +756,7 @@ async fn update_task_actions() {
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         );
         user1
@@ -584,6 +800,7 @@ async fn update_task_actions() {
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         );
         user1
@@ -612,6 +829,7 @@ async fn update_task_actions() {
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         );
         task2.actions.insert(
@@ -621,6 +839,7 @@ async fn update_task_actions() {
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         );
         task2.actions.insert(
@@ -630,6 +849,7 @@ async fn update_task_actions() {
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         );
 
@@ -654,6 +874,323 @@ async fn update_task_actions() {
     .await
 }
 
+#[actix_rt::test]
+async fn update_task_handoffs() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1,
+            user1_tasks,
+            user2_task,
+            ..
+        } = bootstrap_data(&app).await?;
+
+        let task_id = &user1_tasks[0].0.task_id;
+        let target_task_id = &user1_tasks[1].0.task_id;
+        let mut task = user1_tasks[0].1.clone();
+
+        task.handoffs = vec![TaskHandoff {
+            task_id: user2_task.0.task_id.clone(),
+            trigger_id: "run_it".to_string(),
+            fields: [("url".to_string(), "/payload/url".to_string())]
+                .into_iter()
+                .collect(),
+        }];
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect_err("Handoff to a task the user can't trigger should fail");
+
+        task.handoffs = vec![TaskHandoff {
+            task_id: target_task_id.clone(),
+            trigger_id: "no_trigger".to_string(),
+            fields: [("url".to_string(), "/payload/url".to_string())]
+                .into_iter()
+                .collect(),
+        }];
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect_err("Handoff to unknown trigger should fail");
+
+        // The URL input requires the `url` field.
+        task.handoffs = vec![TaskHandoff {
+            task_id: target_task_id.clone(),
+            trigger_id: "run_it".to_string(),
+            fields: [("other".to_string(), "/payload/url".to_string())]
+                .into_iter()
+                .collect(),
+        }];
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect_err("Handoff missing a required field should fail");
+
+        task.handoffs = vec![TaskHandoff {
+            task_id: target_task_id.clone(),
+            trigger_id: "run_it".to_string(),
+            fields: [("url".to_string(), "/payload/url".to_string())]
+                .into_iter()
+                .collect(),
+        }];
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect("Adding handoff");
+
+        let result = user1.client.get_task(task_id).await?;
+        assert_eq!(result.handoffs.0, task.handoffs);
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn update_task_run_limits() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1, user1_tasks, ..
+        } = bootstrap_data(&app).await?;
+
+        let task_id = &user1_tasks[0].0.task_id;
+        let mut task = user1_tasks[0].1.clone();
+        let limit: RunLimit = serde_json::from_value(json!({
+            "max_concurrent": 1,
+            "rate": { "max": 5, "period_secs": 1 },
+        }))?;
+
+        task.run_limit = Some(limit.clone());
+        for action in task.actions.values_mut() {
+            action.run_limit = Some(limit.clone());
+        }
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect("Setting run limits");
+
+        let result = user1.client.get_task(task_id).await?;
+        assert_eq!(result.run_limit.map(|l| l.0), Some(limit.clone()));
+        for action in result.actions.0.values() {
+            assert_eq!(
+                action.run_limit.as_ref(),
+                Some(&limit),
+                "action {}",
+                action.name
+            );
+        }
+
+        task.run_limit = None;
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect("Removing task run limit");
+        let result = user1.client.get_task(task_id).await?;
+        assert!(result.run_limit.is_none());
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn update_task_trigger_priority() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1, user1_tasks, ..
+        } = bootstrap_data(&app).await?;
+
+        let task_id = &user1_tasks[0].0.task_id;
+        let mut task = user1_tasks[0].1.clone();
+        for trigger in task.triggers.values_mut() {
+            trigger.priority = Some(TriggerPriority::High);
+        }
+        user1
+            .client
+            .put_task(task_id, &task)
+            .await
+            .expect("Setting trigger priority");
+
+        let result = user1.client.get_task(task_id).await?;
+        for (local_id, trigger) in &result.triggers.0 {
+            assert_eq!(
+                trigger.priority,
+                Some(TriggerPriority::High),
+                "trigger {}",
+                local_id
+            );
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn export_and_import_task() {
+    run_app_test(|app| async move {
+        let BootstrappedData {
+            user1,
+            user1_tasks,
+            actions,
+            inputs,
+            ..
+        } = bootstrap_data(&app).await?;
+
+        let (task_result, task_input) = &user1_tasks[1];
+        let bundle = user1.client.export_task(&task_result.task_id).await?;
+        assert_eq!(bundle.source_task_id, task_result.task_id);
+        assert_eq!(bundle.task.alias, task_input.alias, "alias");
+        assert_eq!(bundle.task.state, None, "state is not exported");
+        compare_hashmaps!(bundle.task.triggers, task_input.triggers, "triggers");
+        compare_hashmaps!(bundle.task.actions, task_input.actions, "actions");
+        assert_eq!(
+            bundle
+                .actions
+                .iter()
+                .map(|a| a.action_id.clone())
+                .collect::<Vec<_>>(),
+            vec![actions.echo.action_id.clone()],
+            "action definitions"
+        );
+        assert_eq!(
+            bundle
+                .inputs
+                .iter()
+                .map(|i| i.input_id.clone())
+                .collect::<Vec<_>>(),
+            vec![inputs.url.input_id.clone()],
+            "input definitions"
+        );
+        assert_eq!(bundle.action_categories.len(), 1, "action categories");
+
+        let response = user1
+            .client
+            .import_task(&bundle, &TaskImportQuery::default())
+            .await?;
+        assert_eq!(response.status().as_u16(), 409, "alias conflict");
+        let result: TaskImportResult = response.json().await?;
+        assert_eq!(
+            result.conflicts,
+            vec![ImportConflict::AliasInUse {
+                alias: "task_2".to_string()
+            }]
+        );
+
+        let response = user1
+            .client
+            .import_task(
+                &bundle,
+                &TaskImportQuery {
+                    dry_run: true,
+                    force: true,
+                },
+            )
+            .await?;
+        assert_eq!(response.status().as_u16(), 200, "dry run");
+        let result: TaskImportResult = response.json().await?;
+        assert_eq!(result.task_id, None, "dry run does not import");
+        assert_eq!(
+            result.actions,
+            vec![ImportedObject {
+                bundle_id: actions.echo.action_id.clone(),
+                id: actions.echo.action_id.clone(),
+                name: actions.echo.name.clone(),
+                resolution: ImportResolution::Existing,
+            }]
+        );
+
+        let response = user1
+            .client
+            .import_task(
+                &bundle,
+                &TaskImportQuery {
+                    dry_run: false,
+                    force: true,
+                },
+            )
+            .await?;
+        assert_eq!(response.status().as_u16(), 201, "forced import");
+        let result: TaskImportResult = response.json().await?;
+        let new_task_id = result.task_id.expect("task was imported");
+        assert_ne!(new_task_id, task_result.task_id);
+
+        let task = user1.client.get_task(&new_task_id).await?;
+        assert_eq!(task.name, task_input.name);
+        assert_eq!(task.alias, None, "conflicting alias is removed");
+        compare_hashmaps!(task_input.triggers, task.triggers.0, "imported triggers");
+        compare_hashmaps!(task_input.actions, task.actions.0, "imported actions");
+
+        // Make the bundle refer to an action that doesn't exist on this server.
+        let mut new_action_bundle = user1.client.export_task(&task_result.task_id).await?;
+        let new_action_id = ActionId::new();
+        new_action_bundle.actions[0].action_id = new_action_id.clone();
+        new_action_bundle.actions[0].name = "Imported echo".to_string();
+        for action in new_action_bundle.task.actions.values_mut() {
+            action.action_id = new_action_id.clone();
+        }
+        new_action_bundle.task.alias = None;
+
+        let response = user1
+            .client
+            .import_task(
+                &new_action_bundle,
+                &TaskImportQuery {
+                    dry_run: false,
+                    force: true,
+                },
+            )
+            .await?;
+        assert_eq!(
+            response.status().as_u16(),
+            409,
+            "non-admin can not create actions"
+        );
+        let result: TaskImportResult = response.json().await?;
+        assert_eq!(
+            result.conflicts,
+            vec![ImportConflict::MissingAction {
+                action_id: new_action_id.clone(),
+                name: "Imported echo".to_string(),
+            }]
+        );
+
+        let response = app
+            .admin_user
+            .client
+            .import_task(&new_action_bundle, &TaskImportQuery::default())
+            .await?;
+        assert_eq!(response.status().as_u16(), 201, "admin import");
+        let result: TaskImportResult = response.json().await?;
+        assert_eq!(
+            result.actions,
+            vec![ImportedObject {
+                bundle_id: new_action_id.clone(),
+                id: new_action_id.clone(),
+                name: "Imported echo".to_string(),
+                resolution: ImportResolution::Created,
+            }]
+        );
+        assert!(
+            app.admin_user
+                .client
+                .list_actions()
+                .await?
+                .iter()
+                .any(|a| a.action_id == new_action_id),
+            "action was created"
+        );
+
+        Ok(())
+    })
+    .await
+}
+
 #[actix_rt::test]
 async fn list_inputs() {
     run_app_test(|app| async move {
@@ -1,10 +1,16 @@
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, io::Read, time::Duration};
 
 use anyhow::Result;
-use ergo_api::routes::{
-    actions::ActionPayload,
-    inputs::InputPayload,
-    tasks::{InputsLogEntry, TaskActionInput, TaskInput, TaskTriggerInput},
+use ergo_api::{
+    log_retention::{apply_log_retention, RetentionStats},
+    routes::{
+        actions::ActionPayload,
+        inputs::InputPayload,
+        tasks::{
+            InputsLogEntry, LogsQuery, TaskActionInput, TaskInput, TaskSimulateRequest,
+            TaskTriggerInput,
+        },
+    },
 };
 use ergo_database::object_id::{ActionId, InputId, OrgId, TaskId};
 use ergo_tasks::{
@@ -246,6 +252,8 @@ async fn bootstrap_state_machine_task(base: &BootstrappedData) -> (TaskId, TaskI
         enabled: true,
         state: Some(TaskState::StateMachine(smallvec![StateMachineData {
             state: "initial".to_string(),
+            active: Vec::new(),
+            timers: Vec::new(),
             context: json!(null)
         }])),
 
@@ -269,6 +277,7 @@ async fn bootstrap_state_machine_task(base: &BootstrappedData) -> (TaskId, TaskI
                         }])
                     }],
                     description: None,
+                    ..Default::default()
                 }
             )]
             .into_iter()
@@ -282,6 +291,7 @@ async fn bootstrap_state_machine_task(base: &BootstrappedData) -> (TaskId, TaskI
                 action_id: base.script_action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         )]
         .into_iter()
@@ -294,10 +304,14 @@ async fn bootstrap_state_machine_task(base: &BootstrappedData) -> (TaskId, TaskI
                 name: "Run a script".to_string(),
                 description: None,
                 periodic: None,
+                priority: None,
             },
         )]
         .into_iter()
         .collect::<FxHashMap<_, _>>(),
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
     };
 
     let state_machine_task_id = base
@@ -345,6 +359,7 @@ async fn bootstrap_script_task(base: &BootstrappedData) -> (TaskId, TaskInput) {
                 description: None,
                 input_id: base.url_input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         )]
         .into_iter()
@@ -359,10 +374,14 @@ async fn bootstrap_script_task(base: &BootstrappedData) -> (TaskId, TaskInput) {
                     "method".to_string(),
                     serde_json::Value::String("POST".to_string()),
                 )]),
+                run_limit: None,
             },
         )]
         .into_iter()
         .collect(),
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
     };
 
     let script_task_id = base
@@ -443,6 +462,7 @@ async fn bootstrap_dataflow_task(base: &BootstrappedData) -> (TaskId, TaskInput)
                     description: None,
                     input_id: base.url_input_id.clone(),
                     periodic: None,
+                    priority: None,
                 },
             ),
             (
@@ -452,6 +472,7 @@ async fn bootstrap_dataflow_task(base: &BootstrappedData) -> (TaskId, TaskInput)
                     description: None,
                     input_id: base.string_input_id.clone(),
                     periodic: None,
+                    priority: None,
                 },
             ),
         ]
@@ -467,10 +488,14 @@ async fn bootstrap_dataflow_task(base: &BootstrappedData) -> (TaskId, TaskInput)
                     "method".to_string(),
                     serde_json::Value::String("POST".to_string()),
                 )]),
+                run_limit: None,
             },
         )]
         .into_iter()
         .collect(),
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
     };
 
     let dataflow_task_id = base
@@ -576,6 +601,362 @@ async fn state_machine_task() {
     .await
 }
 
+#[actix_rt::test]
+async fn trigger_idempotency_key() {
+    run_app_test(|app| async move {
+        let base = bootstrap(&app).await?;
+        bootstrap_state_machine_task(&base).await;
+        let BootstrappedData { user, .. } = base;
+
+        let script = r##"Ergo.setResult({ value: 5 })"##;
+        let first_log_id = user
+            .client
+            .run_task_trigger_with_idempotency_key(
+                "run_script",
+                "run",
+                "delivery-1",
+                json!({ "script": script }),
+            )
+            .await?
+            .log_id;
+
+        let retry_log_id = user
+            .client
+            .run_task_trigger_with_idempotency_key(
+                "run_script",
+                "run",
+                "delivery-1",
+                json!({ "script": script }),
+            )
+            .await?
+            .log_id;
+        assert_eq!(retry_log_id, first_log_id, "retry returns the first log id");
+
+        let other_log_id = user
+            .client
+            .run_task_trigger_with_idempotency_key(
+                "run_script",
+                "run",
+                "delivery-2",
+                json!({ "script": script }),
+            )
+            .await?
+            .log_id;
+        assert_ne!(other_log_id, first_log_id, "different key runs again");
+
+        wait_for_task_to_finish(&user, &first_log_id).await?;
+        let logs = wait_for_task_to_finish(&user, &other_log_id).await?;
+        assert_eq!(logs.len(), 2, "duplicate delivery does not run again");
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn log_stream() {
+    run_app_test(|app| async move {
+        let base = bootstrap(&app).await?;
+        let (task_id, _) = bootstrap_state_machine_task(&base).await;
+        let BootstrappedData { user, .. } = base;
+
+        let mut stream = user
+            .client
+            .get(format!("logs/stream?task_id={}", task_id))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let script = r##"Ergo.setResult({ value: 5 })"##;
+        let log_id = user
+            .client
+            .run_task_trigger("run_script", "run", json!({ "script": script }))
+            .await?
+            .log_id;
+
+        let mut buffer = String::new();
+        let mut event_types = Vec::new();
+        let read_events = async {
+            while let Some(chunk) = stream.chunk().await? {
+                buffer.push_str(std::str::from_utf8(&chunk)?);
+                while let Some(end) = buffer.find("\n\n") {
+                    let message = buffer[..end].to_string();
+                    buffer.drain(..end + 2);
+
+                    let data = match message.strip_prefix("data: ") {
+                        Some(data) => data,
+                        None => continue,
+                    };
+
+                    let event: serde_json::Value = serde_json::from_str(data)?;
+                    assert_eq!(event["task_id"], json!(task_id.to_string()));
+                    assert_eq!(event["inputs_log_id"], json!(log_id));
+                    if event["type"] == "input_processed" || event["type"] == "action_finished" {
+                        assert_eq!(event["status"], json!("success"));
+                    }
+
+                    event_types.push(event["type"].as_str().unwrap_or_default().to_string());
+                    if event_types.iter().any(|t| t == "input_processed")
+                        && event_types.iter().any(|t| t == "action_finished")
+                    {
+                        return Ok::<_, anyhow::Error>(());
+                    }
+                }
+            }
+
+            Ok(())
+        };
+
+        tokio::time::timeout(Duration::from_secs(20), read_events)
+            .await
+            .expect("timed out waiting for events")?;
+
+        let position = |event_type: &str| event_types.iter().position(|t| t == event_type);
+        assert_eq!(position("input_arrived"), Some(0), "input arrival is first");
+        assert!(
+            matches!(
+                (position("action_started"), position("action_finished")),
+                (Some(started), Some(finished)) if started < finished
+            ),
+            "action start comes before completion, saw {:?}",
+            event_types
+        );
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn log_search() {
+    run_app_test(|app| async move {
+        let base = bootstrap(&app).await?;
+        let (task_id, _) = bootstrap_state_machine_task(&base).await;
+        let BootstrappedData { user, .. } = base;
+
+        let first_script = r##"Ergo.setResult({ value: 5 })"##;
+        let first_log_id = user
+            .client
+            .run_task_trigger("run_script", "run", json!({ "script": first_script }))
+            .await?
+            .log_id;
+        wait_for_task_to_finish(&user, &first_log_id).await?;
+
+        let second_script = r##"Ergo.setResult({ value: 6 })"##;
+        let second_log_id = user
+            .client
+            .run_task_trigger("run_script", "run", json!({ "script": second_script }))
+            .await?
+            .log_id;
+        wait_for_task_to_finish(&user, &second_log_id).await?;
+
+        let (page, cursor) = user
+            .client
+            .search_logs(&LogsQuery {
+                task_id: Some(task_id.clone()),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(page.len(), 1, "first page length");
+        assert_eq!(page[0].inputs_log_id, second_log_id, "newest run first");
+        let cursor = cursor.expect("first page has a cursor");
+
+        let (page, _) = user
+            .client
+            .search_logs(&LogsQuery {
+                task_id: Some(task_id.clone()),
+                limit: Some(1),
+                cursor: Some(cursor),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(page.len(), 1, "second page length");
+        assert_eq!(page[0].inputs_log_id, first_log_id, "second page");
+
+        let (logs, cursor) = user
+            .client
+            .search_logs(&LogsQuery {
+                payload_match: Some(r##"$.script ? (@ like_regex "value: 6")"##.to_string()),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(
+            logs.iter().map(|l| l.inputs_log_id).collect::<Vec<_>>(),
+            vec![second_log_id],
+            "payload match"
+        );
+        assert_eq!(cursor, None, "no cursor on the last page");
+
+        let (logs, _) = user
+            .client
+            .search_logs(&LogsQuery {
+                trigger: Some("run".to_string()),
+                action: Some("run".to_string()),
+                action_status: Some(ActionStatus::Success),
+                status: Some(InputStatus::Success),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(logs.len(), 2, "status and action filters");
+
+        let (logs, _) = user
+            .client
+            .search_logs(&LogsQuery {
+                status: Some(InputStatus::Error),
+                ..Default::default()
+            })
+            .await?;
+        assert!(logs.is_empty(), "no failed runs");
+
+        let response = user
+            .client
+            .get("logs")
+            .query(&[("payload_match", "$.script ? (")])
+            .send()
+            .await?;
+        assert_eq!(response.status().as_u16(), 400, "invalid JSON path");
+
+        let detail = user.client.get_log_detail(&first_log_id).await?;
+        assert_eq!(detail.task_id, task_id);
+        assert_eq!(detail.input_status, InputStatus::Success);
+        assert_eq!(detail.payload, json!({ "script": first_script }));
+        assert_eq!(detail.actions.len(), 1, "detail actions");
+        assert_eq!(detail.actions[0].status, ActionStatus::Success);
+        assert_eq!(
+            detail.actions[0].payload,
+            json!({ "script": first_script }),
+            "action payload"
+        );
+
+        Ok(())
+    })
+    .await
+}
+
+#[actix_rt::test]
+async fn log_retention() {
+    run_app_test(|app| async move {
+        let base = bootstrap(&app).await?;
+        let (task_id, _) = bootstrap_state_machine_task(&base).await;
+        let BootstrappedData { org, user, .. } = base;
+
+        let old_log_id = user
+            .client
+            .run_task_trigger("run_script", "run", json!({ "script": "Ergo.setResult(1)" }))
+            .await?
+            .log_id;
+        wait_for_task_to_finish(&user, &old_log_id).await?;
+
+        let new_log_id = user
+            .client
+            .run_task_trigger("run_script", "run", json!({ "script": "Ergo.setResult(2)" }))
+            .await?
+            .log_id;
+        wait_for_task_to_finish(&user, &new_log_id).await?;
+
+        sqlx::query(
+            "INSERT INTO log_retention_policies (org_id, retain_days, archive) VALUES ($1, 30, true)",
+        )
+        .bind(&org.0)
+        .execute(&app.database.pool)
+        .await?;
+
+        let age = "now() - interval '40 days'";
+        sqlx::query(&format!(
+            "UPDATE inputs_log SET created = {age} WHERE inputs_log_id = $1"
+        ))
+        .bind(&old_log_id)
+        .execute(&app.database.pool)
+        .await?;
+        sqlx::query(&format!(
+            "UPDATE actions_log SET created = {age} WHERE inputs_log_id = $1"
+        ))
+        .bind(&old_log_id)
+        .execute(&app.database.pool)
+        .await?;
+
+        let archive_dir = std::env::temp_dir().join(format!("ergo-logs-{}", Uuid::new_v4()));
+        let mut config = ergo_api::service_config::LogRetentionConfig {
+            archive_dir: None,
+            default_retain_days: None,
+            default_archive: true,
+            check_interval: Duration::from_secs(3600),
+            batch_size: 100,
+        };
+        let pool = ergo_api::service_config::backend_pg_pool(&app.database.config).await?;
+
+        let stats = apply_log_retention(&pool, &config, false).await?;
+        assert_eq!(
+            stats,
+            RetentionStats::default(),
+            "logs to archive are kept without an archive directory"
+        );
+
+        config.archive_dir = Some(archive_dir.clone());
+        let stats = apply_log_retention(&pool, &config, true).await?;
+        assert_eq!(stats.inputs_removed, 1, "dry run count");
+        user.client
+            .get_log_detail(&old_log_id)
+            .await
+            .expect("dry run does not remove logs");
+
+        let stats = apply_log_retention(&pool, &config, false).await?;
+        assert_eq!(
+            stats,
+            RetentionStats {
+                inputs_removed: 1,
+                inputs_archived: 1,
+                actions_removed: 0,
+                actions_archived: 0,
+            }
+        );
+
+        let response = user
+            .client
+            .get(format!("logs/{}", old_log_id))
+            .send()
+            .await?;
+        assert_eq!(response.status().as_u16(), 404, "old log was removed");
+        user.client
+            .get_log_detail(&new_log_id)
+            .await
+            .expect("new log is kept");
+
+        let remaining_actions: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM actions_log WHERE task_id = $1")
+                .bind(&task_id.0)
+                .fetch_one(&app.database.pool)
+                .await?;
+        assert_eq!(remaining_actions, 1, "old actions were removed");
+
+        let mut files = std::fs::read_dir(archive_dir.join(org.to_string()))?
+            .map(|f| f.map(|f| f.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(files.len(), 1, "archive files");
+
+        let mut contents = String::new();
+        flate2::read::MultiGzDecoder::new(std::fs::File::open(files.remove(0))?)
+            .read_to_string(&mut contents)?;
+        let records = contents
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), 1, "archived records");
+        assert_eq!(records[0]["type"], json!("input"));
+        assert_eq!(records[0]["inputs_log_id"], json!(old_log_id));
+        assert_eq!(
+            records[0]["actions"].as_array().map(|a| a.len()),
+            Some(1),
+            "archived actions"
+        );
+
+        std::fs::remove_dir_all(&archive_dir)?;
+        Ok(())
+    })
+    .await
+}
+
 #[actix_rt::test]
 async fn postprocess_script() {
     run_app_test(|app| async move {
@@ -768,6 +1149,55 @@ async fn script_task() {
     .await
 }
 
+#[actix_rt::test]
+async fn simulate_script_task() {
+    run_app_test(|app| async move {
+        let base = bootstrap(&app).await.expect("bootstrapping app");
+        let (script_task_id, script_task) = bootstrap_script_task(&base).await;
+        let BootstrappedData { user, .. } = base;
+
+        let url = "http://localhost:1/a_url";
+        let mut request = TaskSimulateRequest {
+            trigger_id: "request_url".to_string(),
+            payload: json!({ "url": url }),
+            config: None,
+            state: None,
+        };
+
+        let result = user.client.simulate_task(&script_task_id, &request).await?;
+        assert!(result.state_changed);
+        assert_eq!(result.actions.len(), 1);
+        let action = &result.actions[0];
+        assert_eq!(action.task_action_local_id, "send");
+        assert_eq!(action.error, None);
+        let rendered = action.rendered.as_ref().expect("action was rendered");
+        assert_eq!(rendered.get("url"), Some(&json!(url)));
+        assert_eq!(rendered.get("json"), Some(&json!({ "value": 2 })));
+
+        // Nothing was saved or run.
+        let task = user.client.get_task(&script_task_id).await?;
+        assert_eq!(task.state.0, script_task.state.unwrap());
+        assert!(user.client.get_recent_logs().await?.is_empty());
+
+        // Simulate with a different starting state.
+        request.state = Some(TaskState::Js(TaskJsState {
+            context: "{value:5}".to_string(),
+        }));
+        let result = user.client.simulate_task(&script_task_id, &request).await?;
+        let rendered = result.actions[0].rendered.as_ref().unwrap();
+        assert_eq!(rendered.get("json"), Some(&json!({ "value": 6 })));
+
+        request.trigger_id = "no_such_trigger".to_string();
+        user.client
+            .simulate_task(&script_task_id, &request)
+            .await
+            .expect_err("simulating a trigger that does not exist");
+
+        Ok(())
+    })
+    .await
+}
+
 #[actix_rt::test]
 async fn dataflow_task() {
     run_app_test(|app| async move {
@@ -95,13 +95,14 @@ pub fn simple_state_machine() -> (TaskConfig, TaskState) {
     let machine = StateMachine {
         name: "a sample machine".to_string(),
         description: None,
-        initial: "start".to_string(),
+        initial: "initial".to_string(),
         on: smallvec![],
         states: std::array::IntoIter::new([(
             "initial".to_string(),
             StateDefinition {
                 on: smallvec![],
                 description: None,
+                ..Default::default()
             },
         )])
         .collect::<FxHashMap<_, _>>(),
@@ -109,6 +110,8 @@ pub fn simple_state_machine() -> (TaskConfig, TaskState) {
 
     let state = StateMachineData {
         state: "initial".to_string(),
+        active: Vec::new(),
+        timers: Vec::new(),
         context: json!(null),
     };
 
@@ -127,6 +130,7 @@ pub fn simple_task_actions(actions: &BootstrappedActions) -> FxHashMap<String, T
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         ),
         (
@@ -136,6 +140,7 @@ pub fn simple_task_actions(actions: &BootstrappedActions) -> FxHashMap<String, T
                 action_id: actions.echo.action_id.clone(),
                 account_id: None,
                 action_template: None,
+                run_limit: None,
             },
         ),
     ]
@@ -152,6 +157,7 @@ pub fn simple_task_triggers(inputs: &BootstrappedInputs) -> FxHashMap<String, Ta
                 description: Some("Run the task and do something".to_string()),
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         ),
         (
@@ -161,6 +167,7 @@ pub fn simple_task_triggers(inputs: &BootstrappedInputs) -> FxHashMap<String, Ta
                 description: None,
                 input_id: inputs.url.input_id.clone(),
                 periodic: None,
+                priority: None,
             },
         ),
     ]
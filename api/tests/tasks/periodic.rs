@@ -1,12 +1,17 @@
 use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
 use ergo_api::routes::tasks::{NewTaskResult, TaskInput};
 use ergo_database::{object_id::OrgId, RedisPool};
-use ergo_tasks::{inputs::queue::InputQueue, PeriodicSchedule, PeriodicTaskTriggerInput};
+use ergo_notifications::NotifyEvent;
+use ergo_tasks::{
+    inputs::queue::InputQueue, periodic::enqueue_missing_periodic_triggers, PeriodicSchedule,
+    PeriodicTaskTriggerInput,
+};
 use ergo_test::wait_for;
 use serde_json::json;
 
 use crate::{
     common::{run_app_test, TestApp, TestUser},
+    notifications::{listen_for_event, next_notification, notification_manager},
     tasks::{simple_state_machine, simple_task_actions},
 };
 
@@ -68,6 +73,9 @@ async fn bootstrap_data(app: &TestApp) -> BootstrappedData {
         source: serde_json::Value::Null,
         actions: simple_task_actions(&actions),
         triggers,
+        handoffs: Vec::new(),
+        tests: Vec::new(),
+        run_limit: None,
     };
 
     let task = user
@@ -360,3 +368,63 @@ async fn invalid_payload() {}
 #[actix_rt::test]
 #[ignore]
 async fn invalid_schedule() {}
+
+#[actix_rt::test]
+async fn missed_periodic_trigger_notification() {
+    run_app_test(|app| async move {
+        let receiver = listen_for_event(&app, NotifyEvent::PeriodicTriggerMissed).await?;
+        let (inputs, actions) = bootstrap_inputs_and_actions(&app).await;
+        let (config, state) = simple_state_machine();
+
+        let mut triggers = simple_task_triggers(&inputs);
+        let schedule_date = Utc::now() + Duration::days(2);
+        triggers.get_mut("run_it").unwrap().periodic = Some(vec![PeriodicTaskTriggerInput {
+            name: None,
+            enabled: true,
+            payload: json!({ "url": "https://abc.com/" }),
+            schedule: cron_for_date(&schedule_date),
+        }]);
+
+        let task_input = TaskInput {
+            name: "periodic task".to_string(),
+            alias: None,
+            description: None,
+            enabled: true,
+            compiled: config,
+            state: Some(state),
+            source: serde_json::Value::Null,
+            actions: simple_task_actions(&actions),
+            triggers,
+            handoffs: Vec::new(),
+            tests: Vec::new(),
+            run_limit: None,
+        };
+        let task_id = app.admin_user.client.new_task(&task_input).await?.task_id;
+
+        // Lose the scheduled run, as if it had been dropped from the queue.
+        sqlx::query("DELETE FROM inputs_log WHERE task_id=$1 AND status='pending'")
+            .bind(task_id.0)
+            .execute(&app.database.pool)
+            .await?;
+
+        let (notifications, _shutdown) = notification_manager(&app)?;
+        enqueue_missing_periodic_triggers(
+            &app.database.pool,
+            Some(&notifications),
+            Some(&app.redis_key_prefix),
+        )
+        .await?;
+
+        let body = next_notification(&receiver).await?;
+        assert_eq!(body["event"], json!("periodic_trigger_missed"));
+        assert_eq!(body["task_id"], json!(task_id));
+        assert_eq!(body["local_id"], json!("run_it"));
+        assert_eq!(
+            body["payload"]["schedule"],
+            serde_json::to_value(cron_for_date(&schedule_date))?
+        );
+
+        Ok(())
+    })
+    .await;
+}
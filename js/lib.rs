@@ -46,6 +46,8 @@ pub enum Error {
     Deserialize(#[from] serde_v8::Error),
     #[error("JS error: {0}")]
     Runtime(#[from] deno_core::error::AnyError),
+    #[error("Importing {0} is not supported")]
+    UnsupportedImport(String),
 }
 
 impl Error {
@@ -409,6 +411,69 @@ impl Runtime {
         Some(scope.escape(object))
     }
 
+    /// Compile a script or module without running it, to check it for syntax errors.
+    /// Modules are also checked for imports, since the runtime can not load them.
+    pub fn check_syntax(&mut self, name: &str, source: &str, is_module: bool) -> Result<(), Error> {
+        let mut scope = self.runtime.handle_scope();
+        let mut tc_scope = v8::TryCatch::new(&mut scope);
+
+        let code = v8::String::new(&mut tc_scope, source)
+            .ok_or_else(|| Error::Runtime(anyhow::anyhow!("{name} is too large to compile")))?;
+        let resource_name = v8::String::new(&mut tc_scope, name).unwrap();
+        let source_map_url = v8::undefined(&mut tc_scope);
+        let origin = v8::ScriptOrigin::new(
+            &mut tc_scope,
+            resource_name.into(),
+            0,
+            0,
+            false,
+            0,
+            source_map_url.into(),
+            false,
+            false,
+            is_module,
+        );
+
+        let compiled = if is_module {
+            let source = v8::script_compiler::Source::new(code, Some(&origin));
+            match v8::script_compiler::compile_module(&mut tc_scope, source) {
+                Some(module) => {
+                    let requests = module.get_module_requests();
+                    if requests.length() > 0 {
+                        let specifier = requests
+                            .get(&mut tc_scope, 0)
+                            .and_then(|request| {
+                                v8::Local::<v8::ModuleRequest>::try_from(request).ok()
+                            })
+                            .map(|request| {
+                                request.get_specifier().to_rust_string_lossy(&mut tc_scope)
+                            })
+                            .unwrap_or_default();
+                        return Err(Error::UnsupportedImport(specifier));
+                    }
+
+                    true
+                }
+                None => false,
+            }
+        } else {
+            v8::Script::compile(&mut tc_scope, code, Some(&origin)).is_some()
+        };
+
+        if compiled {
+            return Ok(());
+        }
+
+        match tc_scope.exception() {
+            Some(exception) => {
+                let js_error =
+                    deno_core::error::JsError::from_v8_exception(&mut tc_scope, exception);
+                Err(Error::Runtime(js_error.into()))
+            }
+            None => Err(Error::Runtime(anyhow::anyhow!("Failed to compile {name}"))),
+        }
+    }
+
     pub async fn run_main_module(&mut self, url: Url, source: String) -> Result<(), Error> {
         let mod_id = self.load_main_module(&url, Some(source)).await?;
         let mod_done = self.mod_evaluate(mod_id);
@@ -515,6 +580,68 @@ mod tests {
         });
     }
 
+    mod check_syntax {
+        use super::*;
+
+        fn syntax_runtime() -> Runtime {
+            Runtime::new(RuntimeOptions {
+                extensions: core_extensions(None),
+                ..Default::default()
+            })
+        }
+
+        #[test]
+        fn valid_script() {
+            let mut runtime = syntax_runtime();
+            runtime
+                .check_syntax("script.js", "let x = 5; x + 1", false)
+                .expect("valid script");
+        }
+
+        #[test]
+        fn script_syntax_error() {
+            let mut runtime = syntax_runtime();
+            let err = runtime
+                .check_syntax("script.js", "let x = ;", false)
+                .expect_err("invalid script");
+            assert!(matches!(err, Error::Runtime(_)), "{err:?}");
+        }
+
+        #[test]
+        fn script_is_not_run() {
+            let mut runtime = syntax_runtime();
+            runtime
+                .check_syntax("script.js", "globalThis.ran = true;", false)
+                .expect("valid script");
+            let ran: Option<bool> = runtime.get_global_value("ran").unwrap();
+            assert_eq!(ran, None);
+        }
+
+        #[test]
+        fn valid_module() {
+            let mut runtime = syntax_runtime();
+            runtime
+                .check_syntax("https://ergo/module.js", "export const x = 5;", true)
+                .expect("valid module");
+        }
+
+        #[test]
+        fn module_import() {
+            let mut runtime = syntax_runtime();
+            let err = runtime
+                .check_syntax(
+                    "https://ergo/module.js",
+                    "import { x } from './other.js';\nexport const y = x;",
+                    true,
+                )
+                .expect_err("import");
+            assert!(
+                matches!(&err, Error::UnsupportedImport(s) if s == "./other.js"),
+                "{err:?}"
+            );
+        }
+    }
+
     mod run_expression {
         use super::*;
         use serde::Deserialize;
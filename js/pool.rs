@@ -4,10 +4,27 @@ use futures::{
     future::{ready, FutureExt},
     Future,
 };
+use prometheus::{register_int_gauge, IntGauge};
 use tokio::{sync::oneshot, time::error::Elapsed};
 
 lazy_static::lazy_static! {
     static ref NUM_CPUS : usize = num_cpus::get();
+
+    static ref POOL_THREADS: IntGauge = register_int_gauge!(
+        "ergo_js_pool_threads",
+        "Number of worker threads in JS runtime pools"
+    )
+    .unwrap();
+    static ref POOL_QUEUED_JOBS: IntGauge = register_int_gauge!(
+        "ergo_js_pool_queued_jobs",
+        "Jobs waiting for a JS runtime pool worker"
+    )
+    .unwrap();
+    static ref POOL_RUNNING_JOBS: IntGauge = register_int_gauge!(
+        "ergo_js_pool_running_jobs",
+        "Jobs currently running in a JS runtime pool"
+    )
+    .unwrap();
 }
 
 #[async_trait::async_trait]
@@ -66,6 +83,7 @@ impl RuntimePool {
         let threads = itertools::repeat_n(r, num_threads)
             .map(|r| std::thread::spawn(|| worker(r)))
             .collect::<Vec<_>>();
+        POOL_THREADS.add(num_threads as i64);
 
         Self(Arc::new(RuntimePoolInner { sender: s, threads }))
     }
@@ -73,11 +91,13 @@ impl RuntimePool {
     /// Shut down the pool and wait for all the threads to finish processing the remaining jobs.
     pub async fn close(self, timeout: Option<tokio::time::Duration>) -> Result<(), Elapsed> {
         let RuntimePoolInner { sender, threads } = Arc::try_unwrap(self.0).unwrap();
+        let num_threads = threads.len() as i64;
         let stop = tokio::task::spawn_blocking(move || {
             drop(sender);
             for t in threads {
                 t.join();
             }
+            POOL_THREADS.sub(num_threads);
         });
 
         match timeout {
@@ -103,6 +123,7 @@ impl RuntimePool {
             data: Some((Box::new(run_fn), s)),
         };
 
+        POOL_QUEUED_JOBS.inc();
         self.0.sender.send(Box::new(job)).await;
         r.await.unwrap()
     }
@@ -117,8 +138,11 @@ fn worker(r: async_channel::Receiver<Box<dyn AnyJob>>) {
         let local_set = tokio::task::LocalSet::new();
         local_set.spawn_local(async move {
             while let Ok(mut job) = r.recv().await {
+                POOL_QUEUED_JOBS.dec();
+                POOL_RUNNING_JOBS.inc();
                 tokio::task::spawn_local(async move {
                     job.run().await;
+                    POOL_RUNNING_JOBS.dec();
                 });
             }
         });
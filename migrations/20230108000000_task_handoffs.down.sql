@@ -0,0 +1,2 @@
+ALTER TABLE inputs_log DROP COLUMN parent_inputs_log_id;
+ALTER TABLE tasks DROP COLUMN handoffs;
@@ -0,0 +1,5 @@
+ALTER TABLE tasks ADD COLUMN handoffs jsonb not null default '[]'::jsonb;
+COMMENT ON COLUMN tasks.handoffs IS 'Triggers on other tasks that receive data when this task processes an input';
+
+ALTER TABLE inputs_log ADD COLUMN parent_inputs_log_id uuid REFERENCES inputs_log ON DELETE SET NULL;
+CREATE INDEX ON inputs_log (parent_inputs_log_id) WHERE parent_inputs_log_id IS NOT NULL;
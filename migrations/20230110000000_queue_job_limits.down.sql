@@ -0,0 +1,4 @@
+ALTER TABLE queue_stage DROP COLUMN limit_rate_period;
+ALTER TABLE queue_stage DROP COLUMN limit_rate;
+ALTER TABLE queue_stage DROP COLUMN limit_concurrency;
+ALTER TABLE queue_stage DROP COLUMN limit_key;
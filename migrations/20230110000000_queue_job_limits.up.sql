@@ -0,0 +1,4 @@
+ALTER TABLE queue_stage ADD COLUMN limit_key text;
+ALTER TABLE queue_stage ADD COLUMN limit_concurrency int;
+ALTER TABLE queue_stage ADD COLUMN limit_rate int;
+ALTER TABLE queue_stage ADD COLUMN limit_rate_period int;
@@ -0,0 +1,2 @@
+-- 0 is high priority, 1 is normal, and 2 is low.
+ALTER TABLE queue_stage ADD COLUMN priority int not null default 1;
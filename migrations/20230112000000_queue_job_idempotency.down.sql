@@ -0,0 +1,3 @@
+DROP INDEX inputs_log_queue_job_id_idx;
+ALTER TABLE queue_stage DROP COLUMN idempotency_window;
+ALTER TABLE queue_stage DROP COLUMN idempotency_mode;
@@ -0,0 +1,5 @@
+ALTER TABLE queue_stage ADD COLUMN idempotency_mode text;
+ALTER TABLE queue_stage ADD COLUMN idempotency_window int;
+
+-- Used to find earlier inputs sent with the same idempotency key.
+CREATE INDEX inputs_log_queue_job_id_idx ON inputs_log (queue_job_id);
@@ -0,0 +1,4 @@
+BEGIN;
+DROP INDEX IF EXISTS inputs_log_created_idx;
+DROP INDEX IF EXISTS inputs_log_task_created_idx;
+COMMIT;
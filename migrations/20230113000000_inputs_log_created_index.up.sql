@@ -0,0 +1,4 @@
+BEGIN;
+CREATE INDEX inputs_log_created_idx ON inputs_log(created, inputs_log_id);
+CREATE INDEX inputs_log_task_created_idx ON inputs_log(task_id, created);
+COMMIT;
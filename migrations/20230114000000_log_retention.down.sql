@@ -0,0 +1,6 @@
+BEGIN;
+DROP INDEX IF EXISTS actions_log_standalone_created_idx;
+REVOKE DELETE ON actions_log FROM ergo_backend;
+REVOKE DELETE ON inputs_log FROM ergo_backend;
+DROP TABLE log_retention_policies;
+COMMIT;
@@ -0,0 +1,26 @@
+BEGIN;
+CREATE TABLE log_retention_policies (
+  log_retention_policy_id bigint primary key generated always as identity,
+  org_id uuid not null references orgs ON DELETE CASCADE,
+  task_id uuid references tasks ON DELETE CASCADE,
+  retain_days int not null CHECK (retain_days > 0),
+  archive boolean not null default true,
+  created timestamptz not null default now(),
+  updated timestamptz not null default now()
+);
+
+COMMENT ON TABLE log_retention_policies IS 'How long to keep input and action logs. A policy with a task_id overrides the org policy for that task.';
+COMMENT ON COLUMN log_retention_policies.archive IS 'Write expired logs to the archive before deleting them';
+
+CREATE UNIQUE INDEX log_retention_policies_org_idx ON log_retention_policies(org_id) WHERE task_id IS NULL;
+CREATE UNIQUE INDEX log_retention_policies_task_idx ON log_retention_policies(task_id) WHERE task_id IS NOT NULL;
+
+GRANT SELECT ON log_retention_policies TO ergo_backend;
+GRANT SELECT, UPDATE, DELETE, INSERT ON log_retention_policies TO ergo_web;
+
+GRANT DELETE ON inputs_log TO ergo_backend;
+GRANT DELETE ON actions_log TO ergo_backend;
+
+-- Actions that ran without an input are expired separately from inputs.
+CREATE INDEX actions_log_standalone_created_idx ON actions_log(created) WHERE inputs_log_id IS NULL;
+COMMIT;
@@ -0,0 +1,3 @@
+BEGIN;
+ALTER TABLE task_templates DROP COLUMN created_by;
+COMMIT;
@@ -0,0 +1,3 @@
+BEGIN;
+ALTER TABLE task_templates ADD COLUMN created_by uuid REFERENCES users ON DELETE SET NULL;
+COMMIT;
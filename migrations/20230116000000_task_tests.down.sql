@@ -0,0 +1,3 @@
+BEGIN;
+ALTER TABLE task_templates DROP COLUMN tests;
+COMMIT;
@@ -0,0 +1,3 @@
+BEGIN;
+ALTER TABLE task_templates ADD COLUMN tests jsonb NOT NULL DEFAULT '[]'::jsonb;
+COMMIT;
@@ -0,0 +1,7 @@
+BEGIN;
+DELETE FROM notify_listeners WHERE notify_endpoint_id IN
+  (SELECT notify_endpoint_id FROM notify_endpoints WHERE service = 'webhook');
+DELETE FROM notify_endpoints WHERE service = 'webhook';
+ALTER TABLE notify_endpoints DROP COLUMN config;
+-- Postgres can't remove a value from an enum, so 'webhook' stays in notify_service.
+COMMIT;
@@ -0,0 +1,4 @@
+ALTER TYPE notify_service ADD VALUE IF NOT EXISTS 'webhook';
+
+ALTER TABLE notify_endpoints ADD COLUMN config jsonb;
+COMMENT ON COLUMN notify_endpoints.config is 'Settings for services that need more than a destination, such as the secret and headers for a webhook';
@@ -0,0 +1 @@
+ALTER TABLE notify_listeners DROP COLUMN template;
@@ -0,0 +1,2 @@
+ALTER TABLE notify_listeners ADD COLUMN template jsonb;
+COMMENT ON COLUMN notify_listeners.template is 'A Handlebars template for the message, used instead of the default list of fields';
@@ -0,0 +1,4 @@
+DROP TABLE notify_rate_limits;
+DROP TABLE notify_batches;
+DROP TYPE notify_batch_kind;
+ALTER TABLE notify_listeners DROP COLUMN policy;
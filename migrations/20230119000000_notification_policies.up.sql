@@ -0,0 +1,33 @@
+ALTER TABLE notify_listeners ADD COLUMN policy jsonb;
+COMMENT ON COLUMN notify_listeners.policy is 'Rate limit, error collapsing, and digest settings for the listener';
+
+CREATE TYPE notify_batch_kind AS ENUM (
+  'collapse',
+  'rate_limit',
+  'digest'
+);
+
+CREATE TABLE notify_batches (
+  notify_listener_id uuid not null references notify_listeners ON DELETE CASCADE,
+  batch_key text not null,
+  kind notify_batch_kind not null,
+  count int not null,
+  notifications jsonb not null,
+  send_at timestamptz not null,
+  PRIMARY KEY (notify_listener_id, batch_key)
+);
+
+GRANT SELECT, INSERT, DELETE, UPDATE ON notify_batches TO ergo_web;
+GRANT SELECT, INSERT, DELETE, UPDATE ON notify_batches TO ergo_backend;
+
+COMMENT ON TABLE notify_batches is 'Notifications held by a listener policy, to be sent as one message at send_at';
+COMMENT ON COLUMN notify_batches.count is 'The number of notifications added to the batch, including any not kept in notifications';
+
+CREATE TABLE notify_rate_limits (
+  notify_listener_id uuid primary key references notify_listeners ON DELETE CASCADE,
+  window_start timestamptz not null,
+  count int not null
+);
+
+GRANT SELECT, INSERT, DELETE, UPDATE ON notify_rate_limits TO ergo_web;
+GRANT SELECT, INSERT, DELETE, UPDATE ON notify_rate_limits TO ergo_backend;
@@ -0,0 +1,6 @@
+BEGIN;
+DELETE FROM notify_listeners WHERE event IN
+  ('input_error', 'state_changed', 'task_disabled', 'periodic_trigger_missed', 'account_expiring', 'custom');
+ALTER TABLE accounts DROP COLUMN expiry_notified_for;
+-- Postgres can't remove a value from an enum, so the new values stay in notify_event.
+COMMIT;
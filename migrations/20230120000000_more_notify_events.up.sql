@@ -0,0 +1,10 @@
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'input_error';
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'state_changed';
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'task_disabled';
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'periodic_trigger_missed';
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'account_expiring';
+ALTER TYPE notify_event ADD VALUE IF NOT EXISTS 'custom';
+
+ALTER TABLE accounts ADD COLUMN expiry_notified_for timestamptz;
+COMMENT ON COLUMN accounts.expiry_notified_for is 'The value of expires when the account_expiring notification was last sent';
+GRANT UPDATE(expiry_notified_for) ON accounts TO ergo_backend;
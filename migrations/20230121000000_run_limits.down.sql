@@ -0,0 +1,2 @@
+ALTER TABLE task_actions DROP COLUMN run_limit;
+ALTER TABLE tasks DROP COLUMN run_limit;
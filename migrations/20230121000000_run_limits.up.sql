@@ -0,0 +1,5 @@
+ALTER TABLE tasks ADD COLUMN run_limit jsonb;
+COMMENT ON COLUMN tasks.run_limit IS 'Concurrency and rate limits on processing the task''s inputs';
+
+ALTER TABLE task_actions ADD COLUMN run_limit jsonb;
+COMMENT ON COLUMN task_actions.run_limit IS 'Concurrency and rate limits on running the action';
@@ -0,0 +1,2 @@
+ALTER TABLE task_triggers DROP COLUMN priority;
+DROP TYPE trigger_priority;
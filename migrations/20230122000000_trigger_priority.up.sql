@@ -0,0 +1,8 @@
+CREATE TYPE trigger_priority AS ENUM (
+  'high',
+  'normal',
+  'low'
+);
+
+ALTER TABLE task_triggers ADD COLUMN priority trigger_priority;
+COMMENT ON COLUMN task_triggers.priority IS 'The queue priority of inputs sent to the trigger. NULL uses the default for the input source';
@@ -0,0 +1,2 @@
+ALTER TABLE accounts ADD COLUMN run_limit jsonb;
+COMMENT ON COLUMN accounts.run_limit IS 'Concurrency and rate limits shared by every action run that uses the account';
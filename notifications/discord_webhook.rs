@@ -1,4 +1,4 @@
-use super::{Error, Level, Notification};
+use super::{Error, Level, Notification, RenderedMessage};
 
 use serde_json::json;
 
@@ -15,29 +15,45 @@ pub async fn send_discord_webhook(
     client: &reqwest::Client,
     hook: &str,
     notification: &Notification,
+    message: Option<&RenderedMessage>,
 ) -> Result<(), Error> {
     let desc = notification.event.description();
-    let fields = notification
-        .fields()
-        .into_iter()
-        .map(|(name, value, inline)| {
+    let color = color(notification.event.level());
+
+    let payload = match message {
+        Some(message) => json!({
+            "content": message.subject.as_deref().unwrap_or(desc),
+            "embeds": [
+                {
+                    "color": color,
+                    "description": message.body,
+                }
+            ]
+        }),
+        None => {
+            let fields = notification
+                .fields()
+                .into_iter()
+                .map(|(name, value, inline)| {
+                    json!({
+                        "name": name,
+                        "value": value,
+                        "inline": inline,
+                    })
+                })
+                .collect::<Vec<_>>();
+
             json!({
-                "name": name,
-                "value": value,
-                "inline": inline,
+                "content": desc,
+                "embeds": [
+                    {
+                        "color": color,
+                        "fields": fields,
+                    }
+                ]
             })
-        })
-        .collect::<Vec<_>>();
-
-    let payload = json!({
-        "content": desc,
-        "embeds": [
-            {
-                "color": color(notification.event.level()),
-                "fields": fields,
-            }
-        ]
-    });
+        }
+    };
 
     let url = format!("https://discord.com/api/webhooks/{}", hook);
     client.post(&url).json(&payload).send().await?;
@@ -72,7 +88,7 @@ mod tests {
             log_id: Some(uuid::Uuid::new_v4()),
         };
 
-        super::send_discord_webhook(&reqwest::Client::new(), hook.as_str(), &notification)
+        super::send_discord_webhook(&reqwest::Client::new(), hook.as_str(), &notification, None)
             .await
             .expect("Sending notification");
     }
@@ -0,0 +1,301 @@
+use std::time::Duration;
+
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use super::{template::render_notification, Error, Notification, NotifyEvent, RenderedMessage};
+
+pub const DEFAULT_SUBJECT_TEMPLATE: &str = "[Ergo] {{description}}: {{task_name}}";
+pub const DEFAULT_TEXT_TEMPLATE: &str =
+    "{{description}}\n\n{{#each fields}}{{name}}: {{value}}\n{{/each}}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Send everything in plain text. This is only suitable for a local relay or test server.
+    None,
+    /// Connect in plain text and then upgrade the connection with STARTTLS.
+    StartTls,
+    /// Connect with TLS from the start.
+    Tls,
+}
+
+impl std::str::FromStr for SmtpTls {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            _ => Err(Error::ConfigError(format!(
+                "Unknown SMTP TLS mode {}, expected none, starttls, or tls",
+                s
+            ))),
+        }
+    }
+}
+
+/// Handlebars templates for notification emails. These can use the same values as a listener's
+/// [NotifyTemplate](super::NotifyTemplate). Values are HTML-escaped in the HTML template.
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+    pub subject: String,
+    pub text: String,
+    /// If set, emails are sent with both a plain text and an HTML version.
+    pub html: Option<String>,
+}
+
+impl EmailTemplates {
+    /// Check that each template renders.
+    pub fn validate(&self) -> Result<(), Error> {
+        let sample = Notification::sample(NotifyEvent::ActionSuccess);
+        render_notification(&self.subject, &sample, false)?;
+        render_notification(&self.text, &sample, false)?;
+        if let Some(html) = self.html.as_ref() {
+            render_notification(html, &sample, true)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for EmailTemplates {
+    fn default() -> Self {
+        EmailTemplates {
+            subject: DEFAULT_SUBJECT_TEMPLATE.to_string(),
+            text: DEFAULT_TEXT_TEMPLATE.to_string(),
+            html: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The address that notifications are sent from.
+    pub from: String,
+    pub templates: EmailTemplates,
+}
+
+/// Sends notification emails through an SMTP server.
+#[derive(Clone)]
+pub struct EmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    templates: EmailTemplates,
+}
+
+impl EmailSender {
+    pub fn new(config: EmailConfig) -> Result<EmailSender, Error> {
+        config.templates.validate()?;
+
+        let builder = match config.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.host.as_str())
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(config.host.as_str())?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(config.host.as_str())?,
+        };
+
+        let builder = builder
+            .port(config.port)
+            .timeout(Some(Duration::from_secs(30)));
+        let builder = match (config.username, config.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username, password))
+            }
+            _ => builder,
+        };
+
+        Ok(EmailSender {
+            transport: builder.build(),
+            from: config.from.parse()?,
+            templates: config.templates,
+        })
+    }
+
+    /// Send a notification to `to`, which may contain multiple comma-separated addresses. A
+    /// message rendered from a listener's template is sent as plain text, in place of the
+    /// configured templates.
+    pub async fn send(
+        &self,
+        to: &str,
+        notification: &Notification,
+        message: Option<&RenderedMessage>,
+    ) -> Result<(), Error> {
+        let message = self.build_message(to, notification, message)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+
+    fn build_message(
+        &self,
+        to: &str,
+        notification: &Notification,
+        message: Option<&RenderedMessage>,
+    ) -> Result<Message, Error> {
+        let subject = match message.and_then(|m| m.subject.as_ref()) {
+            Some(subject) => subject.clone(),
+            None => render_notification(&self.templates.subject, notification, false)?,
+        };
+        let text = match message {
+            Some(message) => message.body.clone(),
+            None => render_notification(&self.templates.text, notification, false)?,
+        };
+        let html = match message {
+            Some(_) => None,
+            None => self.templates.html.as_ref(),
+        };
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            // Newlines in a header would be rejected, and the subject only needs one line.
+            .subject(subject.lines().next().unwrap_or_default());
+        for address in to.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            builder = builder.to(address.parse()?);
+        }
+
+        let message = match html {
+            Some(html) => {
+                let html = render_notification(html, notification, true)?;
+                builder.multipart(MultiPart::alternative_plain_html(text, html))?
+            }
+            None => builder.header(ContentType::TEXT_PLAIN).body(text)?,
+        };
+
+        Ok(message)
+    }
+}
+
+/// Check that a comma-separated list of addresses can be parsed.
+pub fn validate_addresses(to: &str) -> Result<(), Error> {
+    for address in to.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        address.parse::<Mailbox>()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_database::object_id::TaskId;
+    use serde_json::json;
+
+    use super::*;
+    use crate::NotifyEvent;
+
+    fn notification() -> Notification {
+        Notification {
+            event: NotifyEvent::ActionSuccess,
+            task_id: TaskId::new(),
+            task_name: "a <test> task".to_string(),
+            local_id: "the local id".to_string(),
+            local_object_name: "send email".to_string(),
+            local_object_id: None,
+            payload: Some(json!({ "payload_value": 5})),
+            error: None,
+            log_id: None,
+        }
+    }
+
+    fn config(host: &str, port: u16) -> EmailConfig {
+        EmailConfig {
+            host: host.to_string(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "Ergo <ergo@example.com>".to_string(),
+            templates: EmailTemplates::default(),
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let n = notification();
+        assert_eq!(
+            render_notification(DEFAULT_SUBJECT_TEMPLATE, &n, false).unwrap(),
+            "[Ergo] Action Finished: a <test> task"
+        );
+        assert_eq!(
+            render_notification(DEFAULT_TEXT_TEMPLATE, &n, false).unwrap(),
+            "Action Finished\n\nTask: a <test> task\nAction: send email\nPayload: {\"payload_value\":5}\n"
+        );
+        assert_eq!(
+            render_notification("<h1>{{task_name}}</h1><p>{{level}}</p>", &n, true).unwrap(),
+            "<h1>a &lt;test&gt; task</h1><p>info</p>"
+        );
+
+        let mut config = config("localhost", 25);
+        config.templates.html = Some("{{#each fields}}".to_string());
+        assert!(EmailSender::new(config).is_err(), "invalid template");
+    }
+
+    #[tokio::test]
+    async fn builds_message() {
+        let mut config = config("localhost", 25);
+        config.templates.html = Some("<p>{{object_type}} {{local_object_name}}</p>".to_string());
+        let sender = EmailSender::new(config).unwrap();
+
+        let message = sender
+            .build_message("a@example.com, b@example.com", &notification(), None)
+            .unwrap();
+        let to = message
+            .envelope()
+            .to()
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(to, vec!["a@example.com", "b@example.com"]);
+
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("Subject: [Ergo] Action Finished: a <test> task"));
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("<p>Action send email</p>"));
+
+        sender
+            .build_message("not an address", &notification(), None)
+            .expect_err("invalid address");
+
+        let custom = RenderedMessage {
+            subject: Some("Custom subject".to_string()),
+            body: "Custom body".to_string(),
+        };
+        let message = sender
+            .build_message("a@example.com", &notification(), Some(&custom))
+            .unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("Subject: Custom subject"));
+        assert!(formatted.contains("Custom body"));
+        assert!(!formatted.contains("multipart/alternative"));
+    }
+
+    /// Send to a local SMTP sink such as MailHog, e.g. `TEST_SMTP_HOST=localhost TEST_SMTP_PORT=1025`.
+    #[tokio::test]
+    async fn sends_notification() {
+        dotenv::dotenv().ok();
+
+        let host = std::env::var("TEST_SMTP_HOST").unwrap_or_else(|_| String::new());
+        if host.is_empty() {
+            return;
+        }
+
+        let port = std::env::var("TEST_SMTP_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(1025);
+
+        EmailSender::new(config(&host, port))
+            .unwrap()
+            .send("someone@example.com", &notification(), None)
+            .await
+            .expect("Sending notification");
+    }
+}
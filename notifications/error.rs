@@ -8,6 +8,33 @@ pub enum Error {
     #[error("Queue Error: {0}")]
     QueueError(#[from] ergo_queues::Error),
 
+    #[error("JSON Error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
     #[error("SQL Error: {0}")]
     SqlError(#[from] sqlx::error::Error),
+
+    #[error("Email Error: {0}")]
+    EmailError(#[from] lettre::error::Error),
+
+    #[error("SMTP Error: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+
+    #[error("Invalid email address: {0}")]
+    EmailAddressError(#[from] lettre::address::AddressError),
+
+    #[error("Invalid notification endpoint: {0}")]
+    InvalidEndpoint(String),
+
+    #[error("Invalid notification policy: {0}")]
+    InvalidPolicy(String),
+
+    #[error("Template Error: {0}")]
+    TemplateError(#[from] handlebars::RenderError),
+
+    #[error("Email notifications are not configured")]
+    EmailNotConfigured,
+
+    #[error("Configuration Error: {0}")]
+    ConfigError(String),
 }
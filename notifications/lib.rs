@@ -1,22 +1,39 @@
 mod discord_webhook;
+mod email;
 mod error;
 mod notification;
+mod policy;
+mod slack_webhook;
+mod template;
+mod webhook;
+pub use email::{EmailConfig, EmailTemplates, SmtpTls};
 pub use error::*;
 pub use notification::*;
+pub use policy::{DigestInterval, NotifyPolicy, RateLimit};
+pub use template::{NotifyTemplate, RenderedMessage};
 use uuid::Uuid;
+pub use webhook::{sign_body, WebhookConfig, EVENT_HEADER, SIGNATURE_HEADER};
 
 use std::{borrow::Cow, sync::Arc};
 
 use smallvec::SmallVec;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 
 use async_trait::async_trait;
 use ergo_database::{PostgresPool, RedisPool};
 use ergo_graceful_shutdown::GracefulShutdownConsumer;
 use ergo_queues::{generic_stage::QueueJob, Queue, QueueJobProcessor};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
 use serde::{Deserialize, Serialize};
 
-use self::discord_webhook::send_discord_webhook;
+use self::{
+    discord_webhook::send_discord_webhook,
+    email::EmailSender,
+    policy::{apply_policy, batch_message, restore_batch, BatchKind, BatchRef, ClaimedBatch},
+    slack_webhook::send_slack_webhook,
+    webhook::send_webhook,
+};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -34,6 +51,15 @@ trait Notifier {
 
 const QUEUE_NAME: &str = "notifications";
 
+lazy_static! {
+    static ref NOTIFICATIONS_SENT: IntCounterVec = register_int_counter_vec!(
+        "ergo_notifications_sent_total",
+        "Notifications sent, by service and outcome",
+        &["service", "outcome"]
+    )
+    .unwrap();
+}
+
 pub struct NotificationManager(Arc<NotificationManagerInner>);
 
 impl Clone for NotificationManager {
@@ -47,19 +73,72 @@ pub struct NotificationManagerInner {
     shutdown: GracefulShutdownConsumer,
     queue: Queue,
     queue_name: String,
+    executor: NotifyExecutor,
+}
+
+/// Where to send a notification, and how to format it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotifyDestination {
+    pub service: NotifyService,
+    pub destination: String,
+    /// Settings for the endpoint, for services that need more than a destination.
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+    /// The listener's message template.
+    #[serde(default)]
+    pub template: Option<NotifyTemplate>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct NotificationJob<'a> {
-    service: NotifyService,
-    destination: String,
+    #[serde(flatten)]
+    target: NotifyDestination,
     notification: Cow<'a, Notification>,
 }
 
-#[derive(sqlx::FromRow)]
-struct ServiceAndDestination {
+/// The jobs in the notifications queue.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum NotifyJob<'a> {
+    Send(NotificationJob<'a>),
+    /// Send the summary of a batch of notifications held by a listener's policy.
+    FlushBatch {
+        flush_batch: BatchRef,
+    },
+}
+
+/// A listener that should receive a notification.
+struct ListenerTarget {
+    notify_listener_id: Uuid,
+    policy: NotifyPolicy,
+    target: NotifyDestination,
+}
+
+/// Check that an endpoint's destination and config make sense for its service.
+pub fn validate_endpoint(
     service: NotifyService,
-    destination: String,
+    destination: &str,
+    config: Option<&serde_json::Value>,
+) -> Result<(), Error> {
+    if destination.trim().is_empty() {
+        return Err(Error::InvalidEndpoint(
+            "The destination must not be empty".to_string(),
+        ));
+    }
+
+    match service {
+        NotifyService::Email => email::validate_addresses(destination),
+        NotifyService::Webhook => {
+            if !destination.starts_with("https://") && !destination.starts_with("http://") {
+                return Err(Error::InvalidEndpoint(
+                    "The destination must be an HTTP or HTTPS URL".to_string(),
+                ));
+            }
+
+            webhook_config(config).map(|_| ())
+        }
+        NotifyService::DiscordIncomingWebhook | NotifyService::SlackIncomingWebhook => Ok(()),
+    }
 }
 
 impl NotificationManager {
@@ -67,7 +146,16 @@ impl NotificationManager {
         pg_pool: PostgresPool,
         redis_pool: RedisPool,
         shutdown: GracefulShutdownConsumer,
+        email: Option<EmailConfig>,
     ) -> Result<NotificationManager, Error> {
+        let executor = NotifyExecutor {
+            pg_pool: pg_pool.clone(),
+            http_client: reqwest::ClientBuilder::new()
+                .timeout(std::time::Duration::from_secs(30))
+                .build()?,
+            email: email.map(EmailSender::new).transpose()?,
+        };
+
         let queue_name = match redis_pool.key_prefix() {
             Some(prefix) => format!("{}-{}", prefix, QUEUE_NAME),
             None => QUEUE_NAME.to_string(),
@@ -79,9 +167,15 @@ impl NotificationManager {
             shutdown,
             queue,
             queue_name,
+            executor,
         })))
     }
 
+    /// The queue that notifications are sent through.
+    pub fn queue(&self) -> &Queue {
+        &self.0.queue
+    }
+
     // Enqueue a notification to be sent
     pub async fn notify(
         &self,
@@ -89,18 +183,48 @@ impl NotificationManager {
         org_id: &uuid::Uuid,
         notification: Notification,
     ) -> Result<(), Error> {
-        let notifications = self.get_notifiers(tx, org_id, &notification).await?;
+        let listeners = self.get_notifiers(tx, org_id, &notification).await?;
 
-        for sd in notifications {
-            let payload = NotificationJob {
-                service: sd.service,
-                destination: sd.destination,
-                notification: Cow::Borrowed(&notification),
+        for ListenerTarget {
+            notify_listener_id,
+            policy,
+            target,
+        } in listeners
+        {
+            // Flushing a batch sends to the same endpoint, so it retries the same way.
+            let max_retries = match target.service {
+                NotifyService::Webhook => Some(
+                    webhook_config(target.config.as_ref())?
+                        .max_retries
+                        .unwrap_or(webhook::DEFAULT_MAX_RETRIES),
+                ),
+                _ => None,
             };
+            let retry_backoff = max_retries.map(|_| webhook::RETRY_BACKOFF);
 
-            QueueJob::new(self.0.queue_name.as_str(), &payload)
-                .enqueue(tx)
-                .await?;
+            let decision = apply_policy(tx, notify_listener_id, &policy, &notification).await?;
+            for (batch, send_at) in decision.flush {
+                let payload = NotifyJob::FlushBatch { flush_batch: batch };
+                let mut job = QueueJob::new(self.0.queue_name.as_str(), &payload);
+                job.run_at = Some(send_at);
+                job.max_retries = max_retries;
+                job.retry_backoff = retry_backoff;
+                job.enqueue(tx).await?;
+            }
+
+            if !decision.send_now {
+                continue;
+            }
+
+            let payload = NotifyJob::Send(NotificationJob {
+                target,
+                notification: Cow::Borrowed(&notification),
+            });
+
+            let mut job = QueueJob::new(self.0.queue_name.as_str(), &payload);
+            job.max_retries = max_retries;
+            job.retry_backoff = retry_backoff;
+            job.enqueue(tx).await?;
         }
         Ok(())
     }
@@ -110,7 +234,7 @@ impl NotificationManager {
         tx: &mut PgConnection,
         org_id: &uuid::Uuid,
         notification: &Notification,
-    ) -> Result<Vec<ServiceAndDestination>, Error> {
+    ) -> Result<Vec<ListenerTarget>, Error> {
         let mut object_ids = SmallVec::<[Uuid; 3]>::new();
         object_ids.push(Uuid::nil());
         object_ids.push(notification.task_id.0);
@@ -118,13 +242,16 @@ impl NotificationManager {
             object_ids.push(object_id);
         }
 
-        let notifications = sqlx::query_as!(
-            ServiceAndDestination,
+        let listeners = sqlx::query!(
             r##"SELECT
-          service AS "service: NotifyService", destination
+          notify_listener_id,
+          policy AS "policy: Json<NotifyPolicy>",
+          service AS "service: NotifyService", destination, config,
+          template AS "template: Json<NotifyTemplate>"
           FROM notify_listeners
           JOIN notify_endpoints USING(notify_endpoint_id, org_id)
-          WHERE org_id=$1 AND object_id = ANY($2) AND event=$3"##,
+          WHERE org_id=$1 AND object_id = ANY($2) AND event=$3
+            AND notify_listeners.enabled AND notify_endpoints.enabled"##,
             org_id,
             object_ids.as_slice(),
             notification.event as _,
@@ -132,7 +259,29 @@ impl NotificationManager {
         .fetch_all(tx)
         .await?;
 
-        Ok(notifications)
+        Ok(listeners
+            .into_iter()
+            .map(|row| ListenerTarget {
+                notify_listener_id: row.notify_listener_id,
+                policy: row.policy.map(|p| p.0).unwrap_or_default(),
+                target: NotifyDestination {
+                    service: row.service,
+                    destination: row.destination,
+                    config: row.config,
+                    template: row.template.map(|t| t.0),
+                },
+            })
+            .collect())
+    }
+
+    /// Send a notification right away instead of going through the queue, so that the caller
+    /// can see any errors. This is used to test endpoints and templates.
+    pub async fn send_now(
+        &self,
+        target: &NotifyDestination,
+        notification: &Notification,
+    ) -> Result<(), Error> {
+        self.0.executor.send(target, notification).await
     }
 
     pub fn start_task_queue_loop(&mut self) -> Result<(), Error> {
@@ -143,45 +292,182 @@ impl NotificationManager {
             self.0.shutdown.clone(),
             None,
             None,
-            NotifyExecutor {
-                pg_pool: self.0.pg_pool.clone(),
-                http_client: reqwest::ClientBuilder::new()
-                    .timeout(std::time::Duration::from_secs(30))
-                    .build()?,
-            },
+            self.0.executor.clone(),
         );
 
         Ok(())
     }
 }
 
+fn webhook_config(config: Option<&serde_json::Value>) -> Result<WebhookConfig, Error> {
+    match config {
+        Some(config) => Ok(serde_json::from_value(config.clone())?),
+        None => Ok(WebhookConfig::default()),
+    }
+}
+
 #[derive(Clone)]
 struct NotifyExecutor {
     pg_pool: PostgresPool,
     http_client: reqwest::Client,
+    email: Option<EmailSender>,
 }
 
-#[async_trait]
-impl QueueJobProcessor for NotifyExecutor {
-    type Payload = NotificationJob<'static>;
-    type Error = Error;
+impl NotifyExecutor {
+    async fn send(
+        &self,
+        target: &NotifyDestination,
+        notification: &Notification,
+    ) -> Result<(), Error> {
+        // Templates are checked when they are saved, but if one still fails, send the default
+        // message rather than nothing.
+        let message = match target.template.as_ref().map(|t| t.render(notification)) {
+            Some(Ok(message)) => Some(message),
+            Some(Err(e)) => {
+                tracing::event!(tracing::Level::WARN, err=%e, "Failed to render notification template");
+                None
+            }
+            None => None,
+        };
+        self.deliver(target, notification, message.as_ref()).await
+    }
 
-    async fn process(
+    async fn deliver(
         &self,
-        _item: &ergo_queues::QueueWorkItem<Self::Payload>,
-        data: Self::Payload,
+        target: &NotifyDestination,
+        notification: &Notification,
+        message: Option<&RenderedMessage>,
     ) -> Result<(), Error> {
-        match &data.service {
-            NotifyService::Email => Ok(()),
-            NotifyService::SlackIncomingWebhook => Ok(()),
+        let result = match target.service {
+            NotifyService::Email => match self.email.as_ref() {
+                Some(email) => email.send(&target.destination, notification, message).await,
+                None => Err(Error::EmailNotConfigured),
+            },
+            NotifyService::Webhook => match webhook_config(target.config.as_ref()) {
+                Ok(config) => {
+                    send_webhook(
+                        &self.http_client,
+                        &target.destination,
+                        &config,
+                        notification,
+                        message,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            },
+            NotifyService::SlackIncomingWebhook => {
+                send_slack_webhook(
+                    &self.http_client,
+                    &target.destination,
+                    notification,
+                    message,
+                )
+                .await
+            }
             NotifyService::DiscordIncomingWebhook => {
                 send_discord_webhook(
                     &self.http_client,
-                    &data.destination,
-                    data.notification.as_ref(),
+                    &target.destination,
+                    notification,
+                    message,
                 )
                 .await
             }
+        };
+
+        let outcome = if result.is_ok() { "success" } else { "error" };
+        NOTIFICATIONS_SENT
+            .with_label_values(&[target.service.as_str(), outcome])
+            .inc();
+
+        result
+    }
+
+    /// Send the summary of a batch, if it has anything to report. The batch is removed before
+    /// it is sent, so that sending doesn't block new notifications from being added to it, and put
+    /// back if sending fails and there are attempts left.
+    async fn flush_batch(&self, batch: &BatchRef, final_attempt: bool) -> Result<(), Error> {
+        let row = sqlx::query!(
+            r##"WITH claimed AS (
+                DELETE FROM notify_batches
+                WHERE notify_listener_id=$1 AND batch_key=$2
+                RETURNING notify_listener_id, kind, count, notifications, send_at
+            )
+            SELECT kind AS "kind!: BatchKind", count AS "count!",
+                notifications AS "notifications!: Json<Vec<Notification>>",
+                send_at AS "send_at!",
+                notify_listeners.enabled AND notify_endpoints.enabled AS "enabled!",
+                service AS "service: NotifyService", destination, config
+            FROM claimed
+            JOIN notify_listeners USING (notify_listener_id)
+            JOIN notify_endpoints USING (notify_endpoint_id, org_id)"##,
+            batch.notify_listener_id,
+            batch.batch_key
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+
+        // The batch may have been deleted along with its listener, or already sent.
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(()),
+        };
+
+        let claimed = ClaimedBatch {
+            kind: row.kind,
+            count: row.count,
+            notifications: row.notifications.0,
+            send_at: row.send_at,
+        };
+        let message = if row.enabled {
+            batch_message(claimed.kind, claimed.count, &claimed.notifications)
+        } else {
+            None
+        };
+        let notification = match claimed.kind {
+            BatchKind::Collapse => claimed.notifications.first(),
+            BatchKind::RateLimit | BatchKind::Digest => claimed.notifications.last(),
+        };
+
+        let result = match (message, notification) {
+            (Some(message), Some(notification)) => {
+                let target = NotifyDestination {
+                    service: row.service,
+                    destination: row.destination,
+                    config: row.config,
+                    template: None,
+                };
+                self.deliver(&target, notification, Some(&message)).await
+            }
+            _ => Ok(()),
+        };
+
+        // Put the batch back to be sent again on the next attempt.
+        if result.is_err() && !final_attempt {
+            let mut conn = self.pg_pool.acquire().await?;
+            restore_batch(&mut conn, batch, &claimed).await?;
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+impl QueueJobProcessor for NotifyExecutor {
+    type Payload = NotifyJob<'static>;
+    type Error = Error;
+
+    async fn process(
+        &self,
+        item: &ergo_queues::QueueWorkItem<Self::Payload>,
+        data: Self::Payload,
+    ) -> Result<(), Error> {
+        match data {
+            NotifyJob::Send(job) => self.send(&job.target, job.notification.as_ref()).await,
+            NotifyJob::FlushBatch { flush_batch } => {
+                self.flush_batch(&flush_batch, item.is_final_retry()).await
+            }
         }
     }
 }
@@ -20,6 +20,33 @@ pub struct Notification {
 }
 
 impl Notification {
+    /// A notification with made-up values, for testing endpoints and templates.
+    pub fn sample(event: NotifyEvent) -> Notification {
+        let payload = match event {
+            NotifyEvent::StateChanged => serde_json::json!({
+                "old_state": "running",
+                "new_state": "error",
+            }),
+            NotifyEvent::AccountExpiring => serde_json::json!({
+                "expires": "2023-01-31T00:00:00Z",
+            }),
+            _ => serde_json::json!({ "value": 5 }),
+        };
+
+        Notification {
+            event,
+            task_id: TaskId::new(),
+            task_name: "Test Notification".to_string(),
+            local_id: "test".to_string(),
+            local_object_name: format!("Test {}", event.local_object_type()),
+            local_object_id: None,
+            payload: Some(payload),
+            error: matches!(event, NotifyEvent::ActionError | NotifyEvent::InputError)
+                .then(|| "This is a test error".to_string()),
+            log_id: None,
+        }
+    }
+
     pub fn fields<'a>(&'a self) -> Vec<(&'static str, Cow<'a, str>, bool)> {
         let mut output = Vec::with_capacity(8);
 
@@ -47,13 +74,25 @@ impl Notification {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[serde(rename_all = "snake_case")]
 #[sqlx(type_name = "notify_service", rename_all = "snake_case")]
 pub enum NotifyService {
     Email,
     DiscordIncomingWebhook,
     SlackIncomingWebhook,
+    Webhook,
+}
+
+impl NotifyService {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::DiscordIncomingWebhook => "discord_incoming_webhook",
+            Self::SlackIncomingWebhook => "slack_incoming_webhook",
+            Self::Webhook => "webhook",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, sqlx::Type)]
@@ -65,9 +104,37 @@ pub enum NotifyEvent {
     ActionStarted,
     ActionSuccess,
     ActionError,
+    /// An input failed validation, or the task failed while processing it.
+    InputError,
+    /// The active states of one of a task's state machines changed.
+    StateChanged,
+    /// A task was disabled.
+    TaskDisabled,
+    /// A periodic trigger had no pending run, and had to be scheduled again.
+    PeriodicTriggerMissed,
+    /// An account used by a task is about to expire.
+    AccountExpiring,
+    /// Sent by a task script through `Ergo.notify`.
+    Custom,
 }
 
 impl NotifyEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InputArrived => "input_arrived",
+            Self::InputProcessed => "input_processed",
+            Self::ActionStarted => "action_started",
+            Self::ActionSuccess => "action_success",
+            Self::ActionError => "action_error",
+            Self::InputError => "input_error",
+            Self::StateChanged => "state_changed",
+            Self::TaskDisabled => "task_disabled",
+            Self::PeriodicTriggerMissed => "periodic_trigger_missed",
+            Self::AccountExpiring => "account_expiring",
+            Self::Custom => "custom",
+        }
+    }
+
     pub fn level(&self) -> Level {
         match self {
             Self::InputArrived { .. } => Level::Debug,
@@ -75,6 +142,12 @@ impl NotifyEvent {
             Self::ActionStarted { .. } => Level::Debug,
             Self::ActionSuccess { .. } => Level::Info,
             Self::ActionError { .. } => Level::Error,
+            Self::InputError { .. } => Level::Error,
+            Self::StateChanged { .. } => Level::Info,
+            Self::TaskDisabled { .. } => Level::Warning,
+            Self::PeriodicTriggerMissed { .. } => Level::Warning,
+            Self::AccountExpiring { .. } => Level::Warning,
+            Self::Custom { .. } => Level::Info,
         }
     }
 
@@ -85,13 +158,24 @@ impl NotifyEvent {
             Self::ActionError => "Action Error",
             Self::ActionSuccess => "Action Finished",
             Self::ActionStarted => "Action Started",
+            Self::InputError => "Input Error",
+            Self::StateChanged => "State Changed",
+            Self::TaskDisabled => "Task Disabled",
+            Self::PeriodicTriggerMissed => "Periodic Trigger Missed",
+            Self::AccountExpiring => "Account Expiring",
+            Self::Custom => "Notification",
         }
     }
 
     pub fn local_object_type(&self) -> &'static str {
         match self {
-            Self::InputArrived | Self::InputProcessed => "Input",
+            Self::InputArrived | Self::InputProcessed | Self::InputError => "Input",
             Self::ActionStarted | Self::ActionSuccess | Self::ActionError => "Action",
+            Self::StateChanged => "State Machine",
+            Self::TaskDisabled => "Task",
+            Self::PeriodicTriggerMissed => "Trigger",
+            Self::AccountExpiring => "Account",
+            Self::Custom => "Message",
         }
     }
 }
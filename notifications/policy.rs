@@ -0,0 +1,523 @@
+//! Per-listener policies that limit how often notifications are sent. Notifications that aren't
+//! sent right away are gathered into a batch in `notify_batches`, and a job scheduled for the
+//! end of the batch's window sends a single summary message.
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, PgConnection};
+use uuid::Uuid;
+
+use super::{Error, Notification, RenderedMessage};
+
+/// The most notifications to keep in a batch. Any more are only counted.
+const MAX_BATCH_NOTIFICATIONS: i32 = 50;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NotifyPolicy {
+    /// Send at most this many notifications in each period. The rest are reported in a single
+    /// message when the period ends.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Collapse notifications with the same error from the same task and object within this
+    /// many seconds. The first one is sent right away, and the number of repeats is sent when the
+    /// window ends.
+    #[serde(default)]
+    pub collapse_errors_secs: Option<u32>,
+    /// Gather all notifications into a periodic digest instead of sending them right away.
+    #[serde(default)]
+    pub digest: Option<DigestInterval>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max: u32,
+    pub period_secs: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestInterval {
+    Hourly,
+    Daily,
+}
+
+impl DigestInterval {
+    /// The time that a digest started at `now` should be sent: the start of the next hour or
+    /// day, in UTC.
+    pub fn next_send_time(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let step = match self {
+            Self::Hourly => Duration::hours(1),
+            Self::Daily => Duration::days(1),
+        };
+
+        let next = now + step;
+        let truncated = next
+            .with_nanosecond(0)
+            .and_then(|t| t.with_second(0))
+            .and_then(|t| t.with_minute(0));
+        let truncated = match self {
+            Self::Hourly => truncated,
+            Self::Daily => truncated.and_then(|t| t.with_hour(0)),
+        };
+
+        truncated.unwrap_or(next)
+    }
+}
+
+impl NotifyPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(limit) = self.rate_limit.as_ref() {
+            if limit.max == 0 || limit.period_secs == 0 {
+                return Err(Error::InvalidPolicy(
+                    "The rate limit's max and period must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        if self.collapse_errors_secs == Some(0) {
+            return Err(Error::InvalidPolicy(
+                "The collapse window must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "notify_batch_kind", rename_all = "snake_case")]
+pub enum BatchKind {
+    Collapse,
+    RateLimit,
+    Digest,
+}
+
+/// Identifies a batch to send when its window ends.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchRef {
+    pub notify_listener_id: Uuid,
+    pub batch_key: String,
+}
+
+/// What to do with a notification after applying a listener's policy.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PolicyDecision {
+    /// Send the notification right away.
+    pub send_now: bool,
+    /// Batches started by the notification, and when to send them.
+    pub flush: Vec<(BatchRef, DateTime<Utc>)>,
+}
+
+/// Errors are collapsed if they come from the same event, task, and object and have the same
+/// message.
+fn collapse_key(notification: &Notification) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(notification.event.as_str());
+    hasher.update([0]);
+    hasher.update(notification.task_id.0.as_bytes());
+    hasher.update(notification.local_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(notification.error.as_deref().unwrap_or_default());
+    format!("collapse:{}", hex::encode(hasher.finalize()))
+}
+
+/// Add a notification to a batch, creating the batch if needed. Returns true if the batch is new.
+async fn add_to_batch(
+    tx: &mut PgConnection,
+    notify_listener_id: Uuid,
+    batch_key: &str,
+    kind: BatchKind,
+    send_at: DateTime<Utc>,
+    notification: &Notification,
+) -> Result<bool, Error> {
+    // Collapsed batches only need the first notification.
+    let keep_all = kind != BatchKind::Collapse;
+    let count = sqlx::query_scalar!(
+        r##"INSERT INTO notify_batches
+            (notify_listener_id, batch_key, kind, count, notifications, send_at)
+        VALUES ($1, $2, $3, 1, jsonb_build_array($4::jsonb), $5)
+        ON CONFLICT (notify_listener_id, batch_key) DO UPDATE
+        SET count = notify_batches.count + 1,
+            notifications = CASE
+                WHEN $6 AND jsonb_array_length(notify_batches.notifications) < $7
+                    THEN notify_batches.notifications || jsonb_build_array($4::jsonb)
+                ELSE notify_batches.notifications
+            END
+        RETURNING count"##,
+        notify_listener_id,
+        batch_key,
+        kind as _,
+        Json(notification) as _,
+        send_at,
+        keep_all,
+        MAX_BATCH_NOTIFICATIONS
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(count == 1)
+}
+
+/// A batch that was removed from `notify_batches` to be sent.
+#[derive(Debug)]
+pub struct ClaimedBatch {
+    pub kind: BatchKind,
+    pub count: i32,
+    pub notifications: Vec<Notification>,
+    pub send_at: DateTime<Utc>,
+}
+
+/// Put back a batch that failed to send, so that the next attempt sends it. If another batch with
+/// the same key was started in the meantime, the two are merged, with the older notifications
+/// first.
+pub async fn restore_batch(
+    tx: &mut PgConnection,
+    batch: &BatchRef,
+    claimed: &ClaimedBatch,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r##"INSERT INTO notify_batches
+            (notify_listener_id, batch_key, kind, count, notifications, send_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (notify_listener_id, batch_key) DO UPDATE
+        SET count = notify_batches.count + EXCLUDED.count,
+            notifications = CASE
+                WHEN EXCLUDED.kind = 'collapse' THEN EXCLUDED.notifications
+                ELSE (
+                    SELECT COALESCE(jsonb_agg(value ORDER BY ord), '[]'::jsonb)
+                    FROM jsonb_array_elements(EXCLUDED.notifications || notify_batches.notifications)
+                        WITH ORDINALITY AS n(value, ord)
+                    WHERE ord <= $7
+                )
+            END,
+            send_at = LEAST(notify_batches.send_at, EXCLUDED.send_at)"##,
+        batch.notify_listener_id,
+        batch.batch_key,
+        claimed.kind as _,
+        claimed.count,
+        Json(&claimed.notifications) as _,
+        claimed.send_at,
+        MAX_BATCH_NOTIFICATIONS as i64
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Count a notification against the rate limit. Returns the start of the current window if the
+/// limit has been exceeded.
+async fn check_rate_limit(
+    tx: &mut PgConnection,
+    notify_listener_id: Uuid,
+    limit: &RateLimit,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let result = sqlx::query!(
+        r##"INSERT INTO notify_rate_limits (notify_listener_id, window_start, count)
+        VALUES ($1, now(), 1)
+        ON CONFLICT (notify_listener_id) DO UPDATE
+        SET window_start = CASE
+                WHEN notify_rate_limits.window_start + $2::int * interval '1 second' <= now()
+                    THEN now()
+                ELSE notify_rate_limits.window_start
+            END,
+            count = CASE
+                WHEN notify_rate_limits.window_start + $2::int * interval '1 second' <= now()
+                    THEN 1
+                ELSE notify_rate_limits.count + 1
+            END
+        RETURNING count, window_start"##,
+        notify_listener_id,
+        limit.period_secs as i32
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if result.count as u32 > limit.max {
+        Ok(Some(result.window_start))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Apply a listener's policy to a notification.
+pub async fn apply_policy(
+    tx: &mut PgConnection,
+    notify_listener_id: Uuid,
+    policy: &NotifyPolicy,
+    notification: &Notification,
+) -> Result<PolicyDecision, Error> {
+    let now = Utc::now();
+    let new_batch = |batch_key: &str, send_at: DateTime<Utc>, is_new: bool| {
+        is_new.then(|| {
+            (
+                BatchRef {
+                    notify_listener_id,
+                    batch_key: batch_key.to_string(),
+                },
+                send_at,
+            )
+        })
+    };
+
+    if let Some(interval) = policy.digest {
+        let send_at = interval.next_send_time(now);
+        let is_new = add_to_batch(
+            tx,
+            notify_listener_id,
+            "digest",
+            BatchKind::Digest,
+            send_at,
+            notification,
+        )
+        .await?;
+        return Ok(PolicyDecision {
+            send_now: false,
+            flush: new_batch("digest", send_at, is_new).into_iter().collect(),
+        });
+    }
+
+    let mut decision = PolicyDecision {
+        send_now: true,
+        flush: Vec::new(),
+    };
+
+    if let (Some(window), Some(_)) = (policy.collapse_errors_secs, notification.error.as_ref()) {
+        let key = collapse_key(notification);
+        let send_at = now + Duration::seconds(window as i64);
+        let is_new = add_to_batch(
+            tx,
+            notify_listener_id,
+            &key,
+            BatchKind::Collapse,
+            send_at,
+            notification,
+        )
+        .await?;
+
+        if !is_new {
+            return Ok(PolicyDecision::default());
+        }
+
+        // The first error in the window is sent, subject to the rate limit, and the batch reports
+        // the repeats when the window ends.
+        decision.flush.extend(new_batch(&key, send_at, is_new));
+    }
+
+    if let Some(limit) = policy.rate_limit.as_ref() {
+        if let Some(window_start) = check_rate_limit(tx, notify_listener_id, limit).await? {
+            let send_at = window_start + Duration::seconds(limit.period_secs as i64);
+            let is_new = add_to_batch(
+                tx,
+                notify_listener_id,
+                "rate_limit",
+                BatchKind::RateLimit,
+                send_at,
+                notification,
+            )
+            .await?;
+
+            decision.send_now = false;
+            decision
+                .flush
+                .extend(new_batch("rate_limit", send_at, is_new));
+        }
+    }
+
+    Ok(decision)
+}
+
+fn plural(count: i32, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+fn summary_line(notification: &Notification) -> String {
+    let mut line = format!(
+        "{}: {} - {} {}",
+        notification.event.description(),
+        notification.task_name,
+        notification.event.local_object_type(),
+        notification.local_object_name
+    );
+
+    if let Some(error) = notification.error.as_deref() {
+        line.push_str(": ");
+        line.push_str(error);
+    }
+
+    line
+}
+
+fn summary_lines(count: i32, notifications: &[Notification]) -> String {
+    let mut lines = notifications
+        .iter()
+        .map(|n| format!("- {}", summary_line(n)))
+        .collect::<Vec<_>>();
+
+    let omitted = count - notifications.len() as i32;
+    if omitted > 0 {
+        lines.push(format!("- ...and {} more", omitted));
+    }
+
+    lines.join("\n")
+}
+
+/// Build the message sent when a batch's window ends. `count` is the total number of
+/// notifications added to the batch. Returns `None` if there is nothing to report.
+pub fn batch_message(
+    kind: BatchKind,
+    count: i32,
+    notifications: &[Notification],
+) -> Option<RenderedMessage> {
+    match kind {
+        BatchKind::Collapse => {
+            // The first notification was already sent.
+            let repeats = count - 1;
+            let first = notifications.first()?;
+            if repeats < 1 {
+                return None;
+            }
+
+            Some(RenderedMessage {
+                subject: Some(format!(
+                    "{}: {}",
+                    first.event.description(),
+                    plural(repeats, "more occurrence")
+                )),
+                body: format!(
+                    "The same error happened {} after it was sent.\n{}",
+                    plural(repeats, "more time"),
+                    summary_line(first)
+                ),
+            })
+        }
+        BatchKind::RateLimit => {
+            if notifications.is_empty() {
+                return None;
+            }
+
+            Some(RenderedMessage {
+                subject: Some(format!(
+                    "{} held by the rate limit",
+                    plural(count, "notification")
+                )),
+                body: summary_lines(count, notifications),
+            })
+        }
+        BatchKind::Digest => {
+            if notifications.is_empty() {
+                return None;
+            }
+
+            Some(RenderedMessage {
+                subject: Some(format!("Digest: {}", plural(count, "notification"))),
+                body: summary_lines(count, notifications),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::NotifyEvent;
+
+    #[test]
+    fn parse_policy() {
+        let policy: NotifyPolicy = serde_json::from_value(serde_json::json!({
+            "rate_limit": { "max": 5, "period_secs": 60 },
+            "digest": "daily"
+        }))
+        .unwrap();
+        assert_eq!(
+            policy,
+            NotifyPolicy {
+                rate_limit: Some(RateLimit {
+                    max: 5,
+                    period_secs: 60
+                }),
+                collapse_errors_secs: None,
+                digest: Some(DigestInterval::Daily),
+            }
+        );
+        policy.validate().unwrap();
+
+        let policy: NotifyPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, NotifyPolicy::default());
+
+        NotifyPolicy {
+            collapse_errors_secs: Some(0),
+            ..Default::default()
+        }
+        .validate()
+        .expect_err("empty collapse window");
+    }
+
+    #[test]
+    fn digest_send_time() {
+        let now = Utc.ymd(2023, 1, 31).and_hms_milli(23, 15, 20, 500);
+        assert_eq!(
+            DigestInterval::Hourly.next_send_time(now),
+            Utc.ymd(2023, 2, 1).and_hms(0, 0, 0)
+        );
+        assert_eq!(
+            DigestInterval::Daily.next_send_time(now),
+            Utc.ymd(2023, 2, 1).and_hms(0, 0, 0)
+        );
+
+        let now = Utc.ymd(2023, 1, 15).and_hms(0, 0, 0);
+        assert_eq!(
+            DigestInterval::Hourly.next_send_time(now),
+            Utc.ymd(2023, 1, 15).and_hms(1, 0, 0)
+        );
+        assert_eq!(
+            DigestInterval::Daily.next_send_time(now),
+            Utc.ymd(2023, 1, 16).and_hms(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn collapse_keys() {
+        let first = Notification::sample(NotifyEvent::ActionError);
+        let mut second = first.clone();
+        assert_eq!(collapse_key(&first), collapse_key(&second));
+
+        second.error = Some("a different error".to_string());
+        assert_ne!(collapse_key(&first), collapse_key(&second));
+    }
+
+    #[test]
+    fn messages() {
+        let notification = Notification::sample(NotifyEvent::ActionError);
+
+        assert_eq!(
+            batch_message(BatchKind::Collapse, 1, &[notification.clone()]),
+            None,
+            "no repeats"
+        );
+
+        let message = batch_message(BatchKind::Collapse, 4, &[notification.clone()]).unwrap();
+        assert_eq!(
+            message.subject.as_deref(),
+            Some("Action Error: 3 more occurrences")
+        );
+
+        let message = batch_message(
+            BatchKind::Digest,
+            3,
+            &[notification.clone(), notification.clone()],
+        )
+        .unwrap();
+        assert_eq!(message.subject.as_deref(), Some("Digest: 3 notifications"));
+        assert_eq!(message.body.lines().count(), 3);
+        assert_eq!(message.body.lines().last(), Some("- ...and 1 more"));
+    }
+}
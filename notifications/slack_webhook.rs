@@ -0,0 +1,227 @@
+use super::{Error, Level, Notification, RenderedMessage};
+
+use serde_json::{json, Value};
+
+/// Slack allows up to 10 fields in a section block.
+const MAX_SECTION_FIELDS: usize = 10;
+/// Slack rejects text objects longer than this.
+const MAX_TEXT_LENGTH: usize = 3000;
+
+fn emoji(level: Level) -> &'static str {
+    match level {
+        Level::Debug => ":mag:",
+        Level::Info => ":white_check_mark:",
+        Level::Warning => ":warning:",
+        Level::Error => ":x:",
+    }
+}
+
+/// Escape the characters that Slack treats as control sequences in mrkdwn text.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn truncate(mut s: String, max_len: usize) -> String {
+    if s.len() > max_len {
+        let mut end = max_len.saturating_sub(3);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+        s.push_str("...");
+    }
+    s
+}
+
+fn mrkdwn(text: String) -> Value {
+    json!({
+        "type": "mrkdwn",
+        "text": truncate(text, MAX_TEXT_LENGTH),
+    })
+}
+
+/// Build the Block Kit message for a notification. Inline fields are laid out in columns, and the
+/// others get their own section so that long values such as payloads stay readable. A message
+/// rendered from a template replaces the fields.
+fn slack_payload(notification: &Notification, message: Option<&RenderedMessage>) -> Value {
+    let desc = notification.event.description();
+    let level = notification.event.level();
+
+    if let Some(message) = message {
+        let title = message.subject.as_deref().unwrap_or(desc);
+        return json!({
+            "text": title,
+            "blocks": [
+                {
+                    "type": "section",
+                    "text": mrkdwn(format!("{} *{}*", emoji(level), title)),
+                },
+                {
+                    "type": "section",
+                    "text": mrkdwn(message.body.clone()),
+                },
+            ],
+        });
+    }
+
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": mrkdwn(format!("{} *{}*", emoji(level), desc)),
+    })];
+
+    let (inline, full_width): (Vec<_>, Vec<_>) = notification
+        .fields()
+        .into_iter()
+        .partition(|(_, _, inline)| *inline);
+
+    for chunk in inline.chunks(MAX_SECTION_FIELDS) {
+        let fields = chunk
+            .iter()
+            .map(|(name, value, _)| mrkdwn(format!("*{}*\n{}", name, escape(value))))
+            .collect::<Vec<_>>();
+        blocks.push(json!({
+            "type": "section",
+            "fields": fields,
+        }));
+    }
+
+    for (name, value, _) in full_width {
+        // Leave room for the name and the code block markers.
+        let value = truncate(escape(&value), MAX_TEXT_LENGTH - name.len() - 10);
+        blocks.push(json!({
+            "type": "section",
+            "text": mrkdwn(format!("*{}*\n```{}```", name, value)),
+        }));
+    }
+
+    json!({
+        // Shown in notifications and in clients that can't display blocks.
+        "text": format!("{}: {}", desc, notification.task_name),
+        "blocks": blocks,
+    })
+}
+
+/// Send a notification to a Slack incoming webhook. The hook can be a full URL, or just the part
+/// after "https://hooks.slack.com/services/".
+pub async fn send_slack_webhook(
+    client: &reqwest::Client,
+    hook: &str,
+    notification: &Notification,
+    message: Option<&RenderedMessage>,
+) -> Result<(), Error> {
+    let url = if hook.starts_with("https://") || hook.starts_with("http://") {
+        hook.to_string()
+    } else {
+        format!("https://hooks.slack.com/services/{}", hook)
+    };
+
+    client
+        .post(&url)
+        .json(&slack_payload(notification, message))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_database::object_id::TaskId;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::NotifyEvent;
+
+    fn notification() -> super::Notification {
+        super::Notification {
+            event: NotifyEvent::ActionError,
+            task_id: TaskId::new(),
+            task_name: "a <test> task".to_string(),
+            local_id: "the local id".to_string(),
+            local_object_name: "the local object name".to_string(),
+            local_object_id: Some(Uuid::new_v4()),
+            payload: Some(json!({ "payload_value": 5})),
+            error: Some("it broke".to_string()),
+            log_id: None,
+        }
+    }
+
+    #[test]
+    fn block_kit_payload() {
+        let payload = super::slack_payload(&notification(), None);
+
+        assert_eq!(payload["text"], json!("Action Error: a <test> task"));
+        assert_eq!(
+            payload["blocks"],
+            json!([
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": ":x: *Action Error*" }
+                },
+                {
+                    "type": "section",
+                    "fields": [
+                        { "type": "mrkdwn", "text": "*Task*\na &lt;test&gt; task" },
+                        { "type": "mrkdwn", "text": "*Action*\nthe local object name" }
+                    ]
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "*Error*\n```it broke```" }
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "*Payload*\n```{\"payload_value\":5}```" }
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn truncates_long_values() {
+        let mut n = notification();
+        n.error = Some("é".repeat(4000));
+
+        let payload = super::slack_payload(&n, None);
+        let text = payload["blocks"][2]["text"]["text"].as_str().unwrap();
+        assert!(text.len() <= super::MAX_TEXT_LENGTH);
+        assert!(text.ends_with("...```"));
+    }
+
+    #[test]
+    fn templated_message() {
+        let message = super::RenderedMessage {
+            subject: Some("Custom title".to_string()),
+            body: "The body".to_string(),
+        };
+
+        let payload = super::slack_payload(&notification(), Some(&message));
+        assert_eq!(payload["text"], json!("Custom title"));
+        assert_eq!(
+            payload["blocks"][0]["text"]["text"],
+            json!(":x: *Custom title*")
+        );
+        assert_eq!(payload["blocks"][1]["text"]["text"], json!("The body"));
+    }
+
+    #[tokio::test]
+    async fn sends_notification() {
+        dotenv::dotenv().ok();
+
+        let hook = std::env::var("TEST_SLACK_WEBHOOK_URL").unwrap_or_else(|_| String::new());
+        if hook.is_empty() {
+            return;
+        }
+
+        super::send_slack_webhook(
+            &reqwest::Client::new(),
+            hook.as_str(),
+            &notification(),
+            None,
+        )
+        .await
+        .expect("Sending notification");
+    }
+}
@@ -0,0 +1,158 @@
+//! Custom message templates for notification listeners.
+
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Level, Notification, NotifyEvent};
+
+lazy_static! {
+    // Set up the same way as the action templates in ergo_tasks.
+    static ref HANDLEBARS: handlebars::Handlebars<'static> = {
+        let mut h = handlebars::Handlebars::new();
+        h.strict_mode();
+        h.register_escape_fn(|s| s.to_string());
+        h
+    };
+
+    // The same, but with values HTML-escaped, for HTML email templates.
+    static ref HTML_HANDLEBARS: handlebars::Handlebars<'static> = {
+        let mut h = handlebars::Handlebars::new();
+        h.strict_mode();
+        h
+    };
+}
+
+/// A Handlebars template for the message sent by a listener, used in place of the default list
+/// of fields. Templates can use all the fields of a [Notification], along with `description`,
+/// `level`, `object_type`, and `fields`, which is the default list of `{ name, value }` pairs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NotifyTemplate {
+    /// The title of the message, or the subject for emails. Defaults to the event description.
+    #[serde(default)]
+    pub subject: Option<String>,
+    pub body: String,
+}
+
+/// A message rendered from a [NotifyTemplate].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RenderedMessage {
+    pub subject: Option<String>,
+    pub body: String,
+}
+
+#[derive(Serialize)]
+struct TemplateField<'a> {
+    name: &'static str,
+    value: &'a str,
+}
+
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    #[serde(flatten)]
+    notification: &'a Notification,
+    description: &'static str,
+    level: Level,
+    object_type: &'static str,
+    fields: Vec<TemplateField<'a>>,
+}
+
+impl<'a> TemplateContext<'a> {
+    fn new(
+        notification: &'a Notification,
+        fields: &'a [(&'static str, Cow<'a, str>, bool)],
+    ) -> Self {
+        TemplateContext {
+            notification,
+            description: notification.event.description(),
+            level: notification.event.level(),
+            object_type: notification.event.local_object_type(),
+            fields: fields
+                .iter()
+                .map(|(name, value, _)| TemplateField { name, value })
+                .collect(),
+        }
+    }
+}
+
+/// Render a template against a notification, with the same values that a [NotifyTemplate]
+/// can use. With `html` set, the values are HTML-escaped.
+pub(crate) fn render_notification(
+    template: &str,
+    notification: &Notification,
+    html: bool,
+) -> Result<String, Error> {
+    let fields = notification.fields();
+    let context = TemplateContext::new(notification, &fields);
+    let handlebars: &handlebars::Handlebars = if html { &HTML_HANDLEBARS } else { &HANDLEBARS };
+
+    Ok(handlebars.render_template(template, &context)?)
+}
+
+impl NotifyTemplate {
+    pub fn render(&self, notification: &Notification) -> Result<RenderedMessage, Error> {
+        let fields = notification.fields();
+        let context = TemplateContext::new(notification, &fields);
+
+        let subject = self
+            .subject
+            .as_ref()
+            .map(|s| HANDLEBARS.render_template(s, &context))
+            .transpose()?;
+        let body = HANDLEBARS.render_template(&self.body, &context)?;
+
+        Ok(RenderedMessage { subject, body })
+    }
+
+    /// Check that the template renders for an event.
+    pub fn validate(&self, event: NotifyEvent) -> Result<(), Error> {
+        self.render(&Notification::sample(event)).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let template = NotifyTemplate {
+            subject: Some("{{description}} in {{task_name}}".to_string()),
+            body:
+                "{{#each fields}}{{name}}={{value}};{{/each}} {{level}} {{#if error}}failed{{/if}}"
+                    .to_string(),
+        };
+
+        let message = template
+            .render(&Notification::sample(NotifyEvent::ActionSuccess))
+            .unwrap();
+        assert_eq!(
+            message.subject.as_deref(),
+            Some("Action Finished in Test Notification")
+        );
+        assert_eq!(
+            message.body,
+            r#"Task=Test Notification;Action=Test Action;Payload={"value":5}; info "#
+        );
+    }
+
+    #[test]
+    fn invalid_template() {
+        let template = NotifyTemplate {
+            subject: None,
+            body: "{{not_a_field}}".to_string(),
+        };
+        template
+            .validate(NotifyEvent::ActionError)
+            .expect_err("missing field");
+
+        let template = NotifyTemplate {
+            subject: None,
+            body: "{{#if task_name}}".to_string(),
+        };
+        template
+            .validate(NotifyEvent::ActionError)
+            .expect_err("unclosed block");
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::{Error, Notification, RenderedMessage};
+
+pub const SIGNATURE_HEADER: &str = "X-Ergo-Signature";
+pub const EVENT_HEADER: &str = "X-Ergo-Event";
+
+/// How many times to retry a failed webhook when the endpoint doesn't say.
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+/// The initial delay before retrying a webhook. The queue doubles this for each retry.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Settings for a generic webhook endpoint, stored in `notify_endpoints.config`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// When set, each request has a `X-Ergo-Signature: sha256=<hex digest>` header containing
+    /// the HMAC-SHA256 of the request body, keyed with this secret.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Extra headers to send with each request.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// How many times to retry a request that fails.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+}
+
+/// The body of a webhook request.
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    #[serde(flatten)]
+    notification: &'a Notification,
+    /// The message rendered from the listener's template, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a RenderedMessage>,
+}
+
+/// Compute the value of the signature header for a request body.
+pub fn sign_body(secret: &str, body: &[u8]) -> String {
+    // HMAC accepts keys of any length, so this can't fail.
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POST the notification as JSON to `url`, along with the rendered message, if any. Errors, including error status codes from the
+/// endpoint, are returned so that the queue can retry the job.
+pub async fn send_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    config: &WebhookConfig,
+    notification: &Notification,
+    message: Option<&RenderedMessage>,
+) -> Result<(), Error> {
+    let body = serde_json::to_vec(&WebhookBody {
+        notification,
+        message,
+    })?;
+
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, notification.event.as_str());
+
+    for (name, value) in &config.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+
+    if let Some(secret) = config.secret.as_deref() {
+        request = request.header(SIGNATURE_HEADER, sign_body(secret, &body));
+    }
+
+    request.body(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_database::object_id::TaskId;
+    use serde_json::json;
+
+    use super::*;
+    use crate::NotifyEvent;
+
+    #[test]
+    fn signature() {
+        // Test vector from RFC 4231, test case 2.
+        assert_eq!(
+            sign_body("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_notification() {
+        dotenv::dotenv().ok();
+
+        let url = std::env::var("TEST_WEBHOOK_URL").unwrap_or_else(|_| String::new());
+        if url.is_empty() {
+            return;
+        }
+
+        let notification = Notification {
+            event: NotifyEvent::ActionError,
+            task_id: TaskId::new(),
+            task_name: "a test task".to_string(),
+            local_id: "the local id".to_string(),
+            local_object_name: "the local object name".to_string(),
+            local_object_id: None,
+            payload: Some(json!({ "payload_value": 5})),
+            error: Some("it broke".to_string()),
+            log_id: None,
+        };
+
+        let config = WebhookConfig {
+            secret: Some("a secret".to_string()),
+            headers: [("X-Test".to_string(), "yes".to_string())]
+                .into_iter()
+                .collect(),
+            max_retries: None,
+        };
+
+        send_webhook(&reqwest::Client::new(), &url, &config, &notification, None)
+            .await
+            .expect("Sending notification");
+    }
+}
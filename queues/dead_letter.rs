@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+
+use crate::error::Error;
+
+use super::Queue;
+
+// Move a job from the dead letter list back to the pending list.
+// KEYS:
+//  1. job data key
+//  2. dead letter list
+//  3. stats hash
+//  4-6. pending lists, from high to low priority
+// ARGS:
+//  1. job ID
+//  2. current time
+const REQUEUE_SCRIPT: &str = r##"
+    local removed = redis.call("ZREM", KEYS[2], ARGV[1])
+    if removed == 0 then
+        return false
+    end
+
+    -- Give the job a fresh set of retries. The error history is kept so that it
+    -- covers every attempt if the job ends up back in the dead letter list.
+    redis.call("HDEL", KEYS[1], "err", "st", "end", "suc")
+    redis.call("HSET", KEYS[1], "cr", 0, "qt", ARGV[2])
+    local band = tonumber(redis.call("HGET", KEYS[1], "pri")) or 1
+    redis.call("LPUSH", KEYS[4 + band], ARGV[1])
+    redis.call("HINCRBY", KEYS[3], "enqueued", 1)
+    return true
+"##;
+
+// Remove a job from the dead letter list and delete its data.
+// KEYS:
+//  1. job data key
+//  2. job error history list
+//  3. dead letter list
+// ARGS:
+//  1. job ID
+const PURGE_SCRIPT: &str = r##"
+    local removed = redis.call("ZREM", KEYS[3], ARGV[1])
+    if removed == 0 then
+        return false
+    end
+
+    redis.call("DEL", KEYS[1], KEYS[2])
+    return true
+"##;
+
+lazy_static! {
+    static ref REQUEUE: redis::Script = redis::Script::new(REQUEUE_SCRIPT);
+    static ref PURGE: redis::Script = redis::Script::new(PURGE_SCRIPT);
+}
+
+pub struct RequeueDeadScript(&'static redis::Script);
+
+impl RequeueDeadScript {
+    pub fn new() -> Self {
+        RequeueDeadScript(&REQUEUE)
+    }
+
+    pub async fn run(
+        &self,
+        queue: &Queue,
+        conn: &mut deadpool_redis::Connection,
+        job_id: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let requeued: Option<bool> = self
+            .0
+            .key(queue.job_data_key(job_id))
+            .key(&queue.0.dead_list)
+            .key(&queue.0.stats_hash)
+            .key(queue.0.pending_lists.as_slice())
+            .arg(job_id)
+            .arg(now.timestamp_millis())
+            .invoke_async(&mut **conn)
+            .await?;
+
+        Ok(requeued.unwrap_or(false))
+    }
+}
+
+pub struct PurgeDeadScript(&'static redis::Script);
+
+impl PurgeDeadScript {
+    pub fn new() -> Self {
+        PurgeDeadScript(&PURGE)
+    }
+
+    pub async fn run(
+        &self,
+        queue: &Queue,
+        conn: &mut deadpool_redis::Connection,
+        job_id: &str,
+    ) -> Result<bool, Error> {
+        let purged: Option<bool> = self
+            .0
+            .key(queue.job_data_key(job_id))
+            .key(queue.job_errors_key(job_id))
+            .key(&queue.0.dead_list)
+            .arg(job_id)
+            .invoke_async(&mut **conn)
+            .await?;
+
+        Ok(purged.unwrap_or(false))
+    }
+}
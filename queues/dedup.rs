@@ -0,0 +1,134 @@
+use ergo_database::new_uuid;
+use lazy_static::lazy_static;
+
+use crate::{error::Error, EnqueueOutcome, Idempotency, Job};
+
+use super::Queue;
+
+// Check if a job is a duplicate of one enqueued with the same ID within the idempotency window,
+// and apply the duplicate to the earlier job if appropriate. The dedup marker holds the ID of the
+// latest job enqueued for the ID, which differs from the job ID when the earlier job had already
+// started.
+// KEYS:
+//  1. dedup marker key
+//  2. scheduled items list
+//  3-5. pending lists, from high to low priority
+// ARGV:
+//  1. job ID
+//  2. duplicate mode
+//  3. idempotency window
+//  4. new payload
+//  5. job data key prefix
+//  6. the ID to use if the job has to be enqueued separately from the earlier job
+// Returns the outcome and the ID to enqueue the job with.
+const DEDUP_SCRIPT: &str = r##"
+    if redis.call("SET", KEYS[1], ARGV[1], "NX", "PX", ARGV[3]) then
+        return {"enqueue", ARGV[1]}
+    end
+
+    if ARGV[2] == "drop" then
+        return {"drop", ""}
+    end
+
+    -- Only a job which is still waiting to run can be replaced. Otherwise the new job runs
+    -- after the earlier one, under its own ID so that it doesn't touch the earlier job's data.
+    local current = redis.call("GET", KEYS[1]) or ARGV[1]
+    local data_key = ARGV[5] .. current
+    local waiting = redis.call("ZSCORE", KEYS[2], current) ~= false
+    if not waiting then
+        local band = tonumber(redis.call("HGET", data_key, "pri")) or 1
+        waiting = redis.call("LPOS", KEYS[3 + band], current) ~= false
+    end
+
+    if not waiting then
+        redis.call("SET", KEYS[1], ARGV[6], "KEEPTTL")
+        return {"enqueue", ARGV[6]}
+    end
+
+    local payload = ARGV[4]
+    if ARGV[2] == "coalesce" then
+        local old = cjson.decode(redis.call("HGET", data_key, "pay"))
+        local new = cjson.decode(payload)
+        if type(old) == "table" and type(new) == "table" then
+            for k, v in pairs(new) do
+                old[k] = v
+            end
+            payload = cjson.encode(old)
+        end
+    end
+
+    redis.call("HSET", data_key, "pay", payload)
+    return {"update", current}
+"##;
+
+// Remove a dedup marker if it still refers to a job that failed to enqueue, so that retries of
+// the job aren't dropped as duplicates of a job that doesn't exist.
+// KEYS:
+//  1. dedup marker key
+// ARGV:
+//  1. the ID that the job was going to be enqueued with
+const RELEASE_SCRIPT: &str = r##"
+    if redis.call("GET", KEYS[1]) == ARGV[1] then
+        redis.call("DEL", KEYS[1])
+    end
+"##;
+
+lazy_static! {
+    static ref SCRIPT: redis::Script = redis::Script::new(DEDUP_SCRIPT);
+    static ref RELEASE: redis::Script = redis::Script::new(RELEASE_SCRIPT);
+}
+
+pub struct DedupScript(&'static redis::Script, &'static redis::Script);
+
+impl DedupScript {
+    pub fn new() -> Self {
+        DedupScript(&SCRIPT, &RELEASE)
+    }
+
+    /// Returns the ID to enqueue the job with, or the outcome if the job was a duplicate.
+    pub async fn run(
+        &self,
+        queue: &Queue,
+        conn: &mut deadpool_redis::Connection,
+        job: &Job<'_>,
+        idempotency: &Idempotency,
+    ) -> Result<Result<String, EnqueueOutcome>, Error> {
+        let (result, id): (String, String) = self
+            .0
+            .key(queue.dedup_key(&job.id))
+            .key(&queue.0.scheduled_list)
+            .key(queue.0.pending_lists.as_slice())
+            .arg(&job.id)
+            .arg(idempotency.mode.as_str())
+            .arg(idempotency.window.as_millis() as u64)
+            .arg(job.payload.as_ref())
+            .arg(&queue.0.job_data_prefix)
+            .arg(format!("{}:{}", job.id, new_uuid()))
+            .invoke_async(&mut **conn)
+            .await?;
+
+        let outcome = match result.as_str() {
+            "drop" => Err(EnqueueOutcome::Dropped),
+            "update" => Err(EnqueueOutcome::Updated),
+            _ => Ok(id),
+        };
+
+        Ok(outcome)
+    }
+
+    /// Release the dedup marker set for `job`, after enqueueing it as `job_id` failed.
+    pub async fn release(
+        &self,
+        queue: &Queue,
+        conn: &mut deadpool_redis::Connection,
+        job: &Job<'_>,
+        job_id: &str,
+    ) -> Result<(), Error> {
+        self.1
+            .key(queue.dedup_key(&job.id))
+            .arg(job_id)
+            .invoke_async(&mut **conn)
+            .await?;
+        Ok(())
+    }
+}
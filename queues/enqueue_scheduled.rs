@@ -8,10 +8,11 @@ use super::Queue;
 
 // KEYS:
 //  1. scheduled items list
-//  2. pending items list
-//  3. queue stats hash
+//  2. queue stats hash
+//  3-5. pending lists, from high to low priority
 // ARGV:
 //  1. current time
+//  2. job data key prefix
 const ENQUEUE_SCHEDULED_SCRIPT: &str = r##"
     local move_items = redis.call('ZRANGEBYSCORE', KEYS[1], 0, ARGV[1])
     if #move_items == 0 then
@@ -19,8 +20,11 @@ const ENQUEUE_SCHEDULED_SCRIPT: &str = r##"
     end
 
     redis.call('ZREM', KEYS[1], unpack(move_items))
-    redis.call('LPUSH', KEYS[2], unpack(move_items))
-    redis.call("HINCRBY", KEYS[3], "scheduled", 1)
+    for _, id in ipairs(move_items) do
+        local band = tonumber(redis.call("HGET", ARGV[2] .. id, "pri")) or 1
+        redis.call('LPUSH', KEYS[3 + band], id)
+    end
+    redis.call("HINCRBY", KEYS[2], "scheduled", 1)
     return #move_items
 "##;
 
@@ -44,9 +48,10 @@ impl EnqueueScript {
         let items_enqueued: usize = self
             .0
             .key(&queue.0.scheduled_list)
-            .key(&queue.0.pending_list)
             .key(&queue.0.stats_hash)
+            .key(queue.0.pending_lists.as_slice())
             .arg(now.timestamp_millis() as i64)
+            .arg(&queue.0.job_data_prefix)
             .invoke_async(&mut **conn)
             .await?;
 
@@ -2,7 +2,7 @@ use super::postgres_drain::Drainer;
 use crate::{
     error::Error,
     postgres_drain::{DrainResult, QueueOperation},
-    Job,
+    DuplicateMode, Idempotency, Job, JobLimit, JobPriority, RateLimit,
 };
 
 use async_trait::async_trait;
@@ -21,6 +21,9 @@ pub struct QueueJob<'a, T: Serialize + Send + Sync> {
     pub max_retries: Option<u32>,
     pub run_at: Option<DateTime<Utc>>,
     pub retry_backoff: Option<Duration>,
+    pub limit: Option<JobLimit>,
+    pub priority: JobPriority,
+    pub idempotency: Option<Idempotency>,
 }
 
 impl<'a, T: Serialize + Send + Sync> QueueJob<'a, T> {
@@ -34,6 +37,9 @@ impl<'a, T: Serialize + Send + Sync> QueueJob<'a, T> {
             max_retries: None,
             run_at: None,
             retry_backoff: None,
+            limit: None,
+            priority: JobPriority::Normal,
+            idempotency: None,
         }
     }
 
@@ -67,6 +73,26 @@ impl<'a, T: Serialize + Send + Sync> QueueJob<'a, T> {
         self
     }
 
+    #[must_use]
+    pub fn limit(&mut self, limit: JobLimit) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    #[must_use]
+    pub fn priority(&mut self, priority: JobPriority) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Deduplicate this job against other jobs with the same ID. This should be used with
+    /// an explicit job ID.
+    #[must_use]
+    pub fn idempotency(&mut self, idempotency: Idempotency) -> &mut Self {
+        self.idempotency = Some(idempotency);
+        self
+    }
+
     fn get_id_or_default(&self) -> Cow<'a, str> {
         self.id
             .map(|s| Cow::Borrowed(s))
@@ -90,11 +116,13 @@ pub async fn enqueue_jobs<T: Serialize + Send + Sync>(
     }
 
     let q = format!(
-        r##"INSERT INTO queue_stage (queue, job_id, payload, timeout, max_retries, run_at, retry_backoff)
+        r##"INSERT INTO queue_stage (queue, job_id, payload, timeout, max_retries, run_at, retry_backoff,
+            limit_key, limit_concurrency, limit_rate, limit_rate_period, priority,
+            idempotency_mode, idempotency_window)
             VALUES
             {}
             RETURNING job_id"##,
-        sql_insert_parameters::<7>(jobs.len())
+        sql_insert_parameters::<14>(jobs.len())
     );
 
     let mut query = sqlx::query_as(&q);
@@ -106,7 +134,33 @@ pub async fn enqueue_jobs<T: Serialize + Send + Sync>(
             .bind(job.timeout.map(|t| t.as_millis() as i32))
             .bind(job.max_retries.map(|i| i as i32))
             .bind(job.run_at)
-            .bind(job.retry_backoff.map(|i| i.as_millis() as i32));
+            .bind(job.retry_backoff.map(|i| i.as_millis() as i32))
+            .bind(job.limit.as_ref().map(|l| l.key.as_str()))
+            .bind(
+                job.limit
+                    .as_ref()
+                    .and_then(|l| l.max_concurrent)
+                    .map(|c| c as i32),
+            )
+            .bind(
+                job.limit
+                    .as_ref()
+                    .and_then(|l| l.rate.as_ref())
+                    .map(|r| r.max as i32),
+            )
+            .bind(
+                job.limit
+                    .as_ref()
+                    .and_then(|l| l.rate.as_ref())
+                    .map(|r| r.period.as_millis() as i32),
+            )
+            .bind(job.priority.band() as i32)
+            .bind(job.idempotency.as_ref().map(|i| i.mode.as_str()))
+            .bind(
+                job.idempotency
+                    .as_ref()
+                    .map(|i| i.window.as_millis() as i32),
+            );
     }
 
     let ids: Vec<Result> = query.fetch_all(&mut *tx).await?;
@@ -137,7 +191,9 @@ impl Drainer for QueueDrainer {
     async fn get(&'_ self, tx: &mut Transaction<Postgres>) -> Result<Vec<DrainResult<'_>>, Error> {
         let results = sqlx::query!(
             "SELECT id, queue, job_id, payload,
-            timeout, max_retries, run_at, retry_backoff, operation
+            timeout, max_retries, run_at, retry_backoff, operation,
+            limit_key, limit_concurrency, limit_rate, limit_rate_period, priority,
+            idempotency_mode, idempotency_window
             FROM queue_stage
             ORDER BY id LIMIT 50"
         )
@@ -158,6 +214,30 @@ impl Drainer for QueueDrainer {
                     .map(|op| QueueOperation::from_str(op.as_str()).unwrap()) // TODO no unwrap
                     .unwrap_or(QueueOperation::Add);
 
+                let limit = row.limit_key.map(|key| JobLimit {
+                    key,
+                    max_concurrent: row.limit_concurrency.map(|c| c as u32),
+                    rate: row
+                        .limit_rate
+                        .zip(row.limit_rate_period)
+                        .map(|(max, period)| RateLimit {
+                            max: max as u32,
+                            period: Duration::from_millis(period as u64),
+                        }),
+                });
+
+                let idempotency = row
+                    .idempotency_mode
+                    .zip(row.idempotency_window)
+                    .map(|(mode, window)| {
+                        Ok::<_, Error>(Idempotency {
+                            mode: DuplicateMode::from_str(&mode)
+                                .map_err(|e| Error::DrainError(anyhow::anyhow!(e)))?,
+                            window: Duration::from_millis(window as u64),
+                        })
+                    })
+                    .transpose()?;
+
                 let payload = match (&operation, row.payload.as_ref()) {
                     (QueueOperation::Update, None) => Cow::Borrowed("".as_bytes()),
                     (_, None) => Cow::Borrowed("null".as_bytes()),
@@ -174,6 +254,9 @@ impl Drainer for QueueDrainer {
                         max_retries: row.max_retries.map(|r| r as u32),
                         timeout: row.timeout.map(|t| Duration::from_millis(t as u64)),
                         payload,
+                        limit,
+                        priority: JobPriority::from_band(row.priority),
+                        idempotency,
                     },
                 })
             })
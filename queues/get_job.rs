@@ -6,22 +6,115 @@ use crate::error::Error;
 
 use super::Queue;
 
+// Take the first job from the pending lists that is allowed to run under the queue's limits
+// and the job's own limits. Per-key limit state lives in keys built from the key prefix,
+// since the keys to check depend on which jobs are pending.
+//
+// The pending lists are checked in priority order, so a lower priority job only runs when no
+// higher priority job is able to run.
+//
 // KEYS:
-//  1. pending items list
-//  2. processing list
-//  3. job data hash
+//  1. processing list
+//  2. queue stats hash
+//  3. queue rate limit window
+//  4-6. pending lists, from high to low priority
 // ARGV:
 //  1. queue-default expiration time
+//  2. current time
+//  3. maximum number of pending jobs to look at in each list
+//  4. job data key prefix
+//  5. limit key prefix
+//  6. queue concurrency limit, or 0 for none
+//  7. queue rate limit, or 0 for none
+//  8. queue rate limit period
 const DEQUEUE_ITEM_SCRIPT: &str = r##"
-    local latest_item = redis.call("LPOP", KEYS[1])
-    if latest_item == false then
+    local now = tonumber(ARGV[2])
+
+    -- Count the jobs that are still running. Jobs whose expiration has passed are
+    -- considered dead so that a crashed worker doesn't hold a slot forever.
+    local function running_count(key)
+        local count = 0
+        for _, id in ipairs(redis.call("SMEMBERS", key)) do
+            local expires = redis.call("ZSCORE", KEYS[1], id)
+            if expires and tonumber(expires) > now then
+                count = count + 1
+            else
+                redis.call("SREM", key, id)
+            end
+        end
+        return count
+    end
+
+    local function rate_available(key, max, period)
+        redis.call("ZREMRANGEBYSCORE", key, "-inf", now - period)
+        return redis.call("ZCARD", key) < max
+    end
+
+    local function record_rate(key, id, period)
+        redis.call("ZADD", key, now, id .. ":" .. now)
+        redis.call("PEXPIRE", key, period)
+    end
+
+    local queue_max_concurrent = tonumber(ARGV[6])
+    if queue_max_concurrent > 0 and redis.call("ZCOUNT", KEYS[1], "(" .. now, "+inf") >= queue_max_concurrent then
+        return false
+    end
+
+    local queue_rate = tonumber(ARGV[7])
+    local queue_rate_period = tonumber(ARGV[8])
+    if queue_rate > 0 and not rate_available(KEYS[3], queue_rate, queue_rate_period) then
         return false
     end
 
-    -- Set the default queue expiration. The job worker will update it if needed
-    redis.call("ZADD", KEYS[2], tonumber(ARGV[1]), latest_item)
-    redis.call("HINCRBY", KEYS[3], "retrieved", 1)
-    return latest_item
+    local function try_start(pending_list, id)
+        local limits = redis.call("HMGET", ARGV[4] .. id, "lk", "lc", "lr", "lp")
+        local allowed = true
+        local running_key = nil
+        local rate_key = nil
+        if limits[1] then
+            local prefix = ARGV[5] .. limits[1]
+            if limits[2] then
+                running_key = prefix .. ":running"
+                allowed = running_count(running_key) < tonumber(limits[2])
+            end
+
+            if allowed and limits[3] then
+                rate_key = prefix .. ":rate"
+                allowed = rate_available(rate_key, tonumber(limits[3]), tonumber(limits[4]))
+            end
+        end
+
+        if allowed then
+            redis.call("LREM", pending_list, 1, id)
+            if running_key then
+                redis.call("SADD", running_key, id)
+            end
+            if rate_key then
+                record_rate(rate_key, id, tonumber(limits[4]))
+            end
+            if queue_rate > 0 then
+                record_rate(KEYS[3], id, queue_rate_period)
+            end
+
+            -- Set the default queue expiration. The job worker will update it if needed
+            redis.call("ZADD", KEYS[1], tonumber(ARGV[1]), id)
+            redis.call("HINCRBY", KEYS[2], "retrieved", 1)
+            return true
+        end
+
+        return false
+    end
+
+    for band = 4, 6 do
+        local candidates = redis.call("LRANGE", KEYS[band], 0, tonumber(ARGV[3]) - 1)
+        for _, id in ipairs(candidates) do
+            if try_start(KEYS[band], id) then
+                return id
+            end
+        end
+    end
+
+    return false
 "##;
 
 lazy_static! {
@@ -42,12 +135,27 @@ impl GetJobScript {
         now: &DateTime<Utc>,
     ) -> Result<Option<String>, Error> {
         let now_millis = now.timestamp_millis();
+        let limits = &queue.0.limits;
+        let (rate, rate_period) = limits
+            .rate
+            .as_ref()
+            .map(|r| (r.max, r.period.as_millis() as u64))
+            .unwrap_or((0, 0));
+
         let job_id: Option<String> = self
             .0
-            .key(&queue.0.pending_list)
             .key(&queue.0.processing_list)
             .key(&queue.0.stats_hash)
+            .key(&queue.0.rate_limit_window)
+            .key(queue.0.pending_lists.as_slice())
             .arg(now_millis + queue.0.processing_timeout.as_millis() as i64)
+            .arg(now_millis)
+            .arg(limits.scan_depth.get())
+            .arg(&queue.0.job_data_prefix)
+            .arg(&queue.0.limit_key_prefix)
+            .arg(limits.max_concurrent.unwrap_or(0))
+            .arg(rate)
+            .arg(rate_period)
             .invoke_async(&mut **conn)
             .await?;
 
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use ergo_database::new_uuid;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, time::Duration};
 
 #[derive(Default)]
@@ -11,6 +11,117 @@ pub struct Job<'a> {
     pub max_retries: Option<u32>,
     pub run_at: Option<DateTime<Utc>>,
     pub retry_backoff: Option<Duration>,
+    pub limit: Option<JobLimit>,
+    pub priority: JobPriority,
+    pub idempotency: Option<Idempotency>,
+}
+
+/// What to do when a job is enqueued with the same ID as a job that was enqueued
+/// within the idempotency window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    /// Ignore the new job.
+    Drop,
+    /// Replace the payload of the earlier job, if it hasn't started yet. Otherwise the new job
+    /// is enqueued under a distinct ID derived from the job ID.
+    Replace,
+    /// Merge the fields of the new payload into the earlier job's payload, if the earlier job
+    /// hasn't started yet. Payloads that aren't JSON objects are replaced. Otherwise the new job
+    /// is enqueued under a distinct ID derived from the job ID.
+    Coalesce,
+}
+
+impl DuplicateMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicateMode::Drop => "drop",
+            DuplicateMode::Replace => "replace",
+            DuplicateMode::Coalesce => "coalesce",
+        }
+    }
+}
+
+impl std::str::FromStr for DuplicateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(DuplicateMode::Drop),
+            "replace" => Ok(DuplicateMode::Replace),
+            "coalesce" => Ok(DuplicateMode::Coalesce),
+            _ => Err(format!("Unknown duplicate mode {}", s)),
+        }
+    }
+}
+
+/// Deduplicate jobs that have the same ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Idempotency {
+    pub mode: DuplicateMode,
+    /// How long after a job is first enqueued that later jobs with the same ID count as
+    /// duplicates.
+    pub window: Duration,
+}
+
+/// The result of enqueueing a job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnqueueOutcome {
+    Enqueued,
+    /// The job was a duplicate and was dropped.
+    Dropped,
+    /// The job was a duplicate and its payload was applied to the earlier job.
+    Updated,
+}
+
+/// Pending jobs with a higher priority are always dequeued before jobs with a lower priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl JobPriority {
+    /// All the priorities, in the order that they are dequeued.
+    pub const ALL: [JobPriority; 3] = [JobPriority::High, JobPriority::Normal, JobPriority::Low];
+
+    /// The index of the pending list that holds jobs with this priority.
+    pub fn band(self) -> usize {
+        match self {
+            JobPriority::High => 0,
+            JobPriority::Normal => 1,
+            JobPriority::Low => 2,
+        }
+    }
+
+    /// Get the priority for a band index. Unknown values are treated as normal priority.
+    pub fn from_band(band: i32) -> Self {
+        match band {
+            0 => JobPriority::High,
+            2 => JobPriority::Low,
+            _ => JobPriority::Normal,
+        }
+    }
+}
+
+/// Allow at most `max` jobs to start within each `period`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max: u32,
+    pub period: Duration,
+}
+
+/// Limits shared by every job with the same key. The limits are checked when the job is
+/// dequeued, so jobs with the same key should use the same limits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobLimit {
+    pub key: String,
+    /// The maximum number of jobs with this key that can run at once.
+    pub max_concurrent: Option<u32>,
+    pub rate: Option<RateLimit>,
 }
 
 impl<'a> std::fmt::Debug for Job<'a> {
@@ -22,6 +133,9 @@ impl<'a> std::fmt::Debug for Job<'a> {
             .field("max_retries", &self.max_retries)
             .field("run_at", &self.run_at)
             .field("retry_backoff", &self.retry_backoff)
+            .field("limit", &self.limit)
+            .field("priority", &self.priority)
+            .field("idempotency", &self.idempotency)
             .finish()
     }
 }
@@ -7,16 +7,17 @@ use crate::error::Error;
 // KEYS:
 //  1. job data key
 //  2. processing list
-//  3. pending list
-//  4. scheduled items list
+//  3. scheduled items list
+//  4-6. pending lists, from high to low priority
 // ARGS:
 //  1. job ID
 //  2. current time
 //  3. cancel the job if it has already started running
 const CANCEL_SCRIPT: &str = r##"
-    local was_pending = redis.call("LREM", KEYS[3], 1, ARGV[1])
+    local band = tonumber(redis.call("HGET", KEYS[1], "pri")) or 1
+    local was_pending = redis.call("LREM", KEYS[4 + band], 1, ARGV[1])
     local was_processing = redis.call("ZREM", KEYS[2], ARGV[1])
-    local was_scheduled = redis.call("ZREM", KEYS[4], ARGV[1])
+    local was_scheduled = redis.call("ZREM", KEYS[3], ARGV[1])
 
     local suc = false
     if was_pending == false and was_processing == false and was_scheduled == false then
@@ -56,8 +57,8 @@ impl JobCancelScript {
             .0
             .key(job_data_key)
             .key(&queue.0.processing_list)
-            .key(&queue.0.pending_list)
             .key(&queue.0.scheduled_list)
+            .key(queue.0.pending_lists.as_slice())
             .arg(job_id)
             .arg(now.timestamp_millis())
             .arg(cancel_if_running)
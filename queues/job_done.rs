@@ -11,6 +11,7 @@ use super::Queue;
 //  2. processing list
 //  3. done list
 //  4. queue stats hash
+//  5. job error history list
 // ARGS:
 //  1. job id
 //  2. current time
@@ -26,6 +27,8 @@ const DONE_SCRIPT: &str = r##"
     redis.call("LPUSH", KEYS[3], ARGV[1])
     redis.call("HSET", KEYS[1], "end", ARGV[2], "suc", "true")
     redis.call("HINCRBY", KEYS[4], "succeeded", 1)
+    -- Errors from earlier attempts are only needed while the job may still fail.
+    redis.call("DEL", KEYS[5])
     return {score, true}
 "##;
 
@@ -55,6 +58,7 @@ impl JobDoneScript {
             .key(&queue.0.processing_list)
             .key(&queue.0.done_list)
             .key(&queue.0.stats_hash)
+            .key(queue.job_errors_key(job_id))
             .arg(job_id)
             .arg(now.timestamp_millis())
             .arg(expected_expiration.timestamp_millis())
@@ -9,8 +9,9 @@ use super::Queue;
 //  1. job data key
 //  2. processing list
 //  3. scheduled items list
-//  4. done items list
+//  4. dead letter list
 //  5. stats hash
+//  6. job error history list
 // ARGS:
 //  1. job ID
 //  2. current time
@@ -29,10 +30,16 @@ const ERROR_SCRIPT: &str = r##"
     local retry = tonumber(retries[1])
     local max_retries = tonumber(retries[2])
     redis.call("HINCRBY", KEYS[5], "errored", 1)
+    redis.call("RPUSH", KEYS[6], cjson.encode({ retry = retry, time = tonumber(ARGV[2]), error = ARGV[4] }))
+    -- Expire the error history along with the job data, if the job data has an expiration.
+    local ttl = redis.call("PTTL", KEYS[1])
+    if ttl > 0 then
+        redis.call("PEXPIRE", KEYS[6], ttl)
+    end
     if retry >= max_retries then
-        -- No more retries. Mark the job failed.
+        -- No more retries. Mark the job failed and move it to the dead letter list.
         redis.call("HSET", KEYS[1], "err", ARGV[4], "end", ARGV[2], "suc", "false")
-        redis.call("LPUSH", KEYS[4], ARGV[1])
+        redis.call("ZADD", KEYS[4], ARGV[2], ARGV[1])
         redis.call("HINCRBY", KEYS[5], "failed", 1)
         return {retry, -1}
     else
@@ -72,8 +79,9 @@ impl JobErrorScript {
             .key(job_data_key)
             .key(&queue.0.processing_list)
             .key(&queue.0.scheduled_list)
-            .key(&queue.0.done_list)
+            .key(&queue.0.dead_list)
             .key(&queue.0.stats_hash)
+            .key(queue.job_errors_key(job_id))
             .arg(job_id)
             .arg(now.timestamp_millis())
             .arg(expected_expiration.timestamp_millis())
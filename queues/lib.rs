@@ -1,9 +1,12 @@
 pub mod generic_stage;
 pub mod job;
+pub mod metrics;
 pub mod postgres_drain;
 mod update_stage;
 pub mod work_item;
 
+mod dead_letter;
+mod dedup;
 mod dequeuer_loop;
 mod enqueue_scheduled;
 mod error;
@@ -25,6 +28,7 @@ pub use self::{
 };
 
 use std::{
+    borrow::Cow,
     num::NonZeroU32,
     sync::{Arc, Mutex},
     time::Duration,
@@ -49,6 +53,7 @@ impl std::fmt::Debug for Queue {
             .field("max_retries", &self.0.max_retries)
             .field("processing_timeout", &self.0.processing_timeout)
             .field("retry_backoff", &self.0.retry_backoff)
+            .field("limits", &self.0.limits)
             .finish()
     }
 }
@@ -56,12 +61,19 @@ impl std::fmt::Debug for Queue {
 struct QueueInner {
     pool: RedisPool,
     name: String,
-    pending_list: String,
+    /// The pending lists for each priority, in the order that they are dequeued.
+    pending_lists: [String; 3],
     scheduled_list: String,
     processing_list: String,
     done_list: String,
+    dead_list: String,
     stats_hash: String,
     job_data_prefix: String,
+    job_errors_prefix: String,
+    dedup_prefix: String,
+    limit_key_prefix: String,
+    rate_limit_window: String,
+    limits: QueueLimits,
     processing_timeout: Duration,
     max_retries: u32,
     retry_backoff: Duration,
@@ -72,11 +84,36 @@ struct QueueInner {
     error_script: job_error::JobErrorScript,
     cancel_script: job_cancel::JobCancelScript,
     update_script: update_job::UpdateJobScript,
+    requeue_dead_script: dead_letter::RequeueDeadScript,
+    purge_dead_script: dead_letter::PurgeDeadScript,
+    dedup_script: dedup::DedupScript,
 
     scheduled_job_enqueuer_task: Mutex<Option<(oneshot::Sender<()>, JoinHandle<()>)>>,
     job_dequeuer_task: Mutex<Option<(oneshot::Sender<()>, JoinHandle<()>)>>,
 }
 
+/// Limits on how quickly jobs are taken from the queue. These are enforced in Redis, so every
+/// process using the queue shares the same budget.
+#[derive(Clone, Debug)]
+pub struct QueueLimits {
+    /// The maximum number of jobs from this queue that can run at once.
+    pub max_concurrent: Option<u32>,
+    pub rate: Option<RateLimit>,
+    /// How many pending jobs to look at when the first jobs in the queue are blocked by their
+    /// own limits.
+    pub scan_depth: NonZeroU32,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits {
+            max_concurrent: None,
+            rate: None,
+            scan_depth: NonZeroU32::new(100).unwrap(),
+        }
+    }
+}
+
 pub enum JobStatus {
     Inactive,
     Pending,
@@ -102,11 +139,31 @@ pub struct JobTrackingData {
     pub error_details: Option<String>,
 }
 
+/// The error from a single failed attempt at running a job.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobAttemptError {
+    /// The retry count at the time of the attempt. The first attempt is 0.
+    pub retry: u32,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    pub error: String,
+}
+
+/// A job which exhausted its retries and was moved to the dead letter list.
+#[derive(Debug, Serialize)]
+pub struct DeadJob {
+    pub job: JobTrackingData,
+    pub died_at: DateTime<Utc>,
+    /// The errors from every attempt at running the job, oldest first.
+    pub errors: Vec<JobAttemptError>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct QueueStatus {
     pub current_running: usize,
     pub current_scheduled: usize,
     pub current_pending: usize,
+    pub current_dead: usize,
 
     pub total_retrieved: usize,
     pub total_enqueued: usize,
@@ -123,15 +180,43 @@ impl Queue {
         default_timeout: Option<Duration>,
         default_max_retries: Option<u32>,
         default_retry_backoff: Option<Duration>,
+    ) -> Queue {
+        Self::with_limits(
+            pool,
+            queue_name,
+            default_timeout,
+            default_max_retries,
+            default_retry_backoff,
+            QueueLimits::default(),
+        )
+    }
+
+    pub fn with_limits(
+        pool: RedisPool,
+        queue_name: String,
+        default_timeout: Option<Duration>,
+        default_max_retries: Option<u32>,
+        default_retry_backoff: Option<Duration>,
+        limits: QueueLimits,
     ) -> Queue {
         Queue(Arc::new(QueueInner {
             pool,
-            pending_list: format!("erq:{}:pending", queue_name),
+            pending_lists: [
+                format!("erq:{}:pending:high", queue_name),
+                format!("erq:{}:pending", queue_name),
+                format!("erq:{}:pending:low", queue_name),
+            ],
             scheduled_list: format!("erq:{}:scheduled", queue_name),
             processing_list: format!("erq:{}:processing", queue_name),
             done_list: format!("erq:{}:done", queue_name),
+            dead_list: format!("erq:{}:dead", queue_name),
             stats_hash: format!("erq:{}:stats", queue_name),
             job_data_prefix: format!("erq:{}:job:", queue_name),
+            job_errors_prefix: format!("erq:{}:joberrors:", queue_name),
+            dedup_prefix: format!("erq:{}:dedup:", queue_name),
+            limit_key_prefix: format!("erq:{}:limit:", queue_name),
+            rate_limit_window: format!("erq:{}:rate", queue_name),
+            limits,
             processing_timeout: default_timeout.unwrap_or_else(|| Duration::from_secs_f64(120.0)),
             max_retries: default_max_retries.unwrap_or(3),
             retry_backoff: default_retry_backoff.unwrap_or_else(|| Duration::from_millis(30000)),
@@ -142,6 +227,9 @@ impl Queue {
             error_script: job_error::JobErrorScript::new(),
             cancel_script: job_cancel::JobCancelScript::new(),
             update_script: update_job::UpdateJobScript::new(),
+            requeue_dead_script: dead_letter::RequeueDeadScript::new(),
+            purge_dead_script: dead_letter::PurgeDeadScript::new(),
+            dedup_script: dedup::DedupScript::new(),
             scheduled_job_enqueuer_task: Mutex::new(None),
             job_dequeuer_task: Mutex::new(None),
             name: queue_name,
@@ -152,36 +240,49 @@ impl Queue {
         self.0.name.as_str()
     }
 
-    fn add_id_to_queue(&self, pipe: &mut redis::Pipeline, job: &'_ Job<'_>) {
+    fn add_id_to_queue(&self, pipe: &mut redis::Pipeline, job_id: &str, job: &'_ Job<'_>) {
         if let Some(timestamp) = job.run_at {
-            pipe.zadd(
-                &self.0.scheduled_list,
-                &job.id,
-                timestamp.timestamp_millis(),
-            );
+            pipe.zadd(&self.0.scheduled_list, job_id, timestamp.timestamp_millis());
         } else {
-            pipe.lpush(&self.0.pending_list, &job.id);
+            pipe.lpush(self.pending_list(job.priority), job_id);
         }
     }
 
+    fn pending_list(&self, priority: JobPriority) -> &str {
+        &self.0.pending_lists[priority.band()]
+    }
+
     fn job_data_key(&self, job_id: &str) -> String {
         format!("{}{}", self.0.job_data_prefix, job_id)
     }
 
-    fn initial_job_data_cmd(&self, job: &Job) -> redis::Cmd {
-        let key = self.job_data_key(job.id.as_str());
+    fn job_errors_key(&self, job_id: &str) -> String {
+        format!("{}{}", self.0.job_errors_prefix, job_id)
+    }
+
+    fn dedup_key(&self, job_id: &str) -> String {
+        format!("{}{}", self.0.dedup_prefix, job_id)
+    }
+
+    fn initial_job_data_cmd(&self, job_id: &str, job: &Job) -> redis::Cmd {
+        let key = self.job_data_key(job_id);
         let mut cmd = RedisJobSetCmd::new(&key)
             .payload(job.payload.as_ref())
             .timeout(job.timeout.unwrap_or(self.0.processing_timeout))
             .current_retries(0)
             .max_retries(job.max_retries.unwrap_or(self.0.max_retries))
             .retry_backoff(job.retry_backoff.unwrap_or(self.0.retry_backoff))
+            .priority(job.priority)
             .enqueued_at(&Utc::now());
 
         if let Some(r) = job.run_at.as_ref() {
             cmd = cmd.run_at(r);
         }
 
+        if let Some(limit) = job.limit.as_ref() {
+            cmd = cmd.limit(limit);
+        }
+
         cmd.build()
     }
 
@@ -190,7 +291,10 @@ impl Queue {
         let (
             current_scheduled,
             current_running,
-            current_pending,
+            pending_high,
+            pending_normal,
+            pending_low,
+            current_dead,
             (
                 total_retrieved,
                 total_enqueued,
@@ -200,6 +304,9 @@ impl Queue {
                 total_errored,
             ),
         ): (
+            usize,
+            usize,
+            usize,
             usize,
             usize,
             usize,
@@ -211,13 +318,19 @@ impl Queue {
                 Option<usize>,
                 Option<usize>,
             ),
-        ) = redis::Pipeline::with_capacity(4)
+        ) = redis::Pipeline::with_capacity(7)
             .cmd("ZCARD")
             .arg(&self.0.scheduled_list)
             .cmd("ZCARD")
             .arg(&self.0.processing_list)
             .cmd("LLEN")
-            .arg(&self.0.pending_list)
+            .arg(&self.0.pending_lists[0])
+            .cmd("LLEN")
+            .arg(&self.0.pending_lists[1])
+            .cmd("LLEN")
+            .arg(&self.0.pending_lists[2])
+            .cmd("ZCARD")
+            .arg(&self.0.dead_list)
             .cmd("HMGET")
             .arg(&[
                 &self.0.stats_hash,
@@ -234,7 +347,8 @@ impl Queue {
         Ok(QueueStatus {
             current_running,
             current_scheduled,
-            current_pending,
+            current_pending: pending_high + pending_normal + pending_low,
+            current_dead,
             total_retrieved: total_retrieved.unwrap_or(0),
             total_enqueued: total_enqueued.unwrap_or(0),
             total_scheduled: total_scheduled.unwrap_or(0),
@@ -274,39 +388,239 @@ impl Queue {
             .collect::<Result<Vec<_>, Error>>()
     }
 
+    /// List the pending jobs, with higher priority jobs first.
     pub async fn list_pending(&self) -> Result<Vec<String>, Error> {
-        self.0
+        let mut conn = self.0.pool.get().await?;
+        let mut pending = Vec::new();
+        for list in &self.0.pending_lists {
+            let jobs: Vec<String> = conn.lrange(list, 0, -1).await?;
+            pending.extend(jobs);
+        }
+
+        Ok(pending)
+    }
+
+    /// List the jobs in the dead letter list, along with the time that each one failed.
+    /// Jobs whose data has expired are removed from the list and omitted.
+    pub async fn list_dead(&self) -> Result<Vec<(String, DateTime<Utc>)>, Error> {
+        let mut conn = self.0.pool.get().await?;
+        let list: Vec<String> = conn.zrange_withscores(&self.0.dead_list, 0, -1).await?;
+        let list = list
+            .into_iter()
+            .tuples::<(_, _)>()
+            .map(|chunk| Ok((chunk.0, Utc.timestamp_millis(chunk.1.parse::<i64>()?))))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if list.is_empty() {
+            return Ok(list);
+        }
+
+        let mut pipe = redis::pipe();
+        for (id, _) in &list {
+            pipe.exists(self.job_data_key(id));
+        }
+        let exists: Vec<bool> = pipe.query_async(&mut conn).await?;
+
+        let (live, orphaned): (Vec<_>, Vec<_>) = list
+            .into_iter()
+            .zip(exists)
+            .partition(|(_, exists)| *exists);
+
+        if !orphaned.is_empty() {
+            let ids = orphaned.iter().map(|((id, _), _)| id).collect::<Vec<_>>();
+            conn.zrem::<_, _, ()>(&self.0.dead_list, ids).await?;
+        }
+
+        Ok(live.into_iter().map(|(entry, _)| entry).collect())
+    }
+
+    /// Get the errors from each attempt at running a job, oldest first.
+    pub async fn job_errors(&self, job_id: &str) -> Result<Vec<JobAttemptError>, Error> {
+        let errors: Vec<String> = self
+            .0
             .pool
             .get()
             .await?
-            .lrange(&self.0.pending_list, 0, -1)
+            .lrange(self.job_errors_key(job_id), 0, -1)
+            .await?;
+
+        errors
+            .iter()
+            .map(|e| serde_json::from_str(e).map_err(Error::from))
+            .collect()
+    }
+
+    /// Get a job from the dead letter list, with its payload and the error from every attempt.
+    pub async fn dead_job(&self, job_id: &str) -> Result<Option<DeadJob>, Error> {
+        let died_at: Option<i64> = self
+            .0
+            .pool
+            .get()
+            .await?
+            .zscore(&self.0.dead_list, job_id)
+            .await?;
+        let died_at = match died_at {
+            Some(d) => Utc.timestamp_millis(d),
+            None => return Ok(None),
+        };
+
+        let job = match self.job_info(job_id).await? {
+            Some(job) => job,
+            None => {
+                // The job data expired, so drop the stale entry from the dead letter list.
+                self.0
+                    .pool
+                    .get()
+                    .await?
+                    .zrem::<_, _, ()>(&self.0.dead_list, job_id)
+                    .await?;
+                return Ok(None);
+            }
+        };
+        let errors = self.job_errors(job_id).await?;
+
+        Ok(Some(DeadJob {
+            job,
+            died_at,
+            errors,
+        }))
+    }
+
+    /// Move a job from the dead letter list back to the pending list, with its retry count
+    /// reset. Returns false if the job was not in the dead letter list.
+    pub async fn requeue_dead_job(&self, job_id: &str) -> Result<bool, Error> {
+        let mut conn = self.0.pool.get().await?;
+        self.0
+            .requeue_dead_script
+            .run(self, &mut conn, job_id, &Utc::now())
             .await
-            .map_err(Error::from)
     }
 
-    pub async fn enqueue(&self, item: &'_ Job<'_>) -> Result<(), Error> {
-        let mut pipe = redis::Pipeline::with_capacity(2);
+    /// Delete a job in the dead letter list. Returns false if the job was not in the
+    /// dead letter list.
+    pub async fn purge_dead_job(&self, job_id: &str) -> Result<bool, Error> {
+        let mut conn = self.0.pool.get().await?;
+        self.0.purge_dead_script.run(self, &mut conn, job_id).await
+    }
+
+    /// Delete every job in the dead letter list, and return the number of jobs deleted.
+    pub async fn purge_all_dead_jobs(&self) -> Result<usize, Error> {
+        let mut conn = self.0.pool.get().await?;
+        let ids: Vec<String> = conn.zrange(&self.0.dead_list, 0, -1).await?;
+
+        let mut purged = 0;
+        for id in ids {
+            if self.0.purge_dead_script.run(self, &mut conn, &id).await? {
+                purged += 1;
+            }
+        }
+
+        Ok(purged)
+    }
+
+    /// Run the duplicate check for a job with idempotency rules. Returns the ID to enqueue
+    /// the job with, or the outcome if the job was a duplicate.
+    async fn dedup_job<'a>(
+        &self,
+        conn: &mut deadpool_redis::Connection,
+        item: &'a Job<'_>,
+    ) -> Result<Result<Cow<'a, str>, EnqueueOutcome>, Error> {
+        let idempotency = match item.idempotency.as_ref() {
+            Some(idempotency) => idempotency,
+            None => return Ok(Ok(Cow::Borrowed(item.id.as_str()))),
+        };
+
+        let result = self
+            .0
+            .dedup_script
+            .run(self, conn, item, idempotency)
+            .await?
+            .map(Cow::Owned);
+        if let Err(outcome) = &result {
+            event!(Level::DEBUG, queue=%self.0.name, job=%item.id, ?outcome, "Deduplicated job");
+        }
+
+        Ok(result)
+    }
+
+    /// Release the dedup markers of jobs that failed to enqueue, so that retrying them works.
+    async fn release_dedup_markers(&self, jobs: &[(&Job<'_>, Cow<'_, str>)]) {
+        let result = async {
+            let mut conn = self.0.pool.get().await?;
+            for (item, job_id) in jobs {
+                if item.idempotency.is_some() {
+                    self.0
+                        .dedup_script
+                        .release(self, &mut conn, item, job_id)
+                        .await?;
+                }
+            }
+            Ok::<(), Error>(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            event!(Level::ERROR, queue=%self.0.name, error=?e, "Failed to release dedup markers");
+        }
+    }
 
-        pipe.add_command(self.initial_job_data_cmd(item));
-        self.add_id_to_queue(&mut pipe, item);
+    pub async fn enqueue(&self, item: &'_ Job<'_>) -> Result<EnqueueOutcome, Error> {
+        let mut conn = self.0.pool.get().await?;
+        let job_id = match self.dedup_job(&mut conn, item).await? {
+            Ok(id) => id,
+            Err(outcome) => return Ok(outcome),
+        };
+
+        let mut pipe = redis::Pipeline::with_capacity(2);
+        pipe.atomic();
+        pipe.add_command(self.initial_job_data_cmd(&job_id, item));
+        self.add_id_to_queue(&mut pipe, &job_id, item);
         pipe.cmd("HINCRBY")
             .arg(&[&self.0.stats_hash, "enqueued", "1"]);
 
-        let mut conn = self.0.pool.get().await?;
-        pipe.query_async(&mut conn).await?;
-        Ok(())
+        if let Err(e) = pipe.query_async::<_, ()>(&mut conn).await {
+            self.release_dedup_markers(&[(item, job_id)]).await;
+            return Err(e.into());
+        }
+
+        Ok(EnqueueOutcome::Enqueued)
     }
 
+    /// Enqueue several jobs at once. Jobs with idempotency rules are checked for duplicates
+    /// first, and then every job that should be enqueued is added in a single transaction.
     pub async fn enqueue_multiple(&self, items: &'_ [Job<'_>]) -> Result<(), Error> {
-        let mut pipe = redis::Pipeline::with_capacity(items.len() * 2);
-
+        let mut conn = self.0.pool.get().await?;
+        let mut jobs = Vec::with_capacity(items.len());
         for item in items {
-            pipe.add_command(self.initial_job_data_cmd(item));
-            self.add_id_to_queue(&mut pipe, item);
+            match self.dedup_job(&mut conn, item).await {
+                Ok(Ok(job_id)) => jobs.push((item, job_id)),
+                Ok(Err(_)) => {}
+                Err(e) => {
+                    self.release_dedup_markers(&jobs).await;
+                    return Err(e);
+                }
+            }
         }
 
-        let mut conn = self.0.pool.get().await?;
-        pipe.query_async(&mut conn).await?;
+        if jobs.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::Pipeline::with_capacity(jobs.len() * 2 + 1);
+        pipe.atomic();
+        for (item, job_id) in &jobs {
+            pipe.add_command(self.initial_job_data_cmd(job_id, item));
+            self.add_id_to_queue(&mut pipe, job_id, item);
+        }
+        pipe.cmd("HINCRBY")
+            .arg(&self.0.stats_hash)
+            .arg("enqueued")
+            .arg(jobs.len());
+
+        if let Err(e) = pipe.query_async::<_, ()>(&mut conn).await {
+            self.release_dedup_markers(&jobs).await;
+            return Err(e.into());
+        }
 
         Ok(())
     }
@@ -435,12 +749,23 @@ impl Queue {
         job_id_key: &str,
         now: &DateTime<Utc>,
     ) -> Result<QueueWorkItem<T>, Error> {
-        let (payload, expiration, current_retry, max_retries) = self
+        let start_work::StartedJob {
+            payload,
+            expiration,
+            current_retry,
+            max_retries,
+            ready_at,
+        } = self
             .0
             .start_work_script
             .run(self, conn, job_id, job_id_key, now)
             .await?;
 
+        let waited = (*now - ready_at).to_std().unwrap_or_default();
+        metrics::JOB_WAIT_SECONDS
+            .with_label_values(&[self.name()])
+            .observe(waited.as_secs_f64());
+
         let item = QueueWorkItem::new(
             self.clone(),
             job_id,
@@ -673,6 +998,15 @@ mod tests {
     }
 
     async fn run_queue_test<T, Fut, E>(test: T)
+    where
+        T: Send + Sync + FnOnce(Queue) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Debug,
+    {
+        run_limited_queue_test(QueueLimits::default(), test).await
+    }
+
+    async fn run_limited_queue_test<T, Fut, E>(limits: QueueLimits, test: T)
     where
         T: Send + Sync + FnOnce(Queue) -> Fut,
         Fut: Future<Output = Result<(), E>>,
@@ -681,7 +1015,7 @@ mod tests {
         dotenv::dotenv().ok();
         let queue_name = format!("test-{}", uuid::Uuid::new_v4());
         let pool = ergo_database::RedisPool::new(None, None).expect("Creating connection pool");
-        let queue = Queue::new(pool.clone(), queue_name.clone(), None, None, None);
+        let queue = Queue::with_limits(pool.clone(), queue_name.clone(), None, None, None, limits);
 
         let result = std::panic::AssertUnwindSafe(test(queue))
             .catch_unwind()
@@ -863,4 +1197,583 @@ mod tests {
         })
         .await;
     }
+
+    #[tokio::test]
+    async fn dead_letter() {
+        run_queue_test(|queue| async move {
+            let id = "a-failing-job".to_string();
+            let job = Job {
+                id: id.clone(),
+                payload: SimplePayload::generate()?,
+                max_retries: Some(1),
+                retry_backoff: Some(std::time::Duration::from_millis(0)),
+                ..Default::default()
+            };
+            queue.enqueue(&job).await?;
+
+            for attempt in 0..2 {
+                queue.enqueue_scheduled_items().await?;
+                let mut job = queue
+                    .get_job::<SimplePayload>()
+                    .await?
+                    .expect("job should be ready");
+                let result = job
+                    .process(move |_, _| async move {
+                        Err::<(), _>(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("failure {}", attempt),
+                        ))
+                    })
+                    .await;
+                assert!(result.is_err(), "job should fail");
+            }
+
+            let status = queue.status().await?;
+            assert_eq!(status.current_dead, 1, "job is dead lettered");
+            assert_eq!(status.current_scheduled, 0, "job is not scheduled to retry");
+
+            let dead = queue.list_dead().await?;
+            assert_eq!(dead.len(), 1);
+            assert_eq!(dead[0].0, id);
+
+            let dead_job = queue
+                .dead_job(&id)
+                .await?
+                .expect("dead job info should exist");
+            assert_eq!(dead_job.job.payload, br##"{"data":"A test string"}"##);
+            assert_eq!(dead_job.job.succeeded, Some(false));
+            let errors = dead_job
+                .errors
+                .iter()
+                .map(|e| (e.retry, e.error.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(errors, vec![(0, "failure 0"), (1, "failure 1")]);
+
+            assert!(queue.requeue_dead_job(&id).await?, "requeueing dead job");
+            assert!(
+                !queue.requeue_dead_job(&id).await?,
+                "requeueing job that is no longer dead"
+            );
+            assert!(queue.dead_job(&id).await?.is_none());
+
+            let info = queue.job_info(&id).await?.expect("job info should exist");
+            assert_eq!(info.retry_count, 0, "retries are reset");
+            assert_eq!(info.succeeded, None);
+
+            let mut job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("requeued job should be pending");
+            let result = job
+                .process(|_, _| async move {
+                    Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Other, "fatal"))
+                })
+                .await;
+            assert!(result.is_err(), "job should fail");
+            assert_eq!(
+                queue.job_errors(&id).await?.len(),
+                3,
+                "error history is kept"
+            );
+
+            queue.enqueue_scheduled_items().await?;
+            let mut job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("job should be ready for retry");
+            let _ = job
+                .process(|_, _| async move {
+                    Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Other, "fatal"))
+                })
+                .await;
+            assert_eq!(queue.list_dead().await?.len(), 1, "job is dead again");
+
+            assert_eq!(queue.purge_all_dead_jobs().await?, 1);
+            assert!(queue.list_dead().await?.is_empty());
+            assert!(queue.job_info(&id).await?.is_none(), "job data is deleted");
+            assert!(
+                queue.job_errors(&id).await?.is_empty(),
+                "job errors are deleted"
+            );
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn expired_dead_jobs_removed() {
+        run_queue_test(|queue| async move {
+            let id = "an-expired-dead-job".to_string();
+            let job = Job {
+                id: id.clone(),
+                payload: SimplePayload::generate()?,
+                max_retries: Some(0),
+                ..Default::default()
+            };
+            queue.enqueue(&job).await?;
+
+            let mut job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("job should be ready");
+            let result = job
+                .process(|_, _| async move {
+                    Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Other, "fatal"))
+                })
+                .await;
+            assert!(result.is_err(), "job should fail");
+            assert_eq!(queue.list_dead().await?.len(), 1, "job is dead lettered");
+
+            let mut conn = queue.0.pool.get().await?;
+            conn.del::<_, ()>(queue.job_data_key(&id)).await?;
+
+            assert!(queue.list_dead().await?.is_empty());
+            assert_eq!(queue.status().await?.current_dead, 0, "entry is removed");
+            assert!(queue.dead_job(&id).await?.is_none());
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn job_errors_cleaned_up() {
+        run_queue_test(|queue| async move {
+            let id = "a-flaky-job".to_string();
+            let job = Job {
+                id: id.clone(),
+                payload: SimplePayload::generate()?,
+                max_retries: Some(2),
+                retry_backoff: Some(std::time::Duration::from_millis(0)),
+                ..Default::default()
+            };
+            queue.enqueue(&job).await?;
+
+            // The error history follows the expiration of the job data.
+            let mut conn = queue.0.pool.get().await?;
+            redis::cmd("PEXPIRE")
+                .arg(queue.job_data_key(&id))
+                .arg(60_000)
+                .query_async::<_, ()>(&mut conn)
+                .await?;
+
+            let mut job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("job should be ready");
+            let result = job
+                .process(|_, _| async move {
+                    Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Other, "flaky"))
+                })
+                .await;
+            assert!(result.is_err(), "job should fail");
+            assert_eq!(queue.job_errors(&id).await?.len(), 1);
+
+            let ttl: i64 = redis::cmd("PTTL")
+                .arg(queue.job_errors_key(&id))
+                .query_async(&mut conn)
+                .await?;
+            assert!(
+                ttl > 0 && ttl <= 60_000,
+                "error history has a TTL, saw {}",
+                ttl
+            );
+
+            queue.enqueue_scheduled_items().await?;
+            let mut job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("job should be ready for retry");
+            job.process(|_, _| async move { Ok::<(), Error>(()) })
+                .await?;
+
+            assert!(
+                queue.job_errors(&id).await?.is_empty(),
+                "error history is deleted when the job succeeds"
+            );
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    fn limited_job(id: &str, limit: Option<JobLimit>) -> Result<Job<'static>, Error> {
+        Ok(Job {
+            id: id.to_string(),
+            payload: SimplePayload::generate()?,
+            limit,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn key_concurrency_limit() {
+        run_queue_test(|queue| async move {
+            let limit = JobLimit {
+                key: "account-1".to_string(),
+                max_concurrent: Some(1),
+                rate: None,
+            };
+            queue
+                .enqueue(&limited_job("limited-1", Some(limit.clone()))?)
+                .await?;
+            queue
+                .enqueue(&limited_job("limited-2", Some(limit.clone()))?)
+                .await?;
+
+            let mut first = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("first limited job should run");
+            assert!(
+                queue.get_job::<SimplePayload>().await?.is_none(),
+                "second limited job should wait"
+            );
+
+            queue.enqueue(&limited_job("unlimited", None)?).await?;
+            let unlimited = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("unlimited job should not be blocked");
+            assert_eq!(unlimited.id, "unlimited");
+
+            first
+                .process(|_, _| async move { Ok::<(), Error>(()) })
+                .await?;
+
+            let second = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("second limited job should run after the first finishes");
+            assert_ne!(second.id, first.id);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn key_rate_limit() {
+        run_queue_test(|queue| async move {
+            let limit = JobLimit {
+                key: "account-1".to_string(),
+                max_concurrent: None,
+                rate: Some(RateLimit {
+                    max: 2,
+                    period: std::time::Duration::from_secs(60),
+                }),
+            };
+
+            for i in 0..3 {
+                queue
+                    .enqueue(&limited_job(&format!("job-{}", i), Some(limit.clone()))?)
+                    .await?;
+            }
+
+            assert!(queue.get_job::<SimplePayload>().await?.is_some());
+            assert!(queue.get_job::<SimplePayload>().await?.is_some());
+            assert!(
+                queue.get_job::<SimplePayload>().await?.is_none(),
+                "third job should be rate limited"
+            );
+            assert_eq!(queue.list_pending().await?.len(), 1);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn queue_limits() {
+        let limits = QueueLimits {
+            max_concurrent: Some(2),
+            rate: Some(RateLimit {
+                max: 3,
+                period: std::time::Duration::from_secs(60),
+            }),
+            ..Default::default()
+        };
+
+        run_limited_queue_test(limits, |queue| async move {
+            for i in 0..4 {
+                queue
+                    .enqueue(&limited_job(&format!("job-{}", i), None)?)
+                    .await?;
+            }
+
+            let mut first = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("first job should run");
+            let mut second = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("second job should run");
+            assert!(
+                queue.get_job::<SimplePayload>().await?.is_none(),
+                "concurrency limit should block third job"
+            );
+
+            first
+                .process(|_, _| async move { Ok::<(), Error>(()) })
+                .await?;
+            assert!(
+                queue.get_job::<SimplePayload>().await?.is_some(),
+                "third job should run after a slot frees up"
+            );
+
+            second
+                .process(|_, _| async move { Ok::<(), Error>(()) })
+                .await?;
+            assert_eq!(queue.list_processing().await?.len(), 1);
+            assert!(
+                queue.get_job::<SimplePayload>().await?.is_none(),
+                "rate limit should block fourth job"
+            );
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn priorities() {
+        run_queue_test(|queue| async move {
+            for (id, priority) in [
+                ("low", JobPriority::Low),
+                ("normal", JobPriority::Normal),
+                ("high", JobPriority::High),
+            ] {
+                let job = Job {
+                    id: id.to_string(),
+                    payload: SimplePayload::generate()?,
+                    priority,
+                    ..Default::default()
+                };
+                queue.enqueue(&job).await?;
+            }
+
+            assert_eq!(queue.list_pending().await?, vec!["high", "normal", "low"]);
+            assert_eq!(queue.status().await?.current_pending, 3);
+
+            for expected in ["high", "normal", "low"] {
+                let job = queue
+                    .get_job::<SimplePayload>()
+                    .await?
+                    .expect("job should be ready");
+                assert_eq!(job.id, expected);
+            }
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn scheduled_job_keeps_priority() {
+        run_queue_test(|queue| async move {
+            let normal = Job {
+                id: "normal".to_string(),
+                payload: SimplePayload::generate()?,
+                ..Default::default()
+            };
+            queue.enqueue(&normal).await?;
+
+            let high = Job {
+                id: "high".to_string(),
+                payload: SimplePayload::generate()?,
+                run_at: Some(Utc::now() - Duration::seconds(1)),
+                priority: JobPriority::High,
+                ..Default::default()
+            };
+            queue.enqueue(&high).await?;
+            queue.enqueue_scheduled_items().await?;
+
+            let job = queue
+                .get_job::<SimplePayload>()
+                .await?
+                .expect("job should be ready");
+            assert_eq!(job.id, "high");
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    fn idempotent_job(value: &str, mode: DuplicateMode) -> Job<'static> {
+        Job {
+            id: "idempotent".to_string(),
+            payload: Cow::Owned(format!(r##"{{"{0}":"{0}"}}"##, value).into_bytes()),
+            idempotency: Some(Idempotency {
+                mode,
+                window: std::time::Duration::from_secs(60),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_drop() {
+        run_queue_test(|queue| async move {
+            let first = queue
+                .enqueue(&idempotent_job("first", DuplicateMode::Drop))
+                .await?;
+            assert_eq!(first, EnqueueOutcome::Enqueued);
+            let second = queue
+                .enqueue(&idempotent_job("second", DuplicateMode::Drop))
+                .await?;
+            assert_eq!(second, EnqueueOutcome::Dropped);
+
+            let info = queue.job_info("idempotent").await?.expect("job exists");
+            assert_eq!(info.payload, br##"{"first":"first"}"##);
+            assert_eq!(queue.list_pending().await?.len(), 1);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_in_batch() {
+        run_queue_test(|queue| async move {
+            let plain = Job {
+                id: "plain".to_string(),
+                payload: SimplePayload::generate()?,
+                ..Default::default()
+            };
+            queue
+                .enqueue_multiple(&[
+                    idempotent_job("first", DuplicateMode::Drop),
+                    plain,
+                    idempotent_job("second", DuplicateMode::Drop),
+                ])
+                .await?;
+
+            let info = queue.job_info("idempotent").await?.expect("job exists");
+            assert_eq!(info.payload, br##"{"first":"first"}"##);
+            assert_eq!(queue.list_pending().await?.len(), 2);
+            assert_eq!(queue.status().await?.total_enqueued, 2);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn released_dedup_marker() {
+        run_queue_test(|queue| async move {
+            let job = idempotent_job("first", DuplicateMode::Drop);
+            let mut conn = queue.0.pool.get().await?;
+
+            // A marker that refers to some other job is left alone.
+            queue.enqueue(&job).await?;
+            queue
+                .0
+                .dedup_script
+                .release(&queue, &mut conn, &job, "another-id")
+                .await?;
+            assert_eq!(queue.enqueue(&job).await?, EnqueueOutcome::Dropped);
+
+            // Once released, the job is no longer treated as a duplicate.
+            queue
+                .0
+                .dedup_script
+                .release(&queue, &mut conn, &job, "idempotent")
+                .await?;
+            assert_eq!(queue.enqueue(&job).await?, EnqueueOutcome::Enqueued);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_replace() {
+        run_queue_test(|queue| async move {
+            queue
+                .enqueue(&idempotent_job("first", DuplicateMode::Replace))
+                .await?;
+            let second = queue
+                .enqueue(&idempotent_job("second", DuplicateMode::Replace))
+                .await?;
+            assert_eq!(second, EnqueueOutcome::Updated);
+
+            let info = queue.job_info("idempotent").await?.expect("job exists");
+            assert_eq!(info.payload, br##"{"second":"second"}"##);
+            assert_eq!(queue.list_pending().await?.len(), 1);
+
+            // Once the job has started, a duplicate runs again afterward.
+            let mut first_job = queue
+                .get_job::<serde_json::Value>()
+                .await?
+                .expect("job should be ready");
+            let third = queue
+                .enqueue(&idempotent_job("third", DuplicateMode::Replace))
+                .await?;
+            assert_eq!(third, EnqueueOutcome::Enqueued);
+
+            // The running job's data is untouched by the new job.
+            let info = queue.job_info("idempotent").await?.expect("job exists");
+            assert_eq!(info.payload, br##"{"second":"second"}"##);
+            assert!(info.started_at.is_some(), "first job is still running");
+
+            // Later duplicates replace the new job while it waits.
+            let fourth = queue
+                .enqueue(&idempotent_job("fourth", DuplicateMode::Replace))
+                .await?;
+            assert_eq!(fourth, EnqueueOutcome::Updated);
+            assert_eq!(queue.list_pending().await?.len(), 1);
+
+            let mut second_job = queue
+                .get_job::<serde_json::Value>()
+                .await?
+                .expect("duplicate job should be ready");
+            assert_ne!(second_job.id, first_job.id);
+
+            first_job
+                .process(|_, payload| async move {
+                    assert_eq!(payload, serde_json::json!({ "second": "second" }));
+                    Ok::<(), Error>(())
+                })
+                .await?;
+            second_job
+                .process(|_, payload| async move {
+                    assert_eq!(payload, serde_json::json!({ "fourth": "fourth" }));
+                    Ok::<(), Error>(())
+                })
+                .await?;
+
+            assert!(queue.list_processing().await?.is_empty());
+            assert!(queue.list_pending().await?.is_empty());
+            let status = queue.status().await?;
+            assert_eq!(status.total_succeeded, 2);
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_coalesce() {
+        run_queue_test(|queue| async move {
+            queue
+                .enqueue(&idempotent_job("first", DuplicateMode::Coalesce))
+                .await?;
+            let second = queue
+                .enqueue(&idempotent_job("second", DuplicateMode::Coalesce))
+                .await?;
+            assert_eq!(second, EnqueueOutcome::Updated);
+
+            let info = queue.job_info("idempotent").await?.expect("job exists");
+            let payload: serde_json::Value = serde_json::from_slice(&info.payload)?;
+            assert_eq!(
+                payload,
+                serde_json::json!({ "first": "first", "second": "second" })
+            );
+
+            Ok::<(), Error>(())
+        })
+        .await;
+    }
 }
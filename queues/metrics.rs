@@ -0,0 +1,36 @@
+//! Prometheus metrics for queue activity. The metrics are registered in the default registry, so
+//! they will be included in the output of `prometheus::gather`.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounterVec,
+};
+
+lazy_static! {
+    /// Time from when a job was ready to run until a worker started it.
+    pub static ref JOB_WAIT_SECONDS: HistogramVec = register_histogram_vec!(
+        "erq_job_wait_seconds",
+        "Time from when a job was ready to run until a worker started it",
+        &["queue"],
+        exponential_buckets(0.005, 4.0, 10).unwrap()
+    )
+    .unwrap();
+
+    /// Time spent processing a job, by outcome.
+    pub static ref JOB_RUN_SECONDS: HistogramVec = register_histogram_vec!(
+        "erq_job_run_seconds",
+        "Time spent processing a job",
+        &["queue", "outcome"],
+        exponential_buckets(0.005, 4.0, 10).unwrap()
+    )
+    .unwrap();
+
+    /// Jobs moved from the Postgres staging table into Redis.
+    pub static ref STAGE_DRAINED_JOBS: IntCounterVec = register_int_counter_vec!(
+        "erq_stage_drained_jobs_total",
+        "Jobs drained from the Postgres staging table",
+        &["queue", "operation"]
+    )
+    .unwrap();
+}
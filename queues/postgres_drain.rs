@@ -15,7 +15,7 @@ use tokio::{
 use tracing::{event, instrument, Level};
 
 use super::{Job, Queue};
-use crate::error::Error;
+use crate::{error::Error, metrics};
 use ergo_graceful_shutdown::GracefulShutdownConsumer;
 
 pub enum QueueOperation {
@@ -24,6 +24,16 @@ pub enum QueueOperation {
     Remove,
 }
 
+impl QueueOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Update => "update",
+            Self::Remove => "remove",
+        }
+    }
+}
+
 impl FromStr for QueueOperation {
     type Err = Infallible;
 
@@ -359,6 +369,16 @@ impl<D: Drainer> StageDrainTask<D> {
         }
         tx.commit().await?;
 
+        self.stats.drained += jobs.len();
+        for DrainResult {
+            queue, operation, ..
+        } in &jobs
+        {
+            metrics::STAGE_DRAINED_JOBS
+                .with_label_values(&[queue.as_ref(), operation.as_str()])
+                .inc();
+        }
+
         Ok::<bool, Error>(true)
     }
 }
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use std::time::Duration;
 
+use crate::{JobLimit, JobPriority};
+
 pub(super) enum RedisJobField {
     Payload,
     Timeout,
@@ -13,6 +15,11 @@ pub(super) enum RedisJobField {
     EndedAt,
     Succeeded,
     ErrorDetails,
+    LimitKey,
+    LimitConcurrency,
+    LimitRate,
+    LimitRatePeriod,
+    Priority,
 }
 
 impl RedisJobField {
@@ -29,6 +36,11 @@ impl RedisJobField {
             RedisJobField::EndedAt => "end",
             RedisJobField::Succeeded => "suc",
             RedisJobField::ErrorDetails => "err",
+            RedisJobField::LimitKey => "lk",
+            RedisJobField::LimitConcurrency => "lc",
+            RedisJobField::LimitRate => "lr",
+            RedisJobField::LimitRatePeriod => "lp",
+            RedisJobField::Priority => "pri",
         }
     }
 }
@@ -128,6 +140,29 @@ impl RedisJobSetCmd {
         self
     }
 
+    pub fn limit(mut self, limit: &JobLimit) -> Self {
+        self.0.arg(RedisJobField::LimitKey).arg(&limit.key);
+        if let Some(max_concurrent) = limit.max_concurrent {
+            self.0
+                .arg(RedisJobField::LimitConcurrency)
+                .arg(max_concurrent);
+        }
+
+        if let Some(rate) = limit.rate.as_ref() {
+            self.0
+                .arg(RedisJobField::LimitRate)
+                .arg(rate.max)
+                .arg(RedisJobField::LimitRatePeriod)
+                .arg(rate.period.as_millis() as u64);
+        }
+        self
+    }
+
+    pub fn priority(mut self, priority: JobPriority) -> Self {
+        self.0.arg(RedisJobField::Priority).arg(priority.band());
+        self
+    }
+
     pub fn error_details(mut self, error: &str) -> Self {
         self.0.arg(RedisJobField::ErrorDetails).arg(error);
         self
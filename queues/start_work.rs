@@ -15,8 +15,10 @@ use super::Queue;
 //  1. job ID
 //  2. current time
 //  3. default expiration,
+// Returns the payload, expiration, current retry, max retries, and the time that the job became
+// ready to run.
 const START_WORK_SCRIPT: &str = r##"
-    local job_data = redis.call("HMGET", KEYS[1], "to", "pay", "cr", "mr")
+    local job_data = redis.call("HMGET", KEYS[1], "to", "pay", "cr", "mr", "qt", "ra")
     local expiration = ARGV[2] + ARGV[3]
     -- If the job has a different timeout from the queue default, update it here.
     if job_data[1] ~= ARGV[3] then
@@ -25,13 +27,23 @@ const START_WORK_SCRIPT: &str = r##"
 
     -- Set started time
     redis.call("HSET", KEYS[1], "st", ARGV[2])
-    return {job_data[2], expiration, job_data[3], job_data[4]}
+    local ready_at = math.max(tonumber(job_data[5]) or 0, tonumber(job_data[6]) or 0)
+    return {job_data[2], expiration, job_data[3], job_data[4], ready_at}
 "##;
 
 lazy_static! {
     static ref SCRIPT: redis::Script = redis::Script::new(START_WORK_SCRIPT);
 }
 
+pub struct StartedJob {
+    pub payload: Vec<u8>,
+    pub expiration: DateTime<Utc>,
+    pub current_retry: usize,
+    pub max_retries: usize,
+    /// When the job was enqueued or scheduled to run, whichever is later.
+    pub ready_at: DateTime<Utc>,
+}
+
 pub struct StartWorkScript(&'static redis::Script);
 
 impl StartWorkScript {
@@ -46,8 +58,14 @@ impl StartWorkScript {
         job_id: &str,
         job_id_key: &str,
         now: &DateTime<Utc>,
-    ) -> Result<(Vec<u8>, DateTime<Utc>, usize, usize), Error> {
-        let (payload, expiration, current_retry, max_retries): (Vec<u8>, i64, usize, usize) = self
+    ) -> Result<StartedJob, Error> {
+        let (payload, expiration, current_retry, max_retries, ready_at): (
+            Vec<u8>,
+            i64,
+            usize,
+            usize,
+            i64,
+        ) = self
             .0
             .key(job_id_key)
             .key(&queue.0.processing_list)
@@ -57,11 +75,12 @@ impl StartWorkScript {
             .invoke_async(&mut **conn)
             .await?;
 
-        Ok((
+        Ok(StartedJob {
             payload,
-            Utc.timestamp_millis(expiration),
+            expiration: Utc.timestamp_millis(expiration),
             current_retry,
             max_retries,
-        ))
+            ready_at: Utc.timestamp_millis(ready_at),
+        })
     }
 }
@@ -6,27 +6,28 @@ use super::Queue;
 use crate::Error;
 
 // KEYS:
-//  1. pending items list
-//  2. scheduled items list
-//  3. job data key
+//  1. scheduled items list
+//  2. job data key
+//  3-5. pending lists, from high to low priority
 // ARGV:
 //  1. Job ID
 //  2. Optional new time to run
 //  3. Optional new payload
 const UPDATE_JOB_SCRIPT: &str = r##"
-    local is_scheduled = redis.call("ZSCORE", KEYS[2], ARGV[1])
+    local is_scheduled = redis.call("ZSCORE", KEYS[1], ARGV[1])
     local is_pending = false
     local updates_time = string.len(ARGV[2]) > 0
 
     -- Items being updated will usually be in the scheduled list, and accessing the pending list is O(N), so
     -- look up in the pending list only if we have to, and combine with the removal operation if appropriate.
     if is_scheduled == false then
+        local pending_list = KEYS[3 + (tonumber(redis.call("HGET", KEYS[2], "pri")) or 1)]
         if updates_time then
             -- If we're updating the scheduled time then we unconditionally move the item to the scheduled list,
             -- so remove it here.
-            is_pending = redis.call("LREM", KEYS[1], 1, ARGV[1]) > 0
+            is_pending = redis.call("LREM", pending_list, 1, ARGV[1]) > 0
         else
-            is_pending = redis.call("LPOS", KEYS[1], ARGV[1]) ~= false
+            is_pending = redis.call("LPOS", pending_list, ARGV[1]) ~= false
         end
     end
 
@@ -37,13 +38,13 @@ const UPDATE_JOB_SCRIPT: &str = r##"
 
     if updates_time then
         -- Put the task on the scheduled list at the new time.
-        redis.call("ZADD", KEYS[2], ARGV[2], ARGV[1])
-        redis.call("HSET", KEYS[3], "ra", ARGV[2])
+        redis.call("ZADD", KEYS[1], ARGV[2], ARGV[1])
+        redis.call("HSET", KEYS[2], "ra", ARGV[2])
     end
 
     if string.len(ARGV[3]) > 0 then
         -- Update the payload
-        redis.call("HSET", KEYS[3], "pay", ARGV[3])
+        redis.call("HSET", KEYS[2], "pay", ARGV[3])
     end
 
     return true
@@ -71,9 +72,9 @@ impl UpdateJobScript {
     ) -> Result<bool, Error> {
         let success: bool = self
             .0
-            .key(&queue.0.pending_list)
             .key(&queue.0.scheduled_list)
             .key(job_data_key)
+            .key(queue.0.pending_lists.as_slice())
             .arg(job_id)
             .arg(
                 new_time
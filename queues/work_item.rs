@@ -1,9 +1,9 @@
 use super::Queue;
-use crate::error::Error;
+use crate::{error::Error, metrics};
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
-use std::future::Future;
+use std::{future::Future, time::Instant};
 
 #[derive(Debug)]
 pub struct QueueWorkItem<T: Send + Sync> {
@@ -74,7 +74,14 @@ impl<'a, T: Send + Sync> QueueWorkItem<T> {
         R: 'static,
     {
         let payload = self.data.take().unwrap();
-        match f(self, payload).await {
+        let start = Instant::now();
+        let result = f(self, payload).await;
+        let outcome = if result.is_ok() { "success" } else { "error" };
+        metrics::JOB_RUN_SECONDS
+            .with_label_values(&[self.queue.name(), outcome])
+            .observe(start.elapsed().as_secs_f64());
+
+        match result {
             Ok(val) => {
                 self.queue.done_job(self.id.as_str(), &self.expires).await?;
                 Ok(val)
@@ -1,9 +1,18 @@
 use chrono::{DateTime, Utc};
-use ergo_database::object_id::AccountId;
+use ergo_database::{
+    object_id::{AccountId, OrgId, TaskId},
+    PostgresPool,
+};
+use ergo_graceful_shutdown::GracefulShutdownConsumer;
+use ergo_notifications::{Notification, NotificationManager, NotifyEvent};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
 use uuid::Uuid;
 
+/// How many days before an account expires to notify the tasks that use it.
+pub const EXPIRY_WARNING_DAYS: i32 = 7;
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AccountType {
     pub account_type_id: String,
@@ -20,6 +29,10 @@ pub struct Account {
     pub user_id: Option<Uuid>,
     pub fields: Option<serde_json::Map<String, serde_json::Value>>,
     pub expires: Option<DateTime<Utc>>,
+    /// Limits shared by every action run that uses the account, such as the rate limit of
+    /// the service that the account belongs to.
+    #[serde(default)]
+    pub run_limit: Option<crate::limits::RunLimit>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -28,3 +41,79 @@ pub struct AccountPublicInfo {
     pub account_type_id: String,
     pub name: String,
 }
+
+/// Send an [NotifyEvent::AccountExpiring] notification to each task that uses an account
+/// expiring within [EXPIRY_WARNING_DAYS]. Each account is only reported once for each value of
+/// `expires`, so changing the expiration time re-arms the notification.
+pub async fn notify_expiring_accounts(
+    pool: &PostgresPool,
+    notifications: &NotificationManager,
+) -> Result<(), crate::Error> {
+    let mut tx = pool.begin().await?;
+
+    let expiring = sqlx::query!(
+        r##"WITH expiring AS (
+            UPDATE accounts
+            SET expiry_notified_for = expires
+            WHERE expires > now() AND expires <= now() + $1::int * interval '1 day'
+                AND expiry_notified_for IS DISTINCT FROM expires
+            RETURNING account_id, name, org_id, expires
+        )
+        SELECT DISTINCT expiring.account_id AS "account_id!: AccountId",
+            expiring.name AS "account_name!",
+            expiring.org_id AS "org_id!: OrgId",
+            expiring.expires AS "expires!",
+            tasks.task_id AS "task_id: TaskId",
+            tasks.name AS task_name
+        FROM expiring
+        JOIN task_actions USING (account_id)
+        JOIN tasks USING (task_id)
+        WHERE NOT tasks.deleted"##,
+        EXPIRY_WARNING_DAYS
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for row in expiring {
+        event!(Level::INFO, account_id=%row.account_id, task_id=%row.task_id, expires=%row.expires, "Account expiring");
+        let notification = Notification {
+            event: NotifyEvent::AccountExpiring,
+            task_id: row.task_id,
+            task_name: row.task_name,
+            local_id: row.account_id.to_string(),
+            local_object_name: row.account_name,
+            local_object_id: Some(row.account_id.0),
+            payload: Some(serde_json::json!({ "expires": row.expires })),
+            error: None,
+            log_id: None,
+        };
+
+        notifications
+            .notify(&mut tx, &row.org_id, notification)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub fn monitor_expiring_accounts(
+    mut shutdown: GracefulShutdownConsumer,
+    pool: PostgresPool,
+    notifications: NotificationManager,
+    check_interval: Option<std::time::Duration>,
+) -> tokio::task::JoinHandle<()> {
+    let check_interval = check_interval.unwrap_or_else(|| std::time::Duration::from_secs(3600));
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = notify_expiring_accounts(&pool, &notifications).await {
+                event!(Level::ERROR, error=%e, "Failed to check expiring accounts");
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(check_interval) => continue,
+                _ = shutdown.wait_for_shutdown() => break,
+            }
+        }
+    })
+}
@@ -60,13 +60,14 @@ impl QueueJobProcessor for ActionExecutorJobProcessor {
 
     async fn process(
         &self,
-        _item: &QueueWorkItem<Self::Payload>,
+        item: &QueueWorkItem<Self::Payload>,
         data: ActionInvocation,
     ) -> Result<(), Error> {
         execute(
             &self.pg_pool,
             self.redis_key_prefix.clone(),
             self.notifications.as_ref(),
+            item.is_final_retry(),
             data,
         )
         .await?;
@@ -0,0 +1,812 @@
+use super::{
+    execute::{json_primitive_as_string, Executor, ExecutorError},
+    template::{TemplateField, TemplateFieldFormat, TemplateFields},
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fxhash::FxHashMap;
+use serde_json::json;
+use std::path::PathBuf;
+use tracing::{event, instrument, Level};
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+static FIELD_IMAGE: TemplateField = TemplateField::from_static(
+    "image",
+    TemplateFieldFormat::string_without_default(),
+    false,
+    "The image to run. The image is pulled if it does not exist locally",
+);
+static FIELD_COMMAND: TemplateField = TemplateField::from_static(
+    "command",
+    TemplateFieldFormat::string_array_without_default(),
+    true,
+    "The command and arguments to run. Defaults to the image's command",
+);
+static FIELD_ENV: TemplateField = TemplateField::from_static(
+    "env",
+    TemplateFieldFormat::object_without_default(false),
+    true,
+    "Environment variables to set",
+);
+static FIELD_MOUNTS: TemplateField = TemplateField::from_static(
+    "mounts",
+    TemplateFieldFormat::string_array_without_default(),
+    true,
+    "Bind mounts, in the form host_path:container_path[:ro]",
+);
+static FIELD_NETWORK_MODE: TemplateField = TemplateField::from_static(
+    "network_mode",
+    TemplateFieldFormat::string_without_default(),
+    true,
+    "The network mode for the container, such as 'none', 'host', or a network name",
+);
+static FIELD_CPUS: TemplateField = TemplateField::from_static(
+    "cpus",
+    TemplateFieldFormat::float_without_default(),
+    true,
+    "The number of CPUs the container may use. Unlimited by default",
+);
+static FIELD_MEMORY_MB: TemplateField = TemplateField::from_static(
+    "memory_mb",
+    TemplateFieldFormat::integer_without_default(),
+    true,
+    "The memory limit for the container, in megabytes. Unlimited by default",
+);
+static FIELD_ALLOW_FAILURE: TemplateField = TemplateField::from_static(
+    "allow_failure",
+    TemplateFieldFormat::Boolean { default: false },
+    true,
+    "If true, ignore the container exit code. By default, a nonzero exit code counts as failure",
+);
+
+/// Runs a command in a Docker container, using the Docker Engine API on the local socket.
+#[derive(Debug)]
+pub struct DockerExecutor {
+    template_fields: TemplateFields,
+    socket: PathBuf,
+}
+
+impl DockerExecutor {
+    /// Create an executor that connects to the socket given by `DOCKER_HOST`,
+    /// or the default Docker socket if it is not set.
+    pub fn new() -> DockerExecutor {
+        let socket = std::env::var("DOCKER_HOST")
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET));
+
+        Self::with_socket(socket)
+    }
+
+    pub fn with_socket(socket: PathBuf) -> DockerExecutor {
+        let template_fields = [
+            &FIELD_IMAGE,
+            &FIELD_COMMAND,
+            &FIELD_ENV,
+            &FIELD_MOUNTS,
+            &FIELD_NETWORK_MODE,
+            &FIELD_CPUS,
+            &FIELD_MEMORY_MB,
+            &FIELD_ALLOW_FAILURE,
+        ]
+        .into();
+
+        DockerExecutor {
+            template_fields,
+            socket,
+        }
+    }
+}
+
+#[async_trait]
+impl Executor for DockerExecutor {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[instrument(level = "debug", name = "DockerExecutor::execute", skip(state))]
+    async fn execute(
+        &self,
+        state: super::execute::ExecutorState,
+        payload: FxHashMap<String, serde_json::Value>,
+    ) -> Result<serde_json::Value, ExecutorError> {
+        let image = FIELD_IMAGE.extract_str(&payload)?;
+        let command = FIELD_COMMAND.extract_string_array(&payload)?;
+        let mounts = FIELD_MOUNTS.extract_string_array(&payload)?;
+        let network_mode = FIELD_NETWORK_MODE.extract_str(&payload)?;
+        let cpus: f64 = FIELD_CPUS.extract(&payload)?;
+        let memory_mb: i64 = FIELD_MEMORY_MB.extract(&payload)?;
+        let allow_failure: bool = FIELD_ALLOW_FAILURE.extract(&payload)?;
+
+        let env = FIELD_ENV.extract_object(&payload)?;
+        let mut env_list = Vec::new();
+        if let serde_json::Value::Object(m) = env.as_ref() {
+            for (k, v) in m {
+                let value = json_primitive_as_string("env", Some(k), v, false)?;
+                env_list.push(format!("{}={}", k, value));
+            }
+        }
+
+        let mut host_config = json!({ "Binds": mounts });
+        if !network_mode.is_empty() {
+            host_config["NetworkMode"] = json!(network_mode);
+        }
+        if cpus > 0.0 {
+            host_config["NanoCpus"] = json!((cpus * 1e9) as i64);
+        }
+        if memory_mb > 0 {
+            host_config["Memory"] = json!(memory_mb * 1024 * 1024);
+        }
+
+        let mut config = json!({
+            "Image": image,
+            "Env": env_list,
+            "Tty": false,
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "HostConfig": host_config,
+        });
+        if !command.is_empty() {
+            config["Cmd"] = json!(command);
+        }
+
+        event!(Level::DEBUG, %config);
+
+        let client = client::DockerClient::new(self.socket.clone());
+        let id = match client.create_container(&config).await {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                event!(Level::INFO, %image, "Pulling image");
+                client
+                    .pull_image(image.as_ref())
+                    .await
+                    .map_err(ExecutorError::command_error_without_result)?;
+                client
+                    .create_container(&config)
+                    .await
+                    .map_err(ExecutorError::command_error_without_result)?
+                    .ok_or_else(|| {
+                        ExecutorError::command_error_without_result(anyhow!(
+                            "Image {} not found",
+                            image
+                        ))
+                    })?
+            }
+            Err(e) => return Err(ExecutorError::command_error_without_result(e)),
+        };
+
+        let result = run_container(&client, &id, state.timeout, allow_failure).await;
+
+        // Always clean up the container, whether or not it ran successfully.
+        if let Err(e) = client.remove_container(&id).await {
+            event!(Level::ERROR, err=?e, %id, "Failed to remove container");
+        }
+
+        result
+    }
+
+    fn template_fields(&self) -> &TemplateFields {
+        &self.template_fields
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+async fn run_container(
+    client: &client::DockerClient,
+    id: &str,
+    timeout: Option<std::time::Duration>,
+    allow_failure: bool,
+) -> Result<serde_json::Value, ExecutorError> {
+    client
+        .start_container(id)
+        .await
+        .map_err(ExecutorError::command_error_without_result)?;
+
+    let wait = client.wait_container(id);
+    let exitcode = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => Some(result),
+            Err(_) => {
+                event!(Level::INFO, %id, ?timeout, "Killing container after timeout");
+                client
+                    .kill_container(id)
+                    .await
+                    .map_err(ExecutorError::command_error_without_result)?;
+                None
+            }
+        },
+        None => Some(wait.await),
+    }
+    .transpose()
+    .map_err(ExecutorError::command_error_without_result)?;
+
+    event!(Level::DEBUG, exitcode = ?exitcode);
+
+    let (stdout, stderr) = client
+        .container_logs(id)
+        .await
+        .map_err(ExecutorError::command_error_without_result)?;
+    event!(Level::TRACE, %stdout, %stderr);
+
+    let result = json!({
+        "exitcode": exitcode,
+        "stdout": stdout,
+        "stderr": stderr,
+    });
+
+    let msg = match exitcode {
+        None => Some(format!(
+            "Timed out after {} seconds",
+            timeout.unwrap_or_default().as_secs()
+        )),
+        Some(0) => None,
+        Some(_) if allow_failure => None,
+        Some(code) => Some(format!("Exited with code {}", code)),
+    };
+
+    match msg {
+        Some(msg) => Err(ExecutorError::CommandError {
+            source: anyhow!(msg),
+            result,
+        }),
+        None => Ok(result),
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+mod client {
+    use std::path::PathBuf;
+
+    use anyhow::anyhow;
+    use hyper::{body::Bytes, Body, Client, Method, Request, StatusCode};
+    use hyperlocal::{UnixClientExt, UnixConnector};
+    use serde::Deserialize;
+
+    const API_VERSION: &str = "v1.41";
+
+    pub struct DockerClient {
+        socket: PathBuf,
+        client: Client<UnixConnector>,
+    }
+
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        message: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct CreateResponse {
+        id: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct WaitResponse {
+        status_code: i64,
+    }
+
+    #[derive(Deserialize)]
+    struct PullProgress {
+        error: Option<String>,
+    }
+
+    impl DockerClient {
+        pub fn new(socket: PathBuf) -> Self {
+            DockerClient {
+                socket,
+                client: Client::unix(),
+            }
+        }
+
+        async fn request(
+            &self,
+            method: Method,
+            path: &str,
+            body: Option<&serde_json::Value>,
+        ) -> Result<(StatusCode, Bytes), anyhow::Error> {
+            let uri: hyper::Uri =
+                hyperlocal::Uri::new(&self.socket, &format!("/{}{}", API_VERSION, path)).into();
+            let req = Request::builder().method(method).uri(uri);
+            let req = match body {
+                Some(body) => req
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_vec(body)?))?,
+                None => req.body(Body::empty())?,
+            };
+
+            let response = self.client.request(req).await?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Ok((status, body))
+        }
+
+        /// Send a request and return an error if the response is not successful.
+        async fn checked_request(
+            &self,
+            method: Method,
+            path: &str,
+            body: Option<&serde_json::Value>,
+        ) -> Result<Bytes, anyhow::Error> {
+            let (status, body) = self.request(method, path, body).await?;
+            if status.is_success() {
+                Ok(body)
+            } else {
+                Err(error_from_response(status, &body))
+            }
+        }
+
+        /// Create a container, returning its ID, or `None` if the image does not exist.
+        pub async fn create_container(
+            &self,
+            config: &serde_json::Value,
+        ) -> Result<Option<String>, anyhow::Error> {
+            let (status, body) = self
+                .request(Method::POST, "/containers/create", Some(config))
+                .await?;
+            match status {
+                StatusCode::NOT_FOUND => Ok(None),
+                s if s.is_success() => {
+                    let response: CreateResponse = serde_json::from_slice(&body)?;
+                    Ok(Some(response.id))
+                }
+                s => Err(error_from_response(s, &body)),
+            }
+        }
+
+        /// Pull an image. Docker pulls every tag of the image when no tag is given, so
+        /// an image without one gets `latest`.
+        pub async fn pull_image(&self, image: &str) -> Result<(), anyhow::Error> {
+            let (name, tag) = split_image_tag(image);
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("fromImage", name)
+                .append_pair("tag", tag)
+                .finish();
+            let body = self
+                .checked_request(Method::POST, &format!("/images/create?{}", query), None)
+                .await?;
+
+            // Docker returns success as soon as the pull starts, and reports failures in the
+            // progress stream instead.
+            for line in body.split(|&b| b == b'\n') {
+                if let Ok(PullProgress { error: Some(error) }) = serde_json::from_slice(line) {
+                    return Err(anyhow!("Pulling image {}: {}", image, error));
+                }
+            }
+
+            Ok(())
+        }
+
+        pub async fn start_container(&self, id: &str) -> Result<(), anyhow::Error> {
+            self.checked_request(Method::POST, &format!("/containers/{}/start", id), None)
+                .await?;
+            Ok(())
+        }
+
+        /// Wait for the container to exit, and return its exit code.
+        pub async fn wait_container(&self, id: &str) -> Result<i64, anyhow::Error> {
+            let body = self
+                .checked_request(Method::POST, &format!("/containers/{}/wait", id), None)
+                .await?;
+            let response: WaitResponse = serde_json::from_slice(&body)?;
+            Ok(response.status_code)
+        }
+
+        pub async fn kill_container(&self, id: &str) -> Result<(), anyhow::Error> {
+            self.checked_request(Method::POST, &format!("/containers/{}/kill", id), None)
+                .await?;
+            Ok(())
+        }
+
+        /// Return the stdout and stderr output of the container.
+        pub async fn container_logs(&self, id: &str) -> Result<(String, String), anyhow::Error> {
+            let body = self
+                .checked_request(
+                    Method::GET,
+                    &format!("/containers/{}/logs?stdout=true&stderr=true", id),
+                    None,
+                )
+                .await?;
+            let (stdout, stderr) = demux_logs(&body);
+            Ok((
+                String::from_utf8_lossy(&stdout).into_owned(),
+                String::from_utf8_lossy(&stderr).into_owned(),
+            ))
+        }
+
+        pub async fn remove_container(&self, id: &str) -> Result<(), anyhow::Error> {
+            self.checked_request(
+                Method::DELETE,
+                &format!("/containers/{}?force=true", id),
+                None,
+            )
+            .await?;
+            Ok(())
+        }
+    }
+
+    fn error_from_response(status: StatusCode, body: &[u8]) -> anyhow::Error {
+        let message = serde_json::from_slice::<ErrorResponse>(body)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned());
+        anyhow!("Docker returned {}: {}", status, message)
+    }
+
+    /// Split an image reference into the name and the tag or digest. The tag defaults to
+    /// `latest`. A colon before the last slash belongs to a registry port, not a tag.
+    pub fn split_image_tag(image: &str) -> (&str, &str) {
+        if let Some((name, digest)) = image.split_once('@') {
+            return (name, digest);
+        }
+
+        let name_start = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+        match image[name_start..].rfind(':') {
+            Some(i) => (&image[..name_start + i], &image[name_start + i + 1..]),
+            None => (image, "latest"),
+        }
+    }
+
+    /// Split the multiplexed log stream of a non-TTY container into stdout and stderr.
+    /// Each frame has an 8 byte header: the stream type, three zero bytes, and then the
+    /// frame length as a big-endian u32.
+    pub fn demux_logs(mut data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+
+        while data.len() >= 8 {
+            let len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            let end = (8 + len).min(data.len());
+            let frame = &data[8..end];
+            match data[0] {
+                2 => stderr.extend_from_slice(frame),
+                _ => stdout.extend_from_slice(frame),
+            }
+            data = &data[end..];
+        }
+
+        (stdout, stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use assert_matches::assert_matches;
+    use hyper::{service::service_fn, Body, Request, Response, StatusCode};
+
+    use super::*;
+    use crate::actions::execute::ExecutorState;
+
+    #[derive(Default)]
+    struct MockState {
+        requests: Vec<String>,
+        create_body: serde_json::Value,
+        image_pulled: bool,
+        pull_query: Option<String>,
+    }
+
+    struct MockDocker {
+        socket: PathBuf,
+        state: Arc<Mutex<MockState>>,
+    }
+
+    impl Drop for MockDocker {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.socket).ok();
+        }
+    }
+
+    fn log_frame(stream: u8, data: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data.as_bytes());
+        frame
+    }
+
+    async fn handle(
+        req: Request<Body>,
+        state: Arc<Mutex<MockState>>,
+        exit_code: i64,
+        hang: bool,
+        image_exists: bool,
+    ) -> Result<Response<Body>, Infallible> {
+        let method = req.method().to_string();
+        let path = req
+            .uri()
+            .path()
+            .strip_prefix("/v1.41")
+            .unwrap_or_default()
+            .to_string();
+        state
+            .lock()
+            .unwrap()
+            .requests
+            .push(format!("{} {}", method, path));
+
+        let response = match (method.as_str(), path.as_str()) {
+            ("POST", "/containers/create") => {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let mut state = state.lock().unwrap();
+                state.create_body = serde_json::from_slice(&body).unwrap();
+                if image_exists || state.image_pulled {
+                    Response::builder()
+                        .status(StatusCode::CREATED)
+                        .body(Body::from(r##"{"Id":"abc","Warnings":[]}"##))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::from(r##"{"message":"No such image"}"##))
+                }
+            }
+            ("POST", "/images/create") => {
+                let query = req.uri().query().unwrap_or_default().to_string();
+                let mut state = state.lock().unwrap();
+                if query.contains("fromImage=no-such-image") {
+                    Response::builder().body(Body::from(concat!(
+                        r##"{"status":"Pulling from library/no-such-image"}"##,
+                        "\n",
+                        r##"{"errorDetail":{"message":"manifest unknown"},"error":"manifest unknown"}"##,
+                        "\n",
+                    )))
+                } else {
+                    state.image_pulled = true;
+                    state.pull_query = Some(query);
+                    Response::builder().body(Body::from(
+                        "{\"status\":\"Pulling\"}\n{\"status\":\"Downloaded\"}\n",
+                    ))
+                }
+            }
+            ("POST", "/containers/abc/wait") => {
+                if hang {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                Response::builder().body(Body::from(json!({ "StatusCode": exit_code }).to_string()))
+            }
+            ("GET", "/containers/abc/logs") => {
+                let mut body = log_frame(1, "the output\n");
+                body.extend(log_frame(2, "an error\n"));
+                Response::builder().body(Body::from(body))
+            }
+            ("POST", "/containers/abc/start")
+            | ("POST", "/containers/abc/kill")
+            | ("DELETE", "/containers/abc") => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty()),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(r##"{"message":"Unknown path"}"##)),
+        };
+
+        Ok(response.unwrap())
+    }
+
+    fn mock_docker(exit_code: i64, hang: bool, image_exists: bool) -> MockDocker {
+        let socket =
+            std::env::temp_dir().join(format!("ergo-docker-{}.sock", uuid::Uuid::new_v4()));
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let listener = tokio::net::UnixListener::bind(&socket).expect("Binding mock socket");
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(_) => break,
+                };
+
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        handle(req, state.clone(), exit_code, hang, image_exists)
+                    });
+                    hyper::server::conn::Http::new()
+                        .serve_connection(stream, service)
+                        .await
+                        .ok();
+                });
+            }
+        });
+
+        MockDocker { socket, state }
+    }
+
+    fn payload(values: serde_json::Value) -> FxHashMap<String, serde_json::Value> {
+        values
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn demux_logs() {
+        let mut data = log_frame(1, "out1 ");
+        data.extend(log_frame(2, "err"));
+        data.extend(log_frame(1, "out2"));
+
+        let (stdout, stderr) = client::demux_logs(&data);
+        assert_eq!(stdout, b"out1 out2");
+        assert_eq!(stderr, b"err");
+    }
+
+    #[test]
+    fn split_image_tag() {
+        assert_eq!(client::split_image_tag("alpine"), ("alpine", "latest"));
+        assert_eq!(client::split_image_tag("alpine:3.16"), ("alpine", "3.16"));
+        assert_eq!(
+            client::split_image_tag("localhost:5000/tools/alpine"),
+            ("localhost:5000/tools/alpine", "latest")
+        );
+        assert_eq!(
+            client::split_image_tag("localhost:5000/alpine:edge"),
+            ("localhost:5000/alpine", "edge")
+        );
+        assert_eq!(
+            client::split_image_tag("alpine@sha256:abcd"),
+            ("alpine", "sha256:abcd")
+        );
+    }
+
+    #[tokio::test]
+    async fn run_container() {
+        let mock = mock_docker(0, false, true);
+        let exec = DockerExecutor::with_socket(mock.socket.clone());
+
+        let result = exec
+            .execute(
+                ExecutorState::new_test_state(),
+                payload(json!({
+                    "image": "alpine:latest",
+                    "command": ["echo", "hi"],
+                    "env": { "A": "b", "N": 5 },
+                    "mounts": ["/tmp/data:/data:ro"],
+                    "network_mode": "none",
+                    "cpus": 0.5,
+                    "memory_mb": 64,
+                })),
+            )
+            .await
+            .expect("Running container");
+
+        assert_eq!(
+            result,
+            json!({ "exitcode": 0, "stdout": "the output\n", "stderr": "an error\n" })
+        );
+
+        let state = mock.state.lock().unwrap();
+        assert_eq!(
+            state.create_body,
+            json!({
+                "Image": "alpine:latest",
+                "Cmd": ["echo", "hi"],
+                "Env": ["A=b", "N=5"],
+                "Tty": false,
+                "AttachStdout": true,
+                "AttachStderr": true,
+                "HostConfig": {
+                    "Binds": ["/tmp/data:/data:ro"],
+                    "NetworkMode": "none",
+                    "NanoCpus": 500_000_000,
+                    "Memory": 64 * 1024 * 1024,
+                },
+            })
+        );
+        assert_eq!(
+            state.requests,
+            vec![
+                "POST /containers/create",
+                "POST /containers/abc/start",
+                "POST /containers/abc/wait",
+                "GET /containers/abc/logs",
+                "DELETE /containers/abc",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn nonzero_exit_code() {
+        let mock = mock_docker(3, false, true);
+        let exec = DockerExecutor::with_socket(mock.socket.clone());
+
+        let err = exec
+            .execute(
+                ExecutorState::new_test_state(),
+                payload(json!({ "image": "alpine" })),
+            )
+            .await
+            .expect_err("Nonzero exit code should fail");
+        assert_matches!(err, ExecutorError::CommandError { result, .. } => {
+            assert_eq!(result["exitcode"], json!(3));
+            assert_eq!(result["stdout"], json!("the output\n"));
+        });
+
+        let result = exec
+            .execute(
+                ExecutorState::new_test_state(),
+                payload(json!({ "image": "alpine", "allow_failure": true })),
+            )
+            .await
+            .expect("allow_failure ignores exit code");
+        assert_eq!(result["exitcode"], json!(3));
+    }
+
+    #[tokio::test]
+    async fn pulls_missing_image() {
+        let mock = mock_docker(0, false, false);
+        let exec = DockerExecutor::with_socket(mock.socket.clone());
+
+        exec.execute(
+            ExecutorState::new_test_state(),
+            payload(json!({ "image": "alpine:3.16" })),
+        )
+        .await
+        .expect("Running container");
+
+        let state = mock.state.lock().unwrap();
+        assert_eq!(
+            &state.requests[0..3],
+            &[
+                "POST /containers/create",
+                "POST /images/create",
+                "POST /containers/create",
+            ]
+        );
+        assert_eq!(
+            state.pull_query.as_deref(),
+            Some("fromImage=alpine&tag=3.16")
+        );
+    }
+
+    #[tokio::test]
+    async fn pull_error() {
+        let mock = mock_docker(0, false, false);
+        let exec = DockerExecutor::with_socket(mock.socket.clone());
+
+        let err = exec
+            .execute(
+                ExecutorState::new_test_state(),
+                payload(json!({ "image": "no-such-image" })),
+            )
+            .await
+            .expect_err("Failed pull should fail");
+        assert!(err.to_string().contains("manifest unknown"), "{}", err);
+
+        let state = mock.state.lock().unwrap();
+        assert_eq!(
+            state.requests,
+            vec!["POST /containers/create", "POST /images/create"]
+        );
+    }
+
+    #[tokio::test]
+    async fn kills_container_on_timeout() {
+        let mock = mock_docker(0, true, true);
+        let exec = DockerExecutor::with_socket(mock.socket.clone());
+
+        let state = ExecutorState {
+            timeout: Some(Duration::from_millis(200)),
+            ..ExecutorState::new_test_state()
+        };
+        let err = exec
+            .execute(state, payload(json!({ "image": "alpine" })))
+            .await
+            .expect_err("Timeout should fail");
+        assert_matches!(err, ExecutorError::CommandError { result, .. } => {
+            assert_eq!(result["exitcode"], json!(null));
+        });
+
+        let state = mock.state.lock().unwrap();
+        assert!(state
+            .requests
+            .contains(&"POST /containers/abc/kill".to_string()));
+        assert_eq!(state.requests.last().unwrap(), "DELETE /containers/abc");
+    }
+}
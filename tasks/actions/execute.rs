@@ -109,6 +109,8 @@ pub struct ExecutorState {
     pub pg_pool: Option<PostgresPool>,
     pub redis_key_prefix: Option<String>,
     pub user_id: UserId,
+    /// How long the action may run, for executors that support timeouts.
+    pub timeout: Option<std::time::Duration>,
 }
 
 #[cfg(test)]
@@ -118,6 +120,7 @@ impl ExecutorState {
             pg_pool: None,
             redis_key_prefix: None,
             user_id: UserId::new(),
+            timeout: None,
         }
     }
 }
@@ -142,6 +145,7 @@ lazy_static! {
         std::array::IntoIter::new([
             Box::new(super::http_executor::HttpExecutor::new()) as Box<dyn Executor>,
             Box::new(super::raw_command_executor::RawCommandExecutor::new()) as Box<dyn Executor>,
+            Box::new(super::docker_executor::DockerExecutor::new()) as Box<dyn Executor>,
             Box::new(super::js_executor::JsExecutor::new()) as Box<dyn Executor>,
             Box::new(super::send_input_executor::SendInputExecutor::new()) as Box<dyn Executor>,
         ])
@@ -167,10 +171,11 @@ pub use native::*;
 mod native {
     use chrono::{DateTime, Utc};
     use ergo_database::{
-        object_id::{AccountId, ActionId, OrgId, TaskId},
+        object_id::{AccountId, ActionId, InputId, OrgId, TaskId, TaskTriggerId},
         PostgresPool,
     };
     use ergo_notifications::{Notification, NotificationManager, NotifyEvent};
+    use ergo_queues::JobPriority;
     use futures::future::TryFutureExt;
     use fxhash::{FxBuildHasher, FxHashMap};
     use serde_json::json;
@@ -180,20 +185,26 @@ mod native {
 
     use crate::{
         actions::{
+            action_result_trigger_id,
             template::{self, TemplateError, TemplateFields},
             ActionInvocation, ActionStatus,
         },
         error::Error,
+        events::{TaskEvent, TaskEventKind},
+        inputs::{enqueue_input, EnqueueInputOptions},
         scripting::{self, run_simple_with_args},
     };
 
     use super::*;
 
+    /// Run an action. `final_attempt` indicates that the job will not be retried if it fails,
+    /// so a failure should be reported to the task's action result trigger.
     #[instrument(name = "execute_action", level = "debug", skip(pg_pool, notifications))]
     pub async fn execute(
         pg_pool: &PostgresPool,
         redis_key_prefix: Option<String>,
         notifications: Option<&NotificationManager>,
+        final_attempt: bool,
         invocation: ActionInvocation,
     ) -> Result<serde_json::Value, Error> {
         event!(Level::DEBUG, ?invocation);
@@ -212,9 +223,35 @@ mod native {
             error: e.into(),
         })?;
 
-        let result = execute_action(pg_pool, redis_key_prefix, notifications, &invocation).await;
+        publish_event(
+            pg_pool,
+            &invocation,
+            TaskEventKind::ActionStarted {
+                actions_log_id: invocation.actions_log_id,
+                inputs_log_id: invocation.input_arrival_id,
+                task_action_local_id: invocation.task_action_local_id.clone(),
+            },
+        )
+        .await;
+
+        let start = std::time::Instant::now();
+        let result = execute_action(
+            pg_pool,
+            redis_key_prefix.clone(),
+            notifications,
+            &invocation,
+        )
+        .await;
         event!(Level::DEBUG, ?result);
 
+        let status_label = if result.is_ok() { "success" } else { "error" };
+        crate::metrics::ACTION_RUNS
+            .with_label_values(&[status_label])
+            .inc();
+        crate::metrics::ACTION_RUN_SECONDS
+            .with_label_values(&[status_label])
+            .observe(start.elapsed().as_secs_f64());
+
         let (status, response) = match &result {
             Ok(r) => (ActionStatus::Success, json!({ "output": r })),
             Err(e) => {
@@ -246,9 +283,124 @@ mod native {
             error: e.into(),
         })?;
 
+        publish_event(
+            pg_pool,
+            &invocation,
+            TaskEventKind::ActionFinished {
+                actions_log_id: invocation.actions_log_id,
+                inputs_log_id: invocation.input_arrival_id,
+                task_action_local_id: invocation.task_action_local_id.clone(),
+                status,
+                result: Some(response.clone()),
+            },
+        )
+        .await;
+
+        // A failed attempt that will be retried doesn't have a final result yet, so wait for
+        // the last attempt before sending the error to the task.
+        let send_result = result.is_ok() || final_attempt;
+
+        // The action has already run at this point, so failing to send the result
+        // shouldn't cause it to be retried.
+        if send_result {
+            if let Err(e) = send_action_result(
+                pg_pool,
+                redis_key_prefix.as_deref(),
+                notifications,
+                &invocation,
+                &response,
+                result.is_ok(),
+            )
+            .await
+            {
+                event!(Level::ERROR, err=?e, "Failed to send action result to task");
+            }
+        }
+
         result
     }
 
+    /// Events are informational, so a failure to publish one doesn't fail the action.
+    async fn publish_event(
+        pg_pool: &PostgresPool,
+        invocation: &ActionInvocation,
+        event: TaskEventKind,
+    ) {
+        if let Err(e) = TaskEvent::new(invocation.task_id.clone(), event)
+            .publish(pg_pool)
+            .await
+        {
+            event!(Level::ERROR, err=?e, "Failed to publish task event");
+        }
+    }
+
+    /// If the task has a trigger for the action's result, send the result to it.
+    async fn send_action_result(
+        pg_pool: &PostgresPool,
+        redis_key_prefix: Option<&str>,
+        notifications: Option<&NotificationManager>,
+        invocation: &ActionInvocation,
+        response: &serde_json::Value,
+        success: bool,
+    ) -> Result<(), Error> {
+        let trigger_id = action_result_trigger_id(&invocation.task_action_local_id, success);
+        let mut conn = pg_pool.acquire().await?;
+        let trigger = sqlx::query!(
+            r##"SELECT task_trigger_id AS "task_trigger_id: TaskTriggerId",
+                input_id AS "input_id: InputId",
+                tt.name AS task_trigger_name,
+                inputs.payload_schema,
+                tasks.org_id AS "org_id: OrgId",
+                tasks.name AS task_name
+            FROM task_triggers tt
+            JOIN tasks USING(task_id)
+            JOIN inputs USING(input_id)
+            WHERE task_id=$1 AND task_trigger_local_id=$2
+                AND tasks.enabled AND NOT tasks.deleted"##,
+            invocation.task_id.0,
+            &trigger_id
+        )
+        .fetch_optional(&mut conn)
+        .await?;
+
+        let trigger = match trigger {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        let payload = json!({
+            "actions_log_id": invocation.actions_log_id,
+            "payload": invocation.payload,
+            "result": response.get("output"),
+            "error": response.get("error"),
+        });
+
+        event!(Level::DEBUG, %trigger_id, ?payload, "Sending action result to task");
+        enqueue_input(EnqueueInputOptions {
+            pg: &mut conn,
+            notifications: notifications.cloned(),
+            org_id: trigger.org_id,
+            user_id: invocation.user_id.clone(),
+            task_id: invocation.task_id.clone(),
+            task_name: trigger.task_name,
+            input_id: trigger.input_id,
+            task_trigger_id: trigger.task_trigger_id,
+            task_trigger_local_id: trigger_id,
+            task_trigger_name: trigger.task_trigger_name,
+            periodic_trigger_id: None,
+            payload_schema: &trigger.payload_schema,
+            payload,
+            redis_key_prefix,
+            trigger_at: None,
+            parent_inputs_log_id: None,
+            priority: JobPriority::Normal,
+            idempotency_key: None,
+        })
+        .await?;
+
+        Ok(())
+    }
+
     #[derive(Debug, sqlx::FromRow)]
     struct ExecuteActionData {
         executor_id: String,
@@ -258,6 +410,7 @@ mod native {
         action_template_fields: Json<TemplateFields>,
         account_required: bool,
         postprocess_script: Option<String>,
+        action_timeout: Option<i32>,
         task_id: TaskId,
         task_name: String,
         task_action_local_id: String,
@@ -288,6 +441,7 @@ mod native {
         actions.template_fields as action_template_fields,
         actions.account_required,
         actions.postprocess_script,
+        actions.timeout as action_timeout,
         task_id as "task_id: TaskId",
         tasks.name AS task_name,
         task_actions.task_action_local_id,
@@ -392,6 +546,10 @@ mod native {
                 .run_as
                 .take()
                 .unwrap_or_else(|| invocation.user_id.clone()),
+            timeout: action
+                .action_timeout
+                .filter(|t| *t > 0)
+                .map(|t| std::time::Duration::from_secs(t as u64)),
         };
 
         let results = executor
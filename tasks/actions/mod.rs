@@ -9,6 +9,7 @@ pub mod queue;
 pub use queue::enqueue_actions;
 pub mod template;
 
+mod docker_executor;
 mod http_executor;
 mod js_executor;
 mod raw_command_executor;
@@ -122,6 +123,8 @@ pub struct TaskAction {
     pub account_id: Option<AccountId>,
     pub name: String,
     pub action_template: Option<TaskActionTemplate>,
+    #[serde(default)]
+    pub run_limit: Option<crate::limits::RunLimit>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
@@ -150,7 +153,32 @@ pub struct ActionInvocation {
 
 pub type ActionInvocations = SmallVec<[ActionInvocation; 1]>;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+/// Triggers with this prefix followed by a task action's local ID receive the result
+/// of the action when it succeeds.
+pub const ACTION_SUCCESS_TRIGGER_PREFIX: &str = "action_success:";
+/// Triggers with this prefix followed by a task action's local ID receive the error
+/// when the action fails.
+pub const ACTION_ERROR_TRIGGER_PREFIX: &str = "action_error:";
+
+/// The ID of the task trigger that receives an action's result.
+pub fn action_result_trigger_id(task_action_local_id: &str, success: bool) -> String {
+    let prefix = if success {
+        ACTION_SUCCESS_TRIGGER_PREFIX
+    } else {
+        ACTION_ERROR_TRIGGER_PREFIX
+    };
+
+    format!("{}{}", prefix, task_action_local_id)
+}
+
+/// If `trigger_id` is an action result trigger, return the local ID of the action.
+pub fn parse_action_result_trigger_id(trigger_id: &str) -> Option<&str> {
+    trigger_id
+        .strip_prefix(ACTION_SUCCESS_TRIGGER_PREFIX)
+        .or_else(|| trigger_id.strip_prefix(ACTION_ERROR_TRIGGER_PREFIX))
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TaskActionInvocation {
     pub name: String,
     pub payload: serde_json::Value,
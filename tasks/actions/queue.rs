@@ -1,14 +1,19 @@
 use std::{borrow::Cow, ops::Deref};
 
-use crate::error::Error;
+use crate::{error::Error, limits::RunLimit};
 
-use ergo_database::RedisPool;
+use ergo_database::{
+    object_id::{AccountId, TaskId},
+    RedisPool,
+};
 use ergo_queues::{
     generic_stage::{enqueue_jobs, QueueJob},
-    Queue,
+    JobLimit, JobPriority, Queue,
 };
+use fxhash::FxHashMap;
 use smallvec::SmallVec;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
+use uuid::Uuid;
 
 use super::ActionInvocations;
 
@@ -39,11 +44,13 @@ pub async fn enqueue_actions(
     tx: &mut PgConnection,
     actions: &ActionInvocations,
     key_prefix: &Option<String>,
+    priority: JobPriority,
 ) -> Result<(), Error> {
     let queue_name = key_prefix
         .as_ref()
         .map(|prefix| Cow::Owned(format!("{}-{}", prefix, QUEUE_NAME)))
         .unwrap_or(Cow::Borrowed(QUEUE_NAME));
+    let limits = task_action_limits(&mut *tx, actions).await?;
     let jobs = actions
         .iter()
         .map(|inv| QueueJob {
@@ -53,6 +60,11 @@ pub async fn enqueue_actions(
             run_at: None,
             max_retries: None,
             retry_backoff: None,
+            limit: limits
+                .get(&(inv.task_id.0, inv.task_action_local_id.clone()))
+                .cloned(),
+            priority,
+            idempotency: None,
             payload: inv,
         })
         .collect::<SmallVec<[QueueJob<_>; 4]>>();
@@ -60,3 +72,51 @@ pub async fn enqueue_actions(
     enqueue_jobs(tx, jobs.as_slice()).await?;
     Ok(())
 }
+
+/// Look up the queue limits of the task actions being invoked, keyed by task ID and task
+/// action ID. When the action's account has a limit, that limit is used instead of the task
+/// action's, since it is shared with every other action that uses the account.
+async fn task_action_limits(
+    tx: &mut PgConnection,
+    actions: &ActionInvocations,
+) -> Result<FxHashMap<(Uuid, String), JobLimit>, Error> {
+    if actions.is_empty() {
+        return Ok(FxHashMap::default());
+    }
+
+    let task_ids = actions.iter().map(|a| a.task_id.0).collect::<Vec<_>>();
+    let rows = sqlx::query!(
+        r##"SELECT ta.task_id, ta.task_action_local_id,
+            ta.run_limit AS "run_limit: Json<RunLimit>",
+            ta.account_id AS "account_id: AccountId",
+            accounts.run_limit AS "account_run_limit: Json<RunLimit>"
+        FROM task_actions ta
+        LEFT JOIN accounts USING(account_id)
+        WHERE ta.task_id = ANY($1)
+            AND (ta.run_limit IS NOT NULL OR accounts.run_limit IS NOT NULL)"##,
+        &task_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let limits = rows
+        .into_iter()
+        .filter_map(|row| {
+            let account_limit = row
+                .account_id
+                .zip(row.account_run_limit)
+                .and_then(|(account_id, limit)| limit.job_limit(format!("account:{}", account_id)));
+            let limit = account_limit.or_else(|| {
+                let key = format!(
+                    "task_action:{}:{}",
+                    TaskId::from_uuid(row.task_id),
+                    row.task_action_local_id
+                );
+                row.run_limit?.job_limit(key)
+            })?;
+            Some(((row.task_id, row.task_action_local_id), limit))
+        })
+        .collect();
+
+    Ok(limits)
+}
@@ -14,6 +14,8 @@ use chrono::{DateTime, Utc};
 use ergo_auth::get_user_info;
 use ergo_database::object_id::{InputId, TaskId, TaskTriggerId};
 #[cfg(not(target_family = "wasm"))]
+use ergo_queues::JobPriority;
+#[cfg(not(target_family = "wasm"))]
 use sqlx::Connection;
 
 static FIELD_TASK: TemplateField = TemplateField::from_static(
@@ -148,6 +150,9 @@ impl Executor for SendInputExecutor {
             redis_key_prefix: state.redis_key_prefix.as_deref(),
             trigger_at: when,
             periodic_trigger_id: None,
+            parent_inputs_log_id: None,
+            priority: JobPriority::Normal,
+            idempotency_key: None,
         })
         .await
         .map_err(ExecutorError::command_error_without_result)?;
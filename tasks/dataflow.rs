@@ -1,4 +1,10 @@
-use crate::{actions::TaskActionInvocations, Error, Result};
+use std::time::{Duration, Instant};
+
+use crate::{
+    actions::{parse_action_result_trigger_id, TaskActionInvocations},
+    scripting::SyntaxChecker,
+    Error, Result, StateMigration, TaskValidateError,
+};
 use ergo_js::ConsoleMessage;
 use fxhash::FxHashMap;
 use schemars::JsonSchema;
@@ -20,15 +26,38 @@ pub struct DataFlowConfig {
     toposorted: Vec<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The record of a single run through the graph.
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct DataFlowLog {
+    /// The `task_trigger_local_id` of the trigger that started the run.
+    pub trigger_id: String,
+    /// The payload that the trigger received.
+    pub payload: serde_json::Value,
+    /// If this run was a replay of an earlier run, the node that it started from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replayed_from: Option<String>,
+    /// Every node that ran, in the order that they ran.
     pub run: Vec<DataFlowNodeLog>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataFlowNodeLog {
     pub node: String,
+    /// The value passed to the node. For trigger nodes this is the payload, and for
+    /// other nodes it is an object keyed by the names of the incoming edges.
+    #[serde(default)]
+    pub input: serde_json::Value,
+    /// The value that the node returned.
+    #[serde(default)]
+    pub output: serde_json::Value,
+    /// How long the node took to run.
+    #[serde(default, with = "serde_millis")]
+    pub duration: Duration,
     pub console: Vec<ConsoleMessage>,
+    /// The error that the node failed with, if it failed. A failed node is always the last
+    /// entry in the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 impl DataFlowConfig {
@@ -43,113 +72,429 @@ impl DataFlowConfig {
     }
 
     pub fn default_state(&self) -> DataFlowState {
-        DataFlowState { nodes: Vec::new() }
+        DataFlowState {
+            nodes: Vec::new(),
+            action_results: Vec::new(),
+        }
+    }
+
+    pub fn validate<TRIGGER, ACTION>(
+        &self,
+        task_triggers: &FxHashMap<String, TRIGGER>,
+        task_actions: &FxHashMap<String, ACTION>,
+    ) -> Vec<TaskValidateError> {
+        let mut errors = Vec::new();
+
+        let num_nodes = self.nodes.len() as u32;
+        for (index, edge) in self.edges.iter().enumerate() {
+            for (end, node) in [("from", edge.from), ("to", edge.to)] {
+                if node >= num_nodes {
+                    errors.push(TaskValidateError::InvalidDataFlowEdge { index, end, node });
+                }
+            }
+        }
+
+        let mut checker = SyntaxChecker::default();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let script = match &node.func {
+                DataFlowNodeFunction::Trigger(trigger) => {
+                    if !task_triggers.contains_key(&trigger.local_id) {
+                        errors.push(TaskValidateError::InvalidDataFlowTrigger {
+                            index,
+                            name: node.name.clone(),
+                            trigger_id: trigger.local_id.clone(),
+                        });
+                    }
+                    None
+                }
+                DataFlowNodeFunction::Action(action) => {
+                    if !task_actions.contains_key(&action.action_id) {
+                        errors.push(TaskValidateError::InvalidDataFlowAction {
+                            index,
+                            name: node.name.clone(),
+                            action_id: action.action_id.clone(),
+                        });
+                    }
+                    Some((&action.payload_code, true))
+                }
+                DataFlowNodeFunction::Js(js) => Some((js, false)),
+                DataFlowNodeFunction::Text(_)
+                | DataFlowNodeFunction::Table
+                | DataFlowNodeFunction::Graph => None,
+            };
+
+            if let Some((js, payload_code)) = script {
+                if let Err(error) = checker.check_script(&node.name, &js.wrapped_code()) {
+                    errors.push(TaskValidateError::DataFlowScriptError {
+                        index,
+                        name: node.name.clone(),
+                        payload_code,
+                        error,
+                    });
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Carry node values from `state`, created with the `from` config, over to this config.
+    /// Values are matched up by node name, and values for nodes that no longer exist are dropped.
+    pub fn migrate_state(
+        &self,
+        from: &DataFlowConfig,
+        state: &DataFlowState,
+    ) -> (DataFlowState, StateMigration) {
+        let mut nodes = vec![serde_json::Value::Null; self.nodes.len()];
+        let mut action_results = Vec::new();
+        let mut kept = 0;
+        let mut dropped = 0;
+        for (old_idx, (old_node, value)) in from.nodes.iter().zip(state.nodes.iter()).enumerate() {
+            if value.is_null() {
+                continue;
+            }
+
+            match self.node_index(&old_node.name) {
+                Some(idx) => {
+                    nodes[idx] = value.clone();
+                    kept += 1;
+
+                    let action_result = state.action_result(old_idx);
+                    if !action_result.is_null() {
+                        action_results.resize(self.nodes.len(), serde_json::Value::Null);
+                        action_results[idx] = action_result.clone();
+                    }
+                }
+                None => dropped += 1,
+            }
+        }
+
+        let migration = match (kept, dropped) {
+            (_, 0) => StateMigration::Kept,
+            (0, _) => StateMigration::Reset,
+            _ => StateMigration::Partial,
+        };
+
+        (
+            DataFlowState {
+                nodes,
+                action_results,
+            },
+            migration,
+        )
+    }
+
+    /// The outputs of the nodes in `state`, keyed by node name.
+    pub fn node_outputs(&self, state: &DataFlowState) -> serde_json::Value {
+        self.nodes
+            .iter()
+            .zip(state.nodes.iter())
+            .filter(|(node, _)| node.func.persist_output())
+            .map(|(node, output)| (node.name.clone(), output.clone()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
     }
 
     pub async fn evaluate_trigger(
         &self,
         task_name: &str,
-        mut state: DataFlowState,
+        state: DataFlowState,
         trigger_id: &str,
         payload: serde_json::Value,
-    ) -> Result<(DataFlowState, Option<DataFlowLog>, TaskActionInvocations)> {
-        if state.nodes.len() != self.nodes.len() {
-            state
-                .nodes
-                .resize(self.nodes.len(), serde_json::Value::Null);
-        }
-
-        let trigger_node = self
-            .nodes
-            .iter()
-            .position(|node| match &node.func {
-                DataFlowNodeFunction::Trigger(trigger) => trigger.local_id == trigger_id,
-                _ => false,
-            })
-            .ok_or_else(|| Error::TaskTriggerNotFound(trigger_id.to_string()))?;
+    ) -> Result<(DataFlowState, DataFlowLog, TaskActionInvocations)> {
+        let trigger_node = self.nodes.iter().position(|node| match &node.func {
+            DataFlowNodeFunction::Trigger(trigger) => trigger.local_id == trigger_id,
+            _ => false,
+        });
+
+        let mut log = DataFlowLog {
+            trigger_id: trigger_id.to_string(),
+            payload: payload.clone(),
+            replayed_from: None,
+            run: Vec::new(),
+        };
 
-        let mut walker = NodeWalker::starting_from(self, trigger_node as u32)?;
+        let trigger_node = match trigger_node {
+            Some(node) => node,
+            None => {
+                return self
+                    .evaluate_action_result(task_name, state, trigger_id, payload, log)
+                    .await
+            }
+        };
 
         // Directly send the payload into the first node. The rest of the nodes have their state built the
         // normal way.
-        let first_node_idx = walker.next().unwrap();
-        let first_node = &self.nodes[first_node_idx];
-        let new_state = first_node
-            .func
-            .execute(
+        let (state, actions) = self
+            .run_from_node(
                 task_name,
-                &first_node.name,
-                &serde_json::Value::Null,
+                state,
+                trigger_node,
                 NodeInput::Single(payload),
+                true,
+                &mut log,
             )
-            .await?;
+            .await
+            .map_err(|e| e.with_dataflow_log(std::mem::take(&mut log)))?;
+
+        Ok((state, log, actions))
+    }
 
-        if first_node.func.persist_output() {
-            state.nodes[first_node_idx] = new_state.state;
+    /// Handle an action result trigger that has no trigger node. The result becomes the
+    /// output of the action nodes that invoke the action, and the nodes downstream of them
+    /// run again. The node's own state, the payload it last sent, is left alone.
+    async fn evaluate_action_result(
+        &self,
+        task_name: &str,
+        mut state: DataFlowState,
+        trigger_id: &str,
+        payload: serde_json::Value,
+        mut log: DataFlowLog,
+    ) -> Result<(DataFlowState, DataFlowLog, TaskActionInvocations)> {
+        let action_nodes = parse_action_result_trigger_id(trigger_id)
+            .map(|action_id| {
+                self.nodes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, node)| match &node.func {
+                        DataFlowNodeFunction::Action(action) => action.action_id == action_id,
+                        _ => false,
+                    })
+                    .map(|(idx, _)| idx)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if action_nodes.is_empty() {
+            return Err(Error::TaskTriggerNotFound(trigger_id.to_string()));
         }
 
-        let mut logs = Vec::new();
         let mut actions = TaskActionInvocations::new();
+        for node_idx in action_nodes {
+            let (new_state, new_actions) = self
+                .run_from_node(
+                    task_name,
+                    state,
+                    node_idx,
+                    NodeInput::Single(payload.clone()),
+                    false,
+                    &mut log,
+                )
+                .await
+                .map_err(|e| e.with_dataflow_log(std::mem::take(&mut log)))?;
+            state = new_state;
+            actions.extend(new_actions);
+        }
 
-        for node_idx in walker {
-            let node = &self.nodes[node_idx];
+        Ok((state, log, actions))
+    }
+
+    /// Run the graph again starting from `node_name`, using the values recorded in `history`
+    /// for everything upstream of that node. The node's recorded input is reused if it ran
+    /// in `history`, otherwise the input is rebuilt from the recorded upstream outputs.
+    pub async fn replay_from_node(
+        &self,
+        task_name: &str,
+        mut state: DataFlowState,
+        history: &DataFlowLog,
+        node_name: &str,
+    ) -> Result<(DataFlowState, DataFlowLog, TaskActionInvocations)> {
+        let start_idx = self
+            .node_index(node_name)
+            .ok_or_else(|| Error::MissingDataFlowNodeName(node_name.to_string()))?;
+
+        state.resize(self.nodes.len());
+
+        // Nodes downstream of the replayed node keep their current state, since they will
+        // be recalculated anyway. Everything else gets the output from the original run.
+        let downstream = NodeWalker::starting_from(self, start_idx as u32)?.collect::<Vec<_>>();
+        for entry in &history.run {
+            let idx = match self.node_index(&entry.node) {
+                Some(idx) => idx,
+                // The node was removed since the run was recorded.
+                None => continue,
+            };
+
+            // An action node's entry holds either the payload it sent or the result that came
+            // back, so action nodes keep their current state instead.
+            let node = &self.nodes[idx];
+            if !downstream.contains(&idx)
+                && node.func.persist_output()
+                && !matches!(node.func, DataFlowNodeFunction::Action(_))
+            {
+                state.nodes[idx] = entry.output.clone();
+            }
+        }
+
+        let recorded_input = history
+            .run
+            .iter()
+            .find(|entry| entry.node == node_name)
+            .map(|entry| entry.input.clone());
+
+        let start_node = &self.nodes[start_idx];
+        let input = match (&start_node.func, recorded_input) {
+            (DataFlowNodeFunction::Trigger(_), Some(input)) => NodeInput::Single(input),
+            (DataFlowNodeFunction::Trigger(trigger), None)
+                if trigger.local_id == history.trigger_id =>
+            {
+                NodeInput::Single(history.payload.clone())
+            }
+            (DataFlowNodeFunction::Trigger(_), None) => {
+                return Err(Error::MissingDataFlowNodeInput(node_name.to_string()))
+            }
+            (_, Some(serde_json::Value::Object(input))) => {
+                NodeInput::Multiple(input.into_iter().collect())
+            }
+            (_, _) => self
+                .gather_inputs(&state, start_idx)
+                .map(NodeInput::Multiple)
+                .ok_or_else(|| Error::MissingDataFlowNodeInput(node_name.to_string()))?,
+        };
 
-            // Gather the inputs for the node
-            let input = self
-                .edges
-                .iter()
-                .filter(|edge| edge.to as usize == node_idx)
-                .map(|edge| {
-                    let from_node = &self.nodes[edge.from as usize];
-                    let node_state = from_node.func.output(
+        let mut log = DataFlowLog {
+            trigger_id: history.trigger_id.clone(),
+            payload: history.payload.clone(),
+            replayed_from: Some(node_name.to_string()),
+            run: Vec::new(),
+        };
+
+        let (state, actions) = self
+            .run_from_node(task_name, state, start_idx, input, true, &mut log)
+            .await
+            .map_err(|e| e.with_dataflow_log(std::mem::take(&mut log)))?;
+
+        Ok((state, log, actions))
+    }
+
+    /// Gather the inputs for a node from the current state. Returns `None` if the node should not
+    /// run because one of its inputs is null.
+    fn gather_inputs(
+        &self,
+        state: &DataFlowState,
+        node_idx: usize,
+    ) -> Option<FxHashMap<String, serde_json::Value>> {
+        let node = &self.nodes[node_idx];
+        self.edges
+            .iter()
+            .filter(|edge| edge.to as usize == node_idx)
+            .map(|edge| {
+                let from_node = &self.nodes[edge.from as usize];
+                let node_state = match from_node.func {
+                    // Downstream nodes see the result of the action, not the payload sent to it.
+                    DataFlowNodeFunction::Action(_) => {
+                        state.action_result(edge.from as usize).clone()
+                    }
+                    _ => from_node.func.output(
                         state
                             .nodes
                             .get(edge.from as usize)
                             .unwrap_or(&serde_json::Value::Null),
-                    );
-
-                    if !node.allow_null_inputs && node_state == serde_json::Value::Null {
-                        // Return an Err just because it's a convenient way to short-circuit the
-                        // iteration.
-                        Err(())
-                    } else {
-                        Ok((edge.name.clone(), node_state))
-                    }
-                })
-                .collect::<Result<FxHashMap<_, _>, ()>>();
-
-            let input = match input {
-                Ok(input) => input,
-                // This just means that the node is not running because one of its inputs is null.
-                // Not a real error, so just continue to the next node.
-                Err(()) => continue,
+                    ),
+                };
+
+                if !node.allow_null_inputs && node_state == serde_json::Value::Null {
+                    None
+                } else {
+                    Some((edge.name.clone(), node_state))
+                }
+            })
+            .collect::<Option<FxHashMap<_, _>>>()
+    }
+
+    /// Run the graph starting from the node at `start_idx`. If `execute_start` is false, the
+    /// start node does not run, and `start_input` is used as its action result instead.
+    async fn run_from_node(
+        &self,
+        task_name: &str,
+        mut state: DataFlowState,
+        start_idx: usize,
+        start_input: NodeInput,
+        execute_start: bool,
+        log: &mut DataFlowLog,
+    ) -> Result<(DataFlowState, TaskActionInvocations)> {
+        state.resize(self.nodes.len());
+
+        let mut walker = NodeWalker::starting_from(self, start_idx as u32)?.peekable();
+        let mut actions = TaskActionInvocations::new();
+
+        let mut start_input = Some(start_input);
+        if !execute_start {
+            if walker.peek() == Some(&start_idx) {
+                walker.next();
+            }
+
+            let output: serde_json::Value = start_input.take().map(Into::into).unwrap_or_default();
+            log.run.push(DataFlowNodeLog {
+                node: self.nodes[start_idx].name.clone(),
+                input: serde_json::Value::Null,
+                output: output.clone(),
+                duration: Duration::ZERO,
+                console: Vec::new(),
+                error: None,
+            });
+            state.action_results[start_idx] = output;
+        }
+
+        for node_idx in walker {
+            let node = &self.nodes[node_idx];
+
+            let input = match start_input.take() {
+                Some(input) => input,
+                None => match self.gather_inputs(&state, node_idx) {
+                    Some(input) => NodeInput::Multiple(input),
+                    // This just means that the node is not running because one of its inputs is null.
+                    // Not a real error, so just continue to the next node.
+                    None => continue,
+                },
             };
 
             let node_state = state
                 .nodes
                 .get(node_idx)
                 .unwrap_or(&serde_json::Value::Null);
-            event!(Level::DEBUG, node=%node.name, state=?node_state, ?input, "Evaluating node");
-            dbg!(&node);
-            dbg!(&node_state);
-            dbg!(&input);
+            let input_value = input.to_value();
+            event!(Level::DEBUG, node=%node.name, state=?node_state, input=?input_value, "Evaluating node");
+
+            let start = Instant::now();
             let result = node
                 .func
-                .execute(
-                    task_name,
-                    &node.name,
-                    node_state,
-                    NodeInput::Multiple(input),
-                )
-                .await?;
-            dbg!(&result);
-
-            if !result.console.is_empty() {
-                logs.push(DataFlowNodeLog {
-                    node: node.name.clone(),
-                    console: result.console,
-                });
-            }
+                .execute(task_name, &node.name, node_state, input)
+                .await;
+            let duration = start.elapsed();
+
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    let console = match &e {
+                        Error::DataflowScript { console, .. } => console.clone(),
+                        _ => Vec::new(),
+                    };
+
+                    log.run.push(DataFlowNodeLog {
+                        node: node.name.clone(),
+                        input: input_value,
+                        output: serde_json::Value::Null,
+                        duration,
+                        console,
+                        error: Some(e.to_string()),
+                    });
+                    return Err(e);
+                }
+            };
+
+            log.run.push(DataFlowNodeLog {
+                node: node.name.clone(),
+                input: input_value,
+                output: result.state.clone(),
+                duration,
+                console: result.console,
+                error: None,
+            });
 
             if node.func.persist_output() {
                 state.nodes[node_idx] = result.state;
@@ -160,19 +505,36 @@ impl DataFlowConfig {
             }
         }
 
-        let log_output = if logs.is_empty() {
-            None
-        } else {
-            Some(DataFlowLog { run: logs })
-        };
-
-        Ok((state, log_output, actions))
+        Ok((state, actions))
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 pub struct DataFlowState {
     nodes: Vec<serde_json::Value>,
+    /// The latest result of each action node's action, which is what the nodes downstream of
+    /// the action node receive.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    action_results: Vec<serde_json::Value>,
+}
+
+impl DataFlowState {
+    fn resize(&mut self, num_nodes: usize) {
+        if self.nodes.len() != num_nodes {
+            self.nodes.resize(num_nodes, serde_json::Value::Null);
+        }
+
+        if self.action_results.len() != num_nodes {
+            self.action_results
+                .resize(num_nodes, serde_json::Value::Null);
+        }
+    }
+
+    fn action_result(&self, node_idx: usize) -> &serde_json::Value {
+        self.action_results
+            .get(node_idx)
+            .unwrap_or(&serde_json::Value::Null)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
@@ -254,6 +616,14 @@ mod tests {
         }
     }
 
+    /// Just the nodes that wrote something to the console.
+    fn console_logs(log: &DataFlowLog) -> Vec<&DataFlowNodeLog> {
+        log.run
+            .iter()
+            .filter(|node| !node.console.is_empty())
+            .collect()
+    }
+
     async fn test_config(
         allow_null_inputs: bool,
         script_error: bool,
@@ -410,16 +780,16 @@ mod tests {
             .as_slice()
         );
 
-        let log = log.expect("log exists");
+        let log = console_logs(&log);
 
-        assert_eq!(log.run[0].node, "add_together");
-        assert_eq!(log.run[0].console.len(), 1);
-        assert_eq!(log.run[0].console[0].message, "added together 1\n");
+        assert_eq!(log[0].node, "add_together");
+        assert_eq!(log[0].console.len(), 1);
+        assert_eq!(log[0].console[0].message, "added together 1\n");
 
-        assert_eq!(log.run[1].node, "send_email");
-        assert_eq!(log.run[1].console.len(), 1);
+        assert_eq!(log[1].node, "send_email");
+        assert_eq!(log[1].console.len(), 1);
         assert_eq!(
-            log.run[1].console[0].message,
+            log[1].console[0].message,
             "Sending the email: The value: 5\n"
         );
 
@@ -437,10 +807,10 @@ mod tests {
         // send it.
         assert!(actions.is_empty());
 
-        let log = log.expect("log exists");
-        assert_eq!(log.run[0].node, "add_together");
-        assert_eq!(log.run[0].console.len(), 1);
-        assert_eq!(log.run[0].console[0].message, "added together 0\n");
+        let log = console_logs(&log);
+        assert_eq!(log[0].node, "add_together");
+        assert_eq!(log[0].console.len(), 1);
+        assert_eq!(log[0].console[0].message, "added together 0\n");
 
         assert_eq!(
             state.nodes,
@@ -486,16 +856,16 @@ mod tests {
             .as_slice()
         );
 
-        let log = log.expect("log exists");
+        let log = console_logs(&log);
 
-        assert_eq!(log.run[0].node, "add_together");
-        assert_eq!(log.run[0].console.len(), 1);
-        assert_eq!(log.run[0].console[0].message, "added together 3\n");
+        assert_eq!(log[0].node, "add_together");
+        assert_eq!(log[0].console.len(), 1);
+        assert_eq!(log[0].console[0].message, "added together 3\n");
 
-        assert_eq!(log.run[1].node, "send_email");
-        assert_eq!(log.run[1].console.len(), 1);
+        assert_eq!(log[1].node, "send_email");
+        assert_eq!(log[1].console.len(), 1);
         assert_eq!(
-            log.run[1].console[0].message,
+            log[1].console[0].message,
             "Sending the email: The value: 7\n"
         );
     }
@@ -525,7 +895,7 @@ mod tests {
         );
 
         assert!(actions.is_empty());
-        assert!(log.is_none());
+        assert!(console_logs(&log).is_empty());
 
         println!("Sending 2 to trigger2");
         let (state, log, actions) = config
@@ -554,20 +924,329 @@ mod tests {
             .as_slice()
         );
 
-        let log = log.expect("log exists");
+        let log = console_logs(&log);
 
-        assert_eq!(log.run[0].node, "add_together");
-        assert_eq!(log.run[0].console.len(), 1);
-        assert_eq!(log.run[0].console[0].message, "added together 3\n");
+        assert_eq!(log[0].node, "add_together");
+        assert_eq!(log[0].console.len(), 1);
+        assert_eq!(log[0].console[0].message, "added together 3\n");
 
-        assert_eq!(log.run[1].node, "send_email");
-        assert_eq!(log.run[1].console.len(), 1);
+        assert_eq!(log[1].node, "send_email");
+        assert_eq!(log[1].console.len(), 1);
         assert_eq!(
-            log.run[1].console[0].message,
+            log[1].console[0].message,
             "Sending the email: The value: 7\n"
         );
     }
 
+    #[tokio::test]
+    async fn run_history() {
+        let (_server, config) = test_config(true, false).await;
+        let state = config.default_state();
+
+        let (_, log, _) = config
+            .evaluate_trigger("task", state, "trigger1", json!({ "value": 1 }))
+            .await
+            .unwrap();
+
+        assert_eq!(log.trigger_id, "trigger1");
+        assert_eq!(log.payload, json!({ "value": 1 }));
+        assert_eq!(log.replayed_from, None);
+
+        let nodes = log.run.iter().map(|n| n.node.as_str()).collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 5);
+        assert_eq!(nodes[0], "trigger_a");
+        assert!(nodes.contains(&"add_one"));
+        assert!(nodes.contains(&"add_together"));
+        assert_eq!(nodes[3], "fetch_given_value");
+        assert_eq!(nodes[4], "send_email");
+
+        let fetch = &log.run[3];
+        assert_eq!(fetch.input, json!({ "doc_id": 1 }));
+        assert_eq!(fetch.output, json!({ "result": 5 }));
+
+        let trigger = &log.run[0];
+        assert_eq!(trigger.input, json!({ "value": 1 }));
+        assert_eq!(trigger.output, json!({ "value": 1 }));
+    }
+
+    #[tokio::test]
+    async fn replay_from_node() {
+        let (_server, config) = test_config(true, false).await;
+        let state = config.default_state();
+
+        let (_, history, _) = config
+            .evaluate_trigger("task", state, "trigger1", json!({ "value": 1 }))
+            .await
+            .unwrap();
+
+        // Replay against a blank state to make sure that the upstream values come from the
+        // history and not the state.
+        let (state, log, actions) = config
+            .replay_from_node(
+                "task",
+                config.default_state(),
+                &history,
+                "fetch_given_value",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(log.replayed_from.as_deref(), Some("fetch_given_value"));
+        assert_eq!(log.trigger_id, "trigger1");
+        let nodes = log.run.iter().map(|n| n.node.as_str()).collect::<Vec<_>>();
+        assert_eq!(nodes, vec!["fetch_given_value", "send_email"]);
+
+        assert_eq!(
+            actions.as_slice(),
+            vec![TaskActionInvocation {
+                name: "send_email".to_string(),
+                payload: json!({ "contents": "The value: 5" }),
+            }]
+            .as_slice()
+        );
+
+        assert_eq!(
+            state.nodes,
+            vec![
+                json!({ "value": 1 }),
+                json!(null),
+                json!(2),
+                json!(1),
+                json!({ "result": 5 }),
+                json!(null),
+                json!({ "contents": "The value: 5" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_missing_trigger_input() {
+        let (_server, config) = test_config(true, false).await;
+        let state = config.default_state();
+
+        let (_, history, _) = config
+            .evaluate_trigger("task", state, "trigger1", json!({ "value": 1 }))
+            .await
+            .unwrap();
+
+        let err = config
+            .replay_from_node("task", config.default_state(), &history, "trigger_b")
+            .await
+            .expect_err("trigger_b did not run");
+        assert!(matches!(err, Error::MissingDataFlowNodeInput(node) if node == "trigger_b"));
+
+        let err = config
+            .replay_from_node("task", config.default_state(), &history, "no_node")
+            .await
+            .expect_err("node does not exist");
+        assert!(matches!(err, Error::MissingDataFlowNodeName(_)));
+    }
+
+    #[tokio::test]
+    async fn action_result_feeds_downstream_nodes() {
+        let nodes = vec![
+            test_node(
+                "trigger",
+                false,
+                DataFlowNodeFunction::Trigger(DataFlowTrigger {
+                    local_id: "trigger1".to_string(),
+                }),
+            ),
+            test_node(
+                "send",
+                false,
+                DataFlowNodeFunction::Action(DataFlowAction {
+                    action_id: "send".to_string(),
+                    payload_code: DataFlowJs {
+                        code: "({ value: input.value })".into(),
+                        format: JsCodeFormat::Expression,
+                    },
+                }),
+            ),
+            test_node(
+                "after_send",
+                false,
+                DataFlowNodeFunction::Js(DataFlowJs {
+                    code: "sent.result.id".into(),
+                    format: JsCodeFormat::Expression,
+                }),
+            ),
+        ];
+        let edges = edge_indexes_from_names(
+            &nodes,
+            &[("trigger", "send", "input"), ("send", "after_send", "sent")],
+        )
+        .unwrap();
+        let config = DataFlowConfig::new(nodes, edges).unwrap();
+
+        let (state, _, actions) = config
+            .evaluate_trigger(
+                "task",
+                config.default_state(),
+                "trigger1",
+                json!({ "value": 1 }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(state.nodes[1], json!({ "value": 1 }));
+        assert_eq!(state.nodes[2], json!(null), "no action result yet");
+
+        let result = json!({ "result": { "id": 5 }, "error": null });
+        let (state, log, actions) = config
+            .evaluate_trigger("task", state, "action_success:send", result.clone())
+            .await
+            .unwrap();
+
+        assert!(actions.is_empty(), "action should not run again");
+        assert_eq!(
+            state.nodes,
+            vec![json!({ "value": 1 }), json!({ "value": 1 }), json!(5)]
+        );
+        assert_eq!(state.action_results[1], result);
+        assert_eq!(
+            log.run.iter().map(|n| n.node.as_str()).collect::<Vec<_>>(),
+            vec!["send", "after_send"]
+        );
+
+        // Later trigger runs send the latest result downstream, not the new payload.
+        let (state, _, actions) = config
+            .evaluate_trigger("task", state, "trigger1", json!({ "value": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(
+            state.nodes,
+            vec![json!({ "value": 2 }), json!({ "value": 2 }), json!(5)]
+        );
+
+        let err = config
+            .evaluate_trigger(
+                "task",
+                config.default_state(),
+                "action_success:other",
+                json!({}),
+            )
+            .await
+            .expect_err("no node for the action");
+        assert!(matches!(err, Error::TaskTriggerNotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn validate_config() {
+        let (_server, config) = test_config(true, false).await;
+
+        let triggers = [("trigger1".to_string(), ()), ("trigger2".to_string(), ())]
+            .into_iter()
+            .collect::<FxHashMap<_, _>>();
+        let actions = [("send_email".to_string(), ())]
+            .into_iter()
+            .collect::<FxHashMap<_, _>>();
+
+        let errors = config.validate(&triggers, &actions);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn validate_config_errors() {
+        let nodes = vec![
+            test_node(
+                "trigger",
+                false,
+                DataFlowNodeFunction::Trigger(DataFlowTrigger {
+                    local_id: "missing_trigger".to_string(),
+                }),
+            ),
+            test_node(
+                "bad_js",
+                false,
+                DataFlowNodeFunction::Js(DataFlowJs {
+                    code: "value +".into(),
+                    format: JsCodeFormat::Expression,
+                }),
+            ),
+            test_node(
+                "action",
+                false,
+                DataFlowNodeFunction::Action(DataFlowAction {
+                    action_id: "missing_action".to_string(),
+                    payload_code: DataFlowJs {
+                        code: "return { a: 5 ".into(),
+                        format: JsCodeFormat::Function,
+                    },
+                }),
+            ),
+        ];
+
+        let config = DataFlowConfig {
+            nodes,
+            edges: vec![DataFlowEdge {
+                from: 0,
+                to: 5,
+                name: "value".to_string(),
+            }],
+            toposorted: vec![0, 1, 2],
+        };
+
+        let errors = config.validate::<(), ()>(&FxHashMap::default(), &FxHashMap::default());
+        let paths = errors
+            .iter()
+            .map(|e| e.path().expect("error has path").to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            paths,
+            vec![
+                "edges[0].to",
+                "nodes[0].func.local_id",
+                "nodes[1].func.code",
+                "nodes[2].func.action_id",
+                "nodes[2].func.payload_code.code",
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_state() {
+        let text = |name: &str| {
+            test_node(
+                name,
+                false,
+                DataFlowNodeFunction::Text(DataFlowText {
+                    body: String::new(),
+                    render_as: TextRenderAs::PlainText,
+                }),
+            )
+        };
+
+        let old_config = DataFlowConfig::new(vec![text("a"), text("b"), text("c")], vec![])
+            .expect("creating config");
+        let state = DataFlowState {
+            nodes: vec![json!(1), json!(2), serde_json::Value::Null],
+            action_results: Vec::new(),
+        };
+
+        let new_config = DataFlowConfig::new(vec![text("d"), text("b"), text("a")], vec![])
+            .expect("creating config");
+        let (new_state, migration) = new_config.migrate_state(&old_config, &state);
+        assert_eq!(
+            new_state.nodes,
+            vec![serde_json::Value::Null, json!(2), json!(1)]
+        );
+        assert_eq!(migration, StateMigration::Kept);
+
+        let new_config =
+            DataFlowConfig::new(vec![text("a"), text("d")], vec![]).expect("creating config");
+        let (new_state, migration) = new_config.migrate_state(&old_config, &state);
+        assert_eq!(new_state.nodes, vec![json!(1), serde_json::Value::Null]);
+        assert_eq!(migration, StateMigration::Partial);
+
+        let new_config = DataFlowConfig::new(vec![text("d")], vec![]).expect("creating config");
+        let (new_state, migration) = new_config.migrate_state(&old_config, &state);
+        assert_eq!(new_state.nodes, vec![serde_json::Value::Null]);
+        assert_eq!(migration, StateMigration::Reset);
+    }
+
     #[tokio::test]
     async fn bad_script() {
         let (_server, config) = test_config(true, true).await;
@@ -579,13 +1258,29 @@ mod tests {
             .await
             .expect_err("should have failed");
 
-        if let Error::DataflowScript { node, error, .. } = err {
+        let (error, log) = match err {
+            Error::DataflowRun { error, log } => (error, log),
+            _ => panic!("Unexpected error: {:?}", err),
+        };
+
+        if let Error::DataflowScript { node, error, .. } = *error {
             assert_eq!(node, "fetch_given_value");
             assert!(error
                 .to_string()
                 .contains("ReferenceError: bad_func is not defined"));
         } else {
-            panic!("Unexpected error: {:?}", err);
+            panic!("Unexpected error: {:?}", error);
         }
+
+        let failed = log.run.last().expect("log has the failed node");
+        assert_eq!(failed.node, "fetch_given_value");
+        assert!(failed
+            .error
+            .as_deref()
+            .unwrap_or_default()
+            .contains("bad_func is not defined"));
+        assert!(log.run[..log.run.len() - 1]
+            .iter()
+            .all(|node| node.error.is_none()));
     }
 }
@@ -90,6 +90,26 @@ pub struct DataFlowJs {
     pub format: JsCodeFormat,
 }
 
+impl DataFlowJs {
+    /// The code as it is actually run, wrapped in a function if needed.
+    pub(super) fn wrapped_code(&self) -> String {
+        match self.format {
+            JsCodeFormat::Expression => format!(
+                "{SYNC_FUNCTION_START}return {body}{FUNCTION_END}",
+                body = self.code
+            ),
+            JsCodeFormat::Function => format!(
+                "{SYNC_FUNCTION_START}{body}{FUNCTION_END}",
+                body = self.code
+            ),
+            JsCodeFormat::AsyncFunction => format!(
+                "{ASYNC_FUNCTION_START}{body}{FUNCTION_END}",
+                body = self.code
+            ),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 pub struct DataFlowAction {
     pub action_id: String,
@@ -101,6 +121,15 @@ pub(super) enum NodeInput {
     Multiple(FxHashMap<String, serde_json::Value>),
 }
 
+impl NodeInput {
+    pub(super) fn to_value(&self) -> serde_json::Value {
+        match self {
+            NodeInput::Single(value) => value.clone(),
+            NodeInput::Multiple(map) => json!(map),
+        }
+    }
+}
+
 impl From<NodeInput> for serde_json::Value {
     fn from(input: NodeInput) -> Self {
         match input {
@@ -223,20 +252,7 @@ async fn run_js(
     input: NodeInput,
 ) -> Result<(serde_json::Value, Vec<ConsoleMessage>)> {
     let name = format!("https://ergo/tasks/{task_name}/{node_name}.js");
-    let wrapped = match expr.format {
-        JsCodeFormat::Expression => format!(
-            "{SYNC_FUNCTION_START}return {body}{FUNCTION_END}",
-            body = expr.code
-        ),
-        JsCodeFormat::Function => format!(
-            "{SYNC_FUNCTION_START}{body}{FUNCTION_END}",
-            body = expr.code
-        ),
-        JsCodeFormat::AsyncFunction => format!(
-            "{ASYNC_FUNCTION_START}{body}{FUNCTION_END}",
-            body = expr.code
-        ),
-    };
+    let wrapped = expr.wrapped_code();
 
     POOL.run(move || async move {
         let mut runtime = create_task_script_runtime(true);
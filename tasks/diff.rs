@@ -0,0 +1,195 @@
+//! Structural differences between two versions of a task's configuration.
+
+use std::collections::BTreeSet;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::TaskConfig;
+
+/// A single difference between two JSON values. The path is a JSON pointer to the location of
+/// the change, as in [serde_json::Value::pointer].
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ConfigChange {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl ConfigChange {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added { path, .. } => path,
+            Self::Removed { path, .. } => path,
+            Self::Changed { path, .. } => path,
+        }
+    }
+}
+
+impl TaskConfig {
+    /// List the changes needed to get from this config to `other`.
+    pub fn diff(&self, other: &TaskConfig) -> Vec<ConfigChange> {
+        let old = serde_json::to_value(self).unwrap_or(Value::Null);
+        let new = serde_json::to_value(other).unwrap_or(Value::Null);
+
+        // Comparing the contents of two different task types isn't useful, so just report
+        // the whole config as changed.
+        if old.get("type") != new.get("type") {
+            return vec![ConfigChange::Changed {
+                path: String::new(),
+                old,
+                new,
+            }];
+        }
+
+        diff_json(&old, &new)
+    }
+}
+
+/// List the changes needed to get from `old` to `new`. Objects are compared key by key and
+/// arrays are compared by index.
+pub fn diff_json(old: &Value, new: &Value) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    diff_value(String::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_value(path: String, old: &Value, new: &Value, changes: &mut Vec<ConfigChange>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let keys = old_map
+                .keys()
+                .chain(new_map.keys())
+                .collect::<BTreeSet<_>>();
+
+            for key in keys {
+                let key_path = format!("{}/{}", path, escape_pointer_token(key));
+                match (old_map.get(key), new_map.get(key)) {
+                    (Some(o), Some(n)) => diff_value(key_path, o, n, changes),
+                    (Some(o), None) => changes.push(ConfigChange::Removed {
+                        path: key_path,
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => changes.push(ConfigChange::Added {
+                        path: key_path,
+                        value: n.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(old_list), Value::Array(new_list)) => {
+            for i in 0..old_list.len().max(new_list.len()) {
+                let index_path = format!("{}/{}", path, i);
+                match (old_list.get(i), new_list.get(i)) {
+                    (Some(o), Some(n)) => diff_value(index_path, o, n, changes),
+                    (Some(o), None) => changes.push(ConfigChange::Removed {
+                        path: index_path,
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => changes.push(ConfigChange::Added {
+                        path: index_path,
+                        value: n.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (o, n) => {
+            if o != n {
+                changes.push(ConfigChange::Changed {
+                    path,
+                    old: o.clone(),
+                    new: n.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn identical_values() {
+        let value = json!({ "a": [1, 2, { "b": true }] });
+        assert_eq!(diff_json(&value, &value), vec![]);
+    }
+
+    #[test]
+    fn nested_changes() {
+        let old = json!({
+            "states": {
+                "idle": { "on": [{ "trigger_id": "start" }] },
+                "running": {}
+            },
+            "initial": "idle"
+        });
+        let new = json!({
+            "states": {
+                "idle": { "on": [{ "trigger_id": "begin" }, { "trigger_id": "stop" }] },
+                "a/b": {}
+            },
+            "initial": "idle"
+        });
+
+        assert_eq!(
+            diff_json(&old, &new),
+            vec![
+                ConfigChange::Added {
+                    path: "/states/a~1b".to_string(),
+                    value: json!({}),
+                },
+                ConfigChange::Changed {
+                    path: "/states/idle/on/0/trigger_id".to_string(),
+                    old: json!("start"),
+                    new: json!("begin"),
+                },
+                ConfigChange::Added {
+                    path: "/states/idle/on/1".to_string(),
+                    value: json!({ "trigger_id": "stop" }),
+                },
+                ConfigChange::Removed {
+                    path: "/states/running".to_string(),
+                    value: json!({}),
+                },
+            ]
+        );
+
+        let pointer = diff_json(&old, &new)[1].path().to_string();
+        assert_eq!(new.pointer(&pointer), Some(&json!("begin")));
+    }
+
+    #[test]
+    fn different_task_types() {
+        let js = TaskConfig::Js(crate::scripting::TaskJsConfig {
+            timeout: None,
+            script: "1".to_string(),
+            map: String::new(),
+        });
+        let machine = TaskConfig::StateMachine(Default::default());
+
+        let changes = js.diff(&machine);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path(), "");
+    }
+}
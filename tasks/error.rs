@@ -78,6 +78,16 @@ pub enum Error {
         console: Vec<ConsoleMessage>,
     },
 
+    /// A DataFlow run failed partway through. `log` records the nodes that ran, including
+    /// the one that failed.
+    #[error("{error}")]
+    #[cfg(not(target_family = "wasm"))]
+    DataflowRun {
+        #[source]
+        error: Box<Error>,
+        log: Box<crate::dataflow::DataFlowLog>,
+    },
+
     #[error("Parsing cron schedule: {0}")]
     CronParseError(#[from] cron::error::Error),
 
@@ -90,6 +100,9 @@ pub enum Error {
     #[error("Node {0} does not exist")]
     MissingDataFlowNodeName(String),
 
+    #[error("Node {0} has no recorded input to replay")]
+    MissingDataFlowNodeInput(String),
+
     #[error("Node {0} depends on {1}, which does not exist")]
     BadEdgeIndex(u32, u32),
 
@@ -113,6 +126,17 @@ impl<'a> From<jsonschema::ValidationError<'a>> for Error {
     }
 }
 
+#[cfg(not(target_family = "wasm"))]
+impl Error {
+    /// Attach the log of a partial DataFlow run to this error.
+    pub fn with_dataflow_log(self, log: crate::dataflow::DataFlowLog) -> Error {
+        Error::DataflowRun {
+            error: Box::new(self),
+            log: Box::new(log),
+        }
+    }
+}
+
 #[cfg(not(target_family = "wasm"))]
 impl ergo_database::transaction::TryIntoSqlxError for Error {
     fn try_into_sqlx_error(self) -> Result<sqlx::Error, Self> {
@@ -234,12 +258,107 @@ pub enum TaskValidateError {
         index: usize,
         target: String,
     },
+
+    #[error(
+        "Event handler {source}.on[{index}] has invalid {which} target {target}",
+        source=.state.as_deref().unwrap_or("<root>"),
+        which=.condition.map(|i| format!("condition {}", i)).unwrap_or_else(|| "fallback".to_string())
+    )]
+    InvalidConditionTarget {
+        state: Option<String>,
+        index: usize,
+        /// The index of the condition, or `None` for the fallback target.
+        condition: Option<usize>,
+        target: String,
+    },
+
+    #[error("State name {name} can not contain `.`")]
+    InvalidStateName {
+        parent: Option<String>,
+        name: String,
+    },
+
+    #[error(
+        "State {state} has nested states but its initial state {} is not one of them",
+        .initial.as_deref().unwrap_or("<none>")
+    )]
+    InvalidNestedInitialState {
+        state: String,
+        initial: Option<String>,
+    },
+
+    #[error("State {state}.after[{index}] has unknown trigger id {trigger_id}")]
+    InvalidTimerTrigger {
+        state: String,
+        index: usize,
+        trigger_id: String,
+    },
+
+    #[error("Task script error: {0}")]
+    ScriptError(String),
+
+    #[error("Edge {index} refers to node {node}, which does not exist")]
+    InvalidDataFlowEdge {
+        index: usize,
+        /// Which end of the edge is invalid, `from` or `to`.
+        end: &'static str,
+        node: u32,
+    },
+
+    #[error("Node {name} has unknown trigger id {trigger_id}")]
+    InvalidDataFlowTrigger {
+        index: usize,
+        name: String,
+        trigger_id: String,
+    },
+
+    #[error("Node {name} has unknown action id {action_id}")]
+    InvalidDataFlowAction {
+        index: usize,
+        name: String,
+        action_id: String,
+    },
+
+    #[error("Node {name} script error: {error}")]
+    DataFlowScriptError {
+        index: usize,
+        name: String,
+        /// True if the error is in an action node's payload code.
+        payload_code: bool,
+        error: String,
+    },
+
+    #[error("Handoff {index} has unknown trigger {trigger_id} for task {task_id}")]
+    InvalidHandoffTrigger {
+        index: usize,
+        task_id: String,
+        trigger_id: String,
+    },
+
+    #[error("Handoff {index} field {field} has invalid source {pointer}")]
+    InvalidHandoffSource {
+        index: usize,
+        field: String,
+        pointer: String,
+    },
+
+    #[error("Handoff {index} is missing required field {field}")]
+    MissingHandoffField { index: usize, field: String },
+
+    #[error("Handoff {index} field {field} is not in the trigger's payload schema")]
+    UnknownHandoffField { index: usize, field: String },
 }
 
+/// Nested states are separated by `.` in `state`, and each level is inside a `states` object.
 fn path_segment_for_state(state: &Option<String>) -> ValidatePathSegments {
     state
         .as_ref()
-        .map(|state_name| smallvec!["states".into(), state_name.clone().into(),])
+        .map(|state_path| {
+            state_path
+                .split('.')
+                .flat_map(|name| ["states".into(), name.to_string().into()])
+                .collect()
+        })
         .unwrap_or_else(SmallVec::new)
 }
 
@@ -261,6 +380,79 @@ impl TaskValidateError {
                 path.extend(["on".into(), (*index).into(), "target".into()]);
                 Some(ValidatePath(path))
             }
+            Self::InvalidConditionTarget {
+                state,
+                index,
+                condition,
+                ..
+            } => {
+                let mut path = path_segment_for_state(state);
+                path.extend(["on".into(), (*index).into(), "target".into(), "c".into()]);
+                match condition {
+                    Some(i) => path.extend(["conditions".into(), (*i).into(), "target".into()]),
+                    None => path.push("fallback".into()),
+                }
+                Some(ValidatePath(path))
+            }
+            Self::InvalidStateName { parent, name } => {
+                let mut path = path_segment_for_state(parent);
+                path.extend(["states".into(), name.clone().into()]);
+                Some(ValidatePath(path))
+            }
+            Self::InvalidNestedInitialState { state, .. } => {
+                let mut path = path_segment_for_state(&Some(state.clone()));
+                path.push("initial".into());
+                Some(ValidatePath(path))
+            }
+            Self::InvalidTimerTrigger { state, index, .. } => {
+                let mut path = path_segment_for_state(&Some(state.clone()));
+                path.extend(["after".into(), (*index).into(), "trigger_id".into()]);
+                Some(ValidatePath(path))
+            }
+            Self::ScriptError(_) => Some(ValidatePath(smallvec!["script".into()])),
+            Self::InvalidDataFlowEdge { index, end, .. } => Some(ValidatePath(smallvec![
+                "edges".into(),
+                (*index).into(),
+                (*end).into(),
+            ])),
+            Self::InvalidDataFlowTrigger { index, .. } => Some(ValidatePath(smallvec![
+                "nodes".into(),
+                (*index).into(),
+                "func".into(),
+                "local_id".into(),
+            ])),
+            Self::InvalidDataFlowAction { index, .. } => Some(ValidatePath(smallvec![
+                "nodes".into(),
+                (*index).into(),
+                "func".into(),
+                "action_id".into(),
+            ])),
+            Self::DataFlowScriptError {
+                index,
+                payload_code,
+                ..
+            } => {
+                let mut path: ValidatePathSegments =
+                    smallvec!["nodes".into(), (*index).into(), "func".into()];
+                if *payload_code {
+                    path.push("payload_code".into());
+                }
+                path.push("code".into());
+                Some(ValidatePath(path))
+            }
+            Self::InvalidHandoffTrigger { index, .. } => Some(ValidatePath(smallvec![
+                "handoffs".into(),
+                (*index).into(),
+                "trigger_id".into(),
+            ])),
+            Self::InvalidHandoffSource { index, field, .. }
+            | Self::MissingHandoffField { index, field }
+            | Self::UnknownHandoffField { index, field } => Some(ValidatePath(smallvec![
+                "handoffs".into(),
+                (*index).into(),
+                "fields".into(),
+                field.clone().into(),
+            ])),
         }
     }
 
@@ -269,6 +461,33 @@ impl TaskValidateError {
             Self::InvalidInitialState(_) => Some(Cow::from("a state in the `states` object")),
             Self::InvalidTriggerId { .. } => Some(Cow::from("valid trigger id for this task")),
             Self::InvalidTarget { .. } => Some(Cow::from("a state in the `states` object")),
+            Self::InvalidConditionTarget { .. } => {
+                Some(Cow::from("a state in the `states` object"))
+            }
+            Self::InvalidStateName { .. } => Some(Cow::from("a name without `.`")),
+            Self::InvalidNestedInitialState { .. } => {
+                Some(Cow::from("a state in the nested `states` object"))
+            }
+            Self::InvalidTimerTrigger { .. } => Some(Cow::from("valid trigger id for this task")),
+            Self::ScriptError(_) => None,
+            Self::InvalidDataFlowEdge { .. } => Some(Cow::from("the index of a node")),
+            Self::InvalidDataFlowTrigger { .. } => {
+                Some(Cow::from("valid trigger id for this task"))
+            }
+            Self::InvalidDataFlowAction { .. } => Some(Cow::from("valid action id for this task")),
+            Self::DataFlowScriptError { .. } => None,
+            Self::InvalidHandoffTrigger { .. } => {
+                Some(Cow::from("a trigger on the task that receives the handoff"))
+            }
+            Self::InvalidHandoffSource { .. } => Some(Cow::from(
+                "a JSON pointer starting with /payload or /context",
+            )),
+            Self::MissingHandoffField { .. } => Some(Cow::from(
+                "a field for each property required by the trigger",
+            )),
+            Self::UnknownHandoffField { .. } => {
+                Some(Cow::from("a property in the trigger's payload schema"))
+            }
         }
     }
 }
@@ -0,0 +1,239 @@
+//! Live events for task runs. Events are published with Postgres `NOTIFY`, so events sent
+//! inside a transaction are only delivered if the transaction commits, and every server
+//! instance sees the events regardless of which instance ran the task.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use ergo_database::{object_id::TaskId, PostgresPool};
+use ergo_graceful_shutdown::GracefulShutdownConsumer;
+use ergo_js::ConsoleMessage;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, Executor, Postgres};
+use tokio::sync::broadcast;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::{actions::ActionStatus, error::Error, inputs::InputStatus};
+
+pub const TASK_EVENTS_CHANNEL: &str = "task_events";
+
+/// Postgres limits a notification payload to 8000 bytes. Events larger than this are sent
+/// without their data, and clients can fetch it from the logs instead.
+const MAX_PAYLOAD_SIZE: usize = 7900;
+
+/// How many events a slow subscriber can fall behind before it starts missing events.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TaskEventKind {
+    InputArrived {
+        inputs_log_id: Uuid,
+        task_trigger_local_id: String,
+        payload: Option<serde_json::Value>,
+    },
+    StateChanged {
+        inputs_log_id: Uuid,
+        state: Option<serde_json::Value>,
+    },
+    Console {
+        inputs_log_id: Uuid,
+        messages: Vec<ConsoleMessage>,
+    },
+    InputProcessed {
+        inputs_log_id: Uuid,
+        status: InputStatus,
+        info: Option<serde_json::Value>,
+    },
+    ActionStarted {
+        actions_log_id: Uuid,
+        inputs_log_id: Option<Uuid>,
+        task_action_local_id: String,
+    },
+    ActionFinished {
+        actions_log_id: Uuid,
+        inputs_log_id: Option<Uuid>,
+        task_action_local_id: String,
+        status: ActionStatus,
+        result: Option<serde_json::Value>,
+    },
+}
+
+impl TaskEventKind {
+    /// Remove the parts of the event that can be arbitrarily large.
+    fn strip_data(&mut self) {
+        match self {
+            Self::InputArrived { payload, .. } => *payload = None,
+            Self::StateChanged { state, .. } => *state = None,
+            Self::Console { messages, .. } => messages.clear(),
+            Self::InputProcessed { info, .. } => *info = None,
+            Self::ActionStarted { .. } => {}
+            Self::ActionFinished { result, .. } => *result = None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskEvent {
+    pub task_id: TaskId,
+    pub timestamp: DateTime<Utc>,
+    /// True if the event data was too large to send, and was removed.
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(flatten)]
+    pub event: TaskEventKind,
+}
+
+impl TaskEvent {
+    pub fn new(task_id: TaskId, event: TaskEventKind) -> TaskEvent {
+        TaskEvent {
+            task_id,
+            timestamp: Utc::now(),
+            truncated: false,
+            event,
+        }
+    }
+
+    fn to_payload(mut self) -> Result<String, Error> {
+        let payload = serde_json::to_string(&self)?;
+        if payload.len() <= MAX_PAYLOAD_SIZE {
+            return Ok(payload);
+        }
+
+        self.event.strip_data();
+        self.truncated = true;
+        Ok(serde_json::to_string(&self)?)
+    }
+
+    /// Publish the event. When `executor` is a transaction, the event is sent on commit.
+    pub async fn publish<'c>(
+        self,
+        executor: impl Executor<'c, Database = Postgres>,
+    ) -> Result<(), Error> {
+        let payload = self.to_payload()?;
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(TASK_EVENTS_CHANNEL)
+            .bind(payload)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Listens for task events and passes them on to any subscribers in this process.
+#[derive(Clone)]
+pub struct TaskEvents {
+    sender: broadcast::Sender<Arc<TaskEvent>>,
+}
+
+impl TaskEvents {
+    pub fn new(pg_pool: PostgresPool, shutdown: GracefulShutdownConsumer) -> TaskEvents {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        tokio::spawn(listen(pg_pool, shutdown, sender.clone()));
+        TaskEvents { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<TaskEvent>> {
+        self.sender.subscribe()
+    }
+}
+
+async fn listen(
+    pg_pool: PostgresPool,
+    mut shutdown: GracefulShutdownConsumer,
+    sender: broadcast::Sender<Arc<TaskEvent>>,
+) {
+    let mut listener: Option<PgListener> = None;
+    loop {
+        if listener.is_none() {
+            let l = match PgListener::connect_with(&pg_pool).await {
+                Ok(mut l) => l.listen(TASK_EVENTS_CHANNEL).await.map(|_| l),
+                Err(e) => Err(e),
+            };
+
+            match l {
+                Ok(l) => listener = Some(l),
+                Err(e) => {
+                    event!(Level::ERROR, error=?e, "Error creating task event listener");
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(5)) => continue,
+                        _ = shutdown.wait_for_shutdown() => break,
+                    }
+                }
+            }
+        }
+
+        let l = listener.as_mut().unwrap();
+        tokio::select! {
+            notification = l.try_recv() => {
+                match notification {
+                    Ok(Some(notification)) => {
+                        match serde_json::from_str::<TaskEvent>(notification.payload()) {
+                            // This only fails when there are no subscribers, which is fine.
+                            Ok(e) => { sender.send(Arc::new(e)).ok(); }
+                            Err(e) => event!(Level::ERROR, error=?e, "Failed to parse task event"),
+                        }
+                    }
+                    Ok(None) => {
+                        // The connection was lost, and any events sent in the meantime are gone.
+                        listener = None;
+                    }
+                    Err(e) => {
+                        event!(Level::ERROR, error=?e, "Error receiving task event");
+                        listener = None;
+                    }
+                }
+            }
+            _ = shutdown.wait_for_shutdown() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_events_are_truncated() {
+        let event = TaskEvent::new(
+            TaskId::new(),
+            TaskEventKind::InputArrived {
+                inputs_log_id: Uuid::nil(),
+                task_trigger_local_id: "trigger".to_string(),
+                payload: Some(serde_json::json!({ "value": "a".repeat(MAX_PAYLOAD_SIZE) })),
+            },
+        );
+
+        let payload = event.to_payload().expect("serializing");
+        assert!(payload.len() <= MAX_PAYLOAD_SIZE);
+
+        let event: TaskEvent = serde_json::from_str(&payload).expect("deserializing");
+        assert!(event.truncated);
+        match event.event {
+            TaskEventKind::InputArrived { payload, .. } => assert_eq!(payload, None),
+            _ => panic!("Unexpected event type {:?}", event.event),
+        }
+    }
+
+    #[test]
+    fn small_events_are_intact() {
+        let event = TaskEvent::new(
+            TaskId::new(),
+            TaskEventKind::StateChanged {
+                inputs_log_id: Uuid::nil(),
+                state: Some(serde_json::json!({ "count": 1 })),
+            },
+        );
+
+        let payload = event.to_payload().expect("serializing");
+        let event: TaskEvent = serde_json::from_str(&payload).expect("deserializing");
+        assert!(!event.truncated);
+        match event.event {
+            TaskEventKind::StateChanged { state, .. } => {
+                assert_eq!(state, Some(serde_json::json!({ "count": 1 })))
+            }
+            _ => panic!("Unexpected event type {:?}", event.event),
+        }
+    }
+}
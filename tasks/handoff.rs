@@ -0,0 +1,338 @@
+//! Task sequencing. A task can hand off data to a trigger on another task whenever
+//! it finishes processing an input.
+
+use ergo_database::object_id::TaskId;
+use fxhash::FxHashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::TaskValidateError;
+
+/// The maximum number of handoffs in a chain of runs. Handoffs beyond this are skipped, so
+/// tasks that hand off to each other in a cycle can't loop forever.
+pub const MAX_HANDOFF_DEPTH: i64 = 16;
+
+/// Send values from a task run to a trigger on another task.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+pub struct TaskHandoff {
+    /// The task to send the payload to.
+    pub task_id: TaskId,
+    /// The local ID of the trigger on the other task.
+    pub trigger_id: String,
+    /// The fields of the payload to send. Each value is a JSON pointer into the output of
+    /// the run, which looks like `{ "payload": <the input payload>, "context": <task context> }`.
+    ///
+    /// The context depends on the type of task. For state machines it is an array with the
+    /// context of each machine, for scripts it is the script's context, and for data flow
+    /// tasks it is an object containing the output of each node, keyed by the node name.
+    pub fields: FxHashMap<String, String>,
+}
+
+impl TaskHandoff {
+    /// Build the payload to send from the output of a run.
+    pub fn payload(&self, output: &serde_json::Value) -> serde_json::Value {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, pointer)| {
+                let value = output
+                    .pointer(pointer)
+                    .cloned()
+                    .unwrap_or(serde_json::Value::Null);
+                (name.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        serde_json::Value::Object(fields)
+    }
+
+    /// Check the handoff against the payload schema of the target trigger's input.
+    /// Since the values are only known when the task runs, this checks that the fields
+    /// cover everything that the schema requires and that the schema allows each field.
+    pub fn validate(
+        &self,
+        index: usize,
+        payload_schema: &serde_json::Value,
+    ) -> Vec<TaskValidateError> {
+        let mut errors = Vec::new();
+
+        for (field, pointer) in &self.fields {
+            let valid_source = ["/payload", "/context"].iter().any(|prefix| {
+                pointer
+                    .strip_prefix(prefix)
+                    .map(|rest| rest.is_empty() || rest.starts_with('/'))
+                    .unwrap_or(false)
+            });
+
+            if !valid_source {
+                errors.push(TaskValidateError::InvalidHandoffSource {
+                    index,
+                    field: field.clone(),
+                    pointer: pointer.clone(),
+                });
+            }
+        }
+
+        let properties = payload_schema.get("properties").and_then(|p| p.as_object());
+        let allows_other_fields = payload_schema
+            .get("additionalProperties")
+            .map(|a| a != &serde_json::Value::Bool(false))
+            .unwrap_or(true);
+        if !allows_other_fields {
+            let mut unknown = self
+                .fields
+                .keys()
+                .filter(|field| {
+                    properties
+                        .map(|p| !p.contains_key(field.as_str()))
+                        .unwrap_or(true)
+                })
+                .collect::<Vec<_>>();
+            unknown.sort();
+            errors.extend(unknown.into_iter().map(|field| {
+                TaskValidateError::UnknownHandoffField {
+                    index,
+                    field: field.clone(),
+                }
+            }));
+        }
+
+        let required = payload_schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.as_slice())
+            .unwrap_or_default();
+        for field in required.iter().filter_map(|r| r.as_str()) {
+            if !self.fields.contains_key(field) {
+                errors.push(TaskValidateError::MissingHandoffField {
+                    index,
+                    field: field.to_string(),
+                });
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub use native::*;
+
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use ergo_database::object_id::{InputId, OrgId, TaskTriggerId, UserId};
+    use ergo_notifications::NotificationManager;
+    use ergo_queues::JobPriority;
+    use sqlx::PgConnection;
+    use tracing::{event, Level};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        inputs::{enqueue_input, EnqueueInputOptions},
+        Error, TaskState,
+    };
+
+    /// The value that handoff field pointers are resolved against.
+    pub fn run_output(
+        payload: &serde_json::Value,
+        context: serde_json::Value,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "payload": payload,
+            "context": context,
+        })
+    }
+
+    /// The context of a task, as exposed to handoffs. Data flow tasks don't have their node
+    /// names in the state, so the caller handles those.
+    pub fn state_context(state: &TaskState) -> serde_json::Value {
+        match state {
+            TaskState::StateMachine(machines) => machines
+                .iter()
+                .map(|m| m.context.clone())
+                .collect::<Vec<_>>()
+                .into(),
+            TaskState::Js(state) => serde_json::from_str(&state.context)
+                .unwrap_or_else(|_| serde_json::Value::String(state.context.clone())),
+            TaskState::DataFlow(_) => serde_json::Value::Null,
+        }
+    }
+
+    /// Send the handoff's payload to the other task. The new input is recorded as a child
+    /// of `parent_inputs_log_id`. If the other task has been disabled or deleted, or no longer
+    /// has the trigger, or the chain of handoffs leading to this run is already
+    /// [MAX_HANDOFF_DEPTH] long, the handoff is skipped and `None` is returned.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_handoff(
+        tx: &mut PgConnection,
+        notifications: Option<NotificationManager>,
+        redis_key_prefix: Option<&str>,
+        org_id: &OrgId,
+        user_id: &UserId,
+        parent_inputs_log_id: Uuid,
+        handoff: &TaskHandoff,
+        output: &serde_json::Value,
+    ) -> Result<Option<Uuid>, Error> {
+        let depth = sqlx::query_scalar!(
+            r##"WITH RECURSIVE ancestors AS (
+                SELECT inputs_log_id, parent_inputs_log_id, 1 AS depth
+                FROM inputs_log WHERE inputs_log_id = $1
+                UNION ALL
+                SELECT il.inputs_log_id, il.parent_inputs_log_id, a.depth + 1
+                FROM inputs_log il
+                JOIN ancestors a ON il.inputs_log_id = a.parent_inputs_log_id
+                WHERE a.depth <= $2
+            )
+            SELECT COUNT(*) AS "depth!" FROM ancestors"##,
+            parent_inputs_log_id,
+            MAX_HANDOFF_DEPTH as i32
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if depth > MAX_HANDOFF_DEPTH {
+            event!(
+                Level::WARN,
+                ?handoff,
+                %parent_inputs_log_id,
+                "Skipping handoff that exceeds the maximum handoff depth"
+            );
+            return Ok(None);
+        }
+
+        let trigger = sqlx::query!(
+            r##"SELECT task_trigger_id AS "task_trigger_id: TaskTriggerId",
+                input_id AS "input_id: InputId",
+                tt.name AS task_trigger_name,
+                inputs.payload_schema,
+                tasks.name AS task_name
+            FROM task_triggers tt
+            JOIN tasks USING(task_id)
+            JOIN inputs USING(input_id)
+            WHERE task_id=$1 AND task_trigger_local_id=$2 AND tasks.org_id=$3
+                AND tasks.enabled AND NOT tasks.deleted"##,
+            handoff.task_id.0,
+            &handoff.trigger_id,
+            org_id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // The source task's run already succeeded, so a missing target shouldn't fail it.
+        let trigger = match trigger {
+            Some(t) => t,
+            None => {
+                event!(
+                    Level::WARN,
+                    ?handoff,
+                    "Skipping handoff to missing or disabled task trigger"
+                );
+                return Ok(None);
+            }
+        };
+
+        let payload = handoff.payload(output);
+        event!(Level::DEBUG, ?handoff, ?payload, "Sending handoff");
+        enqueue_input(EnqueueInputOptions {
+            pg: tx,
+            notifications,
+            org_id: org_id.clone(),
+            user_id: user_id.clone(),
+            task_id: handoff.task_id.clone(),
+            task_name: trigger.task_name,
+            input_id: trigger.input_id,
+            task_trigger_id: trigger.task_trigger_id,
+            task_trigger_local_id: handoff.trigger_id.clone(),
+            task_trigger_name: trigger.task_trigger_name,
+            periodic_trigger_id: None,
+            payload_schema: &trigger.payload_schema,
+            payload,
+            redis_key_prefix,
+            trigger_at: None,
+            parent_inputs_log_id: Some(parent_inputs_log_id),
+            priority: JobPriority::Normal,
+            idempotency_key: None,
+        })
+        .await
+        .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn handoff(fields: &[(&str, &str)]) -> TaskHandoff {
+        TaskHandoff {
+            task_id: TaskId::new(),
+            trigger_id: "start".to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn build_payload() {
+        let h = handoff(&[
+            ("id", "/payload/id"),
+            ("count", "/context/0/count"),
+            ("missing", "/context/1/count"),
+        ]);
+        let output = json!({
+            "payload": { "id": "abc" },
+            "context": [ { "count": 5 } ],
+        });
+
+        assert_eq!(
+            h.payload(&output),
+            json!({ "id": "abc", "count": 5, "missing": null })
+        );
+    }
+
+    #[test]
+    fn validate_against_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "count": { "type": "number" },
+            },
+            "required": ["id", "count"],
+            "additionalProperties": false,
+        });
+
+        let h = handoff(&[("id", "/payload/id"), ("count", "/context/0/count")]);
+        assert!(h.validate(0, &schema).is_empty());
+
+        let h = handoff(&[("id", "/state/id"), ("other", "/payload")]);
+        let paths = h
+            .validate(2, &schema)
+            .iter()
+            .map(|e| e.path().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "handoffs[2].fields.id",
+                "handoffs[2].fields.other",
+                "handoffs[2].fields.count",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_open_schema() {
+        let h = handoff(&[("anything", "/payloadx")]);
+        let errors = h.validate(0, &json!({}));
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            TaskValidateError::InvalidHandoffSource { field, .. } if field == "anything"
+        ));
+    }
+}
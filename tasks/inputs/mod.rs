@@ -52,6 +52,32 @@ pub enum InputStatus {
     Error,
 }
 
+/// The queue priority of the inputs sent to a trigger. Inputs with a higher priority are
+/// processed first when the queue is backed up.
+#[derive(Clone, Copy, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(not(target_family = "wasm"), derive(sqlx::Type))]
+#[cfg_attr(
+    not(target_family = "wasm"),
+    sqlx(type_name = "trigger_priority", rename_all = "lowercase")
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerPriority {
+    High,
+    Normal,
+    Low,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl From<TriggerPriority> for ergo_queues::JobPriority {
+    fn from(priority: TriggerPriority) -> Self {
+        match priority {
+            TriggerPriority::High => ergo_queues::JobPriority::High,
+            TriggerPriority::Normal => ergo_queues::JobPriority::Normal,
+            TriggerPriority::Low => ergo_queues::JobPriority::Low,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct InputInvocation {
     pub task_id: TaskId,
@@ -61,6 +87,9 @@ pub struct InputInvocation {
     pub inputs_log_id: uuid::Uuid,
     pub payload: serde_json::Value,
     pub user_id: UserId,
+    #[cfg(not(target_family = "wasm"))]
+    #[serde(default)]
+    pub priority: ergo_queues::JobPriority,
 }
 
 pub fn validate_input_payload(
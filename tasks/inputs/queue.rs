@@ -1,18 +1,27 @@
 use std::{borrow::Cow, ops::Deref};
 
-use crate::{error::Error, inputs::InputInvocation};
+use crate::{
+    error::Error,
+    events::{TaskEvent, TaskEventKind},
+    inputs::{InputInvocation, TriggerPriority},
+    limits::RunLimit,
+};
 
 use chrono::{DateTime, Utc};
 use ergo_database::{new_uuid, object_id::*, RedisPool};
 use ergo_notifications::{Notification, NotificationManager, NotifyEvent};
-use ergo_queues::{generic_stage::QueueJob, Queue};
-use sqlx::{Connection, PgConnection};
+use ergo_queues::{generic_stage::QueueJob, DuplicateMode, Idempotency, JobPriority, Queue};
+use sqlx::{types::Json, Connection, PgConnection};
+use tracing::{event, Level};
 use uuid::Uuid;
 
 use super::validate_input_payload;
 
 const QUEUE_NAME: &str = "er-input";
 
+/// Inputs sent with the same idempotency key within this window are only run once.
+pub const IDEMPOTENCY_WINDOW: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
 #[derive(Clone)]
 pub struct InputQueue(Queue);
 impl Deref for InputQueue {
@@ -60,6 +69,15 @@ pub struct EnqueueInputOptions<'a> {
     pub payload: serde_json::Value,
     pub redis_key_prefix: Option<&'a str>,
     pub trigger_at: Option<DateTime<Utc>>,
+    /// The input that caused this one to be sent, when one task hands off to another.
+    pub parent_inputs_log_id: Option<Uuid>,
+    /// The queue priority of the input, based on where it came from. A priority configured
+    /// on the trigger takes precedence. Actions triggered by the input use the same priority.
+    pub priority: JobPriority,
+    /// A key from the sender of the input. If another input was sent to the same trigger
+    /// with the same key within the [IDEMPOTENCY_WINDOW], this input is dropped and the ID of
+    /// the earlier input is returned.
+    pub idempotency_key: Option<String>,
 }
 
 pub async fn enqueue_input(options: EnqueueInputOptions<'_>) -> Result<Uuid, Error> {
@@ -79,20 +97,95 @@ pub async fn enqueue_input(options: EnqueueInputOptions<'_>) -> Result<Uuid, Err
         payload,
         redis_key_prefix,
         trigger_at,
+        parent_inputs_log_id,
+        priority,
+        idempotency_key,
     } = options;
 
-    validate_input_payload(&input_id, payload_schema, &payload)?;
+    if let Err(e) = validate_input_payload(&input_id, payload_schema, &payload) {
+        if let Some(notify) = notifications {
+            let notification = Notification {
+                task_id,
+                local_id: task_trigger_local_id,
+                local_object_id: Some(task_trigger_id.into_inner()),
+                local_object_name: task_trigger_name,
+                error: Some(e.to_string()),
+                event: NotifyEvent::InputError,
+                task_name,
+                log_id: None,
+                payload: Some(payload),
+            };
+
+            // The validation error is more important to the caller than a failure to notify.
+            let result = pg
+                .transaction(|tx| {
+                    Box::pin(async move { notify.notify(&mut *tx, &org_id, notification).await })
+                })
+                .await;
+            if let Err(notify_err) = result {
+                event!(Level::ERROR, err=?notify_err, "Failed to send input error notification");
+            }
+        }
+
+        return Err(e);
+    }
 
     let input_arrival_id = new_uuid();
     let queue_name = InputQueue::queue_name(redis_key_prefix);
+    let idempotent_job_id = idempotency_key
+        .as_ref()
+        .map(|key| format!("{}:{}", task_trigger_id, key));
 
-    pg.transaction(|tx| {
+    let inputs_log_id = pg.transaction(|tx| {
         let input_id = input_id.clone();
         let task_id = task_id.clone();
         let task_trigger_id = task_trigger_id.clone();
         let user_id = user_id.clone();
 
         Box::pin(async move {
+            if let Some(job_id) = idempotent_job_id.as_ref() {
+                // Make concurrent requests with the same key wait for each other, so that
+                // only one of them enqueues the input.
+                sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                    .bind(job_id)
+                    .execute(&mut *tx)
+                    .await?;
+
+                let window_start =
+                    Utc::now() - chrono::Duration::seconds(IDEMPOTENCY_WINDOW.as_secs() as i64);
+                let existing = sqlx::query_scalar!(
+                    "SELECT inputs_log_id FROM inputs_log
+                    WHERE queue_job_id=$1 AND task_id=$2 AND created >= $3
+                    ORDER BY created DESC LIMIT 1",
+                    job_id,
+                    task_id.0,
+                    window_start
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                if let Some(existing) = existing {
+                    event!(Level::INFO, %existing, idempotency_key=%job_id, "Dropping duplicate input");
+                    return Ok(existing);
+                }
+            }
+
+            let settings = sqlx::query!(
+                r##"SELECT tasks.run_limit AS "run_limit: Json<RunLimit>",
+                    tt.priority AS "priority: TriggerPriority"
+                FROM task_triggers tt
+                JOIN tasks USING (task_id)
+                WHERE task_trigger_id=$1"##,
+                task_trigger_id.0
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            let (run_limit, trigger_priority) = settings
+                .map(|s| (s.run_limit, s.priority))
+                .unwrap_or_default();
+            let limit = run_limit.and_then(|l| l.job_limit(format!("task:{}", task_id)));
+            let priority = trigger_priority.map(JobPriority::from).unwrap_or(priority);
+
             let invocation = InputInvocation {
                 task_trigger_id: task_trigger_id.clone(),
                 periodic_trigger_id: periodic_trigger_id.clone(),
@@ -101,36 +194,56 @@ pub async fn enqueue_input(options: EnqueueInputOptions<'_>) -> Result<Uuid, Err
                 input_id,
                 inputs_log_id: input_arrival_id,
                 user_id,
+                priority,
             };
 
             let job = QueueJob {
                 queue: queue_name.as_ref(),
                 payload: &invocation,
-                id: None,
+                id: idempotent_job_id.as_deref(),
                 run_at: trigger_at,
                 timeout: None,
                 max_retries: None,
                 retry_backoff: None,
+                limit,
+                priority,
+                idempotency: idempotent_job_id.as_ref().map(|_| Idempotency {
+                    mode: DuplicateMode::Drop,
+                    window: IDEMPOTENCY_WINDOW,
+                }),
             };
 
             let job_id = job.enqueue(&mut *tx).await?;
 
             sqlx::query!(
                 r##"INSERT INTO inputs_log
-        (inputs_log_id, task_trigger_id, task_id, task_trigger_local_id, status, payload, queue_job_id, periodic_trigger_id)
+        (inputs_log_id, task_trigger_id, task_id, task_trigger_local_id, status, payload, queue_job_id, periodic_trigger_id,
+            parent_inputs_log_id)
         VALUES
-        ($1, $2, $3, $4, 'pending', $5, $6, $7)"##,
+        ($1, $2, $3, $4, 'pending', $5, $6, $7, $8)"##,
                 input_arrival_id,
                 task_trigger_id.0,
                 task_id.0,
                 task_trigger_local_id,
                 payload,
                 job_id,
-                periodic_trigger_id.as_ref().map(|p| p.0)
+                periodic_trigger_id.as_ref().map(|p| p.0),
+                parent_inputs_log_id
             )
             .execute(&mut *tx)
             .await?;
 
+            TaskEvent::new(
+                task_id.clone(),
+                TaskEventKind::InputArrived {
+                    inputs_log_id: input_arrival_id,
+                    task_trigger_local_id: task_trigger_local_id.clone(),
+                    payload: Some(payload.clone()),
+                },
+            )
+            .publish(&mut *tx)
+            .await?;
+
             if let Some(notify) = notifications {
                 let notification = Notification {
                     task_id,
@@ -146,10 +259,10 @@ pub async fn enqueue_input(options: EnqueueInputOptions<'_>) -> Result<Uuid, Err
                 notify.notify(&mut *tx, &org_id, notification).await?;
             }
 
-            Ok::<(), Error>(())
+            Ok::<Uuid, Error>(input_arrival_id)
         })
     })
     .await?;
 
-    Ok(input_arrival_id)
+    Ok(inputs_log_id)
 }
@@ -2,15 +2,26 @@
 
 pub mod actions;
 pub mod dataflow;
+pub mod diff;
 mod error;
+#[cfg(not(target_family = "wasm"))]
+pub mod events;
+pub mod handoff;
 pub mod inputs;
+pub mod limits;
+#[cfg(not(target_family = "wasm"))]
+pub mod metrics;
 pub mod periodic;
 #[cfg(not(target_family = "wasm"))]
 pub mod queue_drain_runner;
 pub mod scripting;
+#[cfg(not(target_family = "wasm"))]
+pub mod simulate;
 pub mod state_machine;
+#[cfg(not(target_family = "wasm"))]
+pub mod test_cases;
 
-use actions::{Action, TaskAction};
+use actions::Action;
 use ergo_database::object_id::{InputId, PeriodicTriggerId, TaskId, TaskTriggerId};
 pub use error::*;
 use inputs::Input;
@@ -31,12 +42,14 @@ pub enum TaskConfig {
 }
 
 impl TaskConfig {
-    pub fn validate(
+    /// Validate the config against the task's triggers and actions. Only the keys of
+    /// `task_triggers` and `task_actions` are used, so callers can pass whatever they have on hand.
+    pub fn validate<TRIGGER, ACTION>(
         &self,
         actions: &FxHashMap<String, Action>,
         inputs: &FxHashMap<String, Input>,
-        task_triggers: &FxHashMap<String, TaskTrigger>,
-        task_actions: &FxHashMap<String, TaskAction>,
+        task_triggers: &FxHashMap<String, TRIGGER>,
+        task_actions: &FxHashMap<String, ACTION>,
     ) -> Result<(), TaskValidateErrors> {
         let errors = match self {
             Self::StateMachine(machines) => {
@@ -49,11 +62,8 @@ impl TaskConfig {
                 }
                 errors
             }
-            // TODO Some sort of validation for scripts.
-            // At least do a syntax check.
-            Self::Js(_) => Vec::new(),
-            // TODO
-            Self::DataFlow(_) => Vec::new(),
+            Self::Js(config) => config.validate(),
+            Self::DataFlow(config) => config.validate(task_triggers, task_actions),
         };
 
         if errors.is_empty() {
@@ -73,6 +83,88 @@ impl TaskConfig {
             Self::DataFlow(config) => TaskState::DataFlow(config.default_state()),
         }
     }
+
+    /// Convert `state`, which was created with `from_config`, to work with this config.
+    /// Anything that can't be carried over is reset to its default.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn migrate_state(
+        &self,
+        from_config: &TaskConfig,
+        state: &TaskState,
+    ) -> (TaskState, StateMigration) {
+        match (self, from_config, state) {
+            (
+                Self::StateMachine(machines),
+                Self::StateMachine(old_machines),
+                TaskState::StateMachine(old_states),
+            ) => {
+                let mut kept = 0;
+                let mut reset = 0;
+                let states = machines
+                    .iter()
+                    .map(|machine| {
+                        let old_data = old_machines
+                            .iter()
+                            .position(|m| m.name == machine.name)
+                            .and_then(|idx| old_states.get(idx));
+                        let migrated = old_data.and_then(|old| {
+                            machine
+                                .migrate_state(old)
+                                .map(|data| (data.timers.len() == old.timers.len(), data))
+                        });
+
+                        match migrated {
+                            Some((complete, data)) => {
+                                if complete {
+                                    kept += 1;
+                                } else {
+                                    reset += 1;
+                                }
+                                data
+                            }
+                            None => {
+                                reset += 1;
+                                machine.default_state()
+                            }
+                        }
+                    })
+                    .collect::<state_machine::StateMachineStates>();
+
+                // Machines that were removed count as reset too.
+                let removed = old_machines
+                    .iter()
+                    .filter(|old| !machines.iter().any(|m| m.name == old.name))
+                    .count();
+                let migration = match (kept, reset + removed) {
+                    (_, 0) => StateMigration::Kept,
+                    (0, _) => StateMigration::Reset,
+                    _ => StateMigration::Partial,
+                };
+
+                (TaskState::StateMachine(states), migration)
+            }
+            (Self::Js(_), Self::Js(_), TaskState::Js(old)) => {
+                (TaskState::Js(old.clone()), StateMigration::Kept)
+            }
+            (Self::DataFlow(config), Self::DataFlow(old_config), TaskState::DataFlow(old)) => {
+                let (state, migration) = config.migrate_state(old_config, old);
+                (TaskState::DataFlow(state), migration)
+            }
+            _ => (self.default_state(), StateMigration::Reset),
+        }
+    }
+}
+
+/// How much of a task's state survived a change to its config.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateMigration {
+    /// All of the state was carried over.
+    Kept,
+    /// Some of the state was carried over, and the rest was reset.
+    Partial,
+    /// The state could not be carried over, and was reset to the default.
+    Reset,
 }
 
 #[derive(Debug, JsonSchema, Serialize, Deserialize)]
@@ -85,6 +177,8 @@ pub struct TaskTrigger {
     #[schemars(with = "Option<String>")]
     pub last_payload: Option<Box<serde_json::value::RawValue>>,
     pub periodic: Option<Vec<PeriodicTaskTrigger>>,
+    #[serde(default)]
+    pub priority: Option<inputs::TriggerPriority>,
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -101,9 +195,13 @@ mod native {
             ActionInvocation, ActionInvocations, ActionStatus, TaskActionTemplate,
         },
         dataflow::DataFlowState,
+        events::{TaskEvent, TaskEventKind},
+        handoff::{self, TaskHandoff},
         inputs::{enqueue_input, EnqueueInputOptions, InputInvocation, InputStatus},
         scripting::TaskJsState,
-        state_machine::{StateMachineStates, StateMachineWithData},
+        state_machine::{
+            apply_timer_changes, PendingTimer, StateMachineStates, StateMachineWithData,
+        },
         TaskConfig,
     };
     use chrono::{DateTime, Utc};
@@ -117,6 +215,7 @@ mod native {
         PostgresPool,
     };
     use ergo_notifications::{Notification, NotificationManager, NotifyEvent};
+    use ergo_queues::JobPriority;
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
     use smallvec::SmallVec;
@@ -132,6 +231,33 @@ mod native {
         DataFlow(DataFlowState),
     }
 
+    impl TaskState {
+        /// The pending timers in this state that are no longer in `new`, such as after a config
+        /// change. Their scheduled inputs should be cancelled with
+        /// [crate::state_machine::cancel_timers].
+        pub fn dropped_timers(&self, new: &TaskState) -> Vec<PendingTimer> {
+            let old_machines = match self {
+                TaskState::StateMachine(machines) => machines,
+                _ => return Vec::new(),
+            };
+
+            let kept = match new {
+                TaskState::StateMachine(machines) => machines
+                    .iter()
+                    .flat_map(|m| m.timers.iter().map(|t| t.inputs_log_id))
+                    .collect::<Vec<_>>(),
+                _ => Vec::new(),
+            };
+
+            old_machines
+                .iter()
+                .flat_map(|m| m.timers.iter())
+                .filter(|timer| !kept.contains(&timer.inputs_log_id))
+                .cloned()
+                .collect()
+        }
+    }
+
     #[derive(Serialize, Deserialize, FromRow)]
     pub struct Task {
         pub task_id: TaskId,
@@ -153,15 +279,19 @@ mod native {
         /// and applies the input inside a serializable transaction, to ensure that
         /// the applied input doesn't have a race condition with any other concurrent
         /// inputs to the same task.
+        ///
+        /// `final_attempt` is true when the input's job won't be retried if this fails. Periodic
+        /// triggers are rescheduled and error notifications are sent only after the final attempt.
         #[instrument(skip(pool, notifications))]
         pub async fn apply_input(
             pool: &PostgresPool,
             notifications: Option<NotificationManager>,
             redis_key_prefix: Option<String>,
-            reschedule_periodic_task_on_error: bool,
+            final_attempt: bool,
             invocation: InputInvocation,
         ) -> Result<(), Error> {
             let mut conn = pool.acquire().await?;
+            let start = std::time::Instant::now();
 
             let inv = invocation.clone();
             let not = notifications.clone();
@@ -175,6 +305,7 @@ mod native {
                     task_trigger_id,
                     user_id,
                     periodic_trigger_id,
+                    priority,
                     ..
                 } = inv.clone();
                 let notifications = not.clone();
@@ -206,6 +337,7 @@ mod native {
                         task_trigger_name: String,
                         task_actions: Json<SmallVec<[TaskAction; 4]>>,
                         periodic_trigger_id: Option<PeriodicTriggerId>,
+                        handoffs: Json<Vec<TaskHandoff>>,
                     }
 
                     let task = sqlx::query_as!(TaskInputData,
@@ -218,6 +350,7 @@ mod native {
                             tasks.name as task_name,
                             tt.name as task_trigger_name,
                             pt.periodic_trigger_id as "periodic_trigger_id: Option<PeriodicTriggerId>",
+                            tasks.handoffs as "handoffs!: Json<Vec<TaskHandoff>>",
                             jsonb_agg(jsonb_build_object(
                                 'task_action_local_id', ta.task_action_local_id,
                                 'task_action_name', ta.name,
@@ -241,7 +374,7 @@ mod native {
                             LEFT JOIN accounts USING(account_id)
                             WHERE tasks.task_id=$1
                             GROUP BY task_trigger_local_id, compiled, state, tasks.org_id, task_name,
-                                task_trigger_name, periodic_trigger_id"##,
+                                task_trigger_name, periodic_trigger_id, tasks.handoffs"##,
                             task_id.0,
                             task_trigger_id.0,
                             periodic_trigger_id as _
@@ -252,7 +385,8 @@ mod native {
                     let task = task.ok_or(Error::NotFound)?;
 
                     let TaskInputData {
-                        task_trigger_local_id, config, state, org_id, task_name, task_trigger_name, task_actions, periodic_trigger_id: found_periodic_trigger
+                        task_trigger_local_id, config, state, org_id, task_name, task_trigger_name, task_actions, periodic_trigger_id: found_periodic_trigger,
+                        handoffs,
                     } = task;
 
                     if periodic_trigger_id.is_some() && found_periodic_trigger.is_none() {
@@ -261,7 +395,11 @@ mod native {
                         return Err(Error::PeriodicTaskDeleted);
                     }
 
-                    let (new_data, log_info, actions, changed) = match (config.0, state.0) {
+                    // Notifications for state machine changes and from task scripts, sent after
+                    // the task is updated.
+                    let mut extra_notifications = Vec::new();
+
+                    let (new_data, log_info, actions, changed, handoff_context) = match (config.0, state.0) {
                         (TaskConfig::StateMachine(machine), TaskState::StateMachine(state)) => {
                             let num_machines = machine.len();
                             let mut new_data = StateMachineStates::with_capacity(num_machines);
@@ -271,7 +409,11 @@ mod native {
                                 .into_iter()
                                 .zip(state.into_iter())
                                 .enumerate() {
+                                    let machine_name = machine.name.clone();
+                                    let old_state = state.state.clone();
+                                    let old_active = state.active_states();
                                     let mut m = StateMachineWithData::new(task_id.clone(), idx, machine, state);
+                                    m.timer_fired(&input_arrival_id);
                                     let this_actions = m
                                       .apply_trigger(
                                           &task_trigger_local_id,
@@ -281,13 +423,48 @@ mod native {
                                       ).await
                                       .map_err(Error::from)?;
 
-                                  let (data, this_changed) = m.take();
+                                  let timer_changes = m.take_timer_changes();
+                                  let (mut data, mut this_changed) = m.take();
+                                  if !timer_changes.is_empty() {
+                                      apply_timer_changes(
+                                          &mut *tx,
+                                          &notifications,
+                                          redis_key_prefix.as_deref(),
+                                          &org_id,
+                                          &user_id,
+                                          &task_id,
+                                          &task_name,
+                                          &mut data,
+                                          timer_changes,
+                                      ).await?;
+                                      this_changed = true;
+                                  }
+                                  let new_active = data.active_states();
+                                  if new_active != old_active {
+                                      extra_notifications.push(Notification {
+                                          event: NotifyEvent::StateChanged,
+                                          task_id: task_id.clone(),
+                                          task_name: task_name.clone(),
+                                          local_id: machine_name.clone(),
+                                          local_object_name: machine_name,
+                                          local_object_id: None,
+                                          payload: Some(serde_json::json!({
+                                              "old_state": old_state,
+                                              "new_state": &data.state,
+                                              "old_active": old_active,
+                                              "new_active": new_active,
+                                          })),
+                                          error: None,
+                                          log_id: Some(input_arrival_id),
+                                      });
+                                  }
+
                                   new_data.push(data);
                                   actions.extend(this_actions.into_iter());
                                   changed = changed || this_changed;
                             }
 
-                            (TaskState::StateMachine(new_data), serde_json::Value::Null, actions, changed)
+                            (TaskState::StateMachine(new_data), serde_json::Value::Null, actions, changed, None)
                         },
                         (TaskConfig::StateMachine(_), _) =>  {
                             return Err(Error::ConfigStateMismatch("StateMachine"))
@@ -305,8 +482,31 @@ mod native {
                                 }
                             }).collect::<ActionInvocations>();
 
-                            // TODO Return console messages here
-                            (TaskState::Js(run_result.state), serde_json::Value::Null ,actions, run_result.state_changed)
+                            if !run_result.console.is_empty() {
+                                TaskEvent::new(
+                                    task_id.clone(),
+                                    TaskEventKind::Console {
+                                        inputs_log_id: input_arrival_id,
+                                        messages: run_result.console,
+                                    },
+                                )
+                                .publish(&mut *tx)
+                                .await?;
+                            }
+
+                            extra_notifications.extend(run_result.notifications.into_iter().map(|n| Notification {
+                                event: NotifyEvent::Custom,
+                                task_id: task_id.clone(),
+                                task_name: task_name.clone(),
+                                local_id: task_trigger_local_id.clone(),
+                                local_object_name: n.message,
+                                local_object_id: None,
+                                payload: n.data,
+                                error: None,
+                                log_id: Some(input_arrival_id),
+                            }));
+
+                            (TaskState::Js(run_result.state), serde_json::Value::Null ,actions, run_result.state_changed, None)
                         },
                         (TaskConfig::Js(_), _) =>  {
                             return Err(Error::ConfigStateMismatch("Js"))
@@ -325,8 +525,9 @@ mod native {
                             }).collect::<ActionInvocations>();
 
                             let log_out = serde_json::to_value(&log)?;
+                            let handoff_context = (!handoffs.is_empty()).then(|| config.node_outputs(&state));
 
-                            (TaskState::DataFlow(state), log_out, actions, true)
+                            (TaskState::DataFlow(state), log_out, actions, true, handoff_context)
                         }
                         (TaskConfig::DataFlow(_), _) => {
                             return Err(Error::ConfigStateMismatch("DataFlow"))
@@ -345,6 +546,16 @@ mod native {
                         )
                         .execute(&mut *tx)
                         .await?;
+
+                        TaskEvent::new(
+                            task_id.clone(),
+                            TaskEventKind::StateChanged {
+                                inputs_log_id: input_arrival_id,
+                                state: Some(serde_json::to_value(&new_data)?),
+                            },
+                        )
+                        .publish(&mut *tx)
+                        .await?;
                     }
 
                     if !actions.is_empty() {
@@ -399,10 +610,31 @@ mod native {
                         }
 
                         log_query.fetch_all(&mut *tx).await?;
-                        enqueue_actions(&mut *tx, &actions, &redis_key_prefix).await?;
+                        enqueue_actions(&mut *tx, &actions, &redis_key_prefix, priority).await?;
+                    }
+
+                    if !handoffs.is_empty() {
+                        let context = handoff_context.unwrap_or_else(|| handoff::state_context(&new_data));
+                        let output = handoff::run_output(&payload, context);
+                        for handoff in handoffs.iter() {
+                            handoff::send_handoff(
+                                &mut *tx,
+                                notifications.clone(),
+                                redis_key_prefix.as_deref(),
+                                &org_id,
+                                &user_id,
+                                input_arrival_id,
+                                handoff,
+                                &output,
+                            ).await?;
+                        }
                     }
 
                     if let Some(notifications) = notifications {
+                        for notification in extra_notifications {
+                            notifications.notify(&mut *tx, &org_id, notification).await?;
+                        }
+
                         let input_notification = Notification{
                             event: NotifyEvent::InputProcessed,
                             payload: Some(payload),
@@ -439,14 +671,47 @@ mod native {
                 }
                 Err(e) => {
                     event!(Level::ERROR, err=?e, "Error applying input");
-                    (
-                        serde_json::json!({ "msg": e.to_string(), "info": format!("{:?}", e) }),
-                        InputStatus::Error,
-                        Err(e),
-                    )
+                    if let Some(notifications) = notifications.as_ref().filter(|_| final_attempt) {
+                        if let Err(notify_err) =
+                            Self::notify_input_error(pool, notifications, &invocation, &e).await
+                        {
+                            event!(Level::ERROR, err=?notify_err, "Failed to send input error notification");
+                        }
+                    }
+
+                    let log_info = match &e {
+                        // Keep the partial DataFlow log so that the failed run shows up in the history.
+                        Error::DataflowRun { error, log } => {
+                            let mut info = serde_json::to_value(log).unwrap_or_default();
+                            if let Some(info) = info.as_object_mut() {
+                                info.insert(
+                                    "msg".to_string(),
+                                    serde_json::json!(error.to_string()),
+                                );
+                                info.insert(
+                                    "info".to_string(),
+                                    serde_json::json!(format!("{:?}", error)),
+                                );
+                            }
+                            info
+                        }
+                        _ => {
+                            serde_json::json!({ "msg": e.to_string(), "info": format!("{:?}", e) })
+                        }
+                    };
+
+                    (log_info, InputStatus::Error, Err(e))
                 }
             };
 
+            let status_label = if retval.is_ok() { "success" } else { "error" };
+            crate::metrics::TASK_RUNS
+                .with_label_values(&[status_label])
+                .inc();
+            crate::metrics::TASK_RUN_SECONDS
+                .with_label_values(&[status_label])
+                .observe(start.elapsed().as_secs_f64());
+
             event!(Level::INFO, input_arrival_id=%invocation.inputs_log_id, ?status, ?log_info, "Updating input status");
             sqlx::query!(
                 "UPDATE inputs_log SET status=$2, info=$3, updated=now() WHERE inputs_log_id=$1",
@@ -457,10 +722,23 @@ mod native {
             .execute(pool)
             .await?;
 
+            // The input has already been applied, so a failure here shouldn't cause a retry.
+            let processed_event = TaskEvent::new(
+                invocation.task_id.clone(),
+                TaskEventKind::InputProcessed {
+                    inputs_log_id: invocation.inputs_log_id,
+                    status,
+                    info: Some(log_info),
+                },
+            );
+            if let Err(e) = processed_event.publish(pool).await {
+                event!(Level::ERROR, err=?e, "Failed to publish task event");
+            }
+
             // If this was a periodic trigger, enqueue it again.
             if let Some(periodic_id) = invocation
                 .periodic_trigger_id
-                .filter(|_| retval.is_ok() || reschedule_periodic_task_on_error)
+                .filter(|_| retval.is_ok() || final_attempt)
             {
                 let info = sqlx::query!(
                     r##"SELECT
@@ -511,6 +789,9 @@ mod native {
                             payload: info.payload,
                             redis_key_prefix: redis_key_prefix.as_deref(),
                             trigger_at: Some(next_time),
+                            parent_inputs_log_id: None,
+                            priority: JobPriority::Low,
+                            idempotency_key: None,
                         })
                         .await?;
                     }
@@ -519,5 +800,53 @@ mod native {
 
             retval
         }
+
+        /// Notify listeners that an input failed. This runs in its own transaction, since the
+        /// transaction that applied the input was rolled back.
+        async fn notify_input_error(
+            pool: &PostgresPool,
+            notifications: &NotificationManager,
+            invocation: &InputInvocation,
+            error: &Error,
+        ) -> Result<(), Error> {
+            let info = sqlx::query!(
+                r##"SELECT tasks.org_id AS "org_id: OrgId",
+                    tasks.name AS task_name,
+                    tt.task_trigger_local_id,
+                    tt.name AS task_trigger_name
+                FROM tasks
+                JOIN task_triggers tt USING (task_id)
+                WHERE task_id=$1 AND task_trigger_id=$2"##,
+                invocation.task_id.0,
+                invocation.task_trigger_id.0
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            // The task may have been deleted.
+            let info = match info {
+                Some(info) => info,
+                None => return Ok(()),
+            };
+
+            let notification = Notification {
+                event: NotifyEvent::InputError,
+                task_id: invocation.task_id.clone(),
+                task_name: info.task_name,
+                local_id: info.task_trigger_local_id,
+                local_object_name: info.task_trigger_name,
+                local_object_id: Some(invocation.task_trigger_id.0),
+                payload: Some(invocation.payload.clone()),
+                error: Some(error.to_string()),
+                log_id: Some(invocation.inputs_log_id),
+            };
+
+            let mut tx = pool.begin().await?;
+            notifications
+                .notify(&mut *tx, &info.org_id, notification)
+                .await?;
+            tx.commit().await?;
+            Ok(())
+        }
     }
 }
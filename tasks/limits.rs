@@ -0,0 +1,75 @@
+//! Limits on how often a task's inputs or a task action's jobs run. Accounts can also have a
+//! limit, which applies to every action run that uses the account. The limits are enforced by
+//! the job queue, so they apply across every backend process.
+
+use std::num::NonZeroU32;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+pub struct RunLimit {
+    /// The maximum number of jobs that can run at once.
+    #[serde(default)]
+    pub max_concurrent: Option<NonZeroU32>,
+    /// The maximum number of jobs that can start within a period of time.
+    #[serde(default)]
+    pub rate: Option<RunRateLimit>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+pub struct RunRateLimit {
+    pub max: NonZeroU32,
+    pub period_secs: NonZeroU32,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl RunLimit {
+    /// Build the queue limit for jobs that share `key`, or `None` if there is nothing to limit.
+    pub fn job_limit(&self, key: String) -> Option<ergo_queues::JobLimit> {
+        if self.max_concurrent.is_none() && self.rate.is_none() {
+            return None;
+        }
+
+        Some(ergo_queues::JobLimit {
+            key,
+            max_concurrent: self.max_concurrent.map(NonZeroU32::get),
+            rate: self.rate.as_ref().map(|rate| ergo_queues::RateLimit {
+                max: rate.max.get(),
+                period: std::time::Duration::from_secs(rate.period_secs.get() as u64),
+            }),
+        })
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn job_limit() {
+        assert_eq!(RunLimit::default().job_limit("a".to_string()), None);
+
+        let limit: RunLimit = serde_json::from_value(json!({
+            "max_concurrent": 1,
+            "rate": { "max": 5, "period_secs": 2 },
+        }))
+        .unwrap();
+        assert_eq!(
+            limit.job_limit("a".to_string()),
+            Some(ergo_queues::JobLimit {
+                key: "a".to_string(),
+                max_concurrent: Some(1),
+                rate: Some(ergo_queues::RateLimit {
+                    max: 5,
+                    period: std::time::Duration::from_secs(2),
+                }),
+            })
+        );
+
+        serde_json::from_value::<RunLimit>(json!({ "max_concurrent": 0 }))
+            .expect_err("zero concurrency is rejected");
+    }
+}
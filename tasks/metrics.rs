@@ -0,0 +1,43 @@
+//! Prometheus metrics for task and action runs, registered in the default registry.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounterVec,
+};
+
+lazy_static! {
+    /// Inputs applied to tasks, by final status.
+    pub static ref TASK_RUNS: IntCounterVec = register_int_counter_vec!(
+        "ergo_task_runs_total",
+        "Inputs applied to tasks",
+        &["status"]
+    )
+    .unwrap();
+
+    /// Time spent applying an input to a task, by final status.
+    pub static ref TASK_RUN_SECONDS: HistogramVec = register_histogram_vec!(
+        "ergo_task_run_seconds",
+        "Time spent applying an input to a task",
+        &["status"],
+        exponential_buckets(0.005, 4.0, 10).unwrap()
+    )
+    .unwrap();
+
+    /// Actions executed, by final status.
+    pub static ref ACTION_RUNS: IntCounterVec = register_int_counter_vec!(
+        "ergo_action_runs_total",
+        "Actions executed",
+        &["status"]
+    )
+    .unwrap();
+
+    /// Time spent executing an action, by final status.
+    pub static ref ACTION_RUN_SECONDS: HistogramVec = register_histogram_vec!(
+        "ergo_action_run_seconds",
+        "Time spent executing an action",
+        &["status"],
+        exponential_buckets(0.005, 4.0, 10).unwrap()
+    )
+    .unwrap();
+}
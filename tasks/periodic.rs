@@ -58,7 +58,8 @@ mod native {
         PostgresPool,
     };
     use ergo_graceful_shutdown::GracefulShutdownConsumer;
-    use ergo_queues::{remove_pending_job, update_pending_job, JobUpdate};
+    use ergo_notifications::{Notification, NotificationManager, NotifyEvent};
+    use ergo_queues::{remove_pending_job, update_pending_job, JobPriority, JobUpdate};
     use smallvec::SmallVec;
     use sqlx::PgConnection;
     use tracing::{event, instrument, Level};
@@ -209,6 +210,9 @@ mod native {
                         payload: trigger.payload.clone(),
                         redis_key_prefix: redis_key_prefix.as_deref(),
                         trigger_at: Some(next_date),
+                        parent_inputs_log_id: None,
+                        priority: JobPriority::Low,
+                        idempotency_key: None,
                     })
                     .await?;
                 }
@@ -220,6 +224,7 @@ mod native {
 
     pub async fn enqueue_missing_periodic_triggers(
         pool: &PostgresPool,
+        notifications: Option<&NotificationManager>,
         redis_key_prefix: Option<&str>,
     ) -> Result<(), Error> {
         event!(Level::DEBUG, "Checking for missing periodic triggers");
@@ -257,6 +262,26 @@ mod native {
         for trigger in missing_triggers {
             if let Some(next_time) = trigger.schedule.next_run()? {
                 event!(Level::WARN, ?trigger, "Enqueueing missing periodic job");
+                if let Some(notifications) = notifications {
+                    let notification = Notification {
+                        event: NotifyEvent::PeriodicTriggerMissed,
+                        task_id: trigger.task_id.clone(),
+                        task_name: trigger.task_name.clone(),
+                        local_id: trigger.task_trigger_local_id.clone(),
+                        local_object_name: trigger.task_trigger_name.clone(),
+                        local_object_id: Some(trigger.task_trigger_id.0),
+                        payload: Some(serde_json::json!({
+                            "schedule": &trigger.schedule,
+                            "next_run": next_time,
+                        })),
+                        error: None,
+                        log_id: None,
+                    };
+                    notifications
+                        .notify(&mut tx, &trigger.org_id, notification)
+                        .await?;
+                }
+
                 enqueue_input(EnqueueInputOptions {
                     pg: &mut tx,
                     notifications: None,
@@ -273,6 +298,9 @@ mod native {
                     periodic_trigger_id: Some(trigger.periodic_trigger_id),
                     redis_key_prefix,
                     trigger_at: Some(next_time),
+                    parent_inputs_log_id: None,
+                    priority: JobPriority::Low,
+                    idempotency_key: None,
                 })
                 .await?;
             }
@@ -286,14 +314,19 @@ mod native {
     pub fn monitor_missing_periodic_triggers(
         mut shutdown: GracefulShutdownConsumer,
         pool: PostgresPool,
+        notifications: Option<NotificationManager>,
         redis_key_prefix: Option<String>,
         check_interval: Option<std::time::Duration>,
     ) -> tokio::task::JoinHandle<()> {
         let check_interval = check_interval.unwrap_or_else(|| std::time::Duration::from_secs(60));
         tokio::spawn(async move {
             loop {
-                let result =
-                    enqueue_missing_periodic_triggers(&pool, redis_key_prefix.as_deref()).await;
+                let result = enqueue_missing_periodic_triggers(
+                    &pool,
+                    notifications.as_ref(),
+                    redis_key_prefix.as_deref(),
+                )
+                .await;
                 if let Err(e) = result {
                     event!(Level::ERROR, error=%e, "Failed to check missing periodic triggers");
                 }
@@ -1,6 +1,8 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::TaskValidateError;
+
 #[cfg(not(target_family = "wasm"))]
 mod runtime;
 #[cfg(not(target_family = "wasm"))]
@@ -23,9 +25,106 @@ impl TaskJsConfig {
             context: "null".to_string(),
         }
     }
+
+    pub fn validate(&self) -> Vec<TaskValidateError> {
+        let mut checker = SyntaxChecker::default();
+        match checker.check_module("https://ergo/tasks/validate.js", &self.script) {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![TaskValidateError::ScriptError(e)],
+        }
+    }
 }
 
 #[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, Eq, PartialEq)]
 pub struct TaskJsState {
     pub context: String,
 }
+
+/// Checks scripts for syntax errors without running them.
+#[derive(Default)]
+pub struct SyntaxChecker {
+    /// Created on first use, since validating a config often doesn't involve any scripts.
+    #[cfg(not(target_family = "wasm"))]
+    runtime: Option<ergo_js::Runtime>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl SyntaxChecker {
+    fn runtime(&mut self) -> &mut ergo_js::Runtime {
+        self.runtime.get_or_insert_with(create_simple_runtime)
+    }
+
+    /// Check a classic script, such as the wrapped body of a function.
+    pub fn check_script(&mut self, name: &str, source: &str) -> Result<(), String> {
+        self.runtime()
+            .check_syntax(name, source, false)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Check an ES module. Modules can not import anything since there is nowhere to load it
+    /// from.
+    pub fn check_module(&mut self, name: &str, source: &str) -> Result<(), String> {
+        self.runtime()
+            .check_syntax(name, source, true)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(target_family = "wasm")]
+impl SyntaxChecker {
+    /// Check a classic script, such as the wrapped body of a function.
+    pub fn check_script(&mut self, _name: &str, source: &str) -> Result<(), String> {
+        use wasm_bindgen::JsCast;
+
+        // Constructing a Function compiles the code without running it.
+        let function_constructor = js_sys::Reflect::get(&js_sys::global(), &"Function".into())
+            .map_err(|e| format!("{:?}", e))?
+            .unchecked_into::<js_sys::Function>();
+
+        js_sys::Reflect::construct(&function_constructor, &js_sys::Array::of1(&source.into()))
+            .map(|_| ())
+            .map_err(|e| match e.dyn_into::<js_sys::Error>() {
+                Ok(e) => String::from(e.message()),
+                Err(e) => format!("{:?}", e),
+            })
+    }
+
+    /// Modules can't be compiled synchronously in the browser, so these are only checked
+    /// on the server.
+    pub fn check_module(&mut self, _name: &str, _source: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(script: &str) -> TaskJsConfig {
+        TaskJsConfig {
+            timeout: None,
+            script: script.to_string(),
+            map: String::new(),
+        }
+    }
+
+    #[test]
+    fn validate_good_script() {
+        let errors = config("let x = Ergo.getPayload(); Ergo.setContext(x);").validate();
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn validate_syntax_error() {
+        let errors = config("let x = Ergo.getPayload(;").validate();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path().unwrap().to_string(), "script");
+    }
+
+    #[test]
+    fn validate_import() {
+        let errors = config("import { a } from 'https://example.com/a.js';").validate();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], TaskValidateError::ScriptError(_)));
+    }
+}
@@ -13,12 +13,22 @@ use crate::{
 
 use super::{TaskJsConfig, TaskJsState};
 
+/// A notification sent by the script with `Ergo.notify`, or its global alias `notify`. Only
+/// JavaScript tasks can send these; the nodes of a DataFlow task run without the task helpers.
+#[derive(Debug, Deserialize)]
+pub struct ScriptNotification {
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+}
+
 #[derive(Debug)]
 pub struct RunTaskResult {
     pub state_changed: bool,
     pub state: TaskJsState,
     pub console: Vec<ConsoleMessage>,
     pub actions: TaskActionInvocations,
+    pub notifications: Vec<ScriptNotification>,
 }
 
 pub async fn run_task(
@@ -57,11 +67,17 @@ pub async fn run_task(
                     .unwrap_or_else(|_| Some(SmallVec::new()))
                     .unwrap_or_else(SmallVec::new);
 
+                let notifications = runtime
+                    .get_global_value("__ergo_notifyQueue")
+                    .unwrap_or_else(|_| Some(Vec::new()))
+                    .unwrap_or_default();
+
                 Ok(RunTaskResult {
                     state_changed,
                     state,
                     console,
                     actions,
+                    notifications,
                 })
             }
             Err(e) => Err(Error::TaskScript {
@@ -194,4 +210,31 @@ mod tests {
         assert_eq!(result.state_changed, true);
         assert_eq!(result.state.context, r##""context was undefined""##);
     }
+
+    #[tokio::test]
+    async fn notifications() {
+        let script = r##"
+            Ergo.notify('Temperature is too high', { temp: Ergo.getPayload().temp });
+            notify('No data');
+        "##;
+
+        let config = TaskJsConfig {
+            script: script.to_string(),
+            map: String::new(),
+            timeout: None,
+        };
+
+        let state = TaskJsState {
+            context: String::new(),
+        };
+
+        let result = run_task("test task", config, state, json!({ "temp": 90 }))
+            .await
+            .expect("running task");
+        assert_eq!(result.notifications.len(), 2);
+        assert_eq!(result.notifications[0].message, "Temperature is too high");
+        assert_eq!(result.notifications[0].data, Some(json!({ "temp": 90 })));
+        assert_eq!(result.notifications[1].message, "No data");
+        assert_eq!(result.notifications[1].data, None);
+    }
 }
@@ -3,6 +3,13 @@ Ergo.runAction = function(name, payload) {
   globalThis.__ergo_actionQueue.push({ name, payload });
 }
 
+globalThis.__ergo_notifyQueue = [];
+Ergo.notify = function(message, data) {
+  globalThis.__ergo_notifyQueue.push({ message: String(message), data });
+}
+// DataFlow nodes don't load these helpers, so neither form is available there.
+globalThis.notify = Ergo.notify;
+
 Ergo.getPayload = function() {
   return globalThis.__ergo_inputPayload;
 }
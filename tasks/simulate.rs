@@ -0,0 +1,375 @@
+//! Run a trigger against a task without saving anything, to see what the task would do.
+//!
+//! The task's code runs as usual, so scripts can still make network requests, but the new state
+//! is not saved, no actions or timers are enqueued, and no handoffs are sent. Action payloads are
+//! rendered through the action templates with placeholders in place of any account fields.
+
+use chrono::{DateTime, Utc};
+use ergo_database::{
+    object_id::{AccountId, ActionId, TaskId, UserId},
+    PostgresPool,
+};
+use ergo_js::ConsoleMessage;
+use fxhash::FxHashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+
+use crate::{
+    actions::{
+        execute::{validate_and_prepare_invocation, PrepareInvocationAction, ScriptOrTemplate},
+        template::TemplateFields,
+        TaskActionInvocation, TaskActionTemplate,
+    },
+    dataflow::DataFlowLog,
+    handoff::{self, TaskHandoff},
+    scripting,
+    state_machine::{DelayedTrigger, PendingTimer, StateMachineStates, StateMachineWithData},
+    Error, TaskConfig, TaskState,
+};
+
+/// The task and trigger to simulate.
+#[derive(Debug)]
+pub struct Simulation<'a> {
+    pub task_id: &'a TaskId,
+    pub task_name: &'a str,
+    pub user_id: &'a UserId,
+    pub config: TaskConfig,
+    pub state: TaskState,
+    pub handoffs: &'a [TaskHandoff],
+    /// The local ID of the trigger to run.
+    pub trigger_id: &'a str,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimulatedAction {
+    pub task_action_local_id: String,
+    /// The payload that the task sent to the action.
+    pub payload: serde_json::Value,
+    /// The values that the executor would receive after applying the action's template.
+    pub rendered: Option<FxHashMap<String, serde_json::Value>>,
+    /// Why the action would fail, if it would.
+    pub error: Option<String>,
+}
+
+/// A state machine timer that would have been started.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimulatedTimer {
+    /// The path of the state that starts the timer.
+    pub state: String,
+    #[serde(flatten)]
+    pub timer: DelayedTrigger,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimulatedHandoff {
+    pub task_id: TaskId,
+    pub trigger_id: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SimulateResult {
+    pub state: TaskState,
+    pub state_changed: bool,
+    pub console: Vec<ConsoleMessage>,
+    /// The record of the nodes that ran, for DataFlow tasks.
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub log: Option<DataFlowLog>,
+    pub actions: Vec<SimulatedAction>,
+    pub timers_started: Vec<SimulatedTimer>,
+    pub timers_cancelled: Vec<PendingTimer>,
+    pub handoffs: Vec<SimulatedHandoff>,
+}
+
+#[derive(Debug)]
+struct SimulateTaskAction {
+    task_action_local_id: String,
+    task_action_template: Option<Json<TaskActionTemplate>>,
+    action_id: ActionId,
+    action_template_fields: Json<TemplateFields>,
+    action_executor_template: Json<ScriptOrTemplate>,
+    executor_id: String,
+    account_id: Option<AccountId>,
+    account_required: bool,
+    account_fields: Option<Json<TaskActionTemplate>>,
+    account_expires: Option<DateTime<Utc>>,
+}
+
+/// Run the trigger and return what would have happened.
+pub async fn simulate_trigger(
+    pool: &PostgresPool,
+    sim: Simulation<'_>,
+) -> Result<SimulateResult, Error> {
+    let Simulation {
+        task_id,
+        task_name,
+        user_id,
+        config,
+        state,
+        handoffs,
+        trigger_id,
+        payload,
+    } = sim;
+
+    let task_triggers = sqlx::query_scalar!(
+        "SELECT task_trigger_local_id FROM task_triggers WHERE task_id=$1",
+        task_id.0
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|id| (id, ()))
+    .collect::<FxHashMap<_, _>>();
+
+    if !task_triggers.contains_key(trigger_id) {
+        return Err(Error::TaskTriggerNotFound(trigger_id.to_string()));
+    }
+
+    let task_actions = sqlx::query_as!(
+        SimulateTaskAction,
+        r##"SELECT ta.task_action_local_id,
+            NULLIF(ta.action_template, 'null'::jsonb) AS "task_action_template: _",
+            ta.action_id AS "action_id: ActionId",
+            ac.template_fields AS "action_template_fields!: _",
+            ac.executor_template AS "action_executor_template!: _",
+            ac.executor_id,
+            ta.account_id AS "account_id: AccountId",
+            ac.account_required,
+            accounts.fields AS "account_fields: _",
+            accounts.expires AS "account_expires?"
+        FROM task_actions ta
+        JOIN actions ac USING (action_id)
+        LEFT JOIN accounts USING (account_id)
+        WHERE ta.task_id=$1"##,
+        task_id.0
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|action| (action.task_action_local_id.clone(), action))
+    .collect::<FxHashMap<_, _>>();
+
+    config.validate(
+        &FxHashMap::default(),
+        &FxHashMap::default(),
+        &task_triggers,
+        &task_actions,
+    )?;
+
+    let TriggerRun {
+        state: new_state,
+        state_changed,
+        console,
+        log,
+        actions: invocations,
+        timers_started,
+        timers_cancelled,
+        context,
+    } = run_trigger(
+        task_id, task_name, user_id, config, state, trigger_id, &payload,
+    )
+    .await?;
+
+    let mut actions = Vec::with_capacity(invocations.len());
+    for TaskActionInvocation {
+        name: task_action_local_id,
+        payload,
+    } in invocations
+    {
+        let (rendered, error) = match task_actions.get(&task_action_local_id) {
+            Some(task_action) => match render_action(task_action, &payload).await {
+                Ok(rendered) => (Some(rendered), None),
+                Err(e) => (None, Some(e)),
+            },
+            None => (
+                None,
+                Some(Error::TaskActionNotFound(task_action_local_id.clone()).to_string()),
+            ),
+        };
+
+        actions.push(SimulatedAction {
+            task_action_local_id,
+            payload,
+            rendered,
+            error,
+        });
+    }
+
+    let handoffs = if handoffs.is_empty() {
+        Vec::new()
+    } else {
+        let output = handoff::run_output(&payload, context);
+        handoffs
+            .iter()
+            .map(|h| SimulatedHandoff {
+                task_id: h.task_id.clone(),
+                trigger_id: h.trigger_id.clone(),
+                payload: h.payload(&output),
+            })
+            .collect()
+    };
+
+    Ok(SimulateResult {
+        state: new_state,
+        state_changed,
+        console,
+        log,
+        actions,
+        timers_started,
+        timers_cancelled,
+        handoffs,
+    })
+}
+
+/// What a task did in response to a single trigger.
+#[derive(Debug)]
+pub struct TriggerRun {
+    pub state: TaskState,
+    pub state_changed: bool,
+    pub console: Vec<ConsoleMessage>,
+    pub log: Option<DataFlowLog>,
+    pub actions: Vec<TaskActionInvocation>,
+    pub timers_started: Vec<SimulatedTimer>,
+    pub timers_cancelled: Vec<PendingTimer>,
+    /// The task's context after the run, as seen by handoffs.
+    pub context: serde_json::Value,
+}
+
+/// Apply a trigger to a task's state without touching the database.
+pub async fn run_trigger(
+    task_id: &TaskId,
+    task_name: &str,
+    user_id: &UserId,
+    config: TaskConfig,
+    state: TaskState,
+    trigger_id: &str,
+    payload: &serde_json::Value,
+) -> Result<TriggerRun, Error> {
+    let mut console = Vec::new();
+    let mut log = None;
+    let mut timers_started = Vec::new();
+    let mut timers_cancelled = Vec::new();
+    let mut dataflow_context = None;
+
+    let (state, actions, state_changed) = match (config, state) {
+        (TaskConfig::StateMachine(machines), TaskState::StateMachine(states)) => {
+            let mut new_data = StateMachineStates::with_capacity(machines.len());
+            let mut actions = Vec::new();
+            let mut changed = false;
+            for (idx, (machine, data)) in machines.into_iter().zip(states.into_iter()).enumerate() {
+                let mut m = StateMachineWithData::new(task_id.clone(), idx, machine, data);
+                let invocations = m
+                    .apply_trigger(trigger_id, user_id, &None, Some(payload))
+                    .await?;
+
+                let timer_changes = m.take_timer_changes();
+                let (data, this_changed) = m.take();
+                changed = changed || this_changed || !timer_changes.is_empty();
+                timers_started.extend(
+                    timer_changes
+                        .start
+                        .into_iter()
+                        .map(|(state, timer)| SimulatedTimer { state, timer }),
+                );
+                timers_cancelled.extend(timer_changes.cancel);
+
+                new_data.push(data);
+                actions.extend(invocations.into_iter().map(|action| TaskActionInvocation {
+                    name: action.task_action_local_id,
+                    payload: action.payload,
+                }));
+            }
+
+            (TaskState::StateMachine(new_data), actions, changed)
+        }
+        (TaskConfig::StateMachine(_), _) => return Err(Error::ConfigStateMismatch("StateMachine")),
+        (TaskConfig::Js(config), TaskState::Js(state)) => {
+            let result =
+                scripting::immediate::run_task(task_name, config, state, payload.clone()).await?;
+            console = result.console;
+
+            (
+                TaskState::Js(result.state),
+                result.actions.into_iter().collect(),
+                result.state_changed,
+            )
+        }
+        (TaskConfig::Js(_), _) => return Err(Error::ConfigStateMismatch("Js")),
+        (TaskConfig::DataFlow(config), TaskState::DataFlow(state)) => {
+            let (state, run_log, actions) = config
+                .evaluate_trigger(task_name, state, trigger_id, payload.clone())
+                .await?;
+            console = run_log
+                .run
+                .iter()
+                .flat_map(|node| node.console.iter().cloned())
+                .collect();
+            log = Some(run_log);
+            dataflow_context = Some(config.node_outputs(&state));
+
+            (
+                TaskState::DataFlow(state),
+                actions.into_iter().collect(),
+                true,
+            )
+        }
+        (TaskConfig::DataFlow(_), _) => return Err(Error::ConfigStateMismatch("DataFlow")),
+    };
+
+    let context = dataflow_context.unwrap_or_else(|| handoff::state_context(&state));
+
+    Ok(TriggerRun {
+        state,
+        state_changed,
+        console,
+        log,
+        actions,
+        timers_started,
+        timers_cancelled,
+        context,
+    })
+}
+
+/// Apply the action's template to the payload, as if the action was about to run.
+async fn render_action(
+    task_action: &SimulateTaskAction,
+    payload: &serde_json::Value,
+) -> Result<FxHashMap<String, serde_json::Value>, String> {
+    let executor = crate::actions::execute::EXECUTOR_REGISTRY
+        .get(task_action.executor_id.as_str())
+        .ok_or_else(|| format!("Unknown executor {}", task_action.executor_id))?;
+
+    // Don't expose account secrets, but still fill in the fields so the template can be checked.
+    let account_fields = task_action.account_fields.as_ref().map(|fields| {
+        fields
+            .iter()
+            .map(|(name, _)| {
+                (
+                    name.clone(),
+                    serde_json::json!(format!("<account {}>", name)),
+                )
+            })
+            .collect::<TaskActionTemplate>()
+    });
+
+    let action = PrepareInvocationAction {
+        action_id: &task_action.action_id,
+        executor_id: task_action.executor_id.as_str(),
+        account_required: task_action.account_required,
+        account_id: &task_action.account_id,
+        account_expires: task_action.account_expires,
+        account_fields,
+        action_template_fields: &task_action.action_template_fields,
+        task_action_template: task_action
+            .task_action_template
+            .as_ref()
+            .map(|t| t.0.clone()),
+        action_executor_template: &task_action.action_executor_template,
+    };
+
+    validate_and_prepare_invocation(executor, payload, action)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -7,11 +7,7 @@ use thiserror::Error;
 #[cfg(not(target_family = "wasm"))]
 pub use native::*;
 
-use crate::{
-    actions::{Action, TaskAction},
-    inputs::Input,
-    TaskTrigger, TaskValidateError,
-};
+use crate::{actions::Action, inputs::Input, TaskValidateError};
 
 #[derive(Debug, Error)]
 pub enum StateMachineError {
@@ -30,8 +26,53 @@ pub type StateMachineStates = SmallVec<[StateMachineData; 1]>;
 
 #[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StateMachineData {
+    /// The active top-level state.
     pub state: String,
+    /// The paths of the active leaf states, when `state` has nested states. This is empty
+    /// when `state` is itself a leaf.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active: Vec<String>,
     pub context: serde_json::Value,
+    /// Delayed triggers that are waiting to fire.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timers: Vec<PendingTimer>,
+}
+
+#[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingTimer {
+    /// The path of the state that started the timer.
+    pub state: String,
+    pub trigger_id: String,
+    /// The log ID of the scheduled input.
+    pub inputs_log_id: uuid::Uuid,
+}
+
+impl StateMachineData {
+    /// Set the active leaf states, updating `state` to match.
+    pub fn set_active(&mut self, active: Vec<String>) {
+        if let Some(first) = active.first() {
+            self.state = first
+                .split(STATE_PATH_SEPARATOR)
+                .next()
+                .unwrap_or_default()
+                .to_string();
+        }
+
+        if active.len() == 1 && active[0] == self.state {
+            self.active = Vec::new();
+        } else {
+            self.active = active;
+        }
+    }
+
+    /// The paths of all the active leaf states.
+    pub fn active_states(&self) -> Vec<String> {
+        if self.active.is_empty() {
+            vec![self.state.clone()]
+        } else {
+            self.active.clone()
+        }
+    }
 }
 
 #[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
@@ -44,10 +85,92 @@ pub struct StateMachine {
     pub states: FxHashMap<String, StateDefinition>,
 }
 
-#[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+/// Nested states are referred to by their path, with the name of each level separated by `.`,
+/// such as `running.network.connected`.
+pub const STATE_PATH_SEPARATOR: char = '.';
+
+#[derive(Clone, Debug, Default, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
 pub struct StateDefinition {
     pub description: Option<String>,
     pub on: SmallVec<[EventHandler; 2]>,
+    /// Actions to run when entering this state.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entry: Vec<ActionInvokeDef>,
+    /// Actions to run when leaving this state.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exit: Vec<ActionInvokeDef>,
+    /// Nested states. When this is not empty, one of the nested states is always active
+    /// while this state is active, or all of them if `parallel` is set.
+    #[serde(default, skip_serializing_if = "FxHashMap::is_empty")]
+    pub states: FxHashMap<String, StateDefinition>,
+    /// The nested state to enter by default. Required if there are nested states and
+    /// this is not a parallel state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+    /// If true, each of the nested states is a separate region and they are all active
+    /// at the same time.
+    #[serde(default)]
+    pub parallel: bool,
+    /// Triggers to send if the machine stays in this state for some amount of time. These are
+    /// cancelled when the state is exited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<DelayedTrigger>,
+}
+
+/// Send a trigger to the task after a delay. Handle the trigger in `on` like any other
+/// trigger to transition to another state or run actions.
+#[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DelayedTrigger {
+    /// How long to wait after entering the state.
+    pub delay_secs: u64,
+    pub trigger_id: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+impl StateDefinition {
+    fn is_compound(&self) -> bool {
+        !self.states.is_empty()
+    }
+
+    /// Nested state names in a stable order.
+    fn sorted_child_names(&self) -> Vec<&String> {
+        let mut names = self.states.keys().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+/// The changes to the active states when taking a transition.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Transition {
+    /// The states that were exited, deepest first.
+    pub exited: Vec<String>,
+    /// The states that were entered, outermost first.
+    pub entered: Vec<String>,
+    /// The new set of active leaf states.
+    pub active: Vec<String>,
+}
+
+fn join_state_path(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_string()
+    } else {
+        format!("{parent}{STATE_PATH_SEPARATOR}{child}")
+    }
+}
+
+fn parent_state_path(path: &str) -> Option<&str> {
+    path.rsplit_once(STATE_PATH_SEPARATOR)
+        .map(|(parent, _)| parent)
+}
+
+/// Returns true if `path` is `ancestor` or is nested inside it. The empty path is the root
+/// of the machine, and contains every state.
+fn state_path_within(path: &str, ancestor: &str) -> bool {
+    ancestor.is_empty()
+        || path == ancestor
+        || (path.starts_with(ancestor) && path[ancestor.len()..].starts_with(STATE_PATH_SEPARATOR))
 }
 
 #[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
@@ -61,13 +184,20 @@ pub struct EventHandler {
 #[serde(tag = "t", content = "c")]
 pub enum TransitionTarget {
     One(String),
-    // Cond(Vec<TransitionCondition>),
+    /// Check each condition in order and transition to the target of the first one that
+    /// matches. If none match, go to `fallback`, or stay in the current state if there isn't one.
+    Cond {
+        conditions: Vec<TransitionCondition>,
+        #[serde(default)]
+        fallback: Option<String>,
+    },
     Script(String),
 }
 
 #[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TransitionCondition {
     pub target: String,
+    /// A JavaScript expression, with `context` and `payload` available.
     pub cond: String,
 }
 
@@ -98,12 +228,12 @@ pub enum ActionInvokeDefDataField {
 }
 
 impl StateMachine {
-    pub fn validate(
+    pub fn validate<TRIGGER, ACTION>(
         &self,
         actions: &FxHashMap<String, Action>,
         inputs: &FxHashMap<String, Input>,
-        task_triggers: &FxHashMap<String, TaskTrigger>,
-        task_actions: &FxHashMap<String, TaskAction>,
+        task_triggers: &FxHashMap<String, TRIGGER>,
+        task_actions: &FxHashMap<String, ACTION>,
     ) -> Vec<TaskValidateError> {
         let mut errors = Vec::new();
 
@@ -121,27 +251,94 @@ impl StateMachine {
             &self.on,
         );
 
-        for (state_name, state) in self.states.iter() {
+        self.validate_states(
+            actions,
+            inputs,
+            task_triggers,
+            task_actions,
+            &mut errors,
+            "",
+            &self.states,
+        );
+
+        errors
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn validate_states<TRIGGER, ACTION>(
+        &self,
+        actions: &FxHashMap<String, Action>,
+        inputs: &FxHashMap<String, Input>,
+        task_triggers: &FxHashMap<String, TRIGGER>,
+        task_actions: &FxHashMap<String, ACTION>,
+        errors: &mut Vec<TaskValidateError>,
+        parent: &str,
+        states: &FxHashMap<String, StateDefinition>,
+    ) {
+        for (state_name, state) in states.iter() {
+            if state_name.contains(STATE_PATH_SEPARATOR) {
+                errors.push(TaskValidateError::InvalidStateName {
+                    parent: (!parent.is_empty()).then(|| parent.to_string()),
+                    name: state_name.clone(),
+                });
+                // The path to anything inside this state would be ambiguous.
+                continue;
+            }
+
+            let path = join_state_path(parent, state_name);
+
+            if state.is_compound() && !state.parallel {
+                let valid_initial = state
+                    .initial
+                    .as_ref()
+                    .map(|initial| state.states.contains_key(initial))
+                    .unwrap_or(false);
+                if !valid_initial {
+                    errors.push(TaskValidateError::InvalidNestedInitialState {
+                        state: path.clone(),
+                        initial: state.initial.clone(),
+                    });
+                }
+            }
+
             self.validate_handlers(
                 actions,
                 inputs,
                 task_triggers,
                 task_actions,
-                &mut errors,
-                Some(state_name),
+                errors,
+                Some(&path),
                 &state.on,
             );
-        }
 
-        errors
+            for (index, timer) in state.after.iter().enumerate() {
+                if !task_triggers.contains_key(&timer.trigger_id) {
+                    errors.push(TaskValidateError::InvalidTimerTrigger {
+                        state: path.clone(),
+                        index,
+                        trigger_id: timer.trigger_id.clone(),
+                    });
+                }
+            }
+
+            self.validate_states(
+                actions,
+                inputs,
+                task_triggers,
+                task_actions,
+                errors,
+                &path,
+                &state.states,
+            );
+        }
     }
 
-    fn validate_handlers(
+    fn validate_handlers<TRIGGER, ACTION>(
         &self,
         actions: &FxHashMap<String, Action>,
         inputs: &FxHashMap<String, Input>,
-        task_triggers: &FxHashMap<String, TaskTrigger>,
-        task_actions: &FxHashMap<String, TaskAction>,
+        task_triggers: &FxHashMap<String, TRIGGER>,
+        task_actions: &FxHashMap<String, ACTION>,
         errors: &mut Vec<TaskValidateError>,
         state: Option<&String>,
         handlers: &[EventHandler],
@@ -160,7 +357,7 @@ impl StateMachine {
             // Make sure transition target points to a valid state
             match handler.target.as_ref() {
                 Some(TransitionTarget::One(s)) => {
-                    if !self.states.contains_key(s) {
+                    if self.get_state(s).is_none() {
                         errors.push(TaskValidateError::InvalidTarget {
                             state: state.cloned(),
                             index,
@@ -168,6 +365,27 @@ impl StateMachine {
                         });
                     }
                 }
+                Some(TransitionTarget::Cond {
+                    conditions,
+                    fallback,
+                }) => {
+                    let targets = conditions
+                        .iter()
+                        .enumerate()
+                        .map(|(i, c)| (Some(i), &c.target))
+                        .chain(fallback.iter().map(|f| (None, f)));
+
+                    for (condition, target) in targets {
+                        if self.get_state(target).is_none() {
+                            errors.push(TaskValidateError::InvalidConditionTarget {
+                                state: state.cloned(),
+                                index,
+                                condition,
+                                target: target.clone(),
+                            });
+                        }
+                    }
+                }
                 // TODO What can we do here?
                 Some(TransitionTarget::Script(_)) => {}
                 None => {}
@@ -175,25 +393,194 @@ impl StateMachine {
         }
     }
 
+    /// The state machine's starting state. This does not run any entry actions or start
+    /// any timers, since there is no trigger to run them for.
     pub fn default_state(&self) -> StateMachineData {
-        StateMachineData {
+        let mut entered = Vec::new();
+        let mut active = Vec::new();
+        if self.states.contains_key(&self.initial) {
+            self.enter_state(self.initial.clone(), &[], &mut entered, &mut active);
+        }
+
+        let mut data = StateMachineData {
             state: self.initial.to_string(),
+            active: Vec::new(),
             context: serde_json::json!({}),
+            timers: Vec::new(),
+        };
+        data.set_active(active);
+        data
+    }
+
+    /// Carry `old` state data over to this version of the machine. This returns `None` if any
+    /// of the active states no longer exist as leaf states. Timers for states that were removed
+    /// are dropped, and the caller should cancel them with [cancel_timers].
+    pub fn migrate_state(&self, old: &StateMachineData) -> Option<StateMachineData> {
+        let all_active_exist = old.active_states().iter().all(|path| {
+            self.get_state(path)
+                .map(|state| !state.is_compound())
+                .unwrap_or(false)
+        });
+
+        if !all_active_exist {
+            return None;
+        }
+
+        let mut data = old.clone();
+        data.timers
+            .retain(|timer| self.get_state(&timer.state).is_some());
+        Some(data)
+    }
+
+    /// Look up a state by its path.
+    pub fn get_state(&self, path: &str) -> Option<&StateDefinition> {
+        let mut segments = path.split(STATE_PATH_SEPARATOR);
+        let first = segments.next()?;
+        segments.try_fold(self.states.get(first)?, |state, name| {
+            state.states.get(name)
+        })
+    }
+
+    /// Enter the state at `path`, and then its nested states. If `towards` is not empty,
+    /// it is the path of nested states to enter instead of the defaults.
+    fn enter_state(
+        &self,
+        path: String,
+        towards: &[&str],
+        entered: &mut Vec<String>,
+        active: &mut Vec<String>,
+    ) {
+        let state = match self.get_state(&path) {
+            Some(state) => state,
+            None => return,
+        };
+
+        entered.push(path.clone());
+
+        if !state.is_compound() {
+            active.push(path);
+        } else if state.parallel {
+            for child in state.sorted_child_names() {
+                let child_towards = match towards.split_first() {
+                    Some((next, rest)) if next == child => rest,
+                    _ => &[],
+                };
+                self.enter_state(
+                    join_state_path(&path, child),
+                    child_towards,
+                    entered,
+                    active,
+                );
+            }
+        } else {
+            match towards.split_first() {
+                Some((next, rest)) => {
+                    self.enter_state(join_state_path(&path, next), rest, entered, active)
+                }
+                None => match state.initial.as_ref() {
+                    Some(initial) => {
+                        self.enter_state(join_state_path(&path, initial), &[], entered, active)
+                    }
+                    // An invalid config, so just treat it as a leaf.
+                    None => active.push(path),
+                },
+            }
+        }
+    }
+
+    /// Calculate the transition from a handler on `source` to `target`, given the current
+    /// active leaf states. `source` is `None` for handlers at the root of the machine.
+    /// Returns `None` if the target is already active.
+    pub fn transition(
+        &self,
+        active: &[String],
+        source: Option<&str>,
+        target: &str,
+    ) -> Option<Transition> {
+        if active.iter().any(|leaf| state_path_within(leaf, target)) {
+            return None;
+        }
+
+        // Find the innermost state that contains both the source and the target, and doesn't
+        // have parallel regions. Everything inside it gets exited and then the target is
+        // entered from there.
+        let source = source.unwrap_or("");
+        let mut domain = target;
+        while !domain.is_empty() && !state_path_within(source, domain) {
+            domain = parent_state_path(domain).unwrap_or("");
+        }
+        while !domain.is_empty()
+            && self
+                .get_state(domain)
+                .map(|state| state.parallel)
+                .unwrap_or(false)
+        {
+            domain = parent_state_path(domain).unwrap_or("");
         }
+
+        let mut exited = Vec::new();
+        let mut remaining = Vec::new();
+        for leaf in active {
+            if !state_path_within(leaf, domain) {
+                remaining.push(leaf.clone());
+                continue;
+            }
+
+            let mut path = leaf.as_str();
+            while path != domain && !path.is_empty() {
+                if !exited.iter().any(|e| e == path) {
+                    exited.push(path.to_string());
+                }
+                path = parent_state_path(path).unwrap_or("");
+            }
+        }
+        // Deepest first, but otherwise keep the order.
+        exited.sort_by_key(|path| std::cmp::Reverse(path.matches(STATE_PATH_SEPARATOR).count()));
+
+        let below_domain = if domain.is_empty() {
+            target
+        } else {
+            &target[domain.len() + 1..]
+        };
+        let segments = below_domain
+            .split(STATE_PATH_SEPARATOR)
+            .collect::<SmallVec<[&str; 4]>>();
+
+        let mut entered = Vec::new();
+        let mut new_active = Vec::new();
+        self.enter_state(
+            join_state_path(domain, segments[0]),
+            &segments[1..],
+            &mut entered,
+            &mut new_active,
+        );
+
+        remaining.extend(new_active);
+
+        Some(Transition {
+            exited,
+            entered,
+            active: remaining,
+        })
     }
 }
 
 #[cfg(not(target_family = "wasm"))]
 mod native {
+    use chrono::Utc;
     use ergo_database::{
         new_uuid,
-        object_id::{TaskId, UserId},
+        object_id::{InputId, OrgId, TaskId, TaskTriggerId, UserId},
     };
+    use ergo_notifications::NotificationManager;
+    use ergo_queues::{remove_pending_job, JobPriority};
+    use sqlx::PgConnection;
     use tracing::{event, instrument, Level};
 
     use super::*;
     use crate::{
         actions::{ActionInvocation, ActionInvocations},
+        inputs::{enqueue_input, queue::InputQueue, EnqueueInputOptions},
         scripting::{self, run_simple_with_context_and_payload},
     };
 
@@ -204,39 +591,162 @@ mod native {
         machine: StateMachine,
         data: StateMachineData,
         changed: bool,
+        timer_changes: TimerChanges,
     }
 
-    impl EventHandler {
-        async fn resolve_actions(
-            &self,
-            task_id: &TaskId,
-            user_id: &UserId,
-            input_arrival_id: &Option<uuid::Uuid>,
-            context: &serde_json::Value,
-            payload: &Option<&serde_json::Value>,
-        ) -> Result<ActionInvocations, StateMachineError> {
-            match &self.actions {
-                None => Ok(ActionInvocations::new()),
-                Some(actions) => {
-                    let mut output = ActionInvocations::with_capacity(actions.len());
-                    for def in actions {
-                        let built_payload = def.data.build(context, payload).await?;
-                        event!(Level::DEBUG, ?context, ?built_payload, "built payload");
-                        let invocation = ActionInvocation {
-                            input_arrival_id: *input_arrival_id,
-                            actions_log_id: new_uuid(),
-                            task_id: task_id.clone(),
-                            task_action_local_id: def.task_action_local_id.clone(),
-                            user_id: user_id.clone(),
-                            payload: built_payload,
-                        };
-                        output.push(invocation);
-                    }
-                    Ok(output)
-                }
+    /// Timers that need to be scheduled or cancelled after applying a trigger.
+    #[derive(Debug, Default)]
+    pub struct TimerChanges {
+        /// Timers for states that were entered, along with the path of the state.
+        pub start: Vec<(String, DelayedTrigger)>,
+        /// Timers for states that were exited.
+        pub cancel: Vec<PendingTimer>,
+    }
+
+    impl TimerChanges {
+        pub fn is_empty(&self) -> bool {
+            self.start.is_empty() && self.cancel.is_empty()
+        }
+
+        fn enter_state(&mut self, path: &str, state: &StateDefinition) {
+            self.start.extend(
+                state
+                    .after
+                    .iter()
+                    .map(|timer| (path.to_string(), timer.clone())),
+            );
+        }
+
+        fn exit_state(&mut self, path: &str, timers: &mut Vec<PendingTimer>) {
+            // The state may have been entered by an earlier handler for the same trigger.
+            self.start.retain(|(state, _)| state != path);
+
+            let (cancel, keep) = std::mem::take(timers)
+                .into_iter()
+                .partition(|timer| timer.state == path);
+            *timers = keep;
+            self.cancel.extend(cancel);
+        }
+    }
+
+    /// Remove the scheduled inputs of timers that haven't fired yet.
+    pub async fn cancel_timers(
+        tx: &mut PgConnection,
+        redis_key_prefix: Option<&str>,
+        timers: &[PendingTimer],
+    ) -> Result<(), crate::Error> {
+        let queue_name = InputQueue::queue_name(redis_key_prefix);
+        for timer in timers {
+            let job = sqlx::query_scalar!(
+                "DELETE FROM inputs_log
+                WHERE inputs_log_id=$1 AND status='pending'
+                RETURNING queue_job_id",
+                timer.inputs_log_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let Some(job_id) = job {
+                event!(Level::DEBUG, ?timer, %job_id, "Cancelling timer");
+                remove_pending_job(tx, queue_name.as_ref(), &job_id).await?;
             }
         }
 
+        Ok(())
+    }
+
+    /// Schedule and cancel the inputs for state machine timers, and record the new timers
+    /// in `data`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn apply_timer_changes(
+        tx: &mut PgConnection,
+        notifications: &Option<NotificationManager>,
+        redis_key_prefix: Option<&str>,
+        org_id: &OrgId,
+        user_id: &UserId,
+        task_id: &TaskId,
+        task_name: &str,
+        data: &mut StateMachineData,
+        changes: TimerChanges,
+    ) -> Result<(), crate::Error> {
+        cancel_timers(&mut *tx, redis_key_prefix, &changes.cancel).await?;
+
+        for (state, timer) in changes.start {
+            let trigger = sqlx::query!(
+                r##"SELECT task_trigger_id AS "task_trigger_id: TaskTriggerId",
+                    input_id AS "input_id: InputId",
+                    tt.name,
+                    inputs.payload_schema
+                FROM task_triggers tt
+                JOIN inputs USING(input_id)
+                WHERE task_id=$1 AND task_trigger_local_id=$2"##,
+                task_id.0,
+                &timer.trigger_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| crate::Error::TaskTriggerNotFound(timer.trigger_id.clone()))?;
+
+            let trigger_at = Utc::now() + chrono::Duration::seconds(timer.delay_secs as i64);
+            event!(Level::DEBUG, %state, ?timer, %trigger_at, "Starting timer");
+            let inputs_log_id = enqueue_input(EnqueueInputOptions {
+                pg: &mut *tx,
+                notifications: notifications.clone(),
+                org_id: org_id.clone(),
+                user_id: user_id.clone(),
+                task_id: task_id.clone(),
+                task_name: task_name.to_string(),
+                input_id: trigger.input_id,
+                task_trigger_id: trigger.task_trigger_id,
+                task_trigger_local_id: timer.trigger_id.clone(),
+                task_trigger_name: trigger.name,
+                periodic_trigger_id: None,
+                payload_schema: &trigger.payload_schema,
+                payload: timer.payload,
+                redis_key_prefix,
+                trigger_at: Some(trigger_at),
+                parent_inputs_log_id: None,
+                priority: JobPriority::Normal,
+                idempotency_key: None,
+            })
+            .await?;
+
+            data.timers.push(PendingTimer {
+                state,
+                trigger_id: timer.trigger_id,
+                inputs_log_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn resolve_actions(
+        actions: &[ActionInvokeDef],
+        task_id: &TaskId,
+        user_id: &UserId,
+        input_arrival_id: &Option<uuid::Uuid>,
+        context: &serde_json::Value,
+        payload: &Option<&serde_json::Value>,
+    ) -> Result<ActionInvocations, StateMachineError> {
+        let mut output = ActionInvocations::with_capacity(actions.len());
+        for def in actions {
+            let built_payload = def.data.build(context, payload).await?;
+            event!(Level::DEBUG, ?context, ?built_payload, "built payload");
+            let invocation = ActionInvocation {
+                input_arrival_id: *input_arrival_id,
+                actions_log_id: new_uuid(),
+                task_id: task_id.clone(),
+                task_action_local_id: def.task_action_local_id.clone(),
+                user_id: user_id.clone(),
+                payload: built_payload,
+            };
+            output.push(invocation);
+        }
+        Ok(output)
+    }
+
+    impl EventHandler {
         async fn next_state(
             &self,
             context: &serde_json::Value,
@@ -245,6 +755,31 @@ mod native {
             match &self.target {
                 None => Ok(None),
                 Some(TransitionTarget::One(s)) => Ok(Some(s.clone())),
+                Some(TransitionTarget::Cond {
+                    conditions,
+                    fallback,
+                }) => {
+                    // Each condition runs as its own script so that one condition can't affect
+                    // how the others are parsed.
+                    let mut matched = None;
+                    for c in conditions {
+                        let script = format!("return !!(\n{}\n);", c.cond);
+                        let result = scripting::run_simple_with_context_and_payload::<bool>(
+                            &script,
+                            Some(context),
+                            *payload,
+                        )
+                        .await
+                        .map_err(StateMachineError::ScriptError)?;
+
+                        if result {
+                            matched = Some(&c.target);
+                            break;
+                        }
+                    }
+
+                    Ok(matched.or(fallback.as_ref()).cloned())
+                }
                 Some(TransitionTarget::Script(s)) => {
                     scripting::run_simple_with_context_and_payload(
                         s.as_str(),
@@ -333,6 +868,7 @@ mod native {
                 machine,
                 data,
                 changed: false,
+                timer_changes: TimerChanges::default(),
             }
         }
 
@@ -340,6 +876,71 @@ mod native {
             (self.data, self.changed)
         }
 
+        /// Take the timers that need to be started or cancelled as a result of
+        /// the triggers applied so far.
+        pub fn take_timer_changes(&mut self) -> TimerChanges {
+            std::mem::take(&mut self.timer_changes)
+        }
+
+        /// Stop tracking a timer once its trigger has arrived.
+        pub fn timer_fired(&mut self, inputs_log_id: &uuid::Uuid) {
+            let num_timers = self.data.timers.len();
+            self.data
+                .timers
+                .retain(|timer| &timer.inputs_log_id != inputs_log_id);
+            if self.data.timers.len() != num_timers {
+                self.changed = true;
+            }
+        }
+
+        /// Find the handlers for a trigger. Each active leaf state uses the innermost handler
+        /// for the trigger, falling back to the handlers at the root of the machine. Returns the
+        /// path of the state that owns each handler, or `None` for the root.
+        fn find_handlers(
+            &self,
+            active: &[String],
+            trigger_id: &str,
+        ) -> Result<Vec<(Option<String>, EventHandler)>, StateMachineError> {
+            let mut handlers: Vec<(Option<String>, EventHandler)> = Vec::new();
+            for leaf in active {
+                let mut found = None;
+                let mut path = Some(leaf.as_str());
+                while let Some(p) = path {
+                    let state = self.machine.get_state(p).ok_or_else(|| {
+                        StateMachineError::UnknownState {
+                            idx: self.idx,
+                            state: p.to_string(),
+                        }
+                    })?;
+
+                    if let Some(h) = state.on.iter().find(|o| o.trigger_id == trigger_id) {
+                        found = Some((Some(p.to_string()), h));
+                        break;
+                    }
+
+                    path = parent_state_path(p);
+                }
+
+                let found = found.or_else(|| {
+                    // Look it up in the global event handlers
+                    self.machine
+                        .on
+                        .iter()
+                        .find(|o| o.trigger_id == trigger_id)
+                        .map(|h| (None, h))
+                });
+
+                if let Some((source, handler)) = found {
+                    // Parallel regions may find the same handler on a common ancestor.
+                    if !handlers.iter().any(|(s, _)| s == &source) {
+                        handlers.push((source, handler.clone()));
+                    }
+                }
+            }
+
+            Ok(handlers)
+        }
+
         #[instrument(fields(actions))]
         pub async fn apply_trigger(
             &mut self,
@@ -348,50 +949,103 @@ mod native {
             input_arrival_id: &Option<uuid::Uuid>,
             payload: Option<&serde_json::Value>,
         ) -> Result<ActionInvocations, StateMachineError> {
-            let handler = self
-                .machine
-                .states
-                .get(&self.data.state)
-                .ok_or_else(|| StateMachineError::UnknownState {
-                    idx: self.idx,
-                    state: self.data.state.clone(),
-                })?
-                .on
-                .iter()
-                .find(|o| o.trigger_id == trigger_id)
-                .or_else(|| {
-                    // Look it up in the global event handlers
-                    self.machine.on.iter().find(|o| o.trigger_id == trigger_id)
-                });
+            let mut active = self.data.active_states();
+            let handlers = self.find_handlers(&active, trigger_id)?;
+            if handlers.is_empty() {
+                event!(Level::DEBUG, "No handler");
+                return Ok(ActionInvocations::new());
+            }
+
+            let mut actions = ActionInvocations::new();
+            for (source, h) in handlers {
+                if let Some(source) = source.as_deref() {
+                    if !active.iter().any(|leaf| state_path_within(leaf, source)) {
+                        // An earlier handler transitioned out of this state.
+                        continue;
+                    }
+                }
+
+                event!(Level::DEBUG, handler=?h, "Running event handler");
+                let next_state = h.next_state(&self.data.context, &payload).await?;
+
+                let transition = match next_state {
+                    Some(target) => {
+                        if self.machine.get_state(&target).is_none() {
+                            return Err(StateMachineError::UnknownState {
+                                idx: self.idx,
+                                state: target,
+                            });
+                        }
+
+                        self.machine.transition(&active, source.as_deref(), &target)
+                    }
+                    None => None,
+                };
+
+                if let Some(transition) = transition.as_ref() {
+                    for exited in &transition.exited {
+                        self.timer_changes.exit_state(exited, &mut self.data.timers);
+
+                        let state = self.machine.get_state(exited);
+                        if let Some(state) = state {
+                            actions.extend(
+                                resolve_actions(
+                                    &state.exit,
+                                    &self.task_id,
+                                    user_id,
+                                    input_arrival_id,
+                                    &self.data.context,
+                                    &payload,
+                                )
+                                .await?,
+                            );
+                        }
+                    }
+                }
 
-            match handler {
-                Some(h) => {
-                    event!(Level::DEBUG, handler=?h, "Running event handler");
-                    let next_state = h.next_state(&self.data.context, &payload).await?;
-                    let actions = h
-                        .resolve_actions(
+                if let Some(handler_actions) = h.actions.as_ref() {
+                    actions.extend(
+                        resolve_actions(
+                            handler_actions,
                             &self.task_id,
                             user_id,
                             input_arrival_id,
                             &self.data.context,
                             &payload,
                         )
-                        .await?;
+                        .await?,
+                    );
+                }
 
-                    if let Some(s) = next_state {
-                        if self.data.state != s {
-                            self.changed = true;
-                            self.data.state = s;
+                if let Some(transition) = transition {
+                    for entered in &transition.entered {
+                        let state = self.machine.get_state(entered);
+                        if let Some(state) = state {
+                            self.timer_changes.enter_state(entered, state);
+                            actions.extend(
+                                resolve_actions(
+                                    &state.entry,
+                                    &self.task_id,
+                                    user_id,
+                                    input_arrival_id,
+                                    &self.data.context,
+                                    &payload,
+                                )
+                                .await?,
+                            );
                         }
                     }
 
-                    Ok(actions)
-                }
-                None => {
-                    event!(Level::DEBUG, "No handler");
-                    Ok(ActionInvocations::new())
+                    active = transition.active;
+                    self.changed = true;
                 }
             }
+
+            if self.changed {
+                self.data.set_active(active);
+            }
+
+            Ok(actions)
         }
     }
 
@@ -423,5 +1077,621 @@ mod native {
         #[tokio::test]
         #[ignore]
         async fn next_state_script_returns_same_state() {}
+
+        fn action(name: &str) -> ActionInvokeDef {
+            ActionInvokeDef {
+                task_action_local_id: name.to_string(),
+                data: ActionPayloadBuilder::FieldMap(FxHashMap::default()),
+            }
+        }
+
+        fn handler(trigger_id: &str, target: Option<&str>, actions: &[&str]) -> EventHandler {
+            EventHandler {
+                trigger_id: trigger_id.to_string(),
+                target: target.map(|t| TransitionTarget::One(t.to_string())),
+                actions: (!actions.is_empty()).then(|| actions.iter().map(|a| action(a)).collect()),
+            }
+        }
+
+        fn states(list: Vec<(&str, StateDefinition)>) -> FxHashMap<String, StateDefinition> {
+            list.into_iter()
+                .map(|(name, state)| (name.to_string(), state))
+                .collect()
+        }
+
+        /// idle
+        /// running
+        ///   connecting
+        ///   connected
+        /// working (parallel)
+        ///   net
+        ///     up
+        ///     down
+        ///   disk
+        ///     ok
+        ///     full
+        fn nested_machine() -> StateMachine {
+            StateMachine {
+                name: "nested".to_string(),
+                description: None,
+                initial: "idle".to_string(),
+                on: smallvec::smallvec![handler("work", Some("working"), &[])],
+                states: states(vec![
+                    (
+                        "idle",
+                        StateDefinition {
+                            on: smallvec::smallvec![handler("start", Some("running"), &["start"])],
+                            exit: vec![action("exit_idle")],
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        "running",
+                        StateDefinition {
+                            on: smallvec::smallvec![handler("stop", Some("idle"), &[])],
+                            entry: vec![action("enter_running")],
+                            exit: vec![action("exit_running")],
+                            initial: Some("connecting".to_string()),
+                            states: states(vec![
+                                (
+                                    "connecting",
+                                    StateDefinition {
+                                        on: smallvec::smallvec![handler(
+                                            "connected",
+                                            Some("running.connected"),
+                                            &[]
+                                        )],
+                                        exit: vec![action("exit_connecting")],
+                                        ..Default::default()
+                                    },
+                                ),
+                                (
+                                    "connected",
+                                    StateDefinition {
+                                        entry: vec![action("enter_connected")],
+                                        ..Default::default()
+                                    },
+                                ),
+                            ]),
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        "working",
+                        StateDefinition {
+                            parallel: true,
+                            states: states(vec![
+                                (
+                                    "net",
+                                    StateDefinition {
+                                        initial: Some("up".to_string()),
+                                        states: states(vec![
+                                            (
+                                                "up",
+                                                StateDefinition {
+                                                    on: smallvec::smallvec![handler(
+                                                        "tick",
+                                                        Some("working.net.down"),
+                                                        &[]
+                                                    )],
+                                                    ..Default::default()
+                                                },
+                                            ),
+                                            ("down", StateDefinition::default()),
+                                        ]),
+                                        ..Default::default()
+                                    },
+                                ),
+                                (
+                                    "disk",
+                                    StateDefinition {
+                                        initial: Some("ok".to_string()),
+                                        states: states(vec![
+                                            (
+                                                "ok",
+                                                StateDefinition {
+                                                    on: smallvec::smallvec![handler(
+                                                        "tick",
+                                                        Some("working.disk.full"),
+                                                        &[]
+                                                    )],
+                                                    ..Default::default()
+                                                },
+                                            ),
+                                            ("full", StateDefinition::default()),
+                                        ]),
+                                        ..Default::default()
+                                    },
+                                ),
+                            ]),
+                            ..Default::default()
+                        },
+                    ),
+                ]),
+            }
+        }
+
+        fn action_names(actions: &ActionInvocations) -> Vec<&str> {
+            actions
+                .iter()
+                .map(|a| a.task_action_local_id.as_str())
+                .collect()
+        }
+
+        #[test]
+        fn nested_default_state() {
+            let mut machine = nested_machine();
+            assert_eq!(machine.default_state().active_states(), vec!["idle"]);
+
+            machine.initial = "running".to_string();
+            let data = machine.default_state();
+            assert_eq!(data.state, "running");
+            assert_eq!(data.active, vec!["running.connecting"]);
+
+            machine.initial = "working".to_string();
+            let data = machine.default_state();
+            assert_eq!(data.state, "working");
+            assert_eq!(data.active, vec!["working.disk.ok", "working.net.up"]);
+        }
+
+        #[test]
+        fn nested_transitions() {
+            let machine = nested_machine();
+
+            let t = machine
+                .transition(
+                    &["running.connecting".to_string()],
+                    Some("running.connecting"),
+                    "running.connected",
+                )
+                .expect("transition");
+            assert_eq!(t.exited, vec!["running.connecting"]);
+            assert_eq!(t.entered, vec!["running.connected"]);
+            assert_eq!(t.active, vec!["running.connected"]);
+
+            let t = machine
+                .transition(&["running.connected".to_string()], Some("running"), "idle")
+                .expect("transition");
+            assert_eq!(t.exited, vec!["running.connected", "running"]);
+            assert_eq!(t.entered, vec!["idle"]);
+            assert_eq!(t.active, vec!["idle"]);
+
+            let t = machine
+                .transition(&["idle".to_string()], None, "working.net.down")
+                .expect("transition");
+            assert_eq!(t.exited, vec!["idle"]);
+            assert_eq!(
+                t.entered,
+                vec![
+                    "working",
+                    "working.disk",
+                    "working.disk.ok",
+                    "working.net",
+                    "working.net.down"
+                ]
+            );
+            assert_eq!(t.active, vec!["working.disk.ok", "working.net.down"]);
+
+            // Transitioning within a region leaves the other region alone.
+            let t = machine
+                .transition(&t.active, Some("working.net.down"), "working.net.up")
+                .expect("transition");
+            assert_eq!(t.exited, vec!["working.net.down"]);
+            assert_eq!(t.entered, vec!["working.net.up"]);
+            assert_eq!(t.active, vec!["working.disk.ok", "working.net.up"]);
+
+            // Already active
+            assert_eq!(machine.transition(&t.active, None, "working.net"), None);
+        }
+
+        #[tokio::test]
+        async fn nested_entry_and_exit_actions() {
+            let machine = nested_machine();
+            let data = machine.default_state();
+            let mut m = StateMachineWithData::new(TaskId::new(), 0, machine, data);
+            let user_id = UserId::new();
+
+            let actions = m
+                .apply_trigger("start", &user_id, &None, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                action_names(&actions),
+                vec!["exit_idle", "start", "enter_running"]
+            );
+
+            let actions = m
+                .apply_trigger("connected", &user_id, &None, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                action_names(&actions),
+                vec!["exit_connecting", "enter_connected"]
+            );
+
+            // Handled by the parent state
+            let actions = m
+                .apply_trigger("stop", &user_id, &None, None)
+                .await
+                .unwrap();
+            assert_eq!(action_names(&actions), vec!["exit_running"]);
+
+            let (data, changed) = m.take();
+            assert!(changed);
+            assert_eq!(data.state, "idle");
+            assert!(data.active.is_empty());
+        }
+
+        #[tokio::test]
+        async fn timers() {
+            let mut machine = nested_machine();
+            machine.states.get_mut("running").unwrap().after = vec![DelayedTrigger {
+                delay_secs: 30,
+                trigger_id: "stop".to_string(),
+                payload: serde_json::Value::Null,
+            }];
+            let data = machine.default_state();
+            let mut m = StateMachineWithData::new(TaskId::new(), 0, machine, data);
+            let user_id = UserId::new();
+
+            m.apply_trigger("start", &user_id, &None, None)
+                .await
+                .unwrap();
+            let changes = m.take_timer_changes();
+            assert!(changes.cancel.is_empty());
+            assert_eq!(changes.start.len(), 1);
+            assert_eq!(changes.start[0].0, "running");
+            assert_eq!(changes.start[0].1.delay_secs, 30);
+
+            // Moving between child states leaves the parent's timer alone.
+            let timer_id = new_uuid();
+            m.data.timers.push(PendingTimer {
+                state: "running".to_string(),
+                trigger_id: "stop".to_string(),
+                inputs_log_id: timer_id,
+            });
+            m.apply_trigger("connected", &user_id, &None, None)
+                .await
+                .unwrap();
+            assert!(m.take_timer_changes().is_empty());
+
+            m.apply_trigger("stop", &user_id, &None, None)
+                .await
+                .unwrap();
+            let changes = m.take_timer_changes();
+            assert!(changes.start.is_empty());
+            assert_eq!(changes.cancel.len(), 1);
+            assert_eq!(changes.cancel[0].inputs_log_id, timer_id);
+
+            let (data, _) = m.take();
+            assert!(data.timers.is_empty());
+        }
+
+        #[tokio::test]
+        async fn timer_for_state_entered_and_exited_together() {
+            let mut machine = nested_machine();
+            machine.states.get_mut("running").unwrap().after = vec![DelayedTrigger {
+                delay_secs: 30,
+                trigger_id: "stop".to_string(),
+                payload: serde_json::Value::Null,
+            }];
+            let data = machine.default_state();
+            let mut m = StateMachineWithData::new(TaskId::new(), 0, machine, data);
+            let user_id = UserId::new();
+
+            m.apply_trigger("start", &user_id, &None, None)
+                .await
+                .unwrap();
+            m.apply_trigger("stop", &user_id, &None, None)
+                .await
+                .unwrap();
+            assert!(m.take_timer_changes().is_empty());
+        }
+
+        #[test]
+        fn migrate_state() {
+            let old_machine = nested_machine();
+            let mut data = StateMachineData {
+                state: "running".to_string(),
+                active: vec!["running.connected".to_string()],
+                context: json!({ "count": 3 }),
+                timers: vec![
+                    PendingTimer {
+                        state: "running".to_string(),
+                        trigger_id: "timeout".to_string(),
+                        inputs_log_id: uuid::Uuid::new_v4(),
+                    },
+                    PendingTimer {
+                        state: "running.connected".to_string(),
+                        trigger_id: "ping".to_string(),
+                        inputs_log_id: uuid::Uuid::new_v4(),
+                    },
+                ],
+            };
+
+            assert_eq!(old_machine.migrate_state(&data), Some(data.clone()));
+
+            // Removing a state that isn't active keeps the data, but drops its timers.
+            let mut new_machine = nested_machine();
+            new_machine
+                .states
+                .get_mut("running")
+                .unwrap()
+                .states
+                .remove("connecting");
+            data.active = vec!["running.connected".to_string()];
+            data.timers[1].state = "running.connecting".to_string();
+            let migrated = new_machine.migrate_state(&data).expect("state is kept");
+            assert_eq!(migrated.context, json!({ "count": 3 }));
+            assert_eq!(migrated.timers, data.timers[0..1]);
+
+            let old_state =
+                crate::TaskState::StateMachine(StateMachineStates::from_vec(vec![data.clone()]));
+            let new_state =
+                crate::TaskState::StateMachine(StateMachineStates::from_vec(vec![migrated]));
+            assert_eq!(
+                old_state.dropped_timers(&new_state),
+                data.timers[1..],
+                "the removed state's timer is dropped"
+            );
+            let default_state = crate::TaskState::StateMachine(StateMachineStates::from_vec(vec![
+                new_machine.default_state(),
+            ]));
+            assert_eq!(
+                old_state.dropped_timers(&default_state),
+                data.timers,
+                "resetting the state drops every timer"
+            );
+
+            // An active state that was removed can't be migrated.
+            data.active = vec!["running.connecting".to_string()];
+            assert_eq!(new_machine.migrate_state(&data), None);
+
+            // Nor can one that is no longer a leaf state.
+            data.state = "idle".to_string();
+            data.active = Vec::new();
+            new_machine.states.get_mut("idle").unwrap().states =
+                states(vec![("waiting", StateDefinition::default())]);
+            assert_eq!(new_machine.migrate_state(&data), None);
+        }
+
+        #[test]
+        fn timer_fired() {
+            let machine = nested_machine();
+            let mut data = machine.default_state();
+            let timer_id = new_uuid();
+            data.timers.push(PendingTimer {
+                state: "running".to_string(),
+                trigger_id: "stop".to_string(),
+                inputs_log_id: timer_id,
+            });
+            let mut m = StateMachineWithData::new(TaskId::new(), 0, machine, data);
+
+            m.timer_fired(&new_uuid());
+            assert!(!m.changed);
+            m.timer_fired(&timer_id);
+            let (data, changed) = m.take();
+            assert!(changed);
+            assert!(data.timers.is_empty());
+        }
+
+        #[test]
+        fn validate_timer_trigger() {
+            let mut machine = nested_machine();
+            machine.states.get_mut("running").unwrap().after = vec![DelayedTrigger {
+                delay_secs: 30,
+                trigger_id: "nope".to_string(),
+                payload: serde_json::Value::Null,
+            }];
+
+            let triggers = ["start", "stop", "connected", "work", "tick"]
+                .into_iter()
+                .map(|t| (t.to_string(), ()))
+                .collect::<FxHashMap<_, _>>();
+            let errors = machine.validate::<_, ()>(
+                &FxHashMap::default(),
+                &FxHashMap::default(),
+                &triggers,
+                &FxHashMap::default(),
+            );
+
+            let paths = errors
+                .iter()
+                .map(|e| e.path().unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(paths, vec!["states.running.after[0].trigger_id"]);
+        }
+
+        #[tokio::test]
+        async fn parallel_regions() {
+            let machine = nested_machine();
+            let data = machine.default_state();
+            let mut m = StateMachineWithData::new(TaskId::new(), 0, machine, data);
+            let user_id = UserId::new();
+
+            // Handled at the root
+            m.apply_trigger("work", &user_id, &None, None)
+                .await
+                .unwrap();
+            // Handled separately by each region
+            m.apply_trigger("tick", &user_id, &None, None)
+                .await
+                .unwrap();
+
+            let (data, _) = m.take();
+            assert_eq!(data.state, "working");
+            assert_eq!(data.active, vec!["working.disk.full", "working.net.down"]);
+        }
+
+        #[test]
+        fn validate_nested() {
+            let mut machine = nested_machine();
+            let running = machine.states.get_mut("running").unwrap();
+            running.initial = None;
+            running.on[0].target = Some(TransitionTarget::One("running.nope".to_string()));
+
+            let triggers = ["start", "stop", "connected", "work", "tick"]
+                .into_iter()
+                .map(|t| (t.to_string(), ()))
+                .collect::<FxHashMap<_, _>>();
+            let errors = machine.validate::<_, ()>(
+                &FxHashMap::default(),
+                &FxHashMap::default(),
+                &triggers,
+                &FxHashMap::default(),
+            );
+
+            let mut paths = errors
+                .iter()
+                .map(|e| e.path().unwrap().to_string())
+                .collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec!["states.running.initial", "states.running.on[0].target"]
+            );
+        }
+
+        fn cond_handler(fallback: Option<&str>) -> EventHandler {
+            EventHandler {
+                trigger_id: "go".to_string(),
+                target: Some(TransitionTarget::Cond {
+                    conditions: vec![
+                        TransitionCondition {
+                            target: "big".to_string(),
+                            cond: "payload.value > 10".to_string(),
+                        },
+                        TransitionCondition {
+                            target: "medium".to_string(),
+                            cond: "payload.value > context.threshold".to_string(),
+                        },
+                        TransitionCondition {
+                            target: "never".to_string(),
+                            cond: "payload.value > 5".to_string(),
+                        },
+                    ],
+                    fallback: fallback.map(String::from),
+                }),
+                actions: None,
+            }
+        }
+
+        #[tokio::test]
+        async fn next_state_cond_first_match_wins() {
+            let handler = cond_handler(None);
+            let context = json!({ "threshold": 5 });
+
+            let next = handler
+                .next_state(&context, &Some(&json!({ "value": 20 })))
+                .await
+                .unwrap();
+            assert_eq!(next.as_deref(), Some("big"));
+
+            let next = handler
+                .next_state(&context, &Some(&json!({ "value": 7 })))
+                .await
+                .unwrap();
+            assert_eq!(next.as_deref(), Some("medium"));
+        }
+
+        #[tokio::test]
+        async fn next_state_cond_fallback() {
+            let handler = cond_handler(Some("small"));
+            let next = handler
+                .next_state(&json!({ "threshold": 5 }), &Some(&json!({ "value": 1 })))
+                .await
+                .unwrap();
+            assert_eq!(next.as_deref(), Some("small"));
+        }
+
+        #[tokio::test]
+        async fn next_state_cond_isolated() {
+            let handler = EventHandler {
+                trigger_id: "go".to_string(),
+                target: Some(TransitionTarget::Cond {
+                    conditions: vec![
+                        TransitionCondition {
+                            target: "commented".to_string(),
+                            cond: "payload.value > 10 // only big values".to_string(),
+                        },
+                        TransitionCondition {
+                            target: "injected".to_string(),
+                            cond: "false) { return 0; } if (false".to_string(),
+                        },
+                    ],
+                    fallback: Some("fallback".to_string()),
+                }),
+                actions: None,
+            };
+
+            let next = handler
+                .next_state(&json!({}), &Some(&json!({ "value": 20 })))
+                .await
+                .unwrap();
+            assert_eq!(next.as_deref(), Some("commented"));
+
+            handler
+                .next_state(&json!({}), &Some(&json!({ "value": 1 })))
+                .await
+                .expect_err("condition that escapes its expression should fail to parse");
+        }
+
+        #[tokio::test]
+        async fn next_state_cond_no_match() {
+            let handler = cond_handler(None);
+            let next = handler
+                .next_state(&json!({ "threshold": 5 }), &Some(&json!({ "value": 1 })))
+                .await
+                .unwrap();
+            assert_eq!(next, None);
+        }
+
+        #[test]
+        fn validate_cond_targets() {
+            let state = |handler: EventHandler| StateDefinition {
+                on: smallvec::smallvec![handler],
+                ..Default::default()
+            };
+
+            let machine = StateMachine {
+                name: "machine".to_string(),
+                description: None,
+                initial: "small".to_string(),
+                on: SmallVec::new(),
+                states: [
+                    ("small".to_string(), state(cond_handler(Some("missing")))),
+                    ("big".to_string(), state(cond_handler(None))),
+                    ("medium".to_string(), state(cond_handler(None))),
+                ]
+                .into_iter()
+                .collect(),
+            };
+
+            let triggers = [("go".to_string(), ())]
+                .into_iter()
+                .collect::<FxHashMap<_, _>>();
+            let errors = machine.validate::<_, ()>(
+                &FxHashMap::default(),
+                &FxHashMap::default(),
+                &triggers,
+                &FxHashMap::default(),
+            );
+
+            let mut paths = errors
+                .iter()
+                .map(|e| e.path().unwrap().to_string())
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            assert_eq!(
+                paths,
+                vec![
+                    "states.big.on[0].target.c.conditions[2].target",
+                    "states.medium.on[0].target.c.conditions[2].target",
+                    "states.small.on[0].target.c.conditions[2].target",
+                    "states.small.on[0].target.c.fallback",
+                ]
+            );
+        }
     }
 }
@@ -0,0 +1,374 @@
+//! Test cases that are saved with a task. Each test starts from a state, sends a sequence of
+//! payloads to the task's triggers, and then checks the final state and the actions that the
+//! task tried to run. Tests run in the same way as a simulation, so nothing is saved and no
+//! actions are run.
+
+use ergo_database::object_id::{TaskId, UserId};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{simulate::run_trigger, TaskConfig, TaskState};
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct TaskTestCase {
+    pub name: String,
+    /// The state to start from. Defaults to the config's default state.
+    #[serde(default)]
+    pub initial_state: Option<TaskState>,
+    /// The triggers to send, in order.
+    pub steps: Vec<TaskTestStep>,
+    #[serde(default)]
+    pub expect: TaskTestExpectations,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct TaskTestStep {
+    /// The local ID of the trigger.
+    pub trigger_id: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct TaskTestExpectations {
+    /// Values that must appear in the final state.
+    #[serde(default)]
+    pub state: Vec<StateExpectation>,
+    /// The actions that the task should have tried to run over all the steps, in order.
+    /// If omitted, the actions are not checked.
+    #[serde(default)]
+    pub actions: Option<Vec<ActionExpectation>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct StateExpectation {
+    /// A JSON pointer into the final state, such as `/data/0/state` for the state of the first
+    /// state machine.
+    pub pointer: String,
+    pub value: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct ActionExpectation {
+    pub task_action_local_id: String,
+    /// Fields that the action payload must contain. Other fields in the payload are ignored.
+    #[serde(default)]
+    pub payload: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TestAction {
+    /// The index of the step that sent the action.
+    pub step: usize,
+    pub task_action_local_id: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskTestResult {
+    pub name: String,
+    pub passed: bool,
+    pub failures: Vec<String>,
+    /// The state after the last step that ran.
+    pub final_state: Option<TaskState>,
+    pub actions: Vec<TestAction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskTestRun {
+    pub passed: bool,
+    pub results: Vec<TaskTestResult>,
+}
+
+impl TaskTestRun {
+    pub fn num_failed(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed).count()
+    }
+}
+
+/// Run all of a task's test cases against `config`.
+pub async fn run_test_cases(
+    task_id: &TaskId,
+    task_name: &str,
+    user_id: &UserId,
+    config: &TaskConfig,
+    cases: &[TaskTestCase],
+) -> TaskTestRun {
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        results.push(run_test_case(task_id, task_name, user_id, config, case).await);
+    }
+
+    TaskTestRun {
+        passed: results.iter().all(|r| r.passed),
+        results,
+    }
+}
+
+pub async fn run_test_case(
+    task_id: &TaskId,
+    task_name: &str,
+    user_id: &UserId,
+    config: &TaskConfig,
+    case: &TaskTestCase,
+) -> TaskTestResult {
+    let mut failures = Vec::new();
+    let mut actions = Vec::new();
+    let mut state = Some(
+        case.initial_state
+            .clone()
+            .unwrap_or_else(|| config.default_state()),
+    );
+
+    for (index, step) in case.steps.iter().enumerate() {
+        let current = match state.take() {
+            Some(s) => s,
+            None => break,
+        };
+
+        match run_trigger(
+            task_id,
+            task_name,
+            user_id,
+            config.clone(),
+            current,
+            &step.trigger_id,
+            &step.payload,
+        )
+        .await
+        {
+            Ok(run) => {
+                state = Some(run.state);
+                actions.extend(run.actions.into_iter().map(|action| TestAction {
+                    step: index,
+                    task_action_local_id: action.name,
+                    payload: action.payload,
+                }));
+            }
+            Err(e) => failures.push(format!(
+                "Step {} ({}) failed: {}",
+                index, step.trigger_id, e
+            )),
+        }
+    }
+
+    if let Some(state) = state.as_ref() {
+        check_state(state, &case.expect.state, &mut failures);
+    }
+
+    if let Some(expected) = case.expect.actions.as_ref() {
+        check_actions(&actions, expected, &mut failures);
+    }
+
+    TaskTestResult {
+        name: case.name.clone(),
+        passed: failures.is_empty(),
+        failures,
+        final_state: state,
+        actions,
+    }
+}
+
+fn check_state(state: &TaskState, expected: &[StateExpectation], failures: &mut Vec<String>) {
+    if expected.is_empty() {
+        return;
+    }
+
+    let state = match serde_json::to_value(state) {
+        Ok(s) => s,
+        Err(e) => {
+            failures.push(format!("Could not read the final state: {}", e));
+            return;
+        }
+    };
+
+    for expectation in expected {
+        match state.pointer(&expectation.pointer) {
+            Some(value) if value == &expectation.value => {}
+            Some(value) => failures.push(format!(
+                "Expected state {} to be {}, but it was {}",
+                expectation.pointer, expectation.value, value
+            )),
+            None => failures.push(format!(
+                "Expected state {} to be {}, but it was missing",
+                expectation.pointer, expectation.value
+            )),
+        }
+    }
+}
+
+fn check_actions(
+    actions: &[TestAction],
+    expected: &[ActionExpectation],
+    failures: &mut Vec<String>,
+) {
+    if actions.len() != expected.len() {
+        failures.push(format!(
+            "Expected {} actions, but the task sent {}",
+            expected.len(),
+            actions.len()
+        ));
+    }
+
+    for (index, (action, expectation)) in actions.iter().zip(expected.iter()).enumerate() {
+        if action.task_action_local_id != expectation.task_action_local_id {
+            failures.push(format!(
+                "Expected action {} to be {}, but it was {}",
+                index, expectation.task_action_local_id, action.task_action_local_id
+            ));
+            continue;
+        }
+
+        for (field, value) in expectation.payload.iter().flatten() {
+            let actual = action.payload.get(field);
+            if actual != Some(value) {
+                failures.push(format!(
+                    "Expected action {} field {} to be {}, but it was {}",
+                    index,
+                    field,
+                    value,
+                    actual.unwrap_or(&serde_json::Value::Null)
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fxhash::FxHashMap;
+    use serde_json::json;
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::state_machine::{
+        ActionInvokeDef, ActionInvokeDefDataField, ActionPayloadBuilder, EventHandler,
+        StateDefinition, StateMachine, TransitionTarget,
+    };
+
+    /// idle --start--> running, which runs the `notify` action with the payload's `message`.
+    fn test_config() -> TaskConfig {
+        let notify = ActionInvokeDef {
+            task_action_local_id: "notify".to_string(),
+            data: ActionPayloadBuilder::FieldMap(
+                [(
+                    "message".to_string(),
+                    ActionInvokeDefDataField::Input("/message".to_string(), true),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        };
+
+        let states = [
+            (
+                "idle".to_string(),
+                StateDefinition {
+                    on: smallvec![EventHandler {
+                        trigger_id: "start".to_string(),
+                        target: Some(TransitionTarget::One("running".to_string())),
+                        actions: Some(vec![notify]),
+                    }],
+                    ..Default::default()
+                },
+            ),
+            ("running".to_string(), StateDefinition::default()),
+        ]
+        .into_iter()
+        .collect::<FxHashMap<_, _>>();
+
+        TaskConfig::StateMachine(smallvec![StateMachine {
+            name: "machine".to_string(),
+            description: None,
+            initial: "idle".to_string(),
+            on: smallvec![],
+            states,
+        }])
+    }
+
+    fn test_case(expect: TaskTestExpectations) -> TaskTestCase {
+        TaskTestCase {
+            name: "starts".to_string(),
+            initial_state: None,
+            steps: vec![TaskTestStep {
+                trigger_id: "start".to_string(),
+                payload: json!({ "message": "hello" }),
+            }],
+            expect,
+        }
+    }
+
+    async fn run(case: &TaskTestCase) -> TaskTestResult {
+        run_test_case(
+            &TaskId::new(),
+            "test task",
+            &UserId::new(),
+            &test_config(),
+            case,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn passing_test() {
+        let case = test_case(TaskTestExpectations {
+            state: vec![StateExpectation {
+                pointer: "/data/0/state".to_string(),
+                value: json!("running"),
+            }],
+            actions: Some(vec![ActionExpectation {
+                task_action_local_id: "notify".to_string(),
+                payload: Some(
+                    [("message".to_string(), json!("hello"))]
+                        .into_iter()
+                        .collect(),
+                ),
+            }]),
+        });
+
+        let result = run(&case).await;
+        assert!(result.passed, "{:?}", result.failures);
+        assert_eq!(result.actions.len(), 1);
+        assert_eq!(result.actions[0].payload, json!({ "message": "hello" }));
+    }
+
+    #[tokio::test]
+    async fn failing_expectations() {
+        let case = test_case(TaskTestExpectations {
+            state: vec![
+                StateExpectation {
+                    pointer: "/data/0/state".to_string(),
+                    value: json!("idle"),
+                },
+                StateExpectation {
+                    pointer: "/data/0/missing".to_string(),
+                    value: json!(1),
+                },
+            ],
+            actions: Some(vec![]),
+        });
+
+        let result = run(&case).await;
+        assert!(!result.passed);
+        assert_eq!(
+            result.failures,
+            vec![
+                r#"Expected state /data/0/state to be "idle", but it was "running""#,
+                "Expected state /data/0/missing to be 1, but it was missing",
+                "Expected 0 actions, but the task sent 1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn step_error() {
+        let mut case = test_case(TaskTestExpectations::default());
+        case.steps[0].payload = json!({});
+
+        let result = run(&case).await;
+        assert!(!result.passed);
+        assert_eq!(result.failures.len(), 1);
+        assert!(result.failures[0].starts_with("Step 0 (start) failed"));
+        assert_eq!(result.final_state, None);
+    }
+}
@@ -114,7 +114,13 @@ declare namespace Ergo {
 
   function getContext<CONTEXT>(): CONTEXT | undefined;
   function setContext<CONTEXT>(context: CONTEXT): void;
+
+  /** Send a custom notification to the task's notification listeners. */
+  function notify(message: string, data?: unknown): void;
 }
 
+/** An alias for Ergo.notify. */
+declare function notify(message: string, data?: unknown): void;
+
 `;
 }
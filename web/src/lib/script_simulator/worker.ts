@@ -71,11 +71,15 @@ async function runScript(ctx, { script, context, payload }) {
         data: actionData,
       });
     },
+    notify(message: string, data?: unknown) {
+      // Notifications aren't sent from the simulator, so just show them in the console.
+      console.info('Notification:', message, data);
+    },
   };
 
-  let fn = new AsyncFunction('Ergo', script);
+  let fn = new AsyncFunction('Ergo', 'notify', script);
   try {
-    await fn(Ergo);
+    await fn(Ergo, Ergo.notify);
     ctx.resolve({
       context,
       actions,